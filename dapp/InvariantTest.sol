@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract Stateful {
+    bool public primed;
+    bool public broken;
+
+    function prime() public {
+        primed = true;
+    }
+
+    function reset() public {
+        primed = false;
+    }
+
+    function breakIt() public {
+        if (primed) {
+            broken = true;
+        }
+    }
+}
+
+// only a sequence of calls to the deployed contract can break the invariant
+contract InvariantBreaker {
+    Stateful stateful;
+
+    function setUp() public {
+        stateful = new Stateful();
+    }
+
+    function invariantNotBroken() public view returns (bool) {
+        return !stateful.broken();
+    }
+
+    function invariantAlwaysHolds() public pure returns (bool) {
+        return true;
+    }
+}
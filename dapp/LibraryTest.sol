@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+library Math {
+    function double(uint256 x) external pure returns (uint256) {
+        return 2 * x;
+    }
+}
+
+// calls `Math`, so that it must be deployed first
+library Quad {
+    function quadruple(uint256 x) external pure returns (uint256) {
+        return Math.double(Math.double(x));
+    }
+}
+
+contract LibraryTest {
+    function testDouble() public {
+        require(Math.double(2) == 4, "double failed");
+    }
+
+    function testQuadruple() public {
+        require(Quad.quadruple(2) == 8, "quadruple failed");
+    }
+}
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+// each test contract is deployed at the same address when they run in parallel, by the same
+// sender with the same nonce on their own EVM, so they see the state written by each other's
+// tests unless it is isolated
+contract Writer {
+    uint256 written;
+
+    function testWrite() public {
+        require(written == 0, "state leaked from another contract's tests");
+        written = 1;
+    }
+}
+
+contract FirstWriterTest is Writer {}
+
+contract SecondWriterTest is Writer {}
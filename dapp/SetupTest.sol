@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract IsolationTest {
+    uint256 setups;
+    uint256 counter;
+
+    function setUp() public {
+        setups += 1;
+    }
+
+    // the setup only runs once, and its state is restored before each test
+    function testSetupRanOnce() public {
+        require(setups == 1, "setUp ran more than once");
+        counter += 1;
+    }
+
+    function testCounterIsolated() public {
+        require(counter == 0, "state leaked from another test");
+        counter += 1;
+    }
+}
+
+contract FailingSetupTest {
+    function setUp() public {
+        require(false, "setup reverted");
+    }
+
+    function testA() public {}
+
+    function testFailB() public {}
+}
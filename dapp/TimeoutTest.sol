@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract TimeoutTest {
+    function spin() internal pure {
+        uint256 i;
+        while (true) {
+            i++;
+        }
+    }
+
+    function testOk() public pure {}
+
+    function testLoop() public pure {
+        spin();
+    }
+
+    function testFuzzLoop(uint256 x) public pure {
+        if (x > 0) {
+            spin();
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use ethers::core::{types::Bytes, utils::CompiledContract};
+use dapp_solc::parse_abi;
+use ethers::core::{abi::Function, types::Bytes, utils::CompiledContract};
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -10,7 +11,7 @@ pub struct DapptoolsArtifact {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Contract {
-    abi: ethers::abi::Abi,
+    abi: serde_json::Value,
     evm: Evm,
 }
 
@@ -46,7 +47,7 @@ impl DapptoolsArtifact {
             for (contract, data) in value.iter() {
                 let data: Contract = serde_json::from_value(data.clone())?;
                 let data = CompiledContract {
-                    abi: data.abi,
+                    abi: parse_abi(data.abi)?.0,
                     bytecode: data.evm.bytecode.object,
                     runtime_bytecode: data.evm.deployed_bytecode.object,
                 };
@@ -56,6 +57,22 @@ impl DapptoolsArtifact {
 
         Ok(map)
     }
+
+    /// The custom errors in the ABIs of the contracts which declare or use any, named like in
+    /// [`DapptoolsArtifact::contracts`]
+    pub fn custom_errors(&self) -> Result<HashMap<String, Vec<Function>>> {
+        let mut map = HashMap::new();
+        for (key, value) in &self.contracts {
+            for (contract, data) in value.iter() {
+                let (_, errors) = parse_abi(data["abi"].clone())?;
+                if !errors.is_empty() {
+                    map.insert(format!("{}:{}", key, contract), errors);
+                }
+            }
+        }
+
+        Ok(map)
+    }
 }
 
 #[cfg(test)]
@@ -88,5 +105,8 @@ mod tests {
         let mut got = contracts.keys().cloned().collect::<Vec<_>>();
         got.sort_by_key(|name| name.to_lowercase());
         assert_eq!(expected, got);
+
+        // none of the contracts of the artifact has custom errors
+        assert!(data.custom_errors().unwrap().is_empty());
     }
 }
@@ -0,0 +1,163 @@
+use ethers::utils::keccak256;
+use eyre::{Result, WrapErr};
+use regex::Regex;
+use std::{convert::TryInto, str::FromStr};
+
+/// Selects the tests to run by matching regular expressions against the name of their
+/// contract, the path of the file it is declared in and the name of the test function.
+///
+/// The patterns are not anchored, i.e. `Vault` matches `VaultTest` as well.
+#[derive(Clone, Debug)]
+pub struct TestFilter {
+    contract: Regex,
+    path: Regex,
+    test: Regex,
+}
+
+impl TestFilter {
+    pub fn new(contract: &str, path: &str, test: &str) -> Result<Self> {
+        let compile = |kind: &str, pattern: &str| {
+            Regex::new(pattern).wrap_err_with(|| format!("invalid {} pattern `{}`", kind, pattern))
+        };
+        Ok(Self {
+            contract: compile("contract", contract)?,
+            path: compile("path", path)?,
+            test: compile("test", test)?,
+        })
+    }
+
+    /// Whether the tests of the contract may run. The path of contracts whose source is
+    /// unknown is considered to be empty.
+    pub fn matches_contract(&self, name: &str, path: Option<&str>) -> bool {
+        self.contract.is_match(name) && self.path.is_match(path.unwrap_or_default())
+    }
+
+    /// Whether the test function may run, provided its contract matches
+    pub fn matches_test(&self, name: &str) -> bool {
+        self.test.is_match(name)
+    }
+
+    /// The pattern of the test functions, which [`ContractRunner::run_tests`] takes
+    ///
+    /// [`ContractRunner::run_tests`]: crate::ContractRunner::run_tests
+    pub fn test_pattern(&self) -> &Regex {
+        &self.test
+    }
+}
+
+impl Default for TestFilter {
+    /// Matches all tests
+    fn default() -> Self {
+        Self::new(".*", ".*", ".*").expect("valid patterns")
+    }
+}
+
+/// One of the parts the tests are split into, e.g. to run them on several CI machines. Each
+/// test is assigned to a shard by the hash of its contract and function names, so it lands on
+/// the same shard on every run, whichever other tests there are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    /// Counted from 0
+    index: usize,
+    total: usize,
+}
+
+impl Shard {
+    pub fn new(index: usize, total: usize) -> Result<Self> {
+        if index >= total {
+            eyre::bail!(
+                "shard {} is out of range for {} shards, which are counted from 0",
+                index,
+                total
+            )
+        }
+        Ok(Self { index, total })
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Whether the test is assigned to this shard
+    pub fn contains(&self, contract: &str, test: &str) -> bool {
+        let hash = keccak256(format!("{}::{}", contract, test));
+        let hash = u64::from_be_bytes(hash[..8].try_into().expect("8 bytes"));
+        hash % self.total as u64 == self.index as u64
+    }
+}
+
+impl FromStr for Shard {
+    type Err = eyre::Error;
+
+    /// Parses `index/total`, e.g. `0/3` for the first of 3 shards
+    fn from_str(s: &str) -> Result<Self> {
+        let (index, total) =
+            s.split_once('/').ok_or_else(|| eyre::eyre!("expected `index/total`, got `{}`", s))?;
+        let index = index.parse().wrap_err_with(|| format!("invalid shard index `{}`", index))?;
+        let total = total.parse().wrap_err_with(|| format!("invalid shard total `{}`", total))?;
+        Self::new(index, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_everything_by_default() {
+        let filter = TestFilter::default();
+        assert!(filter.matches_contract("GreeterTest", Some("src/Greeter.t.sol")));
+        assert!(filter.matches_contract("GreeterTest", None));
+        assert!(filter.matches_test("testGreeting"));
+    }
+
+    #[test]
+    fn matches_names_with_separators_brackets_and_unicode() {
+        let filter = TestFilter::new("Vault::", ".*", "testDeposit").unwrap();
+        assert!(filter.matches_contract("Vault::Inner", None));
+        assert!(!filter.matches_contract("Vault:Inner", None));
+
+        // brackets must be escaped to be matched literally, angle brackets need not
+        let filter = TestFilter::new(r"Box<T>|Pair\[2\]", ".*", ".*").unwrap();
+        assert!(filter.matches_contract("Box<T>Test", None));
+        assert!(filter.matches_contract("Pair[2]Test", None));
+        assert!(!filter.matches_contract("Pair2Test", None));
+        assert!(TestFilter::new("Pair[2", ".*", ".*").is_err());
+
+        let filter = TestFilter::new("Grüße", "tésts/", "test_ünicode$").unwrap();
+        assert!(filter.matches_contract("GrüßeTest", Some("src/tésts/Grüße.t.sol")));
+        assert!(!filter.matches_contract("GrüßeTest", Some("src/tests/Grüße.t.sol")));
+        assert!(!filter.matches_contract("GrüßeTest", None));
+        assert!(filter.matches_test("test_ünicode"));
+        assert!(!filter.matches_test("test_unicode"));
+    }
+
+    #[test]
+    fn assigns_each_test_to_a_single_shard() {
+        let shards = (0..3).map(|index| Shard::new(index, 3).unwrap()).collect::<Vec<_>>();
+        for test in ["testA", "testB", "testFuzz", "invariantHolds", "test_ünicode"] {
+            let containing = shards.iter().filter(|shard| shard.contains("VaultTest", test));
+            assert_eq!(containing.count(), 1);
+        }
+        // the assignment only depends on the names
+        let shard = Shard::new(1, 3).unwrap();
+        assert_eq!(shard.contains("VaultTest", "testA"), shard.contains("VaultTest", "testA"));
+        assert!(Shard::new(0, 1).unwrap().contains("VaultTest", "testA"));
+
+        assert_eq!("2/3".parse::<Shard>().unwrap(), Shard::new(2, 3).unwrap());
+        assert!("3/3".parse::<Shard>().is_err());
+        assert!("0/0".parse::<Shard>().is_err());
+        assert!("1".parse::<Shard>().is_err());
+        assert!("a/3".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn names_invalid_patterns() {
+        let err = TestFilter::new(".*", "(src", ".*").unwrap_err();
+        assert_eq!(err.to_string(), "invalid path pattern `(src`");
+    }
+}
@@ -0,0 +1,407 @@
+//! The human-readable output of a test run, whose details depend on the [`Verbosity`]
+use crate::{multi_runner::TestResults, TestResult};
+use ansi_term::Colour;
+use dapp_utils::EventDecoder;
+use evm_adapters::{
+    call_tracing::{format_traces, CallTraceNode, SelectorDb},
+    dstest_logs::TestLogEntry,
+    state_diff::format_state_diff,
+};
+
+use ethers::types::Address;
+use eyre::{Result, WrapErr};
+use std::{collections::BTreeMap, fmt::Write, str::FromStr};
+
+/// How much of the results of the tests is printed, from 0 to [`Verbosity::MAX`]:
+///
+/// 0. nothing but the summary of the run
+/// 1. whether each test passed or failed
+/// 2. why the failing tests failed, along with their decoded logs
+/// 3. the call traces of the failing tests, along with the changes they made to the state
+/// 4. the call traces of all the tests, along with the ones of `setUp()`
+///
+/// Recording traces has a cost on every call, so the tests only record them from level 3.
+/// Fuzz tests are traced by calling their counterexample again, so only the failing ones are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Verbosity(u8);
+
+impl Verbosity {
+    pub const MAX: u8 = 4;
+
+    pub fn new(level: u8) -> Result<Self> {
+        if level > Self::MAX {
+            eyre::bail!("the verbosity must be at most {}, got {}", Self::MAX, level)
+        }
+        Ok(Self(level))
+    }
+
+    pub fn level(self) -> u8 {
+        self.0
+    }
+
+    /// Whether each test is listed as passing or failing
+    pub fn lists_tests(self) -> bool {
+        self.0 >= 1
+    }
+
+    /// Whether the reasons and logs of the failing tests are printed
+    pub fn shows_failures(self) -> bool {
+        self.0 >= 2
+    }
+
+    /// Whether the failing tests are traced
+    pub fn traces_failures(self) -> bool {
+        self.0 >= 3
+    }
+
+    /// Whether all the tests are traced, along with `setUp()`
+    pub fn traces_all(self) -> bool {
+        self.0 >= 4
+    }
+}
+
+impl Default for Verbosity {
+    /// Lists the tests along with why the failing ones failed, without tracing them
+    fn default() -> Self {
+        Self(2)
+    }
+}
+
+impl FromStr for Verbosity {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let level = s.parse().wrap_err_with(|| format!("invalid verbosity `{}`", s))?;
+        Self::new(level)
+    }
+}
+
+/// Formats the results of the tests of each contract, dapptools-style
+#[derive(Clone, Debug)]
+pub struct ResultFormatter<'a> {
+    pub verbosity: Verbosity,
+    /// Whether the statuses and the frames of the traces are colored
+    pub color: bool,
+    /// Names the calls and reverts of the traces
+    pub selectors: &'a SelectorDb,
+    /// Decodes the logs which are not DS-Test's
+    pub events: &'a EventDecoder,
+}
+
+impl<'a> ResultFormatter<'a> {
+    pub fn new(
+        verbosity: Verbosity,
+        color: bool,
+        selectors: &'a SelectorDb,
+        events: &'a EventDecoder,
+    ) -> Self {
+        Self { verbosity, color, selectors, events }
+    }
+
+    /// Formats the results of each contract, which are separated by an empty line
+    pub fn format(&self, results: &TestResults) -> String {
+        let mut out = String::new();
+        if !self.verbosity.lists_tests() {
+            return out
+        }
+        for (i, (contract_name, tests)) in results.iter().enumerate() {
+            if i > 0 {
+                let _ = writeln!(out);
+            }
+            if !tests.is_empty() {
+                let _ = writeln!(out, "Running {} tests for {}", tests.len(), contract_name);
+            }
+            for (name, result) in tests {
+                self.format_test(&mut out, name, result);
+            }
+        }
+        out
+    }
+
+    fn format_test(&self, out: &mut String, name: &str, result: &TestResult) {
+        let status = match (result.success, self.color) {
+            (true, true) => Colour::Green.paint("[PASS]").to_string(),
+            (false, true) => Colour::Red.paint("[FAIL]").to_string(),
+            (true, false) => "[PASS]".to_owned(),
+            (false, false) => "[FAIL]".to_owned(),
+        };
+        let gas = result.gas_used.map(|x| x.to_string()).unwrap_or_else(|| "[fuzztest]".to_owned());
+        let _ = writeln!(out, "{} {} (gas: {})", status, name, gas);
+
+        // show why the test failed, as reported by DS-Test or by the revert
+        if !result.success && self.verbosity.shows_failures() {
+            if let Some(ref reason) = result.reason {
+                let _ = writeln!(out, "  reason: {}", reason);
+            }
+            if let Some(ref counterexample) = result.counterexample {
+                let _ = writeln!(out, "  counterexample: {}", counterexample);
+            }
+            if let Some(ref sequence) = result.sequence {
+                let _ = writeln!(out, "  sequence:");
+                for call in sequence {
+                    let _ = writeln!(out, "    {}", call);
+                }
+            }
+            // the failure can be reproduced by passing the seed back via `--fuzz-seed`
+            if let Some(seed) = result.seed {
+                let _ = writeln!(out, "  seed: {}", seed);
+            }
+            // the logs which are not DS-Test's are decoded with the compiled contracts' events
+            for log in &result.logs {
+                let decoded = match log {
+                    TestLogEntry::Raw(raw) => {
+                        self.events.decode_log(raw).map(|log| log.to_string())
+                    }
+                    _ => None,
+                };
+                let _ = writeln!(out, "  {}", decoded.unwrap_or_else(|| log.to_string()));
+            }
+        }
+
+        if self.verbosity.traces_all() || (!result.success && self.verbosity.traces_failures()) {
+            // the runner only keeps the traces of `setUp()` at the highest verbosity, or if it
+            // failed
+            self.format_call_tree(out, "setUp", &result.setup_traces, &result.labels);
+            self.format_call_tree(out, "trace", &result.traces, &result.labels);
+            if !result.state_changes.is_empty() {
+                let _ = writeln!(out, "  state changes:");
+                for line in format_state_diff(&result.state_changes, &result.labels).lines() {
+                    let _ = writeln!(out, "    {}", line);
+                }
+            }
+        }
+    }
+
+    fn format_call_tree(
+        &self,
+        out: &mut String,
+        title: &str,
+        nodes: &[CallTraceNode],
+        labels: &BTreeMap<Address, String>,
+    ) {
+        if nodes.is_empty() {
+            return
+        }
+        let _ = writeln!(out, "  {}:", title);
+        // the frames which succeeded are green, and the ones which reverted red
+        let paint = |text: String, success: bool| match (self.color, success) {
+            (false, _) => text,
+            (true, true) => Colour::Green.paint(text).to_string(),
+            (true, false) => Colour::Red.paint(text).to_string(),
+        };
+        for line in format_traces(nodes, self.selectors, labels, &paint).lines() {
+            let _ = writeln!(out, "    {}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        persistence::{
+            ContractFailures, FailurePersistence, MemoryFailurePersistence, PersistedFailure,
+        },
+        test_helpers::COMPILED,
+        ContractRunner,
+    };
+    use dapp_utils::get_func;
+    use ethers::{abi::Token, types::H256};
+    use evm::Config;
+    use evm_adapters::{
+        call_tracing::{CallKind, CallTracer},
+        fuzz::{CounterExample, FuzzConfig},
+        known_contracts::KnownContracts,
+        sputnik::{
+            helpers::{new_backend, new_vicinity},
+            Executor,
+        },
+        state_diff::{AccountDiff, Change},
+    };
+    use regex::Regex;
+    use std::{sync::Arc, time::Duration};
+
+    fn result(success: bool) -> TestResult {
+        TestResult {
+            success,
+            gas_used: Some(21_000),
+            duration: Duration::from_millis(3),
+            ..Default::default()
+        }
+    }
+
+    /// The results of a failing unit test, of a passing one whose `setUp()` is traced as well,
+    /// and of a failing fuzz test
+    fn fixture() -> TestResults {
+        let test = Address::repeat_byte(1);
+        let greeter = Address::repeat_byte(2);
+        let labels = vec![(test, "GreeterTest".to_owned()), (greeter, "Greeter".to_owned())]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let encode = |signature: &str, args: &[Token]| {
+            get_func(signature).unwrap().encode_input(args).unwrap()
+        };
+
+        let mut tracer = CallTracer { enabled: true, ..Default::default() };
+        tracer.start(CallKind::Call, Address::zero(), test, &encode("setUp()", &[]), 0.into(), 0);
+        tracer.start(CallKind::Create, test, greeter, &[0x60, 0x80], 0.into(), 0);
+        tracer.finish(true, &[], 3_000);
+        tracer.finish(true, &[], 5_000);
+        let setup_traces = tracer.nodes().to_vec();
+
+        tracer.clear();
+        let calldata = encode("testIsolation()", &[]);
+        tracer.start(CallKind::Call, Address::zero(), test, &calldata, 0.into(), 0);
+        let greet = encode("greet(string)", &[Token::String("hi".to_owned())]);
+        tracer.start(CallKind::Call, test, greeter, &greet, 0.into(), 0);
+        tracer.finish(true, &[], 5_000);
+        tracer.start(CallKind::Call, test, greeter, &encode("greeting()", &[]), 0.into(), 0);
+        tracer.finish(true, &ethers::abi::encode(&[Token::String("hi".to_owned())]), 1_200);
+        let revert = encode("Error(string)", &[Token::String("not hi".to_owned())]);
+        tracer.finish(false, &revert, 9_000);
+        let failing_traces = tracer.nodes().to_vec();
+
+        tracer.clear();
+        let calldata = encode("testGreeting()", &[]);
+        tracer.start(CallKind::Call, Address::zero(), test, &calldata, 0.into(), 0);
+        tracer.finish(true, &[], 7_000);
+        let passing_traces = tracer.nodes().to_vec();
+
+        let mut failing = result(false);
+        failing.gas_used = Some(31_000);
+        failing.reason = Some("assertion failed".to_owned());
+        failing.logs = vec![
+            TestLogEntry::Value("Error: a == b not satisfied [string]".to_owned()),
+            TestLogEntry::Named { name: "Expected".to_owned(), value: "hi".to_owned() },
+        ];
+        failing.traces = failing_traces;
+        failing.labels = labels.clone();
+
+        let mut passing = result(true);
+        let greeting = Change { from: H256::zero(), to: H256::from_low_u64_be(1) };
+        let storage = std::iter::once((H256::zero(), greeting)).collect();
+        passing.state_changes =
+            std::iter::once((greeter, AccountDiff { storage, ..Default::default() })).collect();
+        passing.traces = passing_traces;
+        passing.setup_traces = setup_traces;
+        passing.labels = labels;
+
+        let mut fuzzed = result(false);
+        fuzzed.gas_used = None;
+        fuzzed.reason = Some("Revert".to_owned());
+        fuzzed.seed = Some(7);
+        fuzzed.counterexample = Some(CounterExample {
+            calldata: encode("testFuzzGreeting(uint256)", &[Token::Uint(5.into())]).into(),
+            args: vec![Token::Uint(5.into())],
+            names: vec!["x".to_owned()],
+        });
+
+        let tests = vec![
+            ("testFuzzGreeting".to_owned(), fuzzed),
+            ("testGreeting".to_owned(), passing),
+            ("testIsolation".to_owned(), failing),
+        ];
+        std::iter::once(("GreeterTest".to_owned(), tests.into_iter().collect())).collect()
+    }
+
+    fn format(level: u8, color: bool) -> String {
+        let mut selectors = SelectorDb::default();
+        for signature in &["testIsolation()", "testGreeting()", "greet(string)", "setUp()"] {
+            selectors.add_signature(signature).unwrap();
+        }
+        selectors.add_signature("greeting() returns (string)").unwrap();
+        let events = EventDecoder::new(&[]);
+        let formatter =
+            ResultFormatter::new(Verbosity::new(level).unwrap(), color, &selectors, &events);
+        formatter.format(&fixture())
+    }
+
+    /// The level 3 output of a run of `GreeterTest` on the cheatcode executor. The persisted
+    /// counterexample of `testFuzzShrinking` is replayed, so that it fails the same way on every
+    /// run, and the gas used is left out since it depends on the compiler's output.
+    #[test]
+    fn formats_failure_traces() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("GreeterTest").expect("could not find contract");
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &cfg, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let func = get_func("function testFuzzShrinking(uint256 x, uint256 y)").unwrap();
+        let calldata =
+            func.encode_input(&[Token::Uint(101.into()), Token::Uint(1.into())]).unwrap();
+        let failure = PersistedFailure::Fuzz {
+            signature: func.signature(),
+            calldata: calldata.into(),
+            seed: 7,
+        };
+        let persistence = Arc::new(MemoryFailurePersistence::default());
+        persistence.save("GreeterTest", "testFuzzShrinking", &failure).unwrap();
+
+        let verbosity = Verbosity::new(3).unwrap();
+        let mut runner = ContractRunner::new(&mut evm, compiled, addr);
+        runner.known_contracts = KnownContracts::new(COMPILED.iter());
+        runner.failures = Some(ContractFailures::new("GreeterTest", persistence));
+        runner.verbosity = verbosity;
+        let fuzzer = FuzzConfig::deterministic();
+        let tests = runner.run_tests(&".*".parse().unwrap(), Some(&fuzzer)).unwrap();
+        let results = std::iter::once(("GreeterTest".to_owned(), tests.into_iter().collect()))
+            .collect::<TestResults>();
+
+        let selectors =
+            SelectorDb::new(COMPILED.iter().map(|(name, contract)| (name.as_str(), &contract.abi)));
+        let events = EventDecoder::new(&[]);
+        let output = ResultFormatter::new(verbosity, false, &selectors, &events).format(&results);
+        let output = Regex::new(r"(\[|gas: )[0-9]+").unwrap().replace_all(&output, "${1}_");
+
+        let expected = r#"Running 5 tests for GreeterTest
+[PASS] testFailGreeting (gas: _)
+[FAIL] testFuzzShrinking (gas: [fuzztest])
+  reason: product greater than 100
+  counterexample: x = 101, y = 1
+  seed: 7
+  trace:
+    [_] GreeterTest::testFuzzShrinking(101, 1)
+      ← revert: product greater than 100
+  state changes:
+    nonce of 0x0000000000000000000000000000000000000000 changed from 1 to 2
+[PASS] testFuzzing (gas: [fuzztest])
+[PASS] testGreeting (gas: _)
+[PASS] testIsolation (gas: _)
+"#;
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn formats_by_verbosity() {
+        assert_eq!(format(0, false), "");
+        assert_eq!(
+            format(1, false),
+            "Running 3 tests for GreeterTest\n[FAIL] testFuzzGreeting (gas: [fuzztest])\n[PASS] \
+             testGreeting (gas: 21000)\n[FAIL] testIsolation (gas: 31000)\n"
+        );
+        let failures = format(2, false);
+        assert!(failures.contains("  Expected: hi\n"));
+        assert!(!failures.contains("trace:"));
+
+        // the passing tests are traced as well, along with `setUp()`
+        let all = format(4, false);
+        assert!(all.contains(
+            "[PASS] testGreeting (gas: 21000)\n  setUp:\n    [5000] GreeterTest::setUp()\n      \
+             [3000] new Greeter (2 bytes)\n        ← ()\n      ← ()\n  trace:\n    [7000] \
+             GreeterTest::testGreeting()\n      ← ()\n  state changes:\n    storage of \
+             Greeter slot 0x0 changed from 0x0 to 0x1\n"
+        ));
+
+        let colored = format(3, true);
+        assert!(colored.contains("\u{1b}[32m[PASS]\u{1b}[0m testGreeting"));
+        assert!(colored.contains("\u{1b}[31m[FAIL]\u{1b}[0m testIsolation"));
+        assert!(colored.contains("    [9000] \u{1b}[31mGreeterTest::testIsolation()\u{1b}[0m\n"));
+
+        assert!(Verbosity::new(5).is_err());
+        assert_eq!("3".parse::<Verbosity>().unwrap().level(), 3);
+        assert!("-v".parse::<Verbosity>().is_err());
+    }
+}
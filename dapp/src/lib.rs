@@ -4,17 +4,22 @@ pub use artifacts::DapptoolsArtifact;
 mod runner;
 pub use runner::{ContractRunner, TestResult};
 
+mod filter;
+pub use filter::{Shard, TestFilter};
+
+mod format;
+pub use format::{ResultFormatter, Verbosity};
+
 mod multi_runner;
-pub use multi_runner::{MultiContractRunner, MultiContractRunnerBuilder};
+pub use multi_runner::{MultiContractRunner, MultiContractRunnerBuilder, TestResults};
 
-mod fuzz;
+pub mod report;
 
-use ethers::abi;
-use eyre::Result;
+pub mod snapshot;
 
-pub fn decode_revert(error: &[u8]) -> Result<String> {
-    Ok(abi::decode(&[abi::ParamType::String], &error[4..])?[0].to_string())
-}
+pub mod persistence;
+
+pub use dapp_utils::decode_revert;
 
 #[cfg(test)]
 pub mod test_helpers {
@@ -1,17 +1,27 @@
-use crate::{artifacts::DapptoolsArtifact, runner::TestResult, ContractRunner};
-use dapp_solc::SolcBuilder;
-use evm_adapters::Evm;
-
-use ethers::{
-    types::Address,
-    utils::{keccak256, CompiledContract},
+use crate::{
+    artifacts::DapptoolsArtifact,
+    persistence::{ContractFailures, FailurePersistence},
+    runner::TestResult,
+    ContractRunner, Shard, TestFilter, Verbosity,
+};
+use dapp_solc::{LinkReferences, SolcBuilder};
+use dapp_utils::EventDecoder;
+use evm_adapters::{
+    call_tracing::SelectorDb, fuzz::FuzzConfig, gas_report::GasReport, invariant::InvariantConfig,
+    known_contracts::KnownContracts, linker::Linker, Evm,
 };
 
-use proptest::test_runner::TestRunner;
-use regex::Regex;
+use ethers::{abi::Function, types::Address, utils::CompiledContract};
 
-use eyre::Result;
-use std::{collections::HashMap, marker::PhantomData, path::PathBuf};
+use eyre::{Result, WrapErr};
+use rayon::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 /// Builder used for instantiating the multi-contract runner
 #[derive(Clone, Debug, Default)]
@@ -25,42 +35,76 @@ pub struct MultiContractRunnerBuilder<'a> {
     /// The path for the output file
     pub out_path: PathBuf,
     pub no_compile: bool,
-    /// The fuzzer to be used for running fuzz tests
-    pub fuzzer: Option<TestRunner>,
+    /// How the fuzz and invariant tests are run, which are skipped if unset
+    pub fuzzer: Option<FuzzConfig>,
+    /// How the invariant tests call the contracts deployed during `setUp()`
+    pub invariant: InvariantConfig,
+    /// The maximum number of test contracts run in parallel by
+    /// [`MultiContractRunner::test_parallel`], one per core if 0
+    pub jobs: usize,
+    /// How long each test may run for, after which it fails
+    pub timeout: Option<Duration>,
+    /// The part of the selected tests to run, all of them if unset
+    pub shard: Option<Shard>,
+    /// Where the failures of the fuzz and invariant tests are persisted, to be replayed first
+    /// on the next runs. They are not persisted if unset.
+    pub failure_persistence: Option<Arc<dyn FailurePersistence>>,
+    /// How much of the results is printed, which decides the traces the tests record
+    pub verbosity: Verbosity,
 }
 
 impl<'a> MultiContractRunnerBuilder<'a> {
     /// Given an EVM, proceeds to return a runner which is able to execute all tests
     /// against that evm
-    pub fn build<E, S>(self, mut evm: E) -> Result<MultiContractRunner<E, S>>
+    ///
+    /// The test contracts are only deployed once [`MultiContractRunner::test`] selects them.
+    pub fn build<E, S>(self, evm: E) -> Result<MultiContractRunner<E, S>>
     where
         E: Evm<S>,
     {
         // 1. incremental compilation
         // 2. parallel compilation
         // 3. Hardhat / Truffle-style artifacts
-        let contracts = if self.no_compile {
+        let (contracts, link_references, sources, custom_errors) = if self.no_compile {
             let out_file = std::fs::read_to_string(&self.out_path)?;
-            serde_json::from_str::<DapptoolsArtifact>(&out_file)?.contracts()?
+            let artifact = serde_json::from_str::<DapptoolsArtifact>(&out_file)?;
+            let contracts = artifact.contracts()?;
+            // the contracts of the artifact are named after their path, i.e. `path:Name`
+            let sources = contracts
+                .keys()
+                .filter_map(|name| Some((name.clone(), name.rsplit_once(':')?.0.to_owned())))
+                .collect();
+            (contracts, HashMap::new(), sources, artifact.custom_errors()?)
         } else {
-            SolcBuilder::new(self.contracts, self.remappings, self.libraries)?.build_all()?
+            let mut builder = SolcBuilder::new(self.contracts, self.remappings, self.libraries)?;
+            let contracts = builder.build_all()?;
+            (
+                contracts,
+                builder.link_references().clone(),
+                builder.source_paths(),
+                builder.custom_errors().clone(),
+            )
         };
 
-        let mut addresses = HashMap::new();
-        let init_state = contracts.iter().map(|(name, compiled)| {
-            // make a fake address for the contract, maybe anti-pattern
-            let addr = Address::from_slice(&keccak256(&compiled.runtime_bytecode)[..20]);
-            addresses.insert(name.clone(), addr);
-            (addr, compiled.runtime_bytecode.clone())
-        });
-        evm.initialize_contracts(init_state);
-
         Ok(MultiContractRunner {
             contracts,
-            addresses,
+            link_references,
+            sources,
+            custom_errors,
+            addresses: HashMap::new(),
+            libraries: BTreeMap::new(),
+            skipped: BTreeMap::new(),
+            not_in_shard: BTreeMap::new(),
+            shard: self.shard,
+            failure_persistence: self.failure_persistence,
+            verbosity: self.verbosity,
+            jobs: self.jobs,
+            timeout: self.timeout,
+            gas_report: GasReport::default(),
             evm,
             state: PhantomData,
             fuzzer: self.fuzzer,
+            invariant: self.invariant,
         })
     }
 
@@ -69,11 +113,16 @@ impl<'a> MultiContractRunnerBuilder<'a> {
         self
     }
 
-    pub fn fuzzer(mut self, fuzzer: TestRunner) -> Self {
+    pub fn fuzzer(mut self, fuzzer: FuzzConfig) -> Self {
         self.fuzzer = Some(fuzzer);
         self
     }
 
+    pub fn invariant(mut self, invariant: InvariantConfig) -> Self {
+        self.invariant = invariant;
+        self
+    }
+
     pub fn remappings(mut self, remappings: &'a [String]) -> Self {
         self.remappings = remappings;
         self
@@ -93,16 +142,70 @@ impl<'a> MultiContractRunnerBuilder<'a> {
         self.no_compile = flag;
         self
     }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    pub fn failure_persistence(mut self, persistence: Arc<dyn FailurePersistence>) -> Self {
+        self.failure_persistence = Some(persistence);
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
 }
 
+/// The results of the tests of each contract, by contract and test name
+pub type TestResults = BTreeMap<String, BTreeMap<String, TestResult>>;
+
 pub struct MultiContractRunner<E, S> {
     /// Mapping of contract name to compiled bytecode
     contracts: HashMap<String, CompiledContract>,
-    /// Mapping of contract name to the address it's been injected in the EVM state
+    /// The link references of the contracts which call libraries
+    link_references: HashMap<String, LinkReferences>,
+    /// Mapping of contract name to the path of the file it is declared in, if known
+    sources: HashMap<String, String>,
+    /// Mapping of contract name to the custom errors in its ABI, if any
+    custom_errors: HashMap<String, Vec<Function>>,
+    /// Mapping of the name of each test contract deployed so far to its address
     addresses: HashMap<String, Address>,
+    /// Mapping of the name of each library deployed so far to its address
+    libraries: BTreeMap<String, Address>,
+    /// The names of the tests of each contract which the last filter did not select
+    skipped: BTreeMap<String, Vec<String>>,
+    /// The names of the tests of each contract which the filter of the last run selected, but
+    /// which are assigned to other shards
+    not_in_shard: BTreeMap<String, Vec<String>>,
+    /// The part of the selected tests to run
+    shard: Option<Shard>,
+    /// Where the failures of the fuzz and invariant tests are persisted
+    failure_persistence: Option<Arc<dyn FailurePersistence>>,
+    /// How much of the results is printed
+    verbosity: Verbosity,
+    /// The maximum number of test contracts run in parallel
+    jobs: usize,
+    /// How long each test may run for
+    timeout: Option<Duration>,
+    /// The gas used by the tests run in parallel, on their own EVMs
+    gas_report: GasReport,
     /// The EVM instance used in the test runner
     evm: E,
-    fuzzer: Option<TestRunner>,
+    fuzzer: Option<FuzzConfig>,
+    invariant: InvariantConfig,
     state: PhantomData<S>,
 }
 
@@ -110,58 +213,276 @@ impl<E, S> MultiContractRunner<E, S>
 where
     E: Evm<S>,
 {
-    pub fn test(&mut self, pattern: Regex) -> Result<HashMap<String, HashMap<String, TestResult>>> {
+    /// Runs the tests selected by the filter, after deploying their contracts. The tests which
+    /// were filtered out are available via [`MultiContractRunner::skipped`], and the ones which
+    /// are assigned to other shards via [`MultiContractRunner::not_in_shard`]. The contracts
+    /// with no test in the shard are not deployed, nor is their `setUp()` run.
+    pub fn test(&mut self, filter: &TestFilter) -> Result<TestResults> {
         // NB: We also have access to the contract's abi. When running the test.
         // Can this be useful for decorating the stacktrace during a revert?
-        // TODO: Check if the function starts with `prove` or `invariant`
-        // Filter out for contracts that have at least 1 test function
+        // TODO: Check if the function starts with `prove`
+        let known_contracts = self.known_contracts();
         let contracts = std::mem::take(&mut self.contracts);
-        let tests = contracts
-            .iter()
-            .filter(|(_, contract)| contract.abi.functions().any(|x| x.name.starts_with("test")));
 
-        // TODO: Is this pattern OK? We use the memory and then write it back to avoid any
-        // borrow checker issues. Otherwise, we'd need to clone large vectors.
-        let addresses = std::mem::take(&mut self.addresses);
-        let results = tests
+        // select the contracts with at least 1 matching test before deploying any, so that the
+        // other ones are not deployed at all
+        let (tests, skipped, not_in_shard) =
+            select_tests(&contracts, &self.sources, filter, self.shard.as_ref());
+        self.skipped = skipped;
+        self.not_in_shard = not_in_shard;
+
+        // deploy the test contracts by running their constructors, after the libraries they
+        // call, the other contracts are deployed by the tests themselves
+        let tests = tests
             .into_iter()
-            .map(|(name, contract)| {
-                let address = addresses
-                    .get(name)
-                    .ok_or_else(|| eyre::eyre!("could not find contract address"))?;
+            .map(|(name, contract)| Ok((name, contract, self.deploy(&contracts, name)?)))
+            .collect::<Result<Vec<_>>>();
+        let tests = match tests {
+            Ok(tests) => tests,
+            Err(err) => {
+                self.contracts = contracts;
+                return Err(err)
+            }
+        };
 
-                let result = self.run_tests(name, contract, *address, &pattern)?;
-                Ok((name.clone(), result))
+        let results = tests
+            .into_iter()
+            .map(|(name, contract, address)| {
+                let result = self.run_tests(name, contract, address, filter, &known_contracts)?;
+                Ok((name.clone(), result.into_iter().collect()))
             })
             .filter_map(|x: Result<_>| x.ok())
-            .filter_map(|(name, res)| if res.is_empty() { None } else { Some((name, res)) })
-            .collect::<HashMap<_, _>>();
+            .filter(|(_, res): &(_, BTreeMap<_, _>)| !res.is_empty())
+            .collect::<TestResults>();
 
+        // TODO: Is this pattern OK? We use the memory and then write it back to avoid any
+        // borrow checker issues. Otherwise, we'd need to clone large vectors.
         self.contracts = contracts;
-        self.addresses = addresses;
 
         Ok(results)
     }
 
-    // The _name field is unused because we only want it for tracing
+    /// Runs the tests selected by the filter like [`MultiContractRunner::test`], but runs the
+    /// test contracts in parallel on up to `jobs` threads (see
+    /// [`MultiContractRunnerBuilder::jobs`]), each on its own EVM created by `new_evm`. The
+    /// EVMs do not share any state besides their backend, e.g. a [`SharedBackend`] when
+    /// forking, and their gas reports are merged into the one of this runner.
+    ///
+    /// [`SharedBackend`]: evm_adapters::sputnik::SharedBackend
+    pub fn test_parallel<F>(&mut self, filter: &TestFilter, new_evm: F) -> Result<TestResults>
+    where
+        F: Fn() -> E + Sync,
+    {
+        let known_contracts = self.known_contracts();
+        let (tests, skipped, not_in_shard) =
+            select_tests(&self.contracts, &self.sources, filter, self.shard.as_ref());
+        self.skipped = skipped;
+        self.not_in_shard = not_in_shard;
+
+        let contracts = &self.contracts;
+        let linker = Linker::new(contracts, &self.link_references);
+        let fuzzer = self.fuzzer.as_ref();
+        let invariant = &self.invariant;
+        let timeout = self.timeout;
+        let verbosity = self.verbosity;
+        let shard = self.shard.as_ref();
+        let persistence = self.failure_persistence.as_ref();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(self.jobs).build()?;
+        // the results are collected in the order of the tests, whichever completes first
+        let results = pool.install(|| {
+            tests
+                .into_par_iter()
+                .map(|(name, contract)| {
+                    let mut evm = new_evm();
+                    let address = deploy(&mut evm, &linker, name, &mut BTreeMap::new())?;
+                    let result = {
+                        let mut runner = ContractRunner::new(&mut evm, contract, address);
+                        runner.known_contracts = known_contracts.clone();
+                        runner.invariant_config = invariant.clone();
+                        runner.timeout = timeout;
+                        runner.verbosity = verbosity;
+                        runner.failures = persistence
+                            .map(|persistence| ContractFailures::new(name, persistence.clone()));
+                        runner.run_selected_tests(
+                            |test| is_selected(filter, shard, name, test),
+                            fuzzer,
+                        )
+                    };
+                    Ok((name, result, evm.gas_report()))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        let mut merged = BTreeMap::new();
+        for (name, result, gas_report) in results {
+            self.gas_report.merge(gas_report);
+            match result {
+                Ok(result) if !result.is_empty() => {
+                    merged.insert(name.clone(), result.into_iter().collect());
+                }
+                _ => {}
+            }
+        }
+        Ok(merged)
+    }
+
+    /// The names of the tests of each contract which the filter of the last run did not select
+    pub fn skipped(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.skipped
+    }
+
+    /// The names of the tests of each contract which the filter of the last run selected, but
+    /// which are assigned to other shards than the one of this runner
+    pub fn not_in_shard(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.not_in_shard
+    }
+
+    /// Deploys the test contract, along with the libraries it calls, unless it already is
+    fn deploy(
+        &mut self,
+        contracts: &HashMap<String, CompiledContract>,
+        name: &str,
+    ) -> Result<Address> {
+        if let Some(address) = self.addresses.get(name) {
+            return Ok(*address)
+        }
+        let linker = Linker::new(contracts, &self.link_references);
+        let address = deploy(&mut self.evm, &linker, name, &mut self.libraries)?;
+        self.addresses.insert(name.to_owned(), address);
+        Ok(address)
+    }
+
+    /// Returns the functions and custom errors of all the compiled contracts keyed by their
+    /// selector, along with the contracts the functions belong to, to name the calls and reverts
+    /// of traces
+    pub fn selector_db(&self) -> SelectorDb {
+        let mut selectors = SelectorDb::new(
+            self.contracts.iter().map(|(name, contract)| (name.as_str(), &contract.abi)),
+        );
+        for error in self.custom_errors.values().flatten() {
+            selectors.add_error(error.clone());
+        }
+        selectors
+    }
+
+    /// Returns the compiled contracts, to identify the deployed ones, e.g. the contracts of the
+    /// gas report
+    pub fn known_contracts(&self) -> KnownContracts {
+        KnownContracts::new(&self.contracts)
+    }
+
+    /// Returns a decoder of the events of all the compiled contracts, e.g. to show the logs
+    /// emitted by failing tests
+    pub fn event_decoder(&self) -> EventDecoder {
+        let abis = self.contracts.values().map(|contract| contract.abi.clone()).collect::<Vec<_>>();
+        EventDecoder::new(&abis)
+    }
+
+    /// Returns the gas used by the calls made by the tests run so far, if the EVM collects it
+    pub fn gas_report(&self) -> GasReport {
+        let mut report = self.evm.gas_report();
+        report.merge(self.gas_report.clone());
+        report
+    }
+
     #[tracing::instrument(
         name = "contract",
         skip_all,
         err,
-        fields(name = %_name)
+        fields(name = %name)
     )]
     fn run_tests(
         &mut self,
-        _name: &str,
+        name: &str,
         contract: &CompiledContract,
         address: Address,
-        pattern: &Regex,
+        filter: &TestFilter,
+        known_contracts: &KnownContracts,
     ) -> Result<HashMap<String, TestResult>> {
         let mut runner = ContractRunner::new(&mut self.evm, contract, address);
-        runner.run_tests(pattern, self.fuzzer.as_mut())
+        runner.known_contracts = known_contracts.clone();
+        runner.invariant_config = self.invariant.clone();
+        runner.timeout = self.timeout;
+        runner.verbosity = self.verbosity;
+        runner.failures = self
+            .failure_persistence
+            .as_ref()
+            .map(|persistence| ContractFailures::new(name, persistence.clone()));
+        let shard = self.shard.as_ref();
+        runner
+            .run_selected_tests(|test| is_selected(filter, shard, name, test), self.fuzzer.as_ref())
     }
 }
 
+/// Whether the test of the contract is selected by the filter, provided the contract is, and
+/// assigned to the shard if any
+fn is_selected(filter: &TestFilter, shard: Option<&Shard>, contract: &str, test: &str) -> bool {
+    filter.matches_test(test) && shard.map_or(true, |shard| shard.contains(contract, test))
+}
+
+/// The names of the tests of each contract
+type TestNames = BTreeMap<String, Vec<String>>;
+
+/// Selects the test contracts with at least 1 test matching the filter in the shard, and
+/// returns the names of the tests which do not match, and of the ones which match but are
+/// assigned to other shards
+fn select_tests<'a>(
+    contracts: &'a HashMap<String, CompiledContract>,
+    sources: &HashMap<String, String>,
+    filter: &TestFilter,
+    shard: Option<&Shard>,
+) -> (Vec<(&'a String, &'a CompiledContract)>, TestNames, TestNames) {
+    let mut tests = Vec::new();
+    let mut skipped_tests = BTreeMap::new();
+    let mut tests_not_in_shard = BTreeMap::new();
+    for (name, contract) in contracts {
+        let path = sources.get(name).map(String::as_str);
+        let contract_matches = filter.matches_contract(name, path);
+        let (matching, skipped): (Vec<_>, Vec<_>) = test_functions(contract)
+            .partition(|func| contract_matches && filter.matches_test(&func.name));
+        let (selected, not_in_shard): (Vec<_>, Vec<_>) =
+            matching.into_iter().partition(|func| is_selected(filter, shard, name, &func.name));
+        if !skipped.is_empty() {
+            let skipped = skipped.into_iter().map(|func| func.name.clone()).collect();
+            skipped_tests.insert(name.clone(), skipped);
+        }
+        if !not_in_shard.is_empty() {
+            let not_in_shard = not_in_shard.into_iter().map(|func| func.name.clone()).collect();
+            tests_not_in_shard.insert(name.clone(), not_in_shard);
+        }
+        if !selected.is_empty() {
+            tests.push((name, contract));
+        }
+    }
+    // sorted, so that the contracts are deployed in the same order on every run
+    tests.sort_by_key(|(name, _)| *name);
+    (tests, skipped_tests, tests_not_in_shard)
+}
+
+/// Deploys the test contract by running its constructor, after deploying the libraries it calls
+/// which are not in `libraries` yet
+fn deploy<E: Evm<S>, S>(
+    evm: &mut E,
+    linker: &Linker,
+    name: &str,
+    libraries: &mut BTreeMap<String, Address>,
+) -> Result<Address> {
+    linker.deploy_libraries(evm, Address::zero(), name, libraries)?;
+    let (bytecode, _) = linker.link(name, libraries)?;
+    let (address, _, _) = evm
+        .deploy(Address::zero(), bytecode, 0.into())
+        .wrap_err_with(|| format!("could not deploy {}", name))?;
+    Ok(address)
+}
+
+/// The test functions of the contract, including invariants
+fn test_functions(contract: &CompiledContract) -> impl Iterator<Item = &Function> {
+    contract
+        .abi
+        .functions()
+        .filter(|func| func.name.starts_with("test") || func.name.starts_with("invariant"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,7 +491,8 @@ mod tests {
         let mut runner =
             MultiContractRunnerBuilder::default().contracts("./GreetTest.sol").build(evm).unwrap();
 
-        let results = runner.test(Regex::new(".*").unwrap()).unwrap();
+        let results = runner.test(&TestFilter::default()).unwrap();
+        assert!(runner.skipped().is_empty());
 
         // 2 contracts
         assert_eq!(results.len(), 2);
@@ -182,15 +504,39 @@ mod tests {
             assert!(res.iter().all(|(_, result)| result.success));
         }
 
-        let only_gm = runner.test(Regex::new("testGm.*").unwrap()).unwrap();
+        let only_gm = runner.test(&TestFilter::new(".*", ".*", "testGm.*").unwrap()).unwrap();
         assert_eq!(only_gm.len(), 1);
         assert_eq!(only_gm["GmTest"].len(), 1);
+        // the filtered out tests are counted, including the fuzz tests
+        assert_eq!(runner.skipped().len(), 1);
+        assert_eq!(runner.skipped()["GreeterTest"].len(), 5);
+
+        let by_path = TestFilter::new("Greeter", "GreetTest.sol$", ".*").unwrap();
+        let only_greeter = runner.test(&by_path).unwrap();
+        assert_eq!(only_greeter.len(), 1);
+        assert_eq!(only_greeter["GreeterTest"].len(), 3);
+        assert_eq!(runner.skipped()["GmTest"], ["testGm"]);
+
+        let none = TestFilter::new(".*", "NoSuchTest.sol", ".*").unwrap();
+        assert!(runner.test(&none).unwrap().is_empty());
+        assert_eq!(runner.skipped().values().map(Vec::len).sum::<usize>(), 6);
+    }
+
+    fn test_library_linking<S, E: Evm<S>>(evm: E) {
+        let mut runner = MultiContractRunnerBuilder::default()
+            .contracts("./LibraryTest.sol")
+            .build(evm)
+            .unwrap();
+        let results = runner.test(&TestFilter::default()).unwrap();
+        let results = &results["LibraryTest"];
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.success));
     }
 
     fn test_ds_test_fail<S, E: Evm<S>>(evm: E) {
         let mut runner =
             MultiContractRunnerBuilder::default().contracts("./../FooTest.sol").build(evm).unwrap();
-        let results = runner.test(Regex::new(".*").unwrap()).unwrap();
+        let results = runner.test(&TestFilter::default()).unwrap();
         let test = results.get("FooTest").unwrap().get("testFailX").unwrap();
         assert!(test.success);
     }
@@ -202,6 +548,7 @@ mod tests {
             helpers::{new_backend, new_vicinity},
             Executor,
         };
+        use std::sync::Barrier;
 
         #[test]
         fn test_sputnik_multi_runner() {
@@ -213,6 +560,149 @@ mod tests {
             test_multi_runner(evm);
         }
 
+        #[test]
+        fn test_sputnik_library_linking() {
+            let config = Config::istanbul();
+            let gas_limit = 12_500_000;
+            let env = new_vicinity();
+            let backend = new_backend(&env, Default::default());
+            let evm = Executor::new(gas_limit, &config, &backend);
+            test_library_linking(evm);
+        }
+
+        #[test]
+        fn test_sputnik_parallel_multi_runner() {
+            let config = Config::istanbul();
+            let gas_limit = 12_500_000;
+            let env = new_vicinity();
+            let backend = new_backend(&env, Default::default());
+            let new_evm = || Executor::new(gas_limit, &config, &backend);
+            let mut runner = MultiContractRunnerBuilder::default()
+                .contracts("./GreetTest.sol")
+                .jobs(2)
+                .build(new_evm())
+                .unwrap();
+
+            // neither contract gets past the barrier unless both run at the same time, each on
+            // its own EVM, i.e. this deadlocks if they shared one
+            let barrier = Barrier::new(2);
+            let results = runner
+                .test_parallel(&TestFilter::default(), || {
+                    barrier.wait();
+                    new_evm()
+                })
+                .unwrap();
+            assert_eq!(
+                results.keys().map(String::as_str).collect::<Vec<_>>(),
+                ["GmTest", "GreeterTest"]
+            );
+            assert_eq!(results["GreeterTest"].len(), 3);
+            assert!(results.values().flat_map(|tests| tests.values()).all(|result| result.success));
+        }
+
+        #[test]
+        fn test_sputnik_parallel_isolation() {
+            let config = Config::istanbul();
+            let gas_limit = 12_500_000;
+            let env = new_vicinity();
+            let backend = new_backend(&env, Default::default());
+            let new_evm = || Executor::new(gas_limit, &config, &backend);
+            let mut runner = MultiContractRunnerBuilder::default()
+                .contracts("./ParallelTest.sol")
+                .jobs(2)
+                .build(new_evm())
+                .unwrap();
+
+            // both contracts write the same slot of the same address at the same time, which
+            // each of them requires to be unset
+            let barrier = Barrier::new(2);
+            let results = runner
+                .test_parallel(&TestFilter::new("WriterTest$", ".*", ".*").unwrap(), || {
+                    barrier.wait();
+                    new_evm()
+                })
+                .unwrap();
+            assert_eq!(
+                results.keys().map(String::as_str).collect::<Vec<_>>(),
+                ["FirstWriterTest", "SecondWriterTest"]
+            );
+            for result in results.values().flat_map(|tests| tests.values()) {
+                assert!(result.success, "{:?}", result.reason);
+            }
+        }
+
+        #[test]
+        fn test_sputnik_sharding() {
+            let config = Config::istanbul();
+            let gas_limit = 12_500_000;
+            let env = new_vicinity();
+            let backend = new_backend(&env, Default::default());
+            // GreetTest.sol, LibraryTest.sol and SetupTest.sol, without the loops of TimeoutTest.sol
+            let builder = || MultiContractRunnerBuilder::default().contracts("./[GLS]*Test.sol");
+            let outcomes = |results: &TestResults| {
+                results
+                    .iter()
+                    .flat_map(|(contract, tests)| {
+                        tests.iter().map(move |(test, result)| {
+                            ((contract.clone(), test.clone()), result.success)
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let mut runner = builder().build(Executor::new(gas_limit, &config, &backend)).unwrap();
+            let unsharded = outcomes(&runner.test(&TestFilter::default()).unwrap());
+            assert!(runner.not_in_shard().is_empty());
+
+            let mut union = BTreeMap::new();
+            for index in 0..3 {
+                let mut runner = builder()
+                    .shard(Shard::new(index, 3).unwrap())
+                    .build(Executor::new(gas_limit, &config, &backend))
+                    .unwrap();
+                let results = runner.test(&TestFilter::default()).unwrap();
+                // the tests of the other shards are reported on their own, not as skipped
+                assert!(runner.skipped().is_empty());
+                for (contract, tests) in runner.not_in_shard() {
+                    let ran = results.get(contract);
+                    assert!(tests
+                        .iter()
+                        .all(|test| ran.map_or(true, |ran| !ran.contains_key(test))));
+                }
+                for (name, success) in outcomes(&results) {
+                    assert!(union.insert(name.clone(), success).is_none(), "{:?} ran twice", name);
+                }
+            }
+            assert_eq!(union.into_iter().collect::<Vec<_>>(), unsharded);
+        }
+
+        #[test]
+        fn test_sputnik_timeout() {
+            let config = Config::istanbul();
+            let env = new_vicinity();
+            // the executor the CLI uses, which stops the calls being executed once interrupted,
+            // with enough gas for the loops to run for hours
+            let evm = Executor::new_with_cheatcodes(
+                new_backend(&env, Default::default()),
+                1 << 60,
+                &config,
+                false,
+            );
+            let mut runner = MultiContractRunnerBuilder::default()
+                .contracts("./TimeoutTest.sol")
+                .fuzzer(FuzzConfig::deterministic())
+                .timeout(Duration::from_millis(100))
+                .build(evm)
+                .unwrap();
+            let results = runner.test(&TestFilter::default()).unwrap();
+            let results = &results["TimeoutTest"];
+            assert!(results["testOk"].success);
+            for test in ["testLoop", "testFuzzLoop"] {
+                assert!(!results[test].success);
+                assert_eq!(results[test].reason.as_deref(), Some("timed out after 100ms"));
+            }
+        }
+
         #[test]
         fn test_sputnik_ds_test_fail() {
             let config = Config::istanbul();
@@ -0,0 +1,243 @@
+//! Persistence of the failures of fuzz and invariant tests, which are replayed before any new
+//! input is generated on the next runs, so that fixing them can be verified deterministically
+use ethers::types::{Address, Bytes};
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The directory the failures are persisted in by default, relative to the working directory
+pub const DEFAULT_FAILURES_DIR: &str = "cache/failures";
+
+/// The failure of a fuzz or invariant test, as persisted
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PersistedFailure {
+    Fuzz {
+        /// The signature of the test function, to detect that it changed since
+        signature: String,
+        /// The calldata of the counterexample
+        calldata: Bytes,
+        /// The seed the counterexample was found with
+        seed: u64,
+    },
+    Invariant {
+        /// The sequence of calls which broke the invariant
+        sequence: Vec<PersistedCall>,
+        /// The seed the sequence was found with
+        seed: u64,
+    },
+}
+
+/// A call of the sequence which broke an invariant
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PersistedCall {
+    pub sender: Address,
+    pub target: Address,
+    /// The name of the target contract, to detect that another one is deployed there since
+    pub contract: String,
+    pub calldata: Bytes,
+}
+
+/// Where the failures of the tests are persisted, by contract and test name. The entries
+/// which cannot be read are discarded with a warning rather than failing the run.
+pub trait FailurePersistence: fmt::Debug + Send + Sync {
+    /// The failure persisted for the test, if any
+    fn load(&self, contract: &str, test: &str) -> Option<PersistedFailure>;
+
+    /// Persists the failure of the test, replacing the previous one if any
+    fn save(&self, contract: &str, test: &str, failure: &PersistedFailure) -> Result<()>;
+
+    /// Discards the failure persisted for the test, if any, e.g. once it is fixed
+    fn remove(&self, contract: &str, test: &str) -> Result<()>;
+}
+
+/// Persists the failures as JSON files in a directory, in `<contract>/<test>.json`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileFailurePersistence {
+    dir: PathBuf,
+}
+
+impl FileFailurePersistence {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, contract: &str, test: &str) -> PathBuf {
+        self.dir.join(contract).join(format!("{}.json", test))
+    }
+}
+
+impl Default for FileFailurePersistence {
+    /// Persists the failures in [`DEFAULT_FAILURES_DIR`]
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURES_DIR)
+    }
+}
+
+impl FailurePersistence for FileFailurePersistence {
+    fn load(&self, contract: &str, test: &str) -> Option<PersistedFailure> {
+        let path = self.path(contract, test);
+        let contents = std::fs::read_to_string(&path).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(failure) => Some(failure),
+            Err(err) => {
+                tracing::warn!("discarding the corrupted failure {}: {}", path.display(), err);
+                let _ = std::fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    fn save(&self, contract: &str, test: &str, failure: &PersistedFailure) -> Result<()> {
+        let path = self.path(contract, test);
+        let write = || {
+            std::fs::create_dir_all(path.parent().unwrap_or_else(|| Path::new(".")))?;
+            std::fs::write(&path, serde_json::to_string_pretty(failure)?)?;
+            Ok::<_, eyre::Error>(())
+        };
+        write().wrap_err_with(|| format!("could not persist the failure {}", path.display()))
+    }
+
+    fn remove(&self, contract: &str, test: &str) -> Result<()> {
+        let path = self.path(contract, test);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err)
+                .wrap_err_with(|| format!("could not remove the failure {}", path.display())),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps the failures in memory, e.g. for tests
+#[derive(Debug, Default)]
+pub struct MemoryFailurePersistence {
+    /// The failures keyed by `contract::test`
+    failures: Mutex<BTreeMap<String, PersistedFailure>>,
+}
+
+impl MemoryFailurePersistence {
+    fn key(contract: &str, test: &str) -> String {
+        format!("{}::{}", contract, test)
+    }
+}
+
+impl FailurePersistence for MemoryFailurePersistence {
+    fn load(&self, contract: &str, test: &str) -> Option<PersistedFailure> {
+        self.failures.lock().unwrap().get(&Self::key(contract, test)).cloned()
+    }
+
+    fn save(&self, contract: &str, test: &str, failure: &PersistedFailure) -> Result<()> {
+        self.failures.lock().unwrap().insert(Self::key(contract, test), failure.clone());
+        Ok(())
+    }
+
+    fn remove(&self, contract: &str, test: &str) -> Result<()> {
+        self.failures.lock().unwrap().remove(&Self::key(contract, test));
+        Ok(())
+    }
+}
+
+/// The persisted failures of the tests of a contract
+#[derive(Clone, Debug)]
+pub struct ContractFailures {
+    pub contract: String,
+    pub persistence: Arc<dyn FailurePersistence>,
+}
+
+impl ContractFailures {
+    pub fn new(contract: impl Into<String>, persistence: Arc<dyn FailurePersistence>) -> Self {
+        Self { contract: contract.into(), persistence }
+    }
+
+    pub fn load(&self, test: &str) -> Option<PersistedFailure> {
+        self.persistence.load(&self.contract, test)
+    }
+
+    /// Persists the failure of the test, only warning if it cannot be, as the test ran anyway
+    pub fn save(&self, test: &str, failure: &PersistedFailure) {
+        if let Err(err) = self.persistence.save(&self.contract, test, failure) {
+            tracing::warn!("{:?}", err);
+        }
+    }
+
+    /// Discards the failure of the test, with a warning explaining why, e.g. it went stale
+    pub fn discard(&self, test: &str, why: impl fmt::Display) {
+        tracing::warn!("discarding the persisted failure of {}::{}: {}", self.contract, test, why);
+        self.remove(test);
+    }
+
+    /// Discards the failure of the test, e.g. once it is fixed
+    pub fn remove(&self, test: &str) {
+        if let Err(err) = self.persistence.remove(&self.contract, test) {
+            tracing::warn!("{:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failures() -> Vec<PersistedFailure> {
+        vec![
+            PersistedFailure::Fuzz {
+                signature: "testFuzz(uint256)".to_owned(),
+                calldata: vec![1, 2, 3, 4, 5].into(),
+                seed: 7,
+            },
+            PersistedFailure::Invariant {
+                sequence: vec![PersistedCall {
+                    sender: Address::repeat_byte(0x10),
+                    target: Address::repeat_byte(0x11),
+                    contract: "Stateful".to_owned(),
+                    calldata: vec![0xde, 0xad, 0xbe, 0xef].into(),
+                }],
+                seed: 8,
+            },
+        ]
+    }
+
+    fn round_trips_failures(persistence: &dyn FailurePersistence) {
+        let failures = failures();
+        assert_eq!(persistence.load("VaultTest", "testFuzz"), None);
+        persistence.save("VaultTest", "testFuzz", &failures[0]).unwrap();
+        persistence.save("VaultTest", "invariantSolvent", &failures[1]).unwrap();
+        assert_eq!(persistence.load("VaultTest", "testFuzz").as_ref(), Some(&failures[0]));
+        assert_eq!(persistence.load("VaultTest", "invariantSolvent").as_ref(), Some(&failures[1]));
+        assert_eq!(persistence.load("OtherTest", "testFuzz"), None);
+
+        // saving replaces the previous failure
+        persistence.save("VaultTest", "testFuzz", &failures[1]).unwrap();
+        assert_eq!(persistence.load("VaultTest", "testFuzz").as_ref(), Some(&failures[1]));
+
+        persistence.remove("VaultTest", "testFuzz").unwrap();
+        assert_eq!(persistence.load("VaultTest", "testFuzz"), None);
+        // removing a missing failure is not an error
+        persistence.remove("VaultTest", "testFuzz").unwrap();
+    }
+
+    #[test]
+    fn memory_persistence() {
+        round_trips_failures(&MemoryFailurePersistence::default());
+    }
+
+    #[test]
+    fn file_persistence() {
+        let dir = std::env::temp_dir().join(format!("dapp-failures-{}", std::process::id()));
+        let persistence = FileFailurePersistence::new(&dir);
+        round_trips_failures(&persistence);
+
+        // corrupted entries are discarded
+        let path = dir.join("VaultTest").join("testCorrupted.json");
+        std::fs::write(&path, r#"{"kind":"fuzz","calldata":"#).unwrap();
+        assert_eq!(persistence.load("VaultTest", "testCorrupted"), None);
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! The machine-readable report of a test run, e.g. for CI integrations. Its schema is versioned
+//! by [`REPORT_FORMAT`], which is bumped whenever a field is changed or removed.
+use crate::{multi_runner::TestResults, TestResult};
+use dapp_utils::{format_token, EventDecoder};
+use evm_adapters::{dstest_logs::TestLogEntry, fuzz::CounterExample};
+
+use ethers::types::Bytes;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::Write};
+
+/// The version of the schema of [`Report`]
+pub const REPORT_FORMAT: u32 = 1;
+
+/// The results of all the test contracts of a run
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// The version of the schema, i.e. [`REPORT_FORMAT`]
+    pub format: u32,
+    /// The test contracts, sorted by name
+    pub suites: Vec<SuiteResult>,
+}
+
+/// The results of the tests of a contract
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SuiteResult {
+    pub contract: String,
+    /// The tests, sorted by name
+    pub tests: Vec<TestCaseResult>,
+}
+
+/// Whether a test passed, failed, was not selected by the filter or was assigned to another
+/// shard
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStatus {
+    Success,
+    Failure,
+    Skipped,
+    NotInShard,
+}
+
+/// The result of a test
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TestCaseResult {
+    pub name: String,
+    pub status: TestStatus,
+    /// The time it took to run the test, in microseconds
+    pub duration_us: u64,
+    /// The gas used by unit tests
+    pub gas_used: Option<u64>,
+    /// The decoded logs emitted by failing unit tests
+    pub logs: Vec<String>,
+    /// Why the test failed, e.g. its decoded revert reason
+    pub reason: Option<String>,
+    /// The minimal input found to make a fuzz test fail
+    pub counterexample: Option<CounterExampleArgs>,
+    /// The minimal sequence of calls which broke the invariant of failing invariant tests
+    pub sequence: Option<Vec<String>>,
+    /// The seed the inputs of fuzz and invariant tests were generated from
+    pub seed: Option<u64>,
+}
+
+/// The decoded arguments of a fuzz test's counterexample, along with its calldata
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CounterExampleArgs {
+    pub calldata: Bytes,
+    pub args: Vec<NamedValue>,
+}
+
+/// An argument, formatted so that [`dapp_utils::parse_token`] parses it back
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NamedValue {
+    /// The name of the parameter, which is empty if unnamed
+    pub name: String,
+    pub value: String,
+}
+
+impl Report {
+    /// Collects the results of a run, along with the tests which the filter did not select and
+    /// the ones which were assigned to other shards. The logs of the tests which are not
+    /// DS-Test's are decoded with the events of `events`.
+    pub fn new(
+        results: &TestResults,
+        skipped: &BTreeMap<String, Vec<String>>,
+        not_in_shard: &BTreeMap<String, Vec<String>>,
+        events: &EventDecoder,
+    ) -> Self {
+        let mut suites = BTreeMap::<&String, Vec<TestCaseResult>>::new();
+        for (contract, tests) in results {
+            let tests =
+                tests.iter().map(|(name, result)| TestCaseResult::new(name, result, events));
+            suites.entry(contract).or_default().extend(tests);
+        }
+        for (contract, tests) in skipped {
+            let tests = tests.iter().map(|name| TestCaseResult::not_run(name, TestStatus::Skipped));
+            suites.entry(contract).or_default().extend(tests);
+        }
+        for (contract, tests) in not_in_shard {
+            let tests =
+                tests.iter().map(|name| TestCaseResult::not_run(name, TestStatus::NotInShard));
+            suites.entry(contract).or_default().extend(tests);
+        }
+
+        let suites = suites
+            .into_iter()
+            .map(|(contract, mut tests)| {
+                tests.sort_by(|a, b| a.name.cmp(&b.name));
+                SuiteResult { contract: contract.clone(), tests }
+            })
+            .collect();
+        Self { format: REPORT_FORMAT, suites }
+    }
+
+    /// Serializes the report as a single line of JSON
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        serde_json::to_writer(&mut writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl TestCaseResult {
+    fn new(name: &str, result: &TestResult, events: &EventDecoder) -> Self {
+        // the logs which are not DS-Test's are decoded with the compiled contracts' events
+        let logs = result
+            .logs
+            .iter()
+            .map(|log| {
+                let decoded = match log {
+                    TestLogEntry::Raw(raw) => events.decode_log(raw).map(|log| log.to_string()),
+                    _ => None,
+                };
+                decoded.unwrap_or_else(|| log.to_string())
+            })
+            .collect();
+        Self {
+            name: name.to_owned(),
+            status: if result.success { TestStatus::Success } else { TestStatus::Failure },
+            duration_us: result.duration.as_micros() as u64,
+            gas_used: result.gas_used,
+            logs,
+            reason: result.reason.clone(),
+            counterexample: result.counterexample.as_ref().map(CounterExampleArgs::new),
+            sequence: result
+                .sequence
+                .as_ref()
+                .map(|sequence| sequence.iter().map(ToString::to_string).collect()),
+            seed: result.seed,
+        }
+    }
+
+    fn not_run(name: &str, status: TestStatus) -> Self {
+        Self {
+            name: name.to_owned(),
+            status,
+            duration_us: 0,
+            gas_used: None,
+            logs: Vec::new(),
+            reason: None,
+            counterexample: None,
+            sequence: None,
+            seed: None,
+        }
+    }
+}
+
+impl CounterExampleArgs {
+    fn new(counterexample: &CounterExample) -> Self {
+        let args = counterexample
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| NamedValue {
+                name: counterexample.names.get(i).cloned().unwrap_or_default(),
+                value: format_token(arg),
+            })
+            .collect();
+        Self { calldata: counterexample.calldata.clone(), args }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::Token;
+    use std::time::Duration;
+
+    fn result(success: bool) -> TestResult {
+        TestResult {
+            success,
+            gas_used: Some(21_000),
+            logs: vec![TestLogEntry::Named { name: "x".to_owned(), value: "1".to_owned() }],
+            duration: Duration::from_millis(3),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trips_reports() {
+        let mut fuzzed = result(false);
+        fuzzed.gas_used = None;
+        fuzzed.reason = Some("assertion failed".to_owned());
+        fuzzed.seed = Some(7);
+        fuzzed.counterexample = Some(CounterExample {
+            calldata: vec![0xde, 0xad].into(),
+            args: vec![Token::Uint(5.into()), Token::String("a \"b\"".to_owned())],
+            names: vec!["amount".to_owned(), String::new()],
+        });
+        let results = std::iter::once((
+            "TokenTest".to_owned(),
+            vec![("testTransfer".to_owned(), result(true)), ("testFuzz".to_owned(), fuzzed)]
+                .into_iter()
+                .collect(),
+        ))
+        .collect();
+        let skipped = vec![
+            ("TokenTest".to_owned(), vec!["testMint".to_owned()]),
+            ("VaultTest".to_owned(), vec!["testDeposit".to_owned()]),
+        ]
+        .into_iter()
+        .collect();
+        let not_in_shard =
+            std::iter::once(("TokenTest".to_owned(), vec!["testBurn".to_owned()])).collect();
+
+        let report = Report::new(&results, &skipped, &not_in_shard, &EventDecoder::new(&[]));
+        assert_eq!(report.format, 1);
+        let contracts =
+            report.suites.iter().map(|suite| suite.contract.as_str()).collect::<Vec<_>>();
+        assert_eq!(contracts, ["TokenTest", "VaultTest"]);
+        let tests = &report.suites[0].tests;
+        let names = tests.iter().map(|test| test.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["testBurn", "testFuzz", "testMint", "testTransfer"]);
+        assert_eq!(tests[0].status, TestStatus::NotInShard);
+        assert_eq!(tests[2].status, TestStatus::Skipped);
+        assert_eq!(tests[3].status, TestStatus::Success);
+        assert_eq!(tests[3].duration_us, 3000);
+        assert_eq!(tests[3].logs, ["x: 1"]);
+        assert_eq!(report.suites[1].tests[0].status, TestStatus::Skipped);
+
+        let mut json = Vec::new();
+        report.write(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with(r#"{"format":1,"suites":[{"contract":"TokenTest","#));
+        assert!(json.ends_with("}\n"));
+        assert_eq!(json.lines().count(), 1);
+        assert!(json.contains(r#""status":"not_in_shard""#));
+        // the counterexample's arguments are decoded
+        assert!(json.contains(
+            r#""args":[{"name":"amount","value":"5"},{"name":"","value":"\"a \\\"b\\\"\""}]"#
+        ));
+
+        let parsed: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, report);
+    }
+}
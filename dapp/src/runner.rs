@@ -1,36 +1,100 @@
 use ethers::{
-    abi::{Function, Token},
-    prelude::Bytes,
-    types::Address,
+    abi::Function,
+    types::{Address, Bytes},
     utils::CompiledContract,
 };
 
-use evm_adapters::Evm;
+use evm_adapters::{
+    call_tracing::CallTraceNode,
+    dstest_logs::TestLogEntry,
+    fuzz::{fuzz, replay, CounterExample, FuzzConfig, FuzzDictionary, FuzzGas},
+    invariant::{
+        invariant, replay as replay_sequence, InvariantCall, InvariantConfig, InvariantTarget,
+    },
+    known_contracts::KnownContracts,
+    state_diff::StateDiff,
+    Evm, Interrupt,
+};
 
+use crate::{
+    persistence::{ContractFailures, PersistedCall, PersistedFailure},
+    Verbosity,
+};
 use eyre::Result;
 use regex::Regex;
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
 
-use proptest::test_runner::{TestError, TestRunner};
 use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, rc::Rc};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CounterExample {
-    pub calldata: Bytes,
-    // Token does not implement Serde (lol), so we just serialize the calldata
-    #[serde(skip)]
-    pub args: Vec<Token>,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestResult {
     pub success: bool,
 
     pub gas_used: Option<u64>,
 
+    /// The mean and median gas used by the runs of passing fuzz tests
+    pub fuzz_gas: Option<FuzzGas>,
+
     /// Minimal reproduction test case for failing fuzz tests
     pub counterexample: Option<CounterExample>,
+
+    /// The decoded logs emitted by unit tests, e.g. DS-Test's assertion failure details
+    // TestLogEntry does not implement Serde either
+    #[serde(skip)]
+    pub logs: Vec<TestLogEntry>,
+
+    /// Why failing unit tests failed, e.g. their decoded revert reason
+    pub reason: Option<String>,
+
+    /// The call tree of failing unit tests and of the counterexamples of failing fuzz tests,
+    /// if the verbosity asks for it and the EVM records traces. It is kept for the passing unit
+    /// tests too at the highest verbosity.
+    #[serde(skip)]
+    pub traces: Vec<CallTraceNode>,
+
+    /// The call tree of the contract's `setUp()`, kept at the highest verbosity or if it failed
+    #[serde(skip)]
+    pub setup_traces: Vec<CallTraceNode>,
+
+    /// The names of the addresses of the traces, set via the `label` cheatcode or identified
+    /// from their code
+    #[serde(skip)]
+    pub labels: BTreeMap<Address, String>,
+
+    /// The changes made to the state by the traced calls of the test, if the EVM records them
+    #[serde(skip)]
+    pub state_changes: StateDiff,
+
+    /// The time it took to run the test
+    pub duration: Duration,
+
+    /// The minimal sequence of calls which broke the invariant of failing invariant tests
+    pub sequence: Option<Vec<InvariantCall>>,
+
+    /// The seed the inputs of fuzz and invariant tests were generated from, to reproduce them
+    pub seed: Option<u64>,
+}
+
+impl TestResult {
+    /// Fails a test which was interrupted because it ran for too long, dropping whatever it
+    /// found before, e.g. a counterexample
+    fn time_out(&mut self, reason: String) {
+        self.success = false;
+        self.reason = Some(reason);
+        self.counterexample = None;
+        self.sequence = None;
+    }
+
+    /// The result of a test which could not run, e.g. because the contract's `setUp()` failed
+    fn failed(reason: String) -> Self {
+        Self { success: false, reason: Some(reason), ..Default::default() }
+    }
 }
 
 use std::marker::PhantomData;
@@ -45,22 +109,53 @@ pub struct ContractRunner<'a, S, E> {
     pub evm: Rc<RefCell<&'a mut E>>,
     pub contract: &'a CompiledContract,
     pub address: Address,
+    /// The compiled contracts, used to find the functions of the contracts deployed during
+    /// `setUp()` for the invariant tests, and to name the addresses of traces
+    pub known_contracts: KnownContracts,
+    pub invariant_config: InvariantConfig,
+    /// How long each test may run for, after which it fails
+    pub timeout: Option<Duration>,
+    /// Where the failures of the fuzz and invariant tests are persisted, to be replayed first
+    /// on the next runs
+    pub failures: Option<ContractFailures>,
+    /// How much of the results is printed, which decides the traces recorded by the EVM
+    pub verbosity: Verbosity,
     // need to constrain the trait generic
     state: PhantomData<S>,
 }
 
-impl<'a, S, E> ContractRunner<'a, S, E> {
+impl<'a, S, E: Evm<S>> ContractRunner<'a, S, E> {
     pub fn new(evm: &'a mut E, contract: &'a CompiledContract, address: Address) -> Self {
-        Self { evm: Rc::new(RefCell::new(evm)), contract, address, state: PhantomData }
+        // only the DS-Tests have a `failed()` to call after their tests
+        evm.set_dstest(contract.abi.function("failed").is_ok());
+        Self {
+            evm: Rc::new(RefCell::new(evm)),
+            contract,
+            address,
+            known_contracts: KnownContracts::default(),
+            invariant_config: InvariantConfig::default(),
+            timeout: None,
+            failures: None,
+            verbosity: Verbosity::default(),
+            state: PhantomData,
+        }
     }
-}
 
-impl<'a, S, E: Evm<S>> ContractRunner<'a, S, E> {
     /// Runs all tests for a contract whose names match the provided regular expression
     pub fn run_tests(
         &mut self,
         regex: &Regex,
-        fuzzer: Option<&mut TestRunner>,
+        fuzzer: Option<&FuzzConfig>,
+    ) -> Result<HashMap<String, TestResult>> {
+        self.run_selected_tests(|name| regex.is_match(name), fuzzer)
+    }
+
+    /// Runs all tests for a contract whose names are selected by `select`, e.g. the ones of a
+    /// [`Shard`](crate::Shard)
+    pub fn run_selected_tests(
+        &mut self,
+        select: impl Fn(&str) -> bool,
+        fuzzer: Option<&FuzzConfig>,
     ) -> Result<HashMap<String, TestResult>> {
         let start = Instant::now();
         let needs_setup = self.contract.abi.functions().any(|func| func.name == "setUp");
@@ -70,35 +165,110 @@ impl<'a, S, E: Evm<S>> ContractRunner<'a, S, E> {
             .functions()
             .into_iter()
             .filter(|func| func.name.starts_with("test"))
-            .filter(|func| regex.is_match(&func.name))
+            .filter(|func| select(&func.name))
+            .collect::<Vec<_>>();
+        let invariant_fns = self
+            .contract
+            .abi
+            .functions()
+            .filter(|func| func.name.starts_with("invariant") && func.inputs.is_empty())
+            .filter(|func| select(&func.name))
             .collect::<Vec<_>>();
 
+        // the fuzz and invariant tests only run with a fuzzer
+        let runnable_invariants = invariant_fns.iter().filter(|_| fuzzer.is_some());
+        let runnable_fns = test_fns
+            .iter()
+            .filter(|func| fuzzer.is_some() || func.inputs.is_empty())
+            .chain(runnable_invariants)
+            .collect::<Vec<_>>();
+        // without any test to run, `setUp()` is not run either
+        if runnable_fns.is_empty() {
+            return Ok(HashMap::new())
+        }
+
+        // run the setup function only once, and restore the resulting state before each test
+        // so that the tests cannot affect each other. if it fails, none of the tests can run.
+        let mut setup_traces = Vec::new();
+        if needs_setup {
+            let tracing = self.verbosity.traces_failures();
+            let res = self.traced(tracing, |runner| runner.setup(fuzzer.is_some()));
+            if tracing {
+                setup_traces = self.evm.borrow().traces();
+            }
+            if let Err(err) = res {
+                let reason = format!("setUp failed: {}", err);
+                let labels = self.trace_labels(&**self.evm.borrow(), &setup_traces);
+                return Ok(runnable_fns
+                    .iter()
+                    .map(|func| {
+                        let mut result = TestResult::failed(reason.clone());
+                        result.setup_traces = setup_traces.clone();
+                        result.labels = labels.clone();
+                        (func.name.clone(), result)
+                    })
+                    .collect())
+            }
+        }
+        let snapshot = self.evm.borrow_mut().snapshot();
+
         // run all unit tests
         let unit_tests = test_fns
             .iter()
             .filter(|func| func.inputs.is_empty())
             .map(|func| {
-                let result = self.run_test(func, needs_setup)?;
+                self.evm.borrow_mut().restore(snapshot)?;
+                let (mut result, timed_out) =
+                    self.with_timeout(|runner| runner.run_test(func, false))?;
+                if let Some(reason) = timed_out {
+                    result.time_out(reason);
+                }
                 Ok((func.name.clone(), result))
             })
             .collect::<Result<HashMap<_, _>>>()?;
 
-        let map = if let Some(mut fuzzer) = fuzzer {
+        let mut map = if let Some(fuzzer) = fuzzer {
             let fuzz_tests = test_fns
                 .iter()
                 .filter(|func| !func.inputs.is_empty())
                 .map(|func| {
-                    let result = self.run_fuzz_test(func, needs_setup, &mut fuzzer)?;
+                    self.evm.borrow_mut().restore(snapshot)?;
+                    let (mut result, timed_out) =
+                        self.with_timeout(|runner| runner.run_fuzz_test(func, false, fuzzer))?;
+                    if let Some(reason) = timed_out {
+                        result.time_out(reason);
+                    }
                     Ok((func.name.clone(), result))
                 })
                 .collect::<Result<HashMap<_, _>>>()?;
 
+            self.evm.borrow_mut().restore(snapshot)?;
+            // the invariants are checked together, so they share the deadline
+            let (mut invariant_tests, timed_out) =
+                self.with_timeout(|runner| runner.run_invariant_tests(&invariant_fns, fuzzer))?;
+            if let Some(reason) = timed_out {
+                invariant_tests.values_mut().for_each(|result| result.time_out(reason.clone()));
+            }
+
             let mut map = unit_tests;
             map.extend(fuzz_tests);
+            map.extend(invariant_tests);
             map
         } else {
             unit_tests
         };
+        // the state after `setUp()` is not needed anymore
+        self.evm.borrow_mut().clear_snapshots();
+
+        if self.verbosity.traces_all() && !setup_traces.is_empty() {
+            let labels = self.trace_labels(&**self.evm.borrow(), &setup_traces);
+            for result in map.values_mut() {
+                result.setup_traces = setup_traces.clone();
+                for (address, label) in &labels {
+                    result.labels.entry(*address).or_insert_with(|| label.clone());
+                }
+            }
+        }
 
         if !map.is_empty() {
             let duration = Instant::now().duration_since(start);
@@ -120,18 +290,48 @@ impl<'a, S, E: Evm<S>> ContractRunner<'a, S, E> {
             self.evm.borrow_mut().setup(self.address)?;
         }
 
-        let (_, reason, gas_used) = self.evm.borrow_mut().call::<(), _>(
-            Address::zero(),
-            self.address,
-            func,
-            (),
-            0.into(),
-        )?;
-        let success = self.evm.borrow_mut().check_success(self.address, &reason, should_fail);
+        // the raw call is used so that the revert data is available for the failure reason
+        let calldata = func.encode_input(&[])?;
+        let (retdata, reason, gas_used) =
+            self.traced(self.verbosity.traces_failures(), |runner| {
+                runner.evm.borrow_mut().call_raw(
+                    Address::zero(),
+                    runner.address,
+                    calldata.into(),
+                    0.into(),
+                    false,
+                    None,
+                )
+            })?;
+        // the traces have to be collected before evaluating the test, which may make a call
+        let traces = self.evm.borrow().traces();
+        let state_changes = self.evm.borrow().state_changes();
+        let outcome = self.evm.borrow_mut().evaluate(self.address, &retdata, &reason, should_fail);
+        let success = outcome.success;
+        let keep_traces = !success || self.verbosity.traces_all();
+        let labels = if keep_traces {
+            self.trace_labels(&**self.evm.borrow(), &traces)
+        } else {
+            BTreeMap::new()
+        };
         let duration = Instant::now().duration_since(start);
         tracing::trace!(?duration, %success, %gas_used);
 
-        Ok(TestResult { success, gas_used: Some(gas_used), counterexample: None })
+        Ok(TestResult {
+            success,
+            gas_used: outcome.gas_used.or(Some(gas_used)),
+            fuzz_gas: None,
+            counterexample: None,
+            logs: outcome.logs,
+            reason: outcome.reason,
+            traces: if keep_traces { traces } else { Vec::new() },
+            setup_traces: Vec::new(),
+            labels,
+            state_changes: if keep_traces { state_changes } else { StateDiff::new() },
+            duration,
+            sequence: None,
+            seed: None,
+        })
     }
 
     #[tracing::instrument(name = "fuzz-test", skip_all, fields(name = %func.name))]
@@ -139,51 +339,348 @@ impl<'a, S, E: Evm<S>> ContractRunner<'a, S, E> {
         &mut self,
         func: &Function,
         setup: bool,
-        runner: &mut TestRunner,
+        config: &FuzzConfig,
     ) -> Result<TestResult> {
         // call the setup function in each test to reset the test's state.
         if setup {
-            self.evm.borrow_mut().setup(self.address)?;
+            self.setup(true)?;
         }
 
         let start = Instant::now();
         let should_fail = func.name.starts_with("testFail");
 
-        // Get the calldata generation strategy for the function
-        let strat = crate::fuzz::fuzz_calldata(func);
-
-        // Run the strategy
-        let result = runner.run(&strat, |calldata| {
+        let result = {
             let mut evm = self.evm.borrow_mut();
+            // the counterexample persisted by an earlier run is tried before any new input
+            let replayed = match self.persisted_counterexample(func) {
+                Some((calldata, seed)) => {
+                    replay(&mut **evm, func, self.address, should_fail, &calldata, seed)?
+                }
+                None => None,
+            };
+            match replayed {
+                Some(result) => result,
+                None => {
+                    let dictionary = self.fuzz_dictionary(&**evm);
+                    fuzz(&mut **evm, config, func, self.address, should_fail, &dictionary)?
+                }
+            }
+        };
 
-            let (_, reason, _) = evm
-                .call_raw(Address::zero(), self.address, calldata, 0.into(), false)
-                .expect("could not make raw evm call");
+        let duration = Instant::now().duration_since(start);
+        tracing::trace!(?duration, success = %result.success, seed = result.seed);
+
+        if let Some(ref failures) = self.failures {
+            match result.counterexample {
+                Some(ref counterexample) => {
+                    let failure = PersistedFailure::Fuzz {
+                        signature: func.signature(),
+                        calldata: counterexample.calldata.clone(),
+                        seed: result.seed,
+                    };
+                    failures.save(&func.name, &failure);
+                }
+                None if result.success => failures.remove(&func.name),
+                // e.g. interrupted, which says nothing about the persisted failure
+                None => {}
+            }
+        }
 
-            let success = evm.check_success(self.address, &reason, should_fail);
+        // the fuzz runs are not traced, so the counterexample is called again with tracing
+        let (traces, labels, state_changes) = match result.counterexample {
+            Some(ref counterexample) if self.verbosity.traces_failures() => {
+                self.traced(true, |runner| {
+                    runner.evm.borrow_mut().call_raw(
+                        Address::zero(),
+                        runner.address,
+                        counterexample.calldata.clone(),
+                        0.into(),
+                        false,
+                        None,
+                    )
+                })?;
+                let evm = self.evm.borrow();
+                let traces = evm.traces();
+                let labels = self.trace_labels(&**evm, &traces);
+                (traces, labels, evm.state_changes())
+            }
+            _ => (Vec::new(), BTreeMap::new(), StateDiff::new()),
+        };
 
-            // This will panic and get caught by the executor
-            proptest::prop_assert!(success);
+        Ok(TestResult {
+            success: result.success,
+            gas_used: None,
+            fuzz_gas: result.gas,
+            counterexample: result.counterexample,
+            logs: Vec::new(),
+            reason: result.reason,
+            traces,
+            setup_traces: Vec::new(),
+            labels,
+            state_changes,
+            duration,
+            sequence: None,
+            seed: Some(result.seed),
+        })
+    }
 
-            Ok(())
-        });
+    /// The counterexample persisted for the fuzz test by an earlier run, along with the seed it
+    /// was found with. It is discarded if it is no longer a valid input of the test.
+    fn persisted_counterexample(&self, func: &Function) -> Option<(Bytes, u64)> {
+        let failures = self.failures.as_ref()?;
+        match failures.load(&func.name)? {
+            PersistedFailure::Fuzz { signature, calldata, seed } => {
+                if signature != func.signature() {
+                    failures.discard(&func.name, format!("the test was {}", signature));
+                    return None
+                }
+                if let Err(err) = CounterExample::decode(func, calldata.clone()) {
+                    failures.discard(&func.name, err);
+                    return None
+                }
+                Some((calldata, seed))
+            }
+            PersistedFailure::Invariant { .. } => {
+                failures.discard(&func.name, "the test was an invariant");
+                None
+            }
+        }
+    }
 
-        let (success, counterexample) = match result {
-            Ok(_) => (true, None),
-            Err(TestError::Fail(_, value)) => {
-                // skip the function selector when decoding
-                let args = func.decode_input(&value.as_ref()[4..])?;
-                let counterexample = CounterExample { calldata: value.clone(), args };
-                tracing::info!("Found minimal failing case: {}", hex::encode(&value));
-                (false, Some(counterexample))
+    /// The sequence of calls persisted for the invariant by an earlier run, along with the
+    /// seed it was found with. It is discarded if any of its calls is no longer a call of one
+    /// of the targets.
+    fn persisted_sequence(
+        &self,
+        func: &Function,
+        targets: &[InvariantTarget],
+    ) -> Option<(Vec<InvariantCall>, u64)> {
+        let failures = self.failures.as_ref()?;
+        let (sequence, seed) = match failures.load(&func.name)? {
+            PersistedFailure::Invariant { sequence, seed } => (sequence, seed),
+            PersistedFailure::Fuzz { .. } => {
+                failures.discard(&func.name, "the test was a fuzz test");
+                return None
             }
-            result => panic!("Unexpected result: {:?}", result),
         };
+        let calls = sequence
+            .into_iter()
+            .map(|call| {
+                let target = targets
+                    .iter()
+                    .find(|target| target.address == call.target && target.name == call.contract)
+                    .ok_or_else(|| {
+                        eyre::eyre!("{} is no longer deployed at {:?}", call.contract, call.target)
+                    })?;
+                InvariantCall::decode(call.sender, target, call.calldata)
+            })
+            .collect::<Result<Vec<_>>>();
+        match calls {
+            Ok(calls) => Some((calls, seed)),
+            Err(err) => {
+                failures.discard(&func.name, err);
+                None
+            }
+        }
+    }
 
-        let duration = Instant::now().duration_since(start);
-        tracing::trace!(?duration, %success);
+    /// Calls the functions of the contracts deployed during the setup in random sequences,
+    /// checking the `invariants` after each call as configured. The setup must have run, with
+    /// harvesting enabled so that the deployed contracts are known.
+    #[tracing::instrument(name = "invariant-tests", skip_all)]
+    pub fn run_invariant_tests(
+        &mut self,
+        invariants: &[&Function],
+        config: &FuzzConfig,
+    ) -> Result<HashMap<String, TestResult>> {
+        let mut results = HashMap::new();
+        if invariants.is_empty() {
+            return Ok(results)
+        }
+
+        let start = Instant::now();
+        let mut evm = self.evm.borrow_mut();
+        let dictionary = self.fuzz_dictionary(&**evm);
+        let targets = self.invariant_targets(&**evm, &dictionary);
+        tracing::trace!(targets = ?targets.iter().map(|target| &target.name).collect::<Vec<_>>());
+
+        // the sequences persisted by earlier runs are replayed before any new one
+        let mut remaining = invariants.iter().map(|func| (*func).clone()).collect::<Vec<_>>();
+        for func in invariants {
+            let (calls, seed) = match self.persisted_sequence(func, &targets) {
+                Some(persisted) => persisted,
+                None => continue,
+            };
+            let invariant = std::slice::from_ref(*func);
+            let replayed = replay_sequence(
+                &mut **evm,
+                self.address,
+                invariant,
+                &calls,
+                &self.invariant_config,
+            )?;
+            if let Some((sequence, _, reason)) = replayed {
+                let mut result = TestResult::failed(reason);
+                result.duration = Instant::now().duration_since(start);
+                result.sequence = Some(sequence);
+                result.seed = Some(seed);
+                remaining.retain(|remaining| remaining.name != func.name);
+                results.insert(func.name.clone(), result);
+            }
+        }
+
+        // the sequences stop at the first broken invariant, so the other ones are checked again
+        while !remaining.is_empty() {
+            let result = invariant(
+                &mut **evm,
+                config,
+                self.address,
+                &remaining,
+                &targets,
+                &self.invariant_config,
+                &dictionary,
+            )?;
+            let duration = Instant::now().duration_since(start);
+            let test_result = TestResult {
+                success: result.success,
+                reason: result.reason,
+                duration,
+                sequence: result.sequence,
+                seed: Some(result.seed),
+                ..Default::default()
+            };
+
+            match result.broken {
+                Some(broken) => {
+                    remaining.retain(|func| func.name != broken);
+                    results.insert(broken, test_result);
+                }
+                // either all the invariants held, or none of them could be checked
+                None => {
+                    for func in remaining.drain(..) {
+                        results.insert(func.name, test_result.clone());
+                    }
+                }
+            }
+        }
 
-        Ok(TestResult { success, gas_used: None, counterexample })
+        if let Some(ref failures) = self.failures {
+            for (name, result) in &results {
+                match (&result.sequence, result.seed) {
+                    (Some(sequence), Some(seed)) if !result.success => {
+                        let sequence = sequence
+                            .iter()
+                            .map(|call| PersistedCall {
+                                sender: call.sender,
+                                target: call.target,
+                                contract: call.contract.clone(),
+                                calldata: call.calldata.clone(),
+                            })
+                            .collect();
+                        failures.save(name, &PersistedFailure::Invariant { sequence, seed });
+                    }
+                    _ if result.success => failures.remove(name),
+                    _ => {}
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Runs a test with the deadline of `self.timeout`, past which a watchdog thread interrupts
+    /// the EVM: the call being executed is stopped if the EVM supports it, and the calls made
+    /// afterwards fail immediately, e.g. the remaining runs of a fuzz test. Returns the reason
+    /// to fail the test with if it timed out.
+    fn with_timeout<T>(
+        &mut self,
+        run: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<(T, Option<String>)> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok((run(self)?, None)),
+        };
+
+        // the watchdog raises the interrupt unless the test completes first, i.e. drops `done`
+        let interrupt = Interrupt::default();
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = {
+            let interrupt = interrupt.clone();
+            thread::spawn(move || {
+                if finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                    interrupt.raise()
+                }
+            })
+        };
+        self.evm.borrow_mut().set_interrupt(Some(interrupt.clone()));
+        let result = run(self);
+        drop(done);
+        let _ = watchdog.join();
+        self.evm.borrow_mut().set_interrupt(None);
+
+        let timed_out = Some(format!("timed out after {:?}", timeout));
+        Ok((result?, timed_out.filter(|_| interrupt.is_raised())))
+    }
+
+    /// Runs `run` with the recording of call traces and state changes enabled if `enabled`,
+    /// since recording them has a cost on every call. The traces and changes of its last call
+    /// are then available via [`Evm::traces`] and [`Evm::state_changes`].
+    fn traced<T>(&mut self, enabled: bool, run: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.evm.borrow_mut().set_tracing_enabled(enabled);
+        self.evm.borrow_mut().set_state_diff_enabled(enabled);
+        let result = run(self);
+        self.evm.borrow_mut().set_tracing_enabled(false);
+        self.evm.borrow_mut().set_state_diff_enabled(false);
+        result
+    }
+
+    /// Calls the setup function, harvesting the values it writes for fuzzing if `harvest` is set
+    fn setup(&mut self, harvest: bool) -> Result<()> {
+        let mut evm = self.evm.borrow_mut();
+        evm.set_fuzz_dictionary_enabled(harvest);
+        let res = evm.setup(self.address);
+        evm.set_fuzz_dictionary_enabled(false);
+        res
+    }
+
+    /// The values harvested during the setup, along with the address and the constants of the
+    /// test contract
+    fn fuzz_dictionary(&self, evm: &E) -> FuzzDictionary {
+        let mut dictionary = evm.fuzz_dictionary();
+        dictionary.enabled = true;
+        dictionary.insert_address(self.address);
+        dictionary.insert_push_constants(&evm.get_code(self.address));
+        dictionary
+    }
+
+    /// The known contracts deployed during the setup, other than the test contract
+    fn invariant_targets(&self, evm: &E, dictionary: &FuzzDictionary) -> Vec<InvariantTarget> {
+        dictionary
+            .addresses()
+            .iter()
+            .filter(|address| **address != self.address)
+            .filter_map(|&address| {
+                let name = self.known_contracts.identify(&evm.get_code(address))?;
+                let abi = self.known_contracts.abi(name)?;
+                Some(InvariantTarget { address, name: name.clone(), abi: abi.clone() })
+            })
+            .collect()
+    }
+
+    /// Names the addresses of the traces, by their label if they have one, or else by the
+    /// contract their code was compiled from. Each address is identified once.
+    fn trace_labels(&self, evm: &E, traces: &[CallTraceNode]) -> BTreeMap<Address, String> {
+        let mut labels = evm.labels();
+        let mut seen = BTreeSet::new();
+        for node in traces {
+            let address = node.trace.address;
+            if labels.contains_key(&address) || !seen.insert(address) {
+                continue
+            }
+            if let Some(name) = self.known_contracts.identify(&evm.get_code(address)) {
+                labels.insert(address, name.clone());
+            }
+        }
+        labels
     }
 }
 
@@ -192,18 +689,51 @@ mod tests {
     use super::*;
     use crate::test_helpers::COMPILED;
     use evm::Config;
-    use std::marker::PhantomData;
 
     mod sputnik {
+        use crate::persistence::{FailurePersistence, MemoryFailurePersistence};
         use dapp_utils::get_func;
-        use evm_adapters::sputnik::{
-            helpers::{new_backend, new_vicinity},
-            Executor,
+        use evm::backend::MemoryBackend;
+        use evm_adapters::{
+            call_tracing::CallKind,
+            sputnik::{
+                cheatcodes::{CheatcodeStackExecutor, CheatcodeStackState},
+                helpers::{new_backend, new_vicinity},
+                Executor,
+            },
         };
-        use proptest::test_runner::Config as FuzzConfig;
+        use std::sync::Arc;
 
         use super::*;
 
+        type CheatcodeExecutor<'a> = Executor<
+            CheatcodeStackState<'a, MemoryBackend<'a>>,
+            CheatcodeStackExecutor<'a, MemoryBackend<'a>>,
+        >;
+
+        /// Runs `run` with a runner of the test contract, deployed at `0x1000..` on a fresh
+        /// cheatcode-enabled EVM with the provided gas limit
+        fn with_runner<T>(
+            name: &str,
+            gas_limit: u64,
+            run: impl FnOnce(
+                &mut ContractRunner<
+                    '_,
+                    CheatcodeStackState<'_, MemoryBackend<'_>>,
+                    CheatcodeExecutor<'_>,
+                >,
+            ) -> T,
+        ) -> T {
+            let cfg = Config::istanbul();
+            let compiled = COMPILED.get(name).expect("could not find contract");
+            let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+            let vicinity = new_vicinity();
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = Executor::new_with_cheatcodes(backend, gas_limit, &cfg, false);
+            evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+            run(&mut ContractRunner::new(&mut evm, compiled, addr))
+        }
+
         #[test]
         fn test_runner() {
             let cfg = Config::istanbul();
@@ -216,50 +746,233 @@ mod tests {
         }
 
         #[test]
-        fn test_fuzz_shrinking() {
+        fn test_isolation() {
             let cfg = Config::istanbul();
-            let compiled = COMPILED.get("GreeterTest").expect("could not find contract");
+            let compiled = COMPILED.get("IsolationTest").expect("could not find contract");
             let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
             let vicinity = new_vicinity();
             let backend = new_backend(&vicinity, Default::default());
+            let evm = Executor::new(12_000_000, &cfg, &backend);
+            super::test_runner(evm, addr, compiled);
+        }
 
-            let mut evm = Executor::new(12_000_000, &cfg, &backend);
-            evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        #[test]
+        fn test_failing_setup() {
+            let res = with_runner("FailingSetupTest", 12_000_000, |runner| {
+                runner.run_tests(&".*".parse().unwrap(), None).unwrap()
+            });
+            assert_eq!(res.len(), 2);
+            for result in res.values() {
+                assert!(!result.success);
+                assert_eq!(result.reason.as_deref(), Some("setUp failed: setup reverted"));
+            }
+        }
+
+        #[test]
+        fn test_invariants() {
+            // the cheatcode executor harvests the contracts deployed during the setup
+            with_runner("InvariantBreaker", 12_000_000, |runner| {
+                runner.known_contracts =
+                    KnownContracts::new(COMPILED.iter().filter(|(name, _)| *name == "Stateful"));
+
+                let fuzzer = FuzzConfig::deterministic();
+                let res = runner.run_tests(&".*".parse().unwrap(), Some(&fuzzer)).unwrap();
+                assert_eq!(res.len(), 2);
+                assert!(res["invariantAlwaysHolds"].success);
+
+                // the sequence is shrunk to the calls which are needed to break the invariant
+                let result = &res["invariantNotBroken"];
+                assert!(!result.success);
+                let sequence = result.sequence.as_ref().unwrap();
+                let calls = sequence.iter().map(|call| call.call.as_str()).collect::<Vec<_>>();
+                assert_eq!(calls, vec!["prime()", "breakIt()"]);
+                assert!(sequence.iter().all(|call| call.contract == "Stateful"));
+
+                // the calls must be made from somewhere
+                runner.invariant_config.senders.clear();
+                assert!(runner.run_tests(&".*".parse().unwrap(), Some(&fuzzer)).is_err());
+            });
+        }
+
+        #[test]
+        fn test_timeout() {
+            // the cheatcode executor stops the calls being executed once interrupted, and
+            // enough gas for the loops to run for hours
+            let res = with_runner("TimeoutTest", 1 << 60, |runner| {
+                runner.timeout = Some(Duration::from_millis(100));
+                let fuzzer = FuzzConfig::deterministic();
+                runner.run_tests(&".*".parse().unwrap(), Some(&fuzzer)).unwrap()
+            });
+            assert_eq!(res.len(), 3);
+
+            // the tests which time out do not prevent the others from running
+            assert!(res["testOk"].success);
+            for test in ["testLoop", "testFuzzLoop"] {
+                let result = &res[test];
+                assert!(!result.success);
+                assert_eq!(result.reason.as_deref(), Some("timed out after 100ms"));
+                assert!(result.counterexample.is_none());
+                assert!(result.duration < Duration::from_secs(10));
+            }
+        }
 
-            let mut runner = ContractRunner {
-                evm: Rc::new(RefCell::new(&mut evm)),
-                contract: compiled,
-                address: addr,
-                state: PhantomData,
+        #[test]
+        fn test_trace_verbosity() {
+            let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+            let fuzzer = FuzzConfig::deterministic();
+            let run = |level| {
+                with_runner("GreeterTest", 12_000_000, |runner| {
+                    runner.known_contracts =
+                        KnownContracts::new(COMPILED.iter().filter(|(name, _)| *name == "Greeter"));
+                    runner.verbosity = Verbosity::new(level).unwrap();
+                    runner.run_tests(&".*".parse().unwrap(), Some(&fuzzer)).unwrap()
+                })
             };
 
-            let cfg = FuzzConfig::default();
-            let mut fuzzer = TestRunner::new(cfg);
-            let func = get_func("function testFuzzShrinking(uint256 x, uint256 y) public").unwrap();
-            let res = runner.run_fuzz_test(&func, true, &mut fuzzer).unwrap();
-            assert!(!res.success);
+            // nothing is traced by default
+            let res = run(2);
+            assert!(!res["testFuzzShrinking"].success);
+            assert!(res.values().all(|res| res.traces.is_empty() && res.setup_traces.is_empty()));
+            assert!(res.values().all(|res| res.state_changes.is_empty()));
+
+            // only the failures are, the fuzz tests by calling their counterexample again
+            let res = run(3);
+            assert!(res["testGreeting"].traces.is_empty());
+            let traces = &res["testFuzzShrinking"].traces;
+            assert_eq!(traces.len(), 1);
+            assert_eq!(traces[0].trace.address, addr);
+            assert!(!traces[0].trace.success);
+            assert!(res.values().all(|res| res.setup_traces.is_empty()));
+
+            // all the unit tests are, along with `setUp()`
+            let res = run(4);
+            let greeting = &res["testGreeting"];
+            assert_eq!(greeting.traces[0].trace.address, addr);
+            assert!(greeting.traces[0].trace.success);
+            assert_eq!(greeting.traces[1].trace.address, greeting.setup_traces[1].trace.address);
+            assert_eq!(greeting.setup_traces[1].trace.kind, CallKind::Create);
+            // the contract deployed during `setUp()` is named in both
+            assert_eq!(greeting.labels[&greeting.traces[1].trace.address], "Greeter");
+            // along with the greeting the test wrote
+            let changes = &greeting.state_changes[&greeting.traces[1].trace.address];
+            assert_eq!(changes.storage.len(), 1);
+        }
+
+        #[test]
+        fn test_fuzz_failure_persistence() {
+            let persistence = Arc::new(MemoryFailurePersistence::default());
+            with_runner("GreeterTest", 12_000_000, |runner| {
+                runner.failures = Some(ContractFailures::new("GreeterTest", persistence.clone()));
+                let func =
+                    get_func("function testFuzzShrinking(uint256 x, uint256 y) public").unwrap();
+                let res = runner.run_fuzz_test(&func, true, &FuzzConfig::deterministic()).unwrap();
+                assert!(!res.success);
+                let calldata = res.counterexample.unwrap().calldata;
+                let persisted = PersistedFailure::Fuzz {
+                    signature: func.signature(),
+                    calldata: calldata.clone(),
+                    seed: res.seed.unwrap(),
+                };
+                assert_eq!(persistence.load("GreeterTest", "testFuzzShrinking"), Some(persisted));
+
+                // the counterexample is replayed first, so it fails without generating any input
+                let no_runs = FuzzConfig { runs: 0, ..FuzzConfig::deterministic() };
+                let res = runner.run_fuzz_test(&func, true, &no_runs).unwrap();
+                assert!(!res.success);
+                assert_eq!(res.counterexample.unwrap().calldata, calldata);
+
+                // the stale entries are discarded, e.g. once the signature of the test changed
+                let stale = [
+                    ("testFuzzShrinking(uint256)".to_owned(), calldata),
+                    (func.signature(), vec![0xde, 0xad].into()),
+                    (func.signature(), func.short_signature().to_vec().into()),
+                ];
+                for (signature, calldata) in stale {
+                    let failure = PersistedFailure::Fuzz { signature, calldata, seed: 1 };
+                    persistence.save("GreeterTest", "testFuzzShrinking", &failure).unwrap();
+                    let res = runner.run_fuzz_test(&func, true, &no_runs).unwrap();
+                    assert!(res.success);
+                    assert_eq!(persistence.load("GreeterTest", "testFuzzShrinking"), None);
+                }
+            });
+        }
 
-            // get the counterexample with shrinking enabled by default
-            let counterexample = res.counterexample.unwrap();
-            let product_with_shrinking: u64 =
+        #[test]
+        fn test_invariant_failure_persistence() {
+            let persistence = Arc::new(MemoryFailurePersistence::default());
+            // each run starts from a fresh EVM, as it would in another process
+            let run = |fuzzer: &FuzzConfig| {
+                with_runner("InvariantBreaker", 12_000_000, |runner| {
+                    runner.known_contracts = KnownContracts::new(
+                        COMPILED.iter().filter(|(name, _)| *name == "Stateful"),
+                    );
+                    runner.failures =
+                        Some(ContractFailures::new("InvariantBreaker", persistence.clone()));
+                    runner.run_tests(&"invariantNotBroken".parse().unwrap(), Some(fuzzer)).unwrap()
+                })
+            };
+
+            let res = run(&FuzzConfig::deterministic());
+            assert!(!res["invariantNotBroken"].success);
+            let sequence = match persistence.load("InvariantBreaker", "invariantNotBroken") {
+                Some(PersistedFailure::Invariant { sequence, .. }) => sequence,
+                other => panic!("unexpected persisted failure {:?}", other),
+            };
+            let calls = sequence.iter().map(|call| call.contract.as_str()).collect::<Vec<_>>();
+            assert_eq!(calls, ["Stateful", "Stateful"]);
+
+            // the sequence is replayed first, so it fails without generating any sequence
+            let no_runs = FuzzConfig { runs: 0, ..FuzzConfig::deterministic() };
+            let res = run(&no_runs);
+            let result = &res["invariantNotBroken"];
+            assert!(!result.success);
+            let calls = result.sequence.as_ref().unwrap().iter().map(|call| call.call.as_str());
+            assert_eq!(calls.collect::<Vec<_>>(), ["prime()", "breakIt()"]);
+
+            // a sequence calling a contract which is no longer deployed is discarded
+            let mut stale = sequence;
+            stale[0].contract = "Removed".to_owned();
+            let failure = PersistedFailure::Invariant { sequence: stale, seed: 1 };
+            persistence.save("InvariantBreaker", "invariantNotBroken", &failure).unwrap();
+            assert!(run(&no_runs)["invariantNotBroken"].success);
+            assert_eq!(persistence.load("InvariantBreaker", "invariantNotBroken"), None);
+        }
+
+        #[test]
+        fn test_fuzz_shrinking() {
+            with_runner("GreeterTest", 12_000_000, |runner| {
+                let fuzzer = FuzzConfig::deterministic();
+                let func =
+                    get_func("function testFuzzShrinking(uint256 x, uint256 y) public").unwrap();
+                let res = runner.run_fuzz_test(&func, true, &fuzzer).unwrap();
+                assert!(!res.success);
+
+                // get the counterexample with shrinking enabled by default
+                let counterexample = res.counterexample.unwrap();
                 // casting to u64 here is safe because the shrunk result is always gonna be small
                 // enough to fit in a u64, whereas as seen below, that's not possible without
                 // shrinking
-                counterexample.args.into_iter().map(|x| x.into_uint().unwrap().as_u64()).product();
-
-            let mut cfg = FuzzConfig::default();
-            // we reduce the shrinking iters and observe a larger result
-            cfg.max_shrink_iters = 5;
-            let mut fuzzer = TestRunner::new(cfg);
-            let res = runner.run_fuzz_test(&func, true, &mut fuzzer).unwrap();
-            assert!(!res.success);
-
-            // get the non-shrunk result
-            let counterexample = res.counterexample.unwrap();
-            let args =
-                counterexample.args.into_iter().map(|x| x.into_uint().unwrap()).collect::<Vec<_>>();
-            let product_without_shrinking = args[0].saturating_mul(args[1]);
-            assert!(product_without_shrinking > product_with_shrinking.into());
+                let product_with_shrinking: u64 = counterexample
+                    .args
+                    .into_iter()
+                    .map(|x| x.into_uint().unwrap().as_u64())
+                    .product();
+
+                // we reduce the shrinking iters and observe a larger result
+                let fuzzer = FuzzConfig { max_shrink_iters: 5, ..FuzzConfig::deterministic() };
+                let res = runner.run_fuzz_test(&func, true, &fuzzer).unwrap();
+                assert!(!res.success);
+
+                // get the non-shrunk result
+                let counterexample = res.counterexample.unwrap();
+                let args = counterexample
+                    .args
+                    .into_iter()
+                    .map(|x| x.into_uint().unwrap())
+                    .collect::<Vec<_>>();
+                let product_without_shrinking = args[0].saturating_mul(args[1]);
+                assert!(product_without_shrinking > product_with_shrinking.into());
+            });
         }
     }
 
@@ -286,12 +999,7 @@ mod tests {
     pub fn test_runner<S, E: Evm<S>>(mut evm: E, addr: Address, compiled: &CompiledContract) {
         evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
 
-        let mut runner = ContractRunner {
-            evm: Rc::new(RefCell::new(&mut evm)),
-            contract: compiled,
-            address: addr,
-            state: PhantomData,
-        };
+        let mut runner = ContractRunner::new(&mut evm, compiled, addr);
 
         let res = runner.run_tests(&".*".parse().unwrap(), None).unwrap();
         assert!(res.len() > 0);
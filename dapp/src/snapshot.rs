@@ -0,0 +1,383 @@
+//! Gas snapshots, i.e. the gas used by each passing test, stored one test per line like
+//! `GreeterTest::testGreeting (gas: 31034)` to be compared against later runs, in the spirit of
+//! `dapp snapshot`
+use crate::multi_runner::TestResults;
+use evm_adapters::fuzz::FuzzGas;
+
+use eyre::{Result, WrapErr};
+use std::{collections::BTreeMap, fmt, path::Path, str::FromStr};
+
+/// The gas used by a test
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotGas {
+    Unit(u64),
+    /// Fuzz tests use a different amount of gas on each run
+    Fuzz(FuzzGas),
+}
+
+impl SnapshotGas {
+    /// The amount of gas which is compared across snapshots, i.e. the mean for fuzz tests
+    pub fn gas(&self) -> u64 {
+        match self {
+            SnapshotGas::Unit(gas) => *gas,
+            SnapshotGas::Fuzz(gas) => gas.mean,
+        }
+    }
+}
+
+impl fmt::Display for SnapshotGas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotGas::Unit(gas) => write!(f, "gas: {}", gas),
+            SnapshotGas::Fuzz(gas) => write!(f, "mean: {}, median: {}", gas.mean, gas.median),
+        }
+    }
+}
+
+impl FromStr for SnapshotGas {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut fields = BTreeMap::new();
+        for field in s.split(", ") {
+            let (key, value) =
+                field.split_once(": ").ok_or_else(|| eyre::eyre!("invalid gas `{}`", field))?;
+            let value =
+                value.parse::<u64>().wrap_err_with(|| format!("invalid {} `{}`", key, value))?;
+            fields.insert(key, value);
+        }
+        let keys = fields.keys().copied().collect::<Vec<_>>();
+        match keys.as_slice() {
+            ["gas"] => Ok(SnapshotGas::Unit(fields["gas"])),
+            ["mean", "median"] => {
+                Ok(SnapshotGas::Fuzz(FuzzGas { mean: fields["mean"], median: fields["median"] }))
+            }
+            _ => eyre::bail!("invalid gas `{}`", s),
+        }
+    }
+}
+
+/// The gas used by each test, by contract and test name
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasSnapshot(pub BTreeMap<(String, String), SnapshotGas>);
+
+impl GasSnapshot {
+    /// Records the gas used by the tests which passed, as the gas used by failing tests
+    /// depends on where they failed
+    pub fn new(results: &TestResults) -> Self {
+        let mut snapshot = BTreeMap::new();
+        for (contract, tests) in results {
+            for (name, result) in tests.iter().filter(|(_, result)| result.success) {
+                let gas = match (result.gas_used, result.fuzz_gas) {
+                    (_, Some(gas)) => SnapshotGas::Fuzz(gas),
+                    (Some(gas), None) => SnapshotGas::Unit(gas),
+                    // e.g. invariant tests
+                    (None, None) => continue,
+                };
+                snapshot.insert((contract.clone(), name.clone()), gas);
+            }
+        }
+        Self(snapshot)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read the gas snapshot {}", path.display()))?
+            .parse()
+            .wrap_err_with(|| format!("invalid gas snapshot {}", path.display()))
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_string())
+            .wrap_err_with(|| format!("could not write the gas snapshot {}", path.display()))
+    }
+
+    /// Compares the gas used by the tests of a fresh run against this snapshot
+    pub fn diff(&self, fresh: &GasSnapshot) -> SnapshotDiff {
+        let mut names = self.0.keys().chain(fresh.0.keys()).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        let tests = names
+            .into_iter()
+            .map(|name| GasDiff {
+                contract: name.0.clone(),
+                test: name.1.clone(),
+                before: self.0.get(name).copied(),
+                after: fresh.0.get(name).copied(),
+            })
+            .collect();
+        SnapshotDiff { tests }
+    }
+}
+
+impl fmt::Display for GasSnapshot {
+    /// One test per line, sorted by contract and test name
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ((contract, test), gas) in &self.0 {
+            writeln!(f, "{}::{} ({})", contract, test, gas)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GasSnapshot {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut snapshot = BTreeMap::new();
+        for (i, line) in s.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let parse = || {
+                let (name, gas) = line
+                    .trim()
+                    .strip_suffix(')')
+                    .and_then(|line| line.rsplit_once(" ("))
+                    .ok_or_else(|| eyre::eyre!("expected `Contract::test (gas: N)`"))?;
+                let (contract, test) = name
+                    .split_once("::")
+                    .ok_or_else(|| eyre::eyre!("expected `Contract::test`, got `{}`", name))?;
+                Ok::<_, eyre::Error>((
+                    (contract.to_owned(), test.to_owned()),
+                    gas.parse::<SnapshotGas>()?,
+                ))
+            };
+            let (name, gas) = parse().wrap_err_with(|| format!("line {}", i + 1))?;
+            snapshot.insert(name, gas);
+        }
+        Ok(Self(snapshot))
+    }
+}
+
+/// How much more gas a test may use than in the snapshot before it is considered to have
+/// regressed. Any increase is a regression if neither is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GasTolerance {
+    /// The increase in gas which is tolerated
+    pub absolute: Option<u64>,
+    /// The increase in gas which is tolerated, in percent of the gas in the snapshot
+    pub percent: Option<f64>,
+}
+
+/// The change in the gas used by a test between a snapshot and a fresh run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasDiff {
+    pub contract: String,
+    pub test: String,
+    /// The gas used in the snapshot, unless the test was added since
+    pub before: Option<SnapshotGas>,
+    /// The gas used in the fresh run, unless the test was removed since
+    pub after: Option<SnapshotGas>,
+}
+
+impl GasDiff {
+    /// The change in gas, if the test is in both runs
+    pub fn delta(&self) -> Option<i128> {
+        Some(self.after?.gas() as i128 - self.before?.gas() as i128)
+    }
+
+    /// The change in gas in percent of the gas in the snapshot, if the test is in both runs
+    /// and used any gas before
+    pub fn percent(&self) -> Option<f64> {
+        let before = self.before?.gas();
+        if before == 0 {
+            return None
+        }
+        Some(self.delta()? as f64 * 100.0 / before as f64)
+    }
+
+    /// Whether the test uses more gas than tolerated
+    pub fn is_regression(&self, tolerance: &GasTolerance) -> bool {
+        let delta = match self.delta() {
+            Some(delta) if delta > 0 => delta,
+            _ => return false,
+        };
+        let beyond_absolute = tolerance.absolute.map(|absolute| delta > absolute as i128);
+        // an increase from no gas at all is beyond any percentage
+        let beyond_percent = tolerance
+            .percent
+            .map(|percent| self.percent().map(|change| change > percent).unwrap_or(true));
+        match (beyond_absolute, beyond_percent) {
+            (None, None) => true,
+            (absolute, percent) => absolute.unwrap_or(false) || percent.unwrap_or(false),
+        }
+    }
+}
+
+impl fmt::Display for GasDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{} ", self.contract, self.test)?;
+        match (self.before, self.after) {
+            (Some(before), Some(after)) => {
+                write!(f, "({} -> {}, {:+}", before.gas(), after.gas(), self.delta().unwrap())?;
+                if let Some(percent) = self.percent() {
+                    write!(f, ", {:+.3}%", percent)?;
+                }
+                write!(f, ")")
+            }
+            (None, Some(after)) => write!(f, "(added, {})", after),
+            (Some(before), None) => write!(f, "(removed, {})", before),
+            (None, None) => write!(f, "(missing)"),
+        }
+    }
+}
+
+/// The changes in the gas used by the tests between a snapshot and a fresh run
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    /// The tests of both runs, sorted by contract and test name
+    pub tests: Vec<GasDiff>,
+}
+
+impl SnapshotDiff {
+    /// The total gas used in the snapshot and in the fresh run by the tests which are in both
+    pub fn totals(&self) -> (u64, u64) {
+        self.tests
+            .iter()
+            .filter_map(|test| Some((test.before?.gas(), test.after?.gas())))
+            .fold((0, 0), |(before, after), (test_before, test_after)| {
+                (before + test_before, after + test_after)
+            })
+    }
+
+    /// The tests which use more gas than tolerated
+    pub fn regressions(&self, tolerance: &GasTolerance) -> Vec<&GasDiff> {
+        self.tests.iter().filter(|test| test.is_regression(tolerance)).collect()
+    }
+
+    /// Fails if any test uses more gas than tolerated, listing them
+    pub fn check(&self, tolerance: &GasTolerance) -> Result<()> {
+        let regressions = self.regressions(tolerance);
+        if !regressions.is_empty() {
+            let regressions = regressions.iter().map(ToString::to_string).collect::<Vec<_>>();
+            eyre::bail!("the gas usage of tests regressed:\n{}", regressions.join("\n"))
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    /// One line per test whose gas changed, followed by the overall change
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for test in self.tests.iter().filter(|test| test.delta() != Some(0)) {
+            writeln!(f, "{}", test)?;
+        }
+        let (before, after) = self.totals();
+        write!(f, "Overall gas change: {:+}", after as i128 - before as i128)?;
+        if before > 0 {
+            write!(f, " ({:+.3}%)", (after as f64 - before as f64) * 100.0 / before as f64)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestResult;
+
+    fn result(success: bool, gas_used: Option<u64>, fuzz_gas: Option<FuzzGas>) -> TestResult {
+        TestResult { success, gas_used, fuzz_gas, ..Default::default() }
+    }
+
+    #[test]
+    fn writes_and_parses_snapshots() {
+        let fuzz = FuzzGas { mean: 2500, median: 2400 };
+        let tests = vec![
+            ("testB".to_owned(), result(true, Some(1000), None)),
+            ("testA".to_owned(), result(true, Some(3000), None)),
+            ("testFuzz".to_owned(), result(true, None, Some(fuzz))),
+            ("testFails".to_owned(), result(false, Some(500), None)),
+            ("invariantHolds".to_owned(), result(true, None, None)),
+        ];
+        let results = vec![
+            (
+                "ZTest".to_owned(),
+                std::iter::once(("testA".to_owned(), result(true, Some(7), None))).collect(),
+            ),
+            ("ATest".to_owned(), tests.into_iter().collect()),
+        ]
+        .into_iter()
+        .collect();
+
+        let snapshot = GasSnapshot::new(&results);
+        let written = snapshot.to_string();
+        assert_eq!(
+            written,
+            "ATest::testA (gas: 3000)\n\
+             ATest::testB (gas: 1000)\n\
+             ATest::testFuzz (mean: 2500, median: 2400)\n\
+             ZTest::testA (gas: 7)\n"
+        );
+        assert_eq!(written.parse::<GasSnapshot>().unwrap(), snapshot);
+
+        let err = "ATest::testA (gas: 3000)\nATest::testB (gas: lots)\n"
+            .parse::<GasSnapshot>()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+        assert!("ATest.testA (gas: 1)".parse::<GasSnapshot>().is_err());
+        assert!("ATest::testA (mean: 1)".parse::<GasSnapshot>().is_err());
+    }
+
+    #[test]
+    fn diffs_snapshots() {
+        let before = "A::testSame (gas: 100)\n\
+                      A::testMore (gas: 1000)\n\
+                      A::testLess (gas: 2000)\n\
+                      A::testFuzz (mean: 500, median: 400)\n\
+                      A::testRemoved (gas: 10)\n"
+            .parse::<GasSnapshot>()
+            .unwrap();
+        let after = "A::testSame (gas: 100)\n\
+                     A::testMore (gas: 1100)\n\
+                     A::testLess (gas: 1500)\n\
+                     A::testFuzz (mean: 510, median: 300)\n\
+                     A::testAdded (gas: 20)\n"
+            .parse::<GasSnapshot>()
+            .unwrap();
+
+        let diff = before.diff(&after);
+        let names = diff.tests.iter().map(|test| test.test.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["testAdded", "testFuzz", "testLess", "testMore", "testRemoved", "testSame"]
+        );
+        // the added and removed tests are not part of the totals
+        assert_eq!(diff.totals(), (3600, 3210));
+        assert_eq!(
+            diff.to_string(),
+            "A::testAdded (added, gas: 20)\n\
+             A::testFuzz (500 -> 510, +10, +2.000%)\n\
+             A::testLess (2000 -> 1500, -500, -25.000%)\n\
+             A::testMore (1000 -> 1100, +100, +10.000%)\n\
+             A::testRemoved (removed, gas: 10)\n\
+             Overall gas change: -390 (-10.833%)"
+        );
+
+        // any increase is a regression without tolerance
+        let regressions = |tolerance| {
+            diff.regressions(&tolerance).iter().map(|test| test.test.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(regressions(GasTolerance::default()), ["testFuzz", "testMore"]);
+        assert_eq!(regressions(GasTolerance { absolute: Some(50), percent: None }), ["testMore"]);
+        assert_eq!(regressions(GasTolerance { absolute: None, percent: Some(5.0) }), ["testMore"]);
+        let tolerance = GasTolerance { absolute: Some(100), percent: Some(10.0) };
+        assert!(regressions(tolerance).is_empty());
+        assert!(diff.check(&tolerance).is_ok());
+        let err = diff.check(&GasTolerance { absolute: Some(99), percent: None }).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the gas usage of tests regressed:\nA::testMore (1000 -> 1100, +100, +10.000%)"
+        );
+
+        // no change at all
+        let diff = after.diff(&after);
+        assert_eq!(diff.to_string(), "Overall gas change: +0 (+0.000%)");
+        assert!(diff.regressions(&GasTolerance::default()).is_empty());
+        assert_eq!(
+            GasSnapshot::default().diff(&GasSnapshot::default()).to_string(),
+            "Overall gas change: +0"
+        );
+    }
+}
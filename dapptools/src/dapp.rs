@@ -1,17 +1,24 @@
 use ethers::prelude::Provider;
-use evm_adapters::sputnik::ForkMemoryBackend;
-use regex::Regex;
+use evm_adapters::{
+    either::EitherEvm,
+    fuzz::FuzzConfig,
+    invariant::InvariantConfig,
+    sputnik::{ForkedBackend, SharedBackend},
+};
 use structopt::StructOpt;
 
-use dapp::MultiContractRunnerBuilder;
+use dapp::{
+    persistence::FileFailurePersistence,
+    report::Report,
+    snapshot::{GasSnapshot, GasTolerance},
+    MultiContractRunnerBuilder, ResultFormatter, TestFilter,
+};
 use dapp_solc::SolcBuilder;
 
-use ansi_term::Colour;
-
 mod dapp_opts;
-use dapp_opts::{BuildOpts, EvmType, Opts, Subcommands};
+use dapp_opts::{BuildOpts, EvmType, Opts, SnapshotOpts, Subcommands};
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, sync::Arc, time::Duration};
 
 mod utils;
 
@@ -27,11 +34,29 @@ fn main() -> eyre::Result<()> {
             env,
             json,
             pattern,
+            contract_pattern,
+            path_pattern,
             evm_type,
             no_compile,
             fork_url,
             fork_block_number,
+            fork_cache_path,
+            gas_report,
+            invariant_depth,
+            fuzz_runs,
+            fuzz_seed,
+            signatures,
+            jobs,
+            timeout,
+            shard,
+            failures_dir,
+            no_persist_failures,
+            verbosity,
+            no_color,
+            snapshot,
         } => {
+            let filter = TestFilter::new(&contract_pattern, &path_pattern, &pattern)?;
+
             // get the remappings / paths
             let remappings = utils::merge(remappings, remappings_env);
             let lib_paths = utils::default_path(lib_paths)?;
@@ -42,8 +67,24 @@ fn main() -> eyre::Result<()> {
                 .remappings(&remappings)
                 .libraries(&lib_paths)
                 .out_path(out_path)
-                .fuzzer(proptest::test_runner::TestRunner::default())
-                .skip_compilation(no_compile);
+                .fuzzer(FuzzConfig { runs: fuzz_runs, seed: fuzz_seed, ..Default::default() })
+                .invariant(InvariantConfig { depth: invariant_depth, ..Default::default() })
+                .skip_compilation(no_compile)
+                .jobs(jobs)
+                .verbosity(verbosity);
+            let builder = match timeout {
+                Some(timeout) => builder.timeout(Duration::from_secs(timeout)),
+                None => builder,
+            };
+            let builder = match shard {
+                Some(shard) => builder.shard(shard),
+                None => builder,
+            };
+            let builder = if no_persist_failures {
+                builder
+            } else {
+                builder.failure_persistence(Arc::new(FileFailurePersistence::new(failures_dir)))
+            };
 
             // run the tests depending on the chosen EVM
             match evm_type {
@@ -52,22 +93,53 @@ fn main() -> eyre::Result<()> {
                     use evm_adapters::sputnik::Executor;
                     use sputnik::backend::MemoryBackend;
                     let cfg = evm_version.sputnik_cfg();
+                    let vicinity = env.sputnik_state();
 
-                    if let Some(url) = fork_url {
-                        let provider = Provider::try_from(url.as_str())?;
-                        // TODO: Replace Default with something that can be read from disk, e.g.
-                        // some pre-loaded state snapshot from another time?
-                        let backend =
-                            ForkMemoryBackend::new(provider, fork_block_number, Default::default());
-                        let evm = Executor::new(env.gas_limit, &cfg, &backend);
-
-                        test(builder, evm, pattern, json)?;
-                    } else {
-                        let vicinity = env.sputnik_state();
-                        let backend = MemoryBackend::new(&vicinity, Default::default());
-                        let evm = Executor::new(env.gas_limit, &cfg, &backend);
-                        test(builder, evm, pattern, json)?;
-                    }
+                    let fork = match fork_url {
+                        Some(url) => {
+                            let provider = Provider::try_from(url.as_str())?;
+                            // TODO: Replace Default with something that can be read from disk,
+                            // e.g. some pre-loaded state snapshot from another time?
+                            let mut backend =
+                                ForkedBackend::new(provider, fork_block_number, Default::default());
+                            if let Some(cache_path) = fork_cache_path {
+                                backend = backend.with_cache_dir(cache_path);
+                            }
+                            // the executors of the tests run in parallel share the fetched state
+                            Some(SharedBackend::new(backend))
+                        }
+                        None => None,
+                    };
+                    // the cheatcode executor records the traces and stops the runaway calls.
+                    // both backends are run by the same code, whichever one is used.
+                    let new_evm = || match &fork {
+                        Some(backend) => EitherEvm::left(Executor::new_with_cheatcodes(
+                            backend.clone(),
+                            env.gas_limit,
+                            cfg,
+                            false,
+                        )),
+                        None => {
+                            let backend = MemoryBackend::new(&vicinity, Default::default());
+                            EitherEvm::right(Executor::new_with_cheatcodes(
+                                backend,
+                                env.gas_limit,
+                                cfg,
+                                false,
+                            ))
+                        }
+                    };
+                    test(
+                        builder,
+                        new_evm,
+                        &filter,
+                        json,
+                        gas_report,
+                        &signatures,
+                        jobs,
+                        !no_color,
+                        &snapshot,
+                    )?;
                 }
                 #[cfg(feature = "evmodin-evm")]
                 EvmType::EvmOdin => {
@@ -78,10 +150,19 @@ fn main() -> eyre::Result<()> {
 
                     // TODO: Replace this with a proper host. We'll want this to also be
                     // provided generically when we add the Forking host(s).
-                    let host = env.evmodin_state();
-
-                    let evm = EvmOdin::new(host, env.gas_limit, revision, NoopTracer);
-                    test(builder, evm, pattern, json)?;
+                    let new_evm =
+                        || EvmOdin::new(env.evmodin_state(), env.gas_limit, revision, NoopTracer);
+                    test(
+                        builder,
+                        new_evm,
+                        &filter,
+                        json,
+                        gas_report,
+                        &signatures,
+                        jobs,
+                        !no_color,
+                        &snapshot,
+                    )?;
                 }
             }
         }
@@ -106,46 +187,90 @@ fn main() -> eyre::Result<()> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn test<S, E: evm_adapters::Evm<S>>(
     builder: MultiContractRunnerBuilder,
-    evm: E,
-    pattern: Regex,
+    new_evm: impl Fn() -> E + Sync,
+    filter: &TestFilter,
     json: bool,
+    gas_report: bool,
+    signatures: &[String],
+    jobs: usize,
+    color: bool,
+    snapshot_opts: &SnapshotOpts,
 ) -> eyre::Result<()> {
-    let mut runner = builder.build(evm)?;
+    let verbosity = builder.verbosity;
+    let new_evm = || {
+        let mut evm = new_evm();
+        evm.set_gas_reporting_enabled(gas_report);
+        evm
+    };
+    let mut runner = builder.build(new_evm())?;
 
-    let results = runner.test(pattern)?;
+    // each test contract runs on its own EVM when they run in parallel
+    let results =
+        if jobs == 1 { runner.test(filter)? } else { runner.test_parallel(filter, new_evm)? };
+    let mut selectors = runner.selector_db();
+    for signature in signatures {
+        selectors.add_signature(signature)?;
+    }
+    let events = runner.event_decoder();
 
+    // the report goes to stdout on its own, so that it can be piped to other tools
     if json {
-        let res = serde_json::to_string(&results)?;
-        println!("{}", res);
+        Report::new(&results, runner.skipped(), runner.not_in_shard(), &events)
+            .write(std::io::stdout())?;
     } else {
-        // Dapptools-style printing
-        for (i, (contract_name, tests)) in results.iter().enumerate() {
-            if i > 0 {
-                println!()
-            }
-            if !tests.is_empty() {
-                println!("Running {} tests for {}", tests.len(), contract_name);
-            }
+        print!("{}", ResultFormatter::new(verbosity, color, &selectors, &events).format(&results));
 
-            for (name, result) in tests {
-                let status = if result.success {
-                    Colour::Green.paint("[PASS]")
+        // the tests which did not match the filter are counted, so that typos in the patterns
+        // do not go unnoticed
+        let (passed, failed) = results.values().flat_map(|tests| tests.values()).fold(
+            (0, 0),
+            |(passed, failed), result| {
+                if result.success {
+                    (passed + 1, failed)
                 } else {
-                    Colour::Red.paint("[FAIL]")
-                };
-                println!(
-                    "{} {} (gas: {})",
-                    status,
-                    name,
-                    result
-                        .gas_used
-                        .map(|x| x.to_string())
-                        .unwrap_or_else(|| "[fuzztest]".to_string())
-                );
-            }
+                    (passed, failed + 1)
+                }
+            },
+        );
+        let skipped = runner.skipped().values().map(Vec::len).sum::<usize>();
+        let not_in_shard = runner.not_in_shard().values().map(Vec::len).sum::<usize>();
+        println!();
+        print!("{} passed; {} failed; {} skipped by the filter", passed, failed, skipped);
+        if not_in_shard > 0 {
+            print!("; {} not in shard", not_in_shard);
         }
+        println!();
+
+        if gas_report {
+            println!();
+            print!("{}", runner.gas_report().format(&runner.known_contracts()));
+        }
+    }
+
+    let snapshot = GasSnapshot::new(&results);
+    if let Some(ref path) = snapshot_opts.diff {
+        let diff = GasSnapshot::read(path)?.diff(&snapshot);
+        // the report alone goes to stdout in json mode
+        if json {
+            eprintln!("{}", diff);
+        } else {
+            println!();
+            println!("{}", diff);
+        }
+        let tolerance = GasTolerance {
+            absolute: snapshot_opts.gas_tolerance,
+            percent: snapshot_opts.gas_tolerance_pct,
+        };
+        // the regressions only fail the run if a tolerance is set
+        if tolerance != GasTolerance::default() {
+            diff.check(&tolerance)?;
+        }
+    }
+    if let Some(ref path) = snapshot_opts.write {
+        snapshot.write(path)?;
     }
 
     Ok(())
@@ -1,5 +1,6 @@
 use structopt::StructOpt;
 
+use dapp::{Shard, Verbosity};
 use ethers::types::Address;
 use std::{path::PathBuf, str::FromStr};
 
@@ -25,7 +26,21 @@ pub enum Subcommands {
             help = "only run test methods matching regex",
             default_value = ".*"
         )]
-        pattern: regex::Regex,
+        pattern: String,
+
+        #[structopt(
+            long = "--match-contract",
+            help = "only run the tests of contracts matching regex",
+            default_value = ".*"
+        )]
+        contract_pattern: String,
+
+        #[structopt(
+            long = "--match-path",
+            help = "only run the tests of contracts whose source file path matches regex",
+            default_value = ".*"
+        )]
+        path_pattern: String,
 
         #[structopt(flatten)]
         opts: BuildOpts,
@@ -51,6 +66,89 @@ pub enum Subcommands {
 
         #[structopt(help = "pins the block number for the state fork", long)]
         fork_block_number: Option<u64>,
+
+        #[structopt(
+            help = "caches the state fetched for the fork in this directory, to reuse it in later runs",
+            long
+        )]
+        fork_cache_path: Option<PathBuf>,
+
+        #[structopt(
+            help = "print the gas used by the functions of the contracts under test",
+            long
+        )]
+        gas_report: bool,
+
+        #[structopt(
+            help = "the maximum number of calls of the sequences of the invariant tests",
+            long,
+            default_value = "15"
+        )]
+        invariant_depth: usize,
+
+        #[structopt(
+            help = "the number of runs of each fuzz and invariant test",
+            long,
+            default_value = "256"
+        )]
+        fuzz_runs: u32,
+
+        #[structopt(
+            help = "the seed of the fuzz and invariant tests' inputs, to reproduce a failure",
+            long
+        )]
+        fuzz_seed: Option<u64>,
+
+        #[structopt(
+            help = "extra function and custom error signatures to name the calls and reverts of traces, e.g. `error Unauthorized(address)`",
+            long = "signature"
+        )]
+        signatures: Vec<String>,
+
+        #[structopt(
+            help = "the number of test contracts to run in parallel, each on its own EVM, or 0 for one per core",
+            long,
+            default_value = "1"
+        )]
+        jobs: usize,
+
+        #[structopt(
+            help = "the number of seconds each test may run for before it is interrupted and fails",
+            long
+        )]
+        timeout: Option<u64>,
+
+        #[structopt(
+            help = "run only the part of the tests assigned to this shard, as `index/total` with the index counted from 0, e.g. to split them across CI machines",
+            long
+        )]
+        shard: Option<Shard>,
+
+        #[structopt(
+            help = "the directory the failures of the fuzz and invariant tests are persisted in, to replay them first on the next runs",
+            long,
+            default_value = "cache/failures"
+        )]
+        failures_dir: PathBuf,
+
+        #[structopt(
+            help = "neither replay nor persist the failures of the fuzz and invariant tests",
+            long
+        )]
+        no_persist_failures: bool,
+
+        #[structopt(
+            help = "how much of the results to print, from 0 for the summary only, 1 for the passing and failing tests, 2 for why they failed, 3 for the call traces and state changes of the failing tests, to 4 for the ones of all the tests and the traces of their setUp()",
+            long,
+            default_value = "2"
+        )]
+        verbosity: Verbosity,
+
+        #[structopt(help = "print the results without colors", long)]
+        no_color: bool,
+
+        #[structopt(flatten)]
+        snapshot: SnapshotOpts,
     },
     Build {
         #[structopt(flatten)]
@@ -89,6 +187,33 @@ pub struct BuildOpts {
     pub evm_version: EvmVersion,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct SnapshotOpts {
+    #[structopt(
+        help = "write the gas used by the passing tests to this file, e.g. `.gas-snapshot`",
+        long = "snapshot"
+    )]
+    pub write: Option<PathBuf>,
+
+    #[structopt(
+        help = "compare the gas used by the passing tests against the snapshot in this file",
+        long = "diff-snapshot"
+    )]
+    pub diff: Option<PathBuf>,
+
+    #[structopt(
+        help = "fail if a test uses more gas than in the compared snapshot by more than this amount",
+        long
+    )]
+    pub gas_tolerance: Option<u64>,
+
+    #[structopt(
+        help = "fail if a test uses more gas than in the compared snapshot by more than this percentage",
+        long
+    )]
+    pub gas_tolerance_pct: Option<f64>,
+}
+
 #[derive(Clone, Debug)]
 pub enum EvmType {
     #[cfg(feature = "sputnik-evm")]
@@ -119,6 +244,8 @@ pub enum EvmVersion {
     London,
 }
 
+#[cfg(feature = "sputnik-evm")]
+use evm_adapters::sputnik::SpecId;
 #[cfg(feature = "sputnik-evm")]
 use sputnik::Config;
 
@@ -127,14 +254,15 @@ use evmodin::Revision;
 
 impl EvmVersion {
     #[cfg(feature = "sputnik-evm")]
-    pub fn sputnik_cfg(self) -> Config {
+    pub fn sputnik_cfg(self) -> &'static Config {
         use EvmVersion::*;
-        match self {
-            Frontier => Config::frontier(),
-            Istanbul => Config::istanbul(),
-            Berlin => Config::berlin(),
-            other => panic!("The {:?} hard fork is unsupported on Sputnik", other),
-        }
+        let spec = match self {
+            Frontier => SpecId::Frontier,
+            Istanbul => SpecId::Istanbul,
+            Berlin => SpecId::Berlin,
+            London => SpecId::London,
+        };
+        spec.config()
     }
 
     #[cfg(feature = "evmodin-evm")]
@@ -208,7 +336,9 @@ pub struct Env {
 
     #[structopt(help = "the block.gaslimit value during EVM execution", long)]
     pub block_gas_limit: Option<u64>,
-    // TODO: Add configuration option for base fee.
+
+    #[structopt(help = "the block.basefee value during EVM execution", long, default_value = "0")]
+    pub block_base_fee_per_gas: u64,
 }
 
 #[cfg(feature = "sputnik-evm")]
@@ -231,6 +361,7 @@ impl Env {
             block_timestamp: self.block_timestamp.into(),
             block_difficulty: self.block_difficulty.into(),
             block_gas_limit: self.block_gas_limit.unwrap_or(self.gas_limit).into(),
+            block_base_fee_per_gas: self.block_base_fee_per_gas.into(),
             block_hashes: Vec::new(),
         }
     }
@@ -247,6 +378,7 @@ impl Env {
         host.tx_context.block_timestamp = self.block_timestamp;
         host.tx_context.block_difficulty = self.block_difficulty.into();
         host.tx_context.block_gas_limit = self.block_gas_limit.unwrap_or(self.gas_limit);
+        host.tx_context.block_base_fee = self.block_base_fee_per_gas.into();
 
         host
     }
@@ -0,0 +1,34 @@
+use std::process::Command;
+
+/// Runs `dapp test` on the Greeter fixture of the `dapp` crate, with the given extra arguments
+fn dapp_test(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dapp"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../dapp"))
+        .args(&["test", "--contracts", "./GreetTest.sol", "--match-contract", "^GreeterTest$"])
+        .args(&["--fuzz-seed", "1", "--no-persist-failures", "--no-color"])
+        .args(args)
+        .output()
+        .expect("could not run dapp");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn traces_the_failing_tests() {
+    let output = dapp_test(&["--verbosity", "3"]);
+    assert!(output.contains("[FAIL] testFuzzShrinking (gas: [fuzztest])\n"));
+    assert!(output.contains("  trace:\n    ["));
+    assert!(output.contains("] GreeterTest::testFuzzShrinking("));
+    assert!(output.contains("      ← revert: product greater than 100\n"));
+    assert!(output.contains("4 passed; 1 failed;"));
+
+    // the passing tests are traced as well, along with `setUp()`
+    let output = dapp_test(&["--verbosity", "4"]);
+    assert!(output.contains("  setUp:\n    ["));
+    assert!(output.contains("] GreeterTest::setUp()\n"));
+    assert!(output.contains("] new Greeter ("));
+    assert!(output.contains("] Greeter::greet(\"yo\")\n"));
+
+    // and none of them by default
+    assert!(!dapp_test(&[]).contains("trace:"));
+}
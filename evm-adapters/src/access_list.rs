@@ -0,0 +1,79 @@
+//! Recording of the accounts and storage slots accessed during execution, so that access lists
+//! (EIP-2930) can be generated for calls
+use ethers::types::{Address, H256};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+/// Records the accounts and storage slots accessed by a call. The handler hooks which record
+/// them only have shared access to it, hence the `RefCell`. Recording has a cost on every
+/// access, so it does nothing unless enabled.
+#[derive(Clone, Debug, Default)]
+pub struct AccessListTracker {
+    pub enabled: bool,
+    accessed: RefCell<BTreeMap<Address, BTreeSet<H256>>>,
+}
+
+impl AccessListTracker {
+    /// Removes the recorded accesses, e.g. before the next top-level call
+    pub fn clear(&mut self) {
+        self.accessed.get_mut().clear();
+    }
+
+    pub fn access_address(&self, address: Address) {
+        if self.enabled {
+            self.accessed.borrow_mut().entry(address).or_default();
+        }
+    }
+
+    pub fn access_slot(&self, address: Address, slot: H256) {
+        if self.enabled {
+            self.accessed.borrow_mut().entry(address).or_default().insert(slot);
+        }
+    }
+
+    /// The recorded accesses in the shape of `eth_createAccessList`. As there, the addresses in
+    /// `excluded` (typically the sender, the callee and the precompiles, which are warm anyway)
+    /// are left out, unless storage slots of theirs were accessed.
+    pub fn access_list(&self, excluded: &[Address]) -> Vec<(Address, Vec<H256>)> {
+        self.accessed
+            .borrow()
+            .iter()
+            .filter(|(address, slots)| !slots.is_empty() || !excluded.contains(address))
+            .map(|(address, slots)| (*address, slots.iter().copied().collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_accesses() {
+        let mut tracker = AccessListTracker::default();
+        let (sender, callee, other) =
+            (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3));
+        // nothing is recorded when disabled
+        tracker.access_address(other);
+        assert!(tracker.access_list(&[]).is_empty());
+
+        tracker.enabled = true;
+        tracker.access_address(sender);
+        tracker.access_slot(callee, H256::from_low_u64_be(2));
+        tracker.access_slot(callee, H256::from_low_u64_be(1));
+        tracker.access_slot(callee, H256::from_low_u64_be(2));
+        tracker.access_address(other);
+        assert_eq!(
+            tracker.access_list(&[sender, callee]),
+            vec![
+                (callee, vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]),
+                (other, vec![])
+            ]
+        );
+
+        tracker.clear();
+        assert!(tracker.access_list(&[]).is_empty());
+    }
+}
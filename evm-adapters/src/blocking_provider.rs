@@ -2,6 +2,7 @@ use ethers::{
     providers::Middleware,
     types::{Address, BlockId, Bytes, H256, U256},
 };
+
 use tokio::runtime::Runtime;
 
 #[derive(Debug)]
@@ -18,6 +19,8 @@ impl<M: Clone> Clone for BlockingProvider<M> {
     }
 }
 
+#[cfg(feature = "sputnik")]
+use crate::sputnik::vicinity_from_provider;
 #[cfg(feature = "sputnik")]
 use sputnik::backend::MemoryVicinity;
 
@@ -27,28 +30,11 @@ impl<M: Middleware> BlockingProvider<M> {
     }
 
     #[cfg(feature = "sputnik")]
-    pub fn vicinity(&self, pin_block: Option<u64>) -> Result<MemoryVicinity, M::Error> {
-        let block_number = if let Some(pin_block) = pin_block {
-            pin_block
-        } else {
-            self.block_on(self.provider.get_block_number())?.as_u64()
-        };
-
-        let gas_price = self.block_on(self.provider.get_gas_price())?;
-        let chain_id = self.block_on(self.provider.get_chainid())?;
-        let block = self.block_on(self.provider.get_block(block_number))?.expect("block not found");
-
-        Ok(MemoryVicinity {
-            origin: Address::default(),
-            chain_id,
-            block_hashes: Vec::new(),
-            block_number: block.number.expect("block number not found").as_u64().into(),
-            block_coinbase: block.author,
-            block_difficulty: block.difficulty,
-            block_gas_limit: block.gas_limit,
-            block_timestamp: block.timestamp,
-            gas_price,
-        })
+    pub fn vicinity(&self, pin_block: Option<u64>) -> eyre::Result<MemoryVicinity>
+    where
+        M::Error: 'static,
+    {
+        self.block_on(vicinity_from_provider(&self.provider, pin_block))
     }
 
     fn block_on<F: std::future::Future>(&self, f: F) -> F::Output {
@@ -79,4 +65,10 @@ impl<M: Middleware> BlockingProvider<M> {
     ) -> Result<H256, M::Error> {
         self.block_on(self.provider.get_storage_at(address, slot, block))
     }
+
+    /// Fetches the hash of a block, or `None` if the node does not know it
+    pub fn get_block_hash(&self, number: u64) -> Result<Option<H256>, M::Error> {
+        let block = self.block_on(self.provider.get_block(number))?;
+        Ok(block.and_then(|block| block.hash))
+    }
 }
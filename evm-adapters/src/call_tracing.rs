@@ -0,0 +1,550 @@
+//! Recording of the calls and deployments made during execution as a tree, so that failing
+//! tests can be inspected
+use crate::dstest_logs::decode_logs;
+use dapp_utils::{decode_revert, format_output, format_token, func_signature, get_func};
+use ethers::{
+    abi::{Abi, Function, RawLog, Token},
+    types::{Address, U256},
+};
+use eyre::Result;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// Whether a frame is a call or a deployment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+    Call,
+    Create,
+}
+
+/// A call or deployment made during execution
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallTrace {
+    pub kind: CallKind,
+    /// The depth of the frame, the top-level call being at depth 0
+    pub depth: usize,
+    pub caller: Address,
+    /// The callee, or the created contract
+    pub address: Address,
+    /// The calldata, or the init code of the created contract
+    pub data: Vec<u8>,
+    pub value: U256,
+    pub gas_limit: u64,
+    /// Whether the frame succeeded, set when it returns
+    pub success: bool,
+    /// The return or revert data, set when the frame returns
+    pub output: Vec<u8>,
+    /// The gas used by the frame, set when it returns
+    pub gas_used: u64,
+}
+
+/// What a frame did, in execution order
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceItem {
+    /// A call or deployment, by the index of its node
+    Call(usize),
+    /// A log emitted by the frame
+    Log(RawLog),
+}
+
+/// A node of the call tree. The nodes are stored in the order the frames were entered, so the
+/// top-level call is the first one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallTraceNode {
+    pub trace: CallTrace,
+    /// The index of the node of the calling frame
+    pub parent: Option<usize>,
+    pub items: Vec<TraceItem>,
+}
+
+/// Builds the call tree as the executor enters and exits frames. Recording every frame has a
+/// cost, so it does nothing unless enabled.
+#[derive(Clone, Debug, Default)]
+pub struct CallTracer {
+    pub enabled: bool,
+    nodes: Vec<CallTraceNode>,
+    /// The indices of the nodes of the frames which are currently executing
+    stack: Vec<usize>,
+}
+
+impl CallTracer {
+    /// Removes the recorded trace, e.g. before the next top-level call
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.stack.clear();
+    }
+
+    /// The nodes of the call tree recorded since the last [`CallTracer::clear`]
+    pub fn nodes(&self) -> &[CallTraceNode] {
+        &self.nodes
+    }
+
+    /// The index of the node of the frame which is currently executing
+    pub fn current(&self) -> Option<usize> {
+        self.stack.last().copied()
+    }
+
+    /// Records that a frame was entered
+    pub fn start(
+        &mut self,
+        kind: CallKind,
+        caller: Address,
+        address: Address,
+        data: &[u8],
+        value: U256,
+        gas_limit: u64,
+    ) {
+        if !self.enabled {
+            return
+        }
+        let parent = self.stack.last().copied();
+        let index = self.nodes.len();
+        self.nodes.push(CallTraceNode {
+            trace: CallTrace {
+                kind,
+                depth: self.stack.len(),
+                caller,
+                address,
+                data: data.to_vec(),
+                value,
+                gas_limit,
+                success: false,
+                output: Vec::new(),
+                gas_used: 0,
+            },
+            parent,
+            items: Vec::new(),
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent].items.push(TraceItem::Call(index));
+        }
+        self.stack.push(index);
+    }
+
+    /// Records a log emitted by the current frame
+    pub fn log(&mut self, log: RawLog) {
+        if let Some(&index) = self.stack.last() {
+            self.nodes[index].items.push(TraceItem::Log(log));
+        }
+    }
+
+    /// Records that the current frame returned
+    pub fn finish(&mut self, success: bool, output: &[u8], gas_used: u64) {
+        if let Some(index) = self.stack.pop() {
+            let trace = &mut self.nodes[index].trace;
+            trace.success = success;
+            trace.output = output.to_vec();
+            trace.gas_used = gas_used;
+        }
+    }
+}
+
+/// The functions of the compiled contracts, along with extra function and error signatures,
+/// keyed by their selector, so that the calls and reverts of traces can be named even if the
+/// contract at their address is unknown (e.g. because it was deployed at runtime)
+#[derive(Clone, Debug, Default)]
+pub struct SelectorDb {
+    /// The functions by selector, along with the name of their contract, if they have one
+    functions: BTreeMap<[u8; 4], Vec<(Option<String>, Function)>>,
+    /// The custom errors by selector, as functions of the same name and inputs
+    errors: BTreeMap<[u8; 4], Vec<Function>>,
+}
+
+/// How a [`SelectorDb`] resolved calldata or revert data
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution<'a> {
+    /// Nothing is known with the selector of the data
+    Unknown,
+    /// The data decodes cleanly against a single signature, which belongs to `contract` if
+    /// exactly one contract has it
+    Resolved { contract: Option<&'a str>, function: &'a Function, args: Vec<Token> },
+    /// The data decodes cleanly against several signatures, or against none of the ones with
+    /// its selector, which are listed as `Contract::signature`
+    Ambiguous(Vec<String>),
+}
+
+impl SelectorDb {
+    /// Indexes the functions of the named contracts. The errors of their ABIs are not kept by
+    /// [`Abi`], so custom errors have to be added via [`SelectorDb::add_error`].
+    pub fn new<'a>(contracts: impl IntoIterator<Item = (&'a str, &'a Abi)>) -> Self {
+        let mut db = Self::default();
+        for (name, abi) in contracts {
+            for function in abi.functions() {
+                db.add_function(Some(name), function.clone());
+            }
+        }
+        db
+    }
+
+    /// Indexes an extra function or custom error given as a human-readable signature, e.g.
+    /// `transfer(address,uint256)` or `error Unauthorized(address caller)`
+    pub fn add_signature(&mut self, signature: &str) -> Result<()> {
+        match signature.trim().strip_prefix("error ") {
+            // errors are encoded like the calldata of functions of the same name and inputs
+            Some(error) => self.add_error(get_func(error)?),
+            None => self.add_function(None, get_func(signature)?),
+        }
+        Ok(())
+    }
+
+    /// Indexes a custom error, given as a function of the same name and inputs
+    pub fn add_error(&mut self, error: Function) {
+        let candidates = self.errors.entry(error.short_signature()).or_default();
+        if !candidates.contains(&error) {
+            candidates.push(error);
+        }
+    }
+
+    fn add_function(&mut self, contract: Option<&str>, function: Function) {
+        let candidate = (contract.map(ToOwned::to_owned), function);
+        let candidates = self.functions.entry(candidate.1.short_signature()).or_default();
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+
+    /// Resolves the function called with `calldata`
+    pub fn resolve_call(&self, calldata: &[u8]) -> Resolution<'_> {
+        let candidates = selector(calldata).and_then(|selector| self.functions.get(&selector));
+        let candidates = candidates
+            .into_iter()
+            .flatten()
+            .map(|(contract, function)| (contract.as_deref(), function));
+        resolve(candidates, calldata)
+    }
+
+    /// Resolves the custom error a call reverted with
+    pub fn resolve_error(&self, output: &[u8]) -> Resolution<'_> {
+        let candidates = selector(output).and_then(|selector| self.errors.get(&selector));
+        let candidates = candidates.into_iter().flatten().map(|error| (None, error));
+        resolve(candidates, output)
+    }
+}
+
+fn selector(data: &[u8]) -> Option<[u8; 4]> {
+    let mut selector = [0; 4];
+    selector.copy_from_slice(data.get(..4)?);
+    Some(selector)
+}
+
+/// Resolves `data` against the candidates of its selector. The data decodes cleanly if it is
+/// exactly the encoding of the arguments it decodes to.
+fn resolve<'a>(
+    candidates: impl Iterator<Item = (Option<&'a str>, &'a Function)>,
+    data: &[u8],
+) -> Resolution<'a> {
+    let candidates = candidates.collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Resolution::Unknown
+    }
+    let decoded = candidates
+        .iter()
+        .filter_map(|&(contract, function)| {
+            let args = function.decode_input(&data[4..]).ok()?;
+            (ethers::abi::encode(&args) == data[4..]).then(|| (contract, function, args))
+        })
+        .collect::<Vec<_>>();
+    let mut signatures = decoded.iter().map(|(_, function, _)| func_signature(function));
+    let first = signatures.next();
+    if first.is_none() || signatures.any(|signature| Some(signature) != first) {
+        // none, or several different signatures match
+        let listed = if decoded.is_empty() {
+            candidates
+        } else {
+            decoded.iter().map(|(contract, function, _)| (*contract, *function)).collect()
+        };
+        return Resolution::Ambiguous(
+            listed
+                .into_iter()
+                .map(|(contract, function)| qualified(contract, &func_signature(function)))
+                .collect(),
+        )
+    }
+    let contracts = decoded.iter().map(|(contract, ..)| *contract).collect::<BTreeSet<_>>();
+    let (contract, function, args) = decoded.into_iter().next().expect("a signature matched");
+    Resolution::Resolved {
+        contract: if contracts.len() == 1 { contract } else { None },
+        function,
+        args,
+    }
+}
+
+/// Prefixes `item` with the name of its contract, if any
+fn qualified(contract: Option<&str>, item: &str) -> String {
+    match contract {
+        Some(contract) => format!("{}::{}", contract, item),
+        None => item.to_owned(),
+    }
+}
+
+/// Formats the call tree as indented lines, one per call, log and return, each call with the
+/// gas used by its frame. Calls, return values and custom errors are decoded if their selector
+/// is resolved by `selectors`, and addresses are named by their `labels`. The calls and returns
+/// are passed through `paint` along with whether their frame succeeded, e.g. to color them.
+pub fn format_traces(
+    nodes: &[CallTraceNode],
+    selectors: &SelectorDb,
+    labels: &BTreeMap<Address, String>,
+    paint: &dyn Fn(String, bool) -> String,
+) -> String {
+    let mut out = String::new();
+    if !nodes.is_empty() {
+        format_node(&mut out, nodes, 0, selectors, labels, paint);
+    }
+    out
+}
+
+fn format_node(
+    out: &mut String,
+    nodes: &[CallTraceNode],
+    index: usize,
+    selectors: &SelectorDb,
+    labels: &BTreeMap<Address, String>,
+    paint: &dyn Fn(String, bool) -> String,
+) {
+    let node = &nodes[index];
+    let trace = &node.trace;
+    let indent = "  ".repeat(trace.depth);
+    let address =
+        labels.get(&trace.address).cloned().unwrap_or_else(|| format!("{:?}", trace.address));
+    let resolution = match trace.kind {
+        CallKind::Create => Resolution::Unknown,
+        CallKind::Call => selectors.resolve_call(&trace.data),
+    };
+
+    let call = match (trace.kind, &resolution) {
+        (CallKind::Create, _) => format!("new {} ({} bytes)", address, trace.data.len()),
+        (CallKind::Call, Resolution::Resolved { contract, function, args }) => {
+            // the contract is not named again if the address is labeled after it
+            let contract = contract.filter(|contract| {
+                labels.get(&trace.address).map(String::as_str) != Some(*contract)
+            });
+            format!(
+                "{}::{}",
+                address,
+                qualified(contract, &format!("{}({})", function.name, format_tokens(args)))
+            )
+        }
+        (CallKind::Call, Resolution::Ambiguous(candidates)) => format!(
+            "{}::0x{} (one of {})",
+            address,
+            hex::encode(&trace.data),
+            candidates.join(", ")
+        ),
+        (CallKind::Call, Resolution::Unknown) => {
+            format!("{}::0x{}", address, hex::encode(&trace.data))
+        }
+    };
+    let paint_frame = |text: String| paint(text, trace.success);
+    let value =
+        if trace.value.is_zero() { String::new() } else { format!(" {{value: {}}}", trace.value) };
+    let _ = writeln!(out, "{}[{}] {}{}", indent, trace.gas_used, paint_frame(call), value);
+
+    for item in &node.items {
+        match item {
+            TraceItem::Call(child) => format_node(out, nodes, *child, selectors, labels, paint),
+            TraceItem::Log(log) => {
+                for entry in decode_logs(std::slice::from_ref(log)) {
+                    let _ = writeln!(out, "{}  emit {}", indent, entry);
+                }
+            }
+        }
+    }
+
+    let output = if !trace.success {
+        let reason = match selectors.resolve_error(&trace.output) {
+            Resolution::Resolved { function, args, .. } => {
+                format!("{}({})", function.name, format_tokens(&args))
+            }
+            Resolution::Ambiguous(candidates) => {
+                format!("0x{} (one of {})", hex::encode(&trace.output), candidates.join(", "))
+            }
+            Resolution::Unknown => decode_revert(&trace.output),
+        };
+        format!("revert: {}", reason)
+    } else if trace.kind == CallKind::Create || trace.output.is_empty() {
+        "()".to_owned()
+    } else {
+        // the values are rendered one per line if there are several, or if they are nested
+        match &resolution {
+            Resolution::Resolved { function, .. } if !function.outputs.is_empty() => {
+                format_output(function, &trace.output, false)
+            }
+            _ => format!("0x{}", hex::encode(&trace.output)),
+        }
+    };
+    let mut lines = output.lines();
+    let _ =
+        writeln!(out, "{}  ← {}", indent, paint_frame(lines.next().unwrap_or_default().to_owned()));
+    for line in lines {
+        let _ = writeln!(out, "{}    {}", indent, paint_frame(line.to_owned()));
+    }
+}
+
+fn format_tokens(tokens: &[Token]) -> String {
+    tokens.iter().map(format_token).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::parse_abi;
+
+    fn plain(text: String, _success: bool) -> String {
+        text
+    }
+
+    #[test]
+    fn builds_and_formats_call_tree() {
+        let mut tracer = CallTracer::default();
+        // nothing is recorded when disabled
+        tracer.start(CallKind::Call, Address::zero(), Address::zero(), &[], 0.into(), 100);
+        assert!(tracer.nodes().is_empty());
+
+        let func = get_func("function balanceOf(address) returns (uint256)").unwrap();
+        let calldata = func.encode_input(&[Token::Address(Address::repeat_byte(2))]).unwrap();
+        let test = Address::repeat_byte(1);
+        let token = Address::repeat_byte(3);
+
+        tracer.enabled = true;
+        tracer.start(CallKind::Call, Address::zero(), test, &[0xde, 0xad], 0.into(), 1000);
+        tracer.start(CallKind::Call, test, token, &calldata, 0.into(), 900);
+        tracer.finish(true, &ethers::abi::encode(&[Token::Uint(5.into())]), 300);
+        tracer.log(RawLog {
+            topics: vec![ethers::utils::keccak256("log(string)").into()],
+            data: ethers::abi::encode(&[Token::String("gm".to_owned())]),
+        });
+        tracer.finish(false, &[], 500);
+
+        let nodes = tracer.nodes();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].parent, Some(0));
+        assert_eq!(nodes[1].trace.depth, 1);
+        assert!(matches!(nodes[0].items.as_slice(), [TraceItem::Call(1), TraceItem::Log(_)]));
+
+        let mut selectors = SelectorDb::default();
+        selectors.add_signature("balanceOf(address) returns (uint256)").unwrap();
+        let formatted = format_traces(nodes, &selectors, &BTreeMap::new(), &plain);
+        assert_eq!(
+            formatted,
+            format!(
+                "[500] {:?}::0xdead\n  [300] {:?}::balanceOf({:?})\n    ← 5\n  emit gm\n  ← \
+                 revert: <empty revert data>\n",
+                test,
+                token,
+                Address::repeat_byte(2)
+            )
+        );
+        // the calls and returns are painted by whether their frame succeeded
+        let mark =
+            |text: String, success: bool| format!("{}{}", if success { "+" } else { "-" }, text);
+        let marked = format_traces(nodes, &selectors, &BTreeMap::new(), &mark);
+        let lines = marked.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("[500] -{:?}::0xdead", test));
+        assert_eq!(lines[2], "    ← +5");
+        assert_eq!(lines[4], "  ← -revert: <empty revert data>");
+
+        tracer.clear();
+        assert!(tracer.nodes().is_empty());
+    }
+
+    #[test]
+    fn resolves_selectors() {
+        let token = parse_abi(&[
+            "function burn(uint256)",
+            "function transfer(address,uint256) returns (bool)",
+        ])
+        .unwrap();
+        let other = parse_abi(&["function transfer(address,uint256) returns (bool)"]).unwrap();
+        let mut selectors = SelectorDb::new(vec![("Token", &token), ("Other", &other)]);
+        // collides with `burn(uint256)`
+        selectors.add_signature("collate_propagate_storage(bytes16)").unwrap();
+        selectors.add_signature("error Unauthorized(address caller)").unwrap();
+
+        let burn = token.function("burn").unwrap();
+        let calldata = burn.encode_input(&[Token::Uint(1.into())]).unwrap();
+        assert_eq!(
+            selectors.resolve_call(&calldata),
+            Resolution::Resolved {
+                contract: Some("Token"),
+                function: burn,
+                args: vec![Token::Uint(1.into())]
+            }
+        );
+        // both decode a word whose low bytes are zero
+        let calldata = burn.encode_input(&[Token::Uint(U256::one() << 255)]).unwrap();
+        let candidates = vec![
+            "Token::burn(uint256)".to_owned(),
+            "collate_propagate_storage(bytes16)".to_owned(),
+        ];
+        assert_eq!(selectors.resolve_call(&calldata), Resolution::Ambiguous(candidates.clone()));
+        // none decodes truncated calldata
+        assert_eq!(selectors.resolve_call(&calldata[..6]), Resolution::Ambiguous(candidates));
+        assert_eq!(selectors.resolve_call(&[0xde, 0xad, 0xbe, 0xef]), Resolution::Unknown);
+        assert_eq!(selectors.resolve_call(&[0x42]), Resolution::Unknown);
+
+        // the contract of functions several contracts have is unknown
+        let transfer = token.function("transfer").unwrap();
+        let (to, caller) = (Address::repeat_byte(2), Address::repeat_byte(3));
+        let args = vec![Token::Address(to), Token::Uint(1.into())];
+        let calldata = transfer.encode_input(&args).unwrap();
+        assert_eq!(
+            selectors.resolve_call(&calldata),
+            Resolution::Resolved { contract: None, function: transfer, args }
+        );
+
+        // custom errors are named in traces
+        let unauthorized = get_func("Unauthorized(address)").unwrap();
+        let output = unauthorized.encode_input(&[Token::Address(caller)]).unwrap();
+        let mut tracer = CallTracer { enabled: true, ..Default::default() };
+        let unknown = Address::repeat_byte(4);
+        tracer.start(CallKind::Call, Address::zero(), unknown, &calldata, 0.into(), 1000);
+        tracer.finish(false, &output, 100);
+        assert_eq!(
+            format_traces(tracer.nodes(), &selectors, &BTreeMap::new(), &plain),
+            format!(
+                "[100] {:?}::transfer({:?}, 1)\n  ← revert: Unauthorized({:?})\n",
+                unknown, to, caller
+            )
+        );
+        // and so are the addresses with a label
+        let labels = std::iter::once((unknown, "Token".to_owned())).collect();
+        assert!(format_traces(tracer.nodes(), &selectors, &labels, &plain)
+            .starts_with(&format!("[100] Token::transfer({:?}, 1)", to)));
+        // which are not named twice if they are labeled after the contract of the function
+        tracer.clear();
+        let calldata = burn.encode_input(&[Token::Uint(1.into())]).unwrap();
+        tracer.start(CallKind::Call, Address::zero(), unknown, &calldata, 0.into(), 1000);
+        tracer.finish(true, &[], 100);
+        assert!(format_traces(tracer.nodes(), &selectors, &labels, &plain)
+            .starts_with("[100] Token::burn(1)\n"));
+    }
+
+    #[test]
+    fn formats_return_values() {
+        let mut selectors = SelectorDb::default();
+        selectors.add_signature("getReserves() returns (uint112, int112)").unwrap();
+        selectors.add_signature("name() returns (string)").unwrap();
+        let pair = Address::repeat_byte(1);
+        let mut tracer = CallTracer { enabled: true, ..Default::default() };
+        let reserves = [Token::Uint(1.into()), Token::Int(U256::MAX)];
+        let calldata = ethers::utils::id("getReserves()");
+        tracer.start(CallKind::Call, Address::zero(), pair, &calldata, 0.into(), 0);
+        tracer.finish(true, &ethers::abi::encode(&reserves), 100);
+        let calldata = ethers::utils::id("name()");
+        tracer.start(CallKind::Call, Address::zero(), pair, &calldata, 0.into(), 0);
+        // too short to be decoded
+        tracer.finish(true, &[0x12, 0x34], 100);
+
+        // several values are rendered one per line
+        let nodes = tracer.nodes();
+        assert_eq!(
+            format_traces(&nodes[..1], &selectors, &BTreeMap::new(), &plain),
+            format!("[100] {:?}::getReserves()\n  ← [0]: 1\n    [1]: -1\n", pair)
+        );
+        assert!(format_traces(&nodes[1..], &selectors, &BTreeMap::new(), &plain)
+            .contains("  ← 0x1234 (could not decode the return data as (string): "));
+    }
+}
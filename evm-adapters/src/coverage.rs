@@ -0,0 +1,113 @@
+//! Recording of the program counters executed in every contract, so that coverage reports can
+//! be built from them along with the source maps of the contracts. It is only compiled with the
+//! `coverage` feature, so that the executors do not check whether to record on every step
+//! otherwise.
+use ethers::types::H256;
+use std::collections::HashMap;
+
+/// A set of program counters
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn insert(&mut self, index: usize) {
+        let (word, bit) = (index / 64, index % 64);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << bit;
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words.get(index / 64).map_or(false, |word| word & (1 << (index % 64)) != 0)
+    }
+
+    /// The number of elements in the set
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    /// Adds the elements of `other` to the set
+    pub fn union_with(&mut self, other: &BitSet) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// The elements of the set, in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| index * 64 + bit)
+        })
+    }
+}
+
+/// Aggregates the program counters executed by every call, by hash of the executed code (the
+/// init code for deployments). Recording has a cost on every step, so it does nothing unless
+/// enabled.
+#[derive(Clone, Debug, Default)]
+pub struct CoverageRecorder {
+    pub enabled: bool,
+    hits: HashMap<H256, BitSet>,
+}
+
+impl CoverageRecorder {
+    /// Removes the recorded program counters
+    pub fn clear(&mut self) {
+        self.hits.clear();
+    }
+
+    /// Records the program counters executed by a frame running the code with the provided hash
+    pub fn record(&mut self, code_hash: H256, hits: &BitSet) {
+        self.hits.entry(code_hash).or_default().union_with(hits);
+    }
+
+    /// The program counters executed since the recording was enabled, by code hash
+    pub fn coverage(&self) -> &HashMap<H256, BitSet> {
+        &self.hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitset() {
+        let mut set = BitSet::default();
+        assert!(set.is_empty());
+        set.insert(3);
+        set.insert(200);
+        set.insert(3);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(3) && set.contains(200) && !set.contains(4) && !set.contains(1000));
+
+        let mut other = BitSet::default();
+        other.insert(64);
+        other.insert(3);
+        set.union_with(&other);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![3, 64, 200]);
+    }
+
+    #[test]
+    fn aggregates_calls() {
+        let mut recorder = CoverageRecorder::default();
+        let code_hash = H256::repeat_byte(1);
+        let mut first = BitSet::default();
+        first.insert(0);
+        let mut second = BitSet::default();
+        second.insert(5);
+        recorder.record(code_hash, &first);
+        recorder.record(code_hash, &second);
+        assert_eq!(recorder.coverage()[&code_hash].iter().collect::<Vec<_>>(), vec![0, 5]);
+    }
+}
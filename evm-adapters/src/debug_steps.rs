@@ -0,0 +1,108 @@
+//! Recording of the steps executed by the EVM, opcode by opcode, e.g. for an interactive
+//! debugger. The sputnik executor only records them with the `debug-steps` feature, so that it
+//! does not check whether to record on every step otherwise. The evmodin adapter records them
+//! via its tracer instead.
+use ethers::types::H256;
+use std::collections::VecDeque;
+
+/// What to record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebugConfig {
+    /// The number of items to record from the top of the stack at every step
+    pub stack_items: usize,
+    /// The number of steps to keep, the earlier ones being dropped, or `None` to keep all of
+    /// them
+    pub max_steps: Option<usize>,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self { stack_items: 4, max_steps: None }
+    }
+}
+
+/// The state of the EVM right before an opcode is executed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugStep {
+    /// The position of the opcode in the code
+    pub pc: usize,
+    pub opcode: u8,
+    /// The gas left in the frame
+    pub gas_left: u64,
+    /// The items at the top of the stack, the topmost first
+    pub stack: Vec<H256>,
+    /// The depth of the frame, the top-level call being at depth 0
+    pub depth: usize,
+    /// The index of the frame's node in the call tree, if tracing is enabled
+    pub frame: Option<usize>,
+}
+
+/// Records the steps executed during a call. Recording every step has a large cost, so it does
+/// nothing unless configured.
+#[derive(Clone, Debug, Default)]
+pub struct StepRecorder {
+    pub config: Option<DebugConfig>,
+    steps: VecDeque<DebugStep>,
+}
+
+impl StepRecorder {
+    /// Removes the recorded steps, e.g. before the next top-level call
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// Records a step, given the whole stack with its topmost item last, as Sputnik stores it
+    pub fn record(
+        &mut self,
+        pc: usize,
+        opcode: u8,
+        gas_left: u64,
+        stack: &[H256],
+        depth: usize,
+        frame: Option<usize>,
+    ) {
+        let config = match self.config {
+            Some(config) => config,
+            None => return,
+        };
+        if config.max_steps == Some(0) {
+            return
+        }
+        if Some(self.steps.len()) == config.max_steps {
+            self.steps.pop_front();
+        }
+        let stack = stack.iter().rev().take(config.stack_items).copied().collect();
+        self.steps.push_back(DebugStep { pc, opcode, gas_left, stack, depth, frame });
+    }
+
+    /// The steps recorded since the last [`StepRecorder::clear`], in execution order
+    pub fn steps(&self) -> Vec<DebugStep> {
+        self.steps.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_steps() {
+        let mut recorder = StepRecorder::default();
+        let stack = [H256::from_low_u64_be(1), H256::from_low_u64_be(2), H256::from_low_u64_be(3)];
+        // nothing is recorded unless configured
+        recorder.record(0, 0x5b, 100, &stack, 0, None);
+        assert!(recorder.steps().is_empty());
+
+        recorder.config = Some(DebugConfig { stack_items: 2, max_steps: Some(2) });
+        for pc in 0..3 {
+            recorder.record(pc, 0x5b, 100 - pc as u64, &stack, 0, Some(0));
+        }
+        let steps = recorder.steps();
+        assert_eq!(steps.iter().map(|step| step.pc).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(steps[0].stack, vec![H256::from_low_u64_be(3), H256::from_low_u64_be(2)]);
+        assert_eq!(steps[0].gas_left, 99);
+
+        recorder.clear();
+        assert!(recorder.steps().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+//! Decoding of the events which [DS-Test](https://github.com/dapphub/ds-test) emits to report
+//! assertion failures and debug values
+use dapp_utils::format_log_token;
+use ethers::{
+    abi::{ParamType, RawLog, Token},
+    types::{H256, I256, U256},
+};
+use once_cell::sync::Lazy;
+use std::{collections::BTreeMap, fmt};
+
+/// A decoded log emitted during a test
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestLogEntry {
+    /// An unnamed value, e.g. `log(string)` or `log_uint(uint256)`
+    Value(String),
+    /// A named value, e.g. `log_named_uint(string,uint256)`
+    Named { name: String, value: String },
+    /// A log which is not a DS-Test event
+    Raw(RawLog),
+}
+
+impl fmt::Display for TestLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestLogEntry::Value(value) => write!(f, "{}", value),
+            TestLogEntry::Named { name, value } => write!(f, "{}: {}", name, value),
+            TestLogEntry::Raw(log) => {
+                let topics =
+                    log.topics.iter().map(|topic| format!("{:?}", topic)).collect::<Vec<_>>();
+                write!(f, "topics: [{}], data: 0x{}", topics.join(", "), hex::encode(&log.data))
+            }
+        }
+    }
+}
+
+/// The parameter types of the DS-Test events, keyed by their topic
+static DSTEST_EVENTS: Lazy<BTreeMap<H256, Vec<ParamType>>> = Lazy::new(|| {
+    let events = vec![
+        ("log", vec![ParamType::String]),
+        ("logs", vec![ParamType::Bytes]),
+        ("log_address", vec![ParamType::Address]),
+        ("log_bytes32", vec![ParamType::FixedBytes(32)]),
+        ("log_int", vec![ParamType::Int(256)]),
+        ("log_uint", vec![ParamType::Uint(256)]),
+        ("log_bytes", vec![ParamType::Bytes]),
+        ("log_string", vec![ParamType::String]),
+        ("log_named_address", vec![ParamType::String, ParamType::Address]),
+        ("log_named_bytes32", vec![ParamType::String, ParamType::FixedBytes(32)]),
+        (
+            "log_named_decimal_int",
+            vec![ParamType::String, ParamType::Int(256), ParamType::Uint(256)],
+        ),
+        (
+            "log_named_decimal_uint",
+            vec![ParamType::String, ParamType::Uint(256), ParamType::Uint(256)],
+        ),
+        ("log_named_int", vec![ParamType::String, ParamType::Int(256)]),
+        ("log_named_uint", vec![ParamType::String, ParamType::Uint(256)]),
+        ("log_named_bytes", vec![ParamType::String, ParamType::Bytes]),
+        ("log_named_string", vec![ParamType::String, ParamType::String]),
+    ];
+    events
+        .into_iter()
+        .map(|(name, params)| {
+            let types = params.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let signature = format!("{}({})", name, types.join(","));
+            (H256::from(ethers::utils::keccak256(signature)), params)
+        })
+        .collect()
+});
+
+/// Decodes the logs emitted during a test. Logs which are not DS-Test events, or which cannot
+/// be decoded, are returned as [`TestLogEntry::Raw`].
+pub fn decode_logs(logs: &[RawLog]) -> Vec<TestLogEntry> {
+    logs.iter()
+        .map(|log| decode_log(log).unwrap_or_else(|| TestLogEntry::Raw(log.clone())))
+        .collect()
+}
+
+fn decode_log(log: &RawLog) -> Option<TestLogEntry> {
+    // all the parameters of the DS-Test events are unindexed
+    if log.topics.len() != 1 {
+        return None
+    }
+    let params = DSTEST_EVENTS.get(&log.topics[0])?;
+    let tokens = ethers::abi::decode(params, &log.data).ok()?;
+
+    let entry = match tokens.as_slice() {
+        [value] => TestLogEntry::Value(format_log_token(value)),
+        [Token::String(name), value] => {
+            TestLogEntry::Named { name: name.clone(), value: format_log_token(value) }
+        }
+        [Token::String(key), value, Token::Uint(decimals)] => {
+            let value = match value {
+                Token::Int(value) => {
+                    let value = I256::from_raw(*value);
+                    let sign = if value.is_negative() { "-" } else { "" };
+                    format!("{}{}", sign, format_decimal(value.unsigned_abs(), *decimals))
+                }
+                Token::Uint(value) => format_decimal(*value, *decimals),
+                _ => return None,
+            };
+            TestLogEntry::Named { name: key.clone(), value }
+        }
+        _ => return None,
+    };
+    Some(entry)
+}
+
+/// Formats a value scaled down by `10^decimals`, e.g. `1500` with 3 decimals as `1.500`
+fn format_decimal(value: U256, decimals: U256) -> String {
+    if decimals.is_zero() || decimals > U256::from(77) {
+        return value.to_string()
+    }
+    let decimals = decimals.as_usize();
+    let unit = U256::exp10(decimals);
+    format!("{}.{:0>width$}", value / unit, (value % unit).to_string(), width = decimals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(signature: &str, tokens: &[Token]) -> RawLog {
+        RawLog {
+            topics: vec![H256::from(ethers::utils::keccak256(signature))],
+            data: ethers::abi::encode(tokens),
+        }
+    }
+
+    #[test]
+    fn decodes_dstest_logs() {
+        let logs = vec![
+            log("log(string)", &[Token::String("Error: a == b not satisfied [uint]".to_owned())]),
+            log(
+                "log_named_uint(string,uint256)",
+                &[Token::String("  Expected".to_owned()), Token::Uint(1.into())],
+            ),
+            log(
+                "log_named_int(string,int256)",
+                &[Token::String("    Actual".to_owned()), Token::Int(I256::minus_one().into_raw())],
+            ),
+            log(
+                "log_named_decimal_uint(string,uint256,uint256)",
+                &[
+                    Token::String("amount".to_owned()),
+                    Token::Uint(1500.into()),
+                    Token::Uint(3.into()),
+                ],
+            ),
+            log(
+                "log_named_decimal_int(string,int256,uint256)",
+                &[
+                    Token::String("delta".to_owned()),
+                    Token::Int((-I256::from_raw(5.into())).into_raw()),
+                    Token::Uint(2.into()),
+                ],
+            ),
+        ];
+
+        let decoded = decode_logs(&logs).iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            decoded,
+            vec![
+                "Error: a == b not satisfied [uint]",
+                "  Expected: 1",
+                "    Actual: -1",
+                "amount: 1.500",
+                "delta: -0.05",
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_unknown_logs_through() {
+        let unknown = log("Transfer(address,address,uint256)", &[]);
+        assert_eq!(decode_logs(&[unknown.clone()]), vec![TestLogEntry::Raw(unknown)]);
+    }
+}
@@ -0,0 +1,378 @@
+//! Selection of the EVM at runtime, e.g. via a CLI flag, so that the code which uses it (e.g.
+//! the test runner) is compiled once for every EVM
+use crate::{
+    call_tracing::CallTraceNode, fuzz::FuzzDictionary, gas_report::GasReport,
+    state_diff::StateDiff, Evm, GasUsed, Interrupt, OutOfGas,
+};
+
+#[cfg(feature = "coverage")]
+use crate::coverage::BitSet;
+#[cfg(feature = "debug-steps")]
+use crate::debug_steps::{DebugConfig, DebugStep};
+#[cfg(feature = "coverage")]
+use std::collections::HashMap;
+
+use ethers::{
+    abi::RawLog,
+    types::{Address, Bytes, H256, U256},
+};
+
+use eyre::Result;
+use std::{collections::BTreeMap, marker::PhantomData};
+
+/// A value of one of two types, e.g. the return reason of either EVM of an [`EitherEvm`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// Either of two EVMs, whose states are `SL` and `SR`, which implements [`Evm`] by dispatching
+/// to the selected one. Their return reasons and snapshot ids are wrapped in [`Either`].
+///
+/// As the states of the EVMs have different types, their state cannot be reached via
+/// [`Evm::state`] and [`Evm::reset`], but via [`EitherEvm::as_left`] and
+/// [`EitherEvm::as_right`].
+#[derive(Clone, Debug)]
+pub struct EitherEvm<L, R, SL, SR> {
+    evm: Either<L, R>,
+    marker: PhantomData<(SL, SR)>,
+}
+
+impl<L, R, SL, SR> EitherEvm<L, R, SL, SR> {
+    /// Selects the first EVM
+    pub fn left(evm: L) -> Self {
+        Self { evm: Either::Left(evm), marker: PhantomData }
+    }
+
+    /// Selects the second EVM
+    pub fn right(evm: R) -> Self {
+        Self { evm: Either::Right(evm), marker: PhantomData }
+    }
+
+    /// Gets the first EVM, if it is the selected one
+    pub fn as_left(&mut self) -> Option<&mut L> {
+        match &mut self.evm {
+            Either::Left(evm) => Some(evm),
+            Either::Right(_) => None,
+        }
+    }
+
+    /// Gets the second EVM, if it is the selected one
+    pub fn as_right(&mut self) -> Option<&mut R> {
+        match &mut self.evm {
+            Either::Left(_) => None,
+            Either::Right(evm) => Some(evm),
+        }
+    }
+}
+
+/// Runs the same expression against the selected EVM, whichever it is
+macro_rules! dispatch {
+    ($self:expr, $evm:ident => $body:expr) => {
+        match $self {
+            Either::Left($evm) => $body,
+            Either::Right($evm) => $body,
+        }
+    };
+}
+
+/// Wraps the return reason of a call or deployment of the EVM selected on the given side
+macro_rules! wrap_reason {
+    ($side:path, $result:expr) => {
+        $result.map(|(output, reason, gas)| (output, $side(reason), gas))
+    };
+}
+
+impl<L: Evm<SL>, R: Evm<SR>, SL, SR> Evm<()> for EitherEvm<L, R, SL, SR> {
+    type ReturnReason = Either<L::ReturnReason, R::ReturnReason>;
+    type SnapshotId = Either<L::SnapshotId, R::SnapshotId>;
+
+    fn is_success(reason: &Self::ReturnReason) -> bool {
+        match reason {
+            Either::Left(reason) => L::is_success(reason),
+            Either::Right(reason) => R::is_success(reason),
+        }
+    }
+
+    fn is_fail(reason: &Self::ReturnReason) -> bool {
+        match reason {
+            Either::Left(reason) => L::is_fail(reason),
+            Either::Right(reason) => R::is_fail(reason),
+        }
+    }
+
+    fn initialize_contracts<I: IntoIterator<Item = (Address, Bytes)>>(&mut self, contracts: I) {
+        dispatch!(&mut self.evm, evm => evm.initialize_contracts(contracts))
+    }
+
+    fn state(&self) -> &() {
+        &()
+    }
+
+    /// Does nothing, as the state of the selected EVM is not exposed
+    fn reset(&mut self, _state: ()) {}
+
+    fn snapshot(&mut self) -> Self::SnapshotId {
+        match &mut self.evm {
+            Either::Left(evm) => Either::Left(evm.snapshot()),
+            Either::Right(evm) => Either::Right(evm.snapshot()),
+        }
+    }
+
+    fn restore(&mut self, id: Self::SnapshotId) -> Result<()> {
+        match (&mut self.evm, id) {
+            (Either::Left(evm), Either::Left(id)) => evm.restore(id),
+            (Either::Right(evm), Either::Right(id)) => evm.restore(id),
+            (_, id) => eyre::bail!("snapshot {:?} was not taken by the selected EVM", id),
+        }
+    }
+
+    fn clear_snapshots(&mut self) {
+        dispatch!(&mut self.evm, evm => evm.clear_snapshots())
+    }
+
+    fn labels(&self) -> BTreeMap<Address, String> {
+        dispatch!(&self.evm, evm => evm.labels())
+    }
+
+    fn logs(&self) -> Vec<RawLog> {
+        dispatch!(&self.evm, evm => evm.logs())
+    }
+
+    fn pending_expectations(&self) -> usize {
+        dispatch!(&self.evm, evm => evm.pending_expectations())
+    }
+
+    fn gas_used(&self) -> Option<GasUsed> {
+        dispatch!(&self.evm, evm => evm.gas_used())
+    }
+
+    fn out_of_gas(&self) -> Option<OutOfGas> {
+        dispatch!(&self.evm, evm => evm.out_of_gas())
+    }
+
+    fn set_interrupt(&mut self, interrupt: Option<Interrupt>) {
+        dispatch!(&mut self.evm, evm => evm.set_interrupt(interrupt))
+    }
+
+    fn is_interrupted(&self) -> bool {
+        dispatch!(&self.evm, evm => evm.is_interrupted())
+    }
+
+    fn set_dstest(&mut self, dstest: bool) {
+        dispatch!(&mut self.evm, evm => evm.set_dstest(dstest))
+    }
+
+    fn is_dstest(&self) -> bool {
+        dispatch!(&self.evm, evm => evm.is_dstest())
+    }
+
+    fn set_tracing_enabled(&mut self, enabled: bool) {
+        dispatch!(&mut self.evm, evm => evm.set_tracing_enabled(enabled))
+    }
+
+    fn traces(&self) -> Vec<CallTraceNode> {
+        dispatch!(&self.evm, evm => evm.traces())
+    }
+
+    fn set_gas_reporting_enabled(&mut self, enabled: bool) {
+        dispatch!(&mut self.evm, evm => evm.set_gas_reporting_enabled(enabled))
+    }
+
+    fn gas_report(&self) -> GasReport {
+        dispatch!(&self.evm, evm => evm.gas_report())
+    }
+
+    fn set_fuzz_dictionary_enabled(&mut self, enabled: bool) {
+        dispatch!(&mut self.evm, evm => evm.set_fuzz_dictionary_enabled(enabled))
+    }
+
+    fn fuzz_dictionary(&self) -> FuzzDictionary {
+        dispatch!(&self.evm, evm => evm.fuzz_dictionary())
+    }
+
+    fn set_state_diff_enabled(&mut self, enabled: bool) {
+        dispatch!(&mut self.evm, evm => evm.set_state_diff_enabled(enabled))
+    }
+
+    fn state_changes(&self) -> StateDiff {
+        dispatch!(&self.evm, evm => evm.state_changes())
+    }
+
+    #[cfg(feature = "debug-steps")]
+    fn set_debug_config(&mut self, config: Option<DebugConfig>) {
+        dispatch!(&mut self.evm, evm => evm.set_debug_config(config))
+    }
+
+    #[cfg(feature = "debug-steps")]
+    fn debug_steps(&self) -> Vec<DebugStep> {
+        dispatch!(&self.evm, evm => evm.debug_steps())
+    }
+
+    #[cfg(feature = "coverage")]
+    fn set_coverage_enabled(&mut self, enabled: bool) {
+        dispatch!(&mut self.evm, evm => evm.set_coverage_enabled(enabled))
+    }
+
+    #[cfg(feature = "coverage")]
+    fn coverage(&self) -> HashMap<H256, BitSet> {
+        dispatch!(&self.evm, evm => evm.coverage())
+    }
+
+    fn call_raw(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        is_static: bool,
+        gas_limit: Option<u64>,
+    ) -> Result<(Bytes, Self::ReturnReason, u64)> {
+        match &mut self.evm {
+            Either::Left(evm) => wrap_reason!(
+                Either::Left,
+                evm.call_raw(from, to, calldata, value, is_static, gas_limit)
+            ),
+            Either::Right(evm) => wrap_reason!(
+                Either::Right,
+                evm.call_raw(from, to, calldata, value, is_static, gas_limit)
+            ),
+        }
+    }
+
+    fn call_raw_with_access_list(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        is_static: bool,
+        gas_limit: Option<u64>,
+        access_list: Vec<(Address, Vec<H256>)>,
+    ) -> Result<(Bytes, Self::ReturnReason, u64)> {
+        match &mut self.evm {
+            Either::Left(evm) => wrap_reason!(
+                Either::Left,
+                evm.call_raw_with_access_list(
+                    from,
+                    to,
+                    calldata,
+                    value,
+                    is_static,
+                    gas_limit,
+                    access_list
+                )
+            ),
+            Either::Right(evm) => wrap_reason!(
+                Either::Right,
+                evm.call_raw_with_access_list(
+                    from,
+                    to,
+                    calldata,
+                    value,
+                    is_static,
+                    gas_limit,
+                    access_list
+                )
+            ),
+        }
+    }
+
+    fn deploy(
+        &mut self,
+        from: Address,
+        bytecode: Bytes,
+        value: U256,
+    ) -> Result<(Address, Self::ReturnReason, u64)> {
+        match &mut self.evm {
+            Either::Left(evm) => wrap_reason!(Either::Left, evm.deploy(from, bytecode, value)),
+            Either::Right(evm) => wrap_reason!(Either::Right, evm.deploy(from, bytecode, value)),
+        }
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        dispatch!(&mut self.evm, evm => evm.set_balance(address, balance))
+    }
+
+    fn get_balance(&self, address: Address) -> U256 {
+        dispatch!(&self.evm, evm => evm.get_balance(address))
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) -> Result<()> {
+        dispatch!(&mut self.evm, evm => evm.set_nonce(address, nonce))
+    }
+
+    fn get_nonce(&self, address: Address) -> U256 {
+        dispatch!(&self.evm, evm => evm.get_nonce(address))
+    }
+
+    fn get_code(&self, address: Address) -> Bytes {
+        dispatch!(&self.evm, evm => evm.get_code(address))
+    }
+
+    fn setup(&mut self, address: Address) -> Result<()> {
+        dispatch!(&mut self.evm, evm => evm.setup(address))
+    }
+
+    fn failed(&mut self, address: Address) -> Result<bool> {
+        dispatch!(&mut self.evm, evm => evm.failed(address))
+    }
+
+    fn try_check_success(
+        &mut self,
+        address: Address,
+        reason: &Self::ReturnReason,
+        should_fail: bool,
+    ) -> Result<bool> {
+        match (&mut self.evm, reason) {
+            (Either::Left(evm), Either::Left(reason)) => {
+                evm.try_check_success(address, reason, should_fail)
+            }
+            (Either::Right(evm), Either::Right(reason)) => {
+                evm.try_check_success(address, reason, should_fail)
+            }
+            (_, reason) => eyre::bail!("{:?} was not returned by the selected EVM", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(all(feature = "sputnik", feature = "evmodin"))]
+    fn runs_tests_on_either_evm() {
+        use super::*;
+        use crate::{
+            evmodin::{CallExecutingHost, EvmOdin},
+            sputnik::{
+                helpers::{new_backend, new_vicinity},
+                Executor,
+            },
+            test_helpers::{solidity_unit_test, COMPILED},
+        };
+        use evmodin::{tracing::NoopTracer, util::mocked_host::MockedHost, Revision};
+        use sputnik::Config;
+
+        let compiled = COMPILED.get("GreeterTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let cfg = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+
+        let host = CallExecutingHost::new(MockedHost::default(), Revision::Istanbul);
+        let mut evms = vec![
+            EitherEvm::left(Executor::new(12_000_000, &cfg, &backend)),
+            EitherEvm::right(EvmOdin::new(host, 12_000_000, Revision::Istanbul, NoopTracer)),
+        ];
+
+        // the snapshots of an EVM cannot be restored by the other
+        let snapshot = evms[0].snapshot();
+        assert!(evms[1].restore(snapshot).is_err());
+
+        // the same code runs against both
+        for evm in evms {
+            solidity_unit_test(evm, addr, compiled);
+        }
+    }
+}
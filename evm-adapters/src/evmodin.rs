@@ -1,10 +1,31 @@
-use crate::Evm;
+use crate::{
+    call_tracing::{self, CallTraceNode, CallTracer},
+    debug_steps::{DebugConfig, DebugStep, StepRecorder},
+    hevm::{
+        apply_state_cheatcode, encode_error, prank_caller, HevmCall, HevmState, Prank,
+        CHEATCODE_STUB, HEVM_ABI,
+    },
+    state_diff::{AccountDiff, Change, StateDiff},
+    Evm, GasUsed, Interrupt, CHEATCODE_ADDRESS,
+};
 
-use ethers::types::{Address, Bytes, U256};
+use ethers::{
+    abi::RawLog,
+    types::{Address, Bytes, H256, U256},
+};
 
-use evmodin::{tracing::Tracer, AnalyzedCode, CallKind, Host, Message, Revision, StatusCode};
+use evmodin::{
+    host::{AccessStatus, StorageStatus, TxContext},
+    tracing::{NoopTracer, Tracer},
+    AnalyzedCode, CallKind, ExecutionState, Host, Message, OpCode, Output, Revision, StatusCode,
+};
 
 use eyre::Result;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+};
 
 // TODO: Check if we can implement this as the base layer of an ethers-provider
 // Middleware stack instead of doing RPC calls.
@@ -15,84 +36,1184 @@ pub struct EvmOdin<S, T> {
     pub call_kind: Option<CallKind>,
     pub revision: Revision,
     pub tracer: T,
+    /// The hosts snapshotted via `snapshot`, in the order they were taken
+    snapshots: Vec<S>,
+    /// The gas used by the last call
+    gas_used: Option<GasUsed>,
+    /// The logs emitted by the last call, if it succeeded
+    logs: Vec<RawLog>,
+    /// The changes made to the state by the last call, if their recording is enabled
+    state_changes: Option<StateDiff>,
+    /// Makes the calls fail once raised, see [`Evm::set_interrupt`]
+    interrupt: Option<Interrupt>,
+    /// Whether `failed()` is called on the test contracts, see [`Evm::set_dstest`]
+    dstest: bool,
 }
 
 impl<S: Host, T: Tracer> EvmOdin<S, T> {
     /// Given a gas limit, vm revision, and initialized host state
     pub fn new(host: S, gas_limit: u64, revision: Revision, tracer: T) -> Self {
-        Self { host, gas_limit, revision, tracer, call_kind: None }
+        Self {
+            host,
+            gas_limit,
+            revision,
+            tracer,
+            call_kind: None,
+            snapshots: Vec::new(),
+            gas_used: None,
+            logs: Vec::new(),
+            state_changes: None,
+            interrupt: None,
+            dstest: false,
+        }
+    }
+
+    /// Accounts for the intrinsic cost of a transaction, which evmodin does not charge, given
+    /// the gas used by its execution, and applies its refunds, which are capped to half of the
+    /// gas used (a fifth from London on) like on Sputnik
+    fn adjust_gas(&self, base: u64, execution: u64, refund: i64, data: &[u8]) -> GasUsed {
+        let non_zero_byte_cost = if self.revision >= Revision::Istanbul {
+            dapp_utils::NON_ZERO_BYTE_COST
+        } else {
+            dapp_utils::NON_ZERO_BYTE_COST_FRONTIER
+        };
+        let intrinsic = base + dapp_utils::calldata_cost(data, non_zero_byte_cost);
+        let raw = execution + intrinsic;
+        let max_refund = if self.revision >= Revision::London { raw / 5 } else { raw / 2 };
+        let refunded = (refund.max(0) as u64).min(max_refund);
+        GasUsed { raw, adjusted: execution.saturating_sub(refunded) }
+    }
+}
+
+/// The base cost of a call transaction
+const GAS_TRANSACTION_CALL: u64 = 21000;
+
+/// The base cost of a deployment transaction, from Homestead on
+const GAS_TRANSACTION_CREATE: u64 = 53000;
+
+/// Helper trait for exposing additional functionality over EVMOdin Hosts
+pub trait HostExt: Host {
+    /// Gets the bytecode at the specified address. `None` if the specified address
+    /// is not a contract account.
+    fn get_code(&self, address: &Address) -> Option<&bytes::Bytes>;
+    /// Sets the bytecode at the specified address to the provided value, keeping its storage.
+    fn set_code(&mut self, address: Address, code: bytes::Bytes);
+    /// Gets the nonce of the specified address
+    fn get_nonce(&self, address: &Address) -> u64;
+    /// Sets the nonce of the specified address to the provided value.
+    fn set_nonce(&mut self, address: Address, nonce: u64);
+    /// Sets the balance of the specified address to the provided value.
+    fn set_balance(&mut self, address: Address, balance: U256);
+    /// Notifies the host that a top-level call to (or deployment at) the specified address is
+    /// about to be executed against it, i.e. that a new transaction starts. Does nothing by
+    /// default.
+    fn start_call(&mut self, _destination: Address) {}
+    /// Takes the logs emitted since the last time they were taken. Hosts which do not record
+    /// logs return none.
+    fn take_logs(&mut self) -> Vec<RawLog> {
+        Vec::new()
+    }
+    /// Sets the revision of the calls which the host executes itself. Does nothing by default.
+    fn set_revision(&mut self, _revision: Revision) {}
+    /// Sets the context of the transaction and of its block, e.g. `block.timestamp`
+    fn set_tx_context(&mut self, context: TxContext);
+    /// Gets the addresses of the accounts which exist
+    fn addresses(&self) -> Vec<Address>;
+    /// Gets the storage slots of the specified address which hold a non-zero value or were
+    /// written since the start of the current top-level call, along with their value
+    fn storage(&self, address: &Address) -> BTreeMap<H256, StorageSlot>;
+}
+
+/// A storage slot of an account, as tracked by a [`HostExt`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageSlot {
+    pub value: H256,
+    /// Whether the slot was written since the start of the current top-level call, in which
+    /// case its value may differ from the one it had then
+    pub dirty: bool,
+}
+
+/// The changes between two states of a host, see [`StateDiff`]. The accounts which no longer
+/// exist are the ones which self-destructed.
+fn state_diff<S: HostExt>(before: &S, after: &S) -> StateDiff {
+    let addresses =
+        before.addresses().into_iter().chain(after.addresses()).collect::<BTreeSet<_>>();
+    let mut diff = StateDiff::new();
+    for address in addresses {
+        let mut account = AccountDiff {
+            destroyed: before.account_exists(address) && !after.account_exists(address),
+            ..Default::default()
+        };
+
+        let (old, new) = (before.get_balance(address), after.get_balance(address));
+        if old != new {
+            account.balance = Some(Change { from: old, to: new });
+        }
+        let (old, new) = (before.get_nonce(&address), after.get_nonce(&address));
+        if old != new {
+            account.nonce = Some(Change { from: old.into(), to: new.into() });
+        }
+        let code = |host: &S| host.get_code(&address).cloned().unwrap_or_default();
+        let (old, new) = (code(before), code(after));
+        if old != new {
+            account.code = Some(Change { from: old.into(), to: new.into() });
+        }
+        // only the slots written by the call may have changed, unless the account was
+        // destroyed along with its storage
+        let destroyed = account.destroyed;
+        let written = after.storage(&address).into_iter().filter(|(_, slot)| slot.dirty);
+        let cleared = before.storage(&address).into_iter().filter(|_| destroyed);
+        for (slot, _) in written.chain(cleared) {
+            let (from, to) = (before.get_storage(address, slot), after.get_storage(address, slot));
+            if from != to {
+                account.storage.insert(slot, Change { from, to });
+            }
+        }
+
+        if !account.is_empty() {
+            diff.insert(address, account);
+        }
+    }
+    diff
+}
+
+/// The opcodes introduced after Frontier, along with the revision which introduced them
+const NEW_OPCODES: &[(u8, &str, Revision)] = &[
+    (0xf4, "DELEGATECALL", Revision::Homestead),
+    (0x3d, "RETURNDATASIZE", Revision::Byzantium),
+    (0x3e, "RETURNDATACOPY", Revision::Byzantium),
+    (0xfa, "STATICCALL", Revision::Byzantium),
+    (0xfd, "REVERT", Revision::Byzantium),
+    (0x1b, "SHL", Revision::Constantinople),
+    (0x1c, "SHR", Revision::Constantinople),
+    (0x1d, "SAR", Revision::Constantinople),
+    (0x3f, "EXTCODEHASH", Revision::Constantinople),
+    (0xf5, "CREATE2", Revision::Constantinople),
+    (0x46, "CHAINID", Revision::Istanbul),
+    (0x47, "SELFBALANCE", Revision::Istanbul),
+    (0x48, "BASEFEE", Revision::London),
+];
+
+/// Fails if the code contains an opcode which is not available in the revision, naming it.
+/// The push data is skipped, but the data appended to the code (e.g. the metadata) is not, so
+/// it is only meant to explain an `UndefinedInstruction` status.
+fn check_opcodes(code: &[u8], revision: Revision) -> Result<()> {
+    let mut pc = 0;
+    while let Some(&opcode) = code.get(pc) {
+        if let Some((_, name, since)) =
+            NEW_OPCODES.iter().find(|(op, _, since)| *op == opcode && *since > revision)
+        {
+            eyre::bail!(
+                "`{}` (0x{:02x}) at pc {} is not available before {:?}, the code ran with {:?}",
+                name,
+                opcode,
+                pc,
+                since,
+                revision
+            )
+        }
+        // PUSH1..PUSH32 are followed by their data
+        pc += match opcode {
+            0x60..=0x7f => (opcode - 0x5f) as usize + 1,
+            _ => 1,
+        };
+    }
+    Ok(())
+}
+
+/// The maximum depth of nested calls
+const CALL_DEPTH_LIMIT: i32 = 1024;
+
+/// The cost of every byte of the code deployed by a contract creation
+const GAS_CODE_DEPOSIT: i64 = 200;
+
+/// A host which executes the calls and deployments made by the contracts, against the state of
+/// the wrapped host, instead of letting it answer them (e.g. `MockedHost` returns canned
+/// outputs). The changes made by the frames which fail are rolled back, by restoring a copy
+/// of the wrapped host taken before them, so it should be cheap to clone.
+///
+/// The calls to the cheatcode address are intercepted, and the cheatcodes supported by
+/// [`apply_state_cheatcode`] applied. The others revert. Precompiles are not supported: calls
+/// to them are calls to accounts without code.
+#[derive(Clone, Debug)]
+pub struct CallExecutingHost<S> {
+    pub host: S,
+    pub revision: Revision,
+    /// The overrides set via cheatcodes
+    pub cheats: HostCheats,
+    /// The accounts in whose context the frames which are currently executing run, the
+    /// innermost last, so that `DELEGATECALL` and `CALLCODE` act on the caller's storage
+    contexts: Vec<Address>,
+    /// The logs emitted by the frames which have not failed, in the order they were emitted
+    logs: Vec<RawLog>,
+    /// The tracer the frames executed by the host are reported to, if any
+    tracer: Option<SharedTracer>,
+}
+
+/// The state of a [`CallExecutingHost`] before a frame, which is restored if the frame fails
+#[derive(Clone)]
+struct Checkpoint<S> {
+    host: S,
+    /// The number of logs emitted before the frame
+    logs: usize,
+}
+
+/// The overrides which the cheatcodes applied by a [`CallExecutingHost`] can set. Unlike the
+/// state, they are not rolled back when the frame which set them fails. The block environment
+/// set via `warp` and `roll` is set on the wrapped host, and is not rolled back either.
+#[derive(Clone, Debug, Default)]
+pub struct HostCheats {
+    /// The sender override for the next calls, if any
+    pub prank: Option<Prank>,
+}
+
+impl<S: HostExt + Clone> CallExecutingHost<S> {
+    pub fn new(host: S, revision: Revision) -> Self {
+        Self {
+            host,
+            revision,
+            cheats: Default::default(),
+            contexts: Vec::new(),
+            logs: Vec::new(),
+            tracer: None,
+        }
+    }
+
+    /// Reports the frames executed by the host to the tracer, which should be shared with the
+    /// [`EvmOdin`] executing the top-level calls, so that they are recorded in its call tree
+    pub fn with_tracer(mut self, tracer: SharedTracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    fn checkpoint(&self) -> Checkpoint<S> {
+        Checkpoint { host: self.host.clone(), logs: self.logs.len() }
+    }
+
+    /// Rolls back the changes made since the checkpoint, including the logs emitted, but
+    /// keeping the block environment
+    fn revert_to(&mut self, checkpoint: Checkpoint<S>) {
+        let context = self.host.get_tx_context();
+        self.host = checkpoint.host;
+        self.host.set_tx_context(context);
+        self.logs.truncate(checkpoint.logs);
+    }
+
+    /// Applies a call to the cheatcode address, which uses no gas
+    fn apply_cheatcode(&mut self, message: &Message) -> Output {
+        let revert = |reason: &str| Output {
+            status_code: StatusCode::Revert,
+            gas_left: message.gas,
+            output_data: encode_error(reason).into(),
+            gas_refund: 0,
+            create_address: None,
+        };
+        let call = match HevmCall::decode(&message.input_data) {
+            Ok(call) => call,
+            Err(err) => return revert(&err.to_string()),
+        };
+        // the interpreter passes the static flag on to the calls made from static frames
+        if message.is_static && !call.is_read_only() {
+            return revert("cannot use state-changing cheatcode in static context")
+        }
+        match apply_state_cheatcode(self, &call, message.sender, message.depth as usize) {
+            Some(Ok(retdata)) => Output {
+                status_code: StatusCode::Success,
+                gas_left: message.gas,
+                output_data: retdata.into(),
+                gas_refund: 0,
+                create_address: None,
+            },
+            Some(Err(reason)) => revert(&reason),
+            None => {
+                let func = HEVM_ABI
+                    .functions()
+                    .find(|func| message.input_data.starts_with(&func.short_signature()))
+                    .expect("decoded cheatcodes are in the ABI");
+                revert(&format!("`{}` is not supported by evmodin", func.signature()))
+            }
+        }
+    }
+
+    /// Moves `value` from an account to another, returning false if the sender cannot afford it
+    fn transfer(&mut self, from: Address, to: Address, value: U256) -> bool {
+        let balance = self.host.get_balance(from);
+        if balance < value {
+            return false
+        }
+        self.host.set_balance(from, balance - value);
+        let balance = self.host.get_balance(to);
+        self.host.set_balance(to, balance + value);
+        true
+    }
+
+    /// Executes the code in the context of the message's destination, rolling back the changes
+    /// it made if it failed
+    fn execute(&mut self, code: &[u8], message: Message, checkpoint: Checkpoint<S>) -> Output {
+        self.contexts.push(message.destination);
+        let revision = self.revision;
+        let code = AnalyzedCode::analyze(code);
+        let output = match self.tracer.clone() {
+            Some(mut tracer) => code.execute(self, &mut tracer, None, message, revision),
+            None => code.execute(self, &mut NoopTracer, None, message, revision),
+        };
+        self.contexts.pop();
+
+        if !matches!(output.status_code, StatusCode::Success) {
+            self.revert_to(checkpoint);
+        }
+        output
+    }
+
+    fn call_inner(&mut self, message: &Message) -> Output {
+        // the interpreter makes sure that the calls are not static when they transfer value
+        let checkpoint = self.checkpoint();
+        // `DELEGATECALL` and `CALLCODE` execute the code of the destination in the caller's
+        // context, the interpreter already routes the sender and value of `DELEGATECALL`
+        let context = match message.kind {
+            CallKind::DelegateCall | CallKind::CallCode => {
+                self.contexts.last().copied().unwrap_or(message.sender)
+            }
+            _ => message.destination,
+        };
+        if !matches!(message.kind, CallKind::DelegateCall) &&
+            !self.transfer(message.sender, context, message.value)
+        {
+            return failure(StatusCode::InsufficientBalance)
+        }
+
+        let code = self.host.get_code(&message.destination).cloned().unwrap_or_default();
+        if code.is_empty() {
+            return Output {
+                status_code: StatusCode::Success,
+                gas_left: message.gas,
+                output_data: Default::default(),
+                gas_refund: 0,
+                create_address: None,
+            }
+        }
+        #[allow(deprecated)]
+        let message = Message {
+            sender: message.sender,
+            destination: context,
+            depth: message.depth,
+            kind: message.kind,
+            input_data: message.input_data.clone(),
+            value: message.value,
+            gas: message.gas,
+            is_static: message.is_static,
+        };
+        self.execute(&code, message, checkpoint)
+    }
+
+    fn create(&mut self, message: &Message) -> Output {
+        // the message's input is the init code
+        let (sender, init_code) = (message.sender, &message.input_data);
+        let nonce = self.host.get_nonce(&sender);
+        let address = match message.kind {
+            CallKind::Create2 { salt } => ethers::utils::get_create2_address(
+                sender,
+                salt.as_bytes().to_vec(),
+                init_code.to_vec(),
+            ),
+            _ => ethers::utils::get_contract_address(sender, nonce),
+        };
+        // the sender's nonce is increased even if the deployment fails
+        self.host.set_nonce(sender, nonce + 1);
+
+        let checkpoint = self.checkpoint();
+        let occupied = self.host.get_code(&address).map_or(false, |code| !code.is_empty());
+        if occupied || self.host.get_nonce(&address) != 0 {
+            return failure(StatusCode::Failure)
+        }
+        if !self.transfer(sender, address, message.value) {
+            return failure(StatusCode::InsufficientBalance)
+        }
+        // the account must exist for the constructor to write to its storage
+        self.host.set_code(address, Default::default());
+        if self.revision >= Revision::Spurious {
+            self.host.set_nonce(address, 1);
+        }
+
+        #[allow(deprecated)]
+        let init_message = Message {
+            sender,
+            destination: address,
+            depth: message.depth,
+            kind: message.kind,
+            input_data: Default::default(),
+            value: message.value,
+            gas: message.gas,
+            is_static: false,
+        };
+        let mut output = self.execute(init_code, init_message, checkpoint.clone());
+        if !matches!(output.status_code, StatusCode::Success) {
+            return output
+        }
+
+        let deposit = output.output_data.len() as i64 * GAS_CODE_DEPOSIT;
+        if output.gas_left < deposit {
+            self.revert_to(checkpoint);
+            return failure(StatusCode::OutOfGas)
+        }
+        self.host.set_code(address, std::mem::take(&mut output.output_data));
+        Output { gas_left: output.gas_left - deposit, create_address: Some(address), ..output }
+    }
+}
+
+/// The output of a frame which failed before or while executing, consuming all of its gas
+fn failure(status_code: StatusCode) -> Output {
+    Output {
+        status_code,
+        gas_left: 0,
+        output_data: Default::default(),
+        gas_refund: 0,
+        create_address: None,
+    }
+}
+
+impl<S: HostExt + Clone> Host for CallExecutingHost<S> {
+    fn account_exists(&self, address: Address) -> bool {
+        self.host.account_exists(address)
+    }
+
+    fn get_storage(&self, address: Address, key: H256) -> H256 {
+        self.host.get_storage(address, key)
+    }
+
+    fn set_storage(&mut self, address: Address, key: H256, value: H256) -> StorageStatus {
+        self.host.set_storage(address, key, value)
+    }
+
+    fn get_balance(&self, address: Address) -> U256 {
+        self.host.get_balance(address)
+    }
+
+    fn get_code_size(&self, address: Address) -> U256 {
+        if address == *CHEATCODE_ADDRESS {
+            return CHEATCODE_STUB.len().into()
+        }
+        self.host.get_code_size(address)
+    }
+
+    fn get_code_hash(&self, address: Address) -> H256 {
+        if address == *CHEATCODE_ADDRESS {
+            return H256::from(ethers::utils::keccak256(CHEATCODE_STUB))
+        }
+        self.host.get_code_hash(address)
+    }
+
+    fn copy_code(&self, address: Address, offset: usize, buffer: &mut [u8]) -> usize {
+        if address == *CHEATCODE_ADDRESS {
+            let code = CHEATCODE_STUB.get(offset..).unwrap_or_default();
+            let len = code.len().min(buffer.len());
+            buffer[..len].copy_from_slice(&code[..len]);
+            return len
+        }
+        self.host.copy_code(address, offset, buffer)
+    }
+
+    fn selfdestruct(&mut self, address: Address, beneficiary: Address) {
+        self.host.selfdestruct(address, beneficiary)
+    }
+
+    fn call(&mut self, message: &Message) -> Output {
+        if message.depth > CALL_DEPTH_LIMIT {
+            return failure(StatusCode::CallDepthExceeded)
+        }
+        if message.destination == *CHEATCODE_ADDRESS {
+            return self.apply_cheatcode(message)
+        }
+
+        let sender = prank_caller(&mut self.cheats.prank, message.sender, message.depth as usize);
+        #[allow(deprecated)]
+        let message = &Message {
+            sender,
+            destination: message.destination,
+            depth: message.depth,
+            kind: message.kind,
+            input_data: message.input_data.clone(),
+            value: message.value,
+            gas: message.gas,
+            is_static: message.is_static,
+        };
+        match message.kind {
+            CallKind::Create | CallKind::Create2 { .. } => self.create(message),
+            _ => self.call_inner(message),
+        }
+    }
+
+    fn get_tx_context(&self) -> TxContext {
+        self.host.get_tx_context()
+    }
+
+    fn get_block_hash(&self, block_number: u64) -> H256 {
+        self.host.get_block_hash(block_number)
+    }
+
+    fn emit_log(&mut self, _address: Address, data: &[u8], topics: &[H256]) {
+        // recorded here rather than by the wrapped host, so that they can be rolled back
+        self.logs.push(RawLog { topics: topics.to_vec(), data: data.to_vec() });
+    }
+
+    fn access_account(&mut self, address: Address) -> AccessStatus {
+        self.host.access_account(address)
+    }
+
+    fn access_storage(&mut self, address: Address, key: H256) -> AccessStatus {
+        self.host.access_storage(address, key)
+    }
+}
+
+impl<S: HostExt + Clone> HostExt for CallExecutingHost<S> {
+    fn get_code(&self, address: &Address) -> Option<&bytes::Bytes> {
+        self.host.get_code(address)
+    }
+
+    fn set_code(&mut self, address: Address, code: bytes::Bytes) {
+        self.host.set_code(address, code)
+    }
+
+    fn get_nonce(&self, address: &Address) -> u64 {
+        self.host.get_nonce(address)
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) {
+        self.host.set_nonce(address, nonce)
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.host.set_balance(address, balance)
+    }
+
+    fn start_call(&mut self, destination: Address) {
+        self.contexts = vec![destination];
+        self.host.start_call(destination)
+    }
+
+    fn take_logs(&mut self) -> Vec<RawLog> {
+        std::mem::take(&mut self.logs)
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision;
+        self.host.set_revision(revision)
+    }
+
+    fn set_tx_context(&mut self, context: TxContext) {
+        self.host.set_tx_context(context)
+    }
+
+    fn addresses(&self) -> Vec<Address> {
+        self.host.addresses()
+    }
+
+    fn storage(&self, address: &Address) -> BTreeMap<H256, StorageSlot> {
+        self.host.storage(address)
+    }
+}
+
+impl<S: HostExt + Clone> HevmState for CallExecutingHost<S> {
+    fn warp(&mut self, timestamp: U256) {
+        let mut context = self.host.get_tx_context();
+        context.block_timestamp = timestamp.low_u64();
+        self.host.set_tx_context(context);
+    }
+
+    fn roll(&mut self, number: U256) {
+        let mut context = self.host.get_tx_context();
+        context.block_number = number.low_u64();
+        self.host.set_tx_context(context);
+    }
+
+    fn store(&mut self, who: Address, slot: H256, value: H256) {
+        if !self.host.account_exists(who) {
+            self.host.set_balance(who, 0.into());
+        }
+        self.host.set_storage(who, slot, value);
+    }
+
+    fn load(&self, who: Address, slot: H256) -> H256 {
+        self.host.get_storage(who, slot)
+    }
+
+    fn deal(&mut self, who: Address, balance: U256) {
+        self.host.set_balance(who, balance);
+    }
+
+    fn etch(&mut self, who: Address, code: Vec<u8>) {
+        self.host.set_code(who, code.into());
+    }
+
+    fn prank_mut(&mut self) -> &mut Option<Prank> {
+        &mut self.cheats.prank
+    }
+}
+
+impl<S: HostExt + Clone, Tr: Tracer> Evm<S> for EvmOdin<S, Tr> {
+    type ReturnReason = StatusCode;
+    type SnapshotId = usize;
+
+    fn is_success(reason: &Self::ReturnReason) -> bool {
+        matches!(reason, StatusCode::Success)
+    }
+
+    fn is_fail(reason: &Self::ReturnReason) -> bool {
+        matches!(reason, StatusCode::Revert)
+    }
+
+    fn reset(&mut self, state: S) {
+        self.host = state;
+    }
+
+    fn snapshot(&mut self) -> usize {
+        self.snapshots.push(self.host.clone());
+        self.snapshots.len() - 1
+    }
+
+    fn restore(&mut self, id: usize) -> Result<()> {
+        let host =
+            self.snapshots.get(id).ok_or_else(|| eyre::eyre!("no snapshot with id {}", id))?;
+        self.host = host.clone();
+        self.snapshots.truncate(id + 1);
+        Ok(())
+    }
+
+    fn clear_snapshots(&mut self) {
+        self.snapshots.clear();
+    }
+
+    fn initialize_contracts<I: IntoIterator<Item = (Address, Bytes)>>(&mut self, contracts: I) {
+        contracts.into_iter().for_each(|(address, bytecode)| {
+            self.host.set_code(address, bytecode.0);
+        })
+    }
+
+    fn state(&self) -> &S {
+        &self.host
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.host.set_balance(address, balance)
+    }
+
+    fn get_balance(&self, address: Address) -> U256 {
+        self.host.get_balance(address)
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) -> Result<()> {
+        self.host.set_nonce(address, nonce);
+        Ok(())
+    }
+
+    fn get_nonce(&self, address: Address) -> U256 {
+        self.host.get_nonce(&address).into()
+    }
+
+    fn get_code(&self, address: Address) -> Bytes {
+        self.host.get_code(&address).cloned().unwrap_or_default().into()
+    }
+
+    fn logs(&self) -> Vec<RawLog> {
+        self.logs.clone()
+    }
+
+    fn set_state_diff_enabled(&mut self, enabled: bool) {
+        self.state_changes = if enabled { Some(StateDiff::default()) } else { None };
+    }
+
+    fn state_changes(&self) -> StateDiff {
+        self.state_changes.clone().unwrap_or_default()
+    }
+
+    fn gas_used(&self) -> Option<GasUsed> {
+        self.gas_used
+    }
+
+    fn set_interrupt(&mut self, interrupt: Option<Interrupt>) {
+        // evmodin cannot stop the call being executed, the calls made afterwards are stopped
+        self.interrupt = interrupt;
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.interrupt.as_ref().map_or(false, Interrupt::is_raised)
+    }
+
+    fn set_dstest(&mut self, dstest: bool) {
+        self.dstest = dstest;
+    }
+
+    fn is_dstest(&self) -> bool {
+        self.dstest
+    }
+
+    /// Runs the selected function. Calls whose code uses an opcode which is not available in
+    /// the revision fail with an error naming it, rather than with `UndefinedInstruction`.
+    fn call_raw(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        is_static: bool,
+        gas_limit: Option<u64>,
+    ) -> Result<(Bytes, Self::ReturnReason, u64)> {
+        let kind = self.call_kind.unwrap_or(CallKind::Call);
+        self.execute_call(from, to, to, calldata, value, is_static, gas_limit, kind)
+    }
+
+    fn deploy(
+        &mut self,
+        from: Address,
+        bytecode: Bytes,
+        value: U256,
+    ) -> Result<(Address, Self::ReturnReason, u64)> {
+        let address = ethers::utils::get_contract_address(from, self.host.get_nonce(&from));
+        self.create(from, address, CallKind::Create, bytecode, value)
+    }
+}
+
+impl<S: HostExt + Clone, Tr: Tracer> EvmOdin<S, Tr> {
+    /// Like [`Evm::deploy`], but deploys at the address derived from the deployer, the salt and
+    /// the hash of the init code, as via `CREATE2`
+    pub fn deploy2(
+        &mut self,
+        from: Address,
+        bytecode: Bytes,
+        value: U256,
+        salt: H256,
+    ) -> Result<(Address, StatusCode, u64)> {
+        let address =
+            ethers::utils::get_create2_address(from, salt.as_bytes().to_vec(), bytecode.to_vec());
+        self.create(from, address, CallKind::Create2 { salt }, bytecode, value)
+    }
+
+    /// Like [`Evm::call_raw`], but executes the code of `code_address` via the provided kind of
+    /// call. `DelegateCall`s and `CallCode`s run it against the storage and balance of `to`, as
+    /// if `to` made the call, e.g. to test the implementation behind a proxy. `DelegateCall`s
+    /// transfer no value, but `msg.value` is the provided one.
+    pub fn call_with_kind(
+        &mut self,
+        from: Address,
+        to: Address,
+        code_address: Address,
+        calldata: Bytes,
+        value: U256,
+        kind: CallKind,
+    ) -> Result<(Bytes, StatusCode, u64)> {
+        self.execute_call(from, to, code_address, calldata, value, false, None, kind)
+    }
+
+    /// Discards the logs of the previous call, including the ones the host still holds
+    fn clear_logs(&mut self) {
+        self.logs.clear();
+        self.host.take_logs();
+    }
+
+    /// Takes the logs emitted by a call from the host, unless it failed
+    fn collect_logs(&mut self, output: &Output) {
+        let logs = self.host.take_logs();
+        if matches!(output.status_code, StatusCode::Success) {
+            self.logs = logs;
+        }
+    }
+
+    /// Sets `block.timestamp` for the calls executed from now on
+    pub fn set_block_timestamp(&mut self, timestamp: u64) {
+        self.update_tx_context(|context| context.block_timestamp = timestamp)
+    }
+
+    /// Sets `block.number` for the calls executed from now on
+    pub fn set_block_number(&mut self, number: u64) {
+        self.update_tx_context(|context| context.block_number = number)
+    }
+
+    /// Sets `block.coinbase` for the calls executed from now on
+    pub fn set_block_coinbase(&mut self, coinbase: Address) {
+        self.update_tx_context(|context| context.block_coinbase = coinbase)
+    }
+
+    /// Sets `block.chainid` for the calls executed from now on
+    pub fn set_chain_id(&mut self, chain_id: U256) {
+        self.update_tx_context(|context| context.chain_id = chain_id)
+    }
+
+    /// Sets `block.basefee` for the calls executed from now on
+    pub fn set_block_base_fee(&mut self, base_fee: U256) {
+        self.update_tx_context(|context| context.block_base_fee = base_fee)
+    }
+
+    /// Sets `block.difficulty` for the calls executed from now on
+    pub fn set_block_difficulty(&mut self, difficulty: U256) {
+        self.update_tx_context(|context| context.block_difficulty = difficulty)
+    }
+
+    /// Sets `tx.gasprice` for the calls executed from now on
+    pub fn set_gas_price(&mut self, gas_price: U256) {
+        self.update_tx_context(|context| context.tx_gas_price = gas_price)
+    }
+
+    fn update_tx_context(&mut self, update: impl FnOnce(&mut TxContext)) {
+        let mut context = self.host.get_tx_context();
+        update(&mut context);
+        self.host.set_tx_context(context);
+    }
+
+    /// Sets the revision of the calls and deployments executed from now on, including the
+    /// ones the host executes itself
+    pub fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision;
+        self.host.set_revision(revision);
+    }
+
+    /// Like [`Evm::call_raw`], but executes the call with the provided revision instead of the
+    /// adapter's, e.g. to compare the gas used across forks
+    pub fn call_with_revision(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        revision: Revision,
+    ) -> Result<(Bytes, StatusCode, u64)> {
+        let previous = self.revision;
+        self.set_revision(revision);
+        let result = self.call_raw(from, to, calldata, value, false, None);
+        self.set_revision(previous);
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn execute_call(
+        &mut self,
+        from: Address,
+        to: Address,
+        code_address: Address,
+        calldata: Bytes,
+        value: U256,
+        is_static: bool,
+        gas_limit: Option<u64>,
+        kind: CallKind,
+    ) -> Result<(Bytes, StatusCode, u64)> {
+        if self.is_interrupted() {
+            self.gas_used = None;
+            self.state_changes = self.state_changes.as_ref().map(|_| StateDiff::new());
+            return Ok((Bytes::default(), StatusCode::Failure, 0))
+        }
+        let gas_limit = gas_limit.unwrap_or(self.gas_limit);
+        // For the `func.constant` field usage
+        #[allow(deprecated)]
+        let message = Message {
+            sender: from,
+            destination: to,
+            // What should this be?
+            depth: 0,
+            kind,
+            input_data: calldata.0.clone(),
+            value,
+            gas: gas_limit as i64,
+            is_static,
+        };
+
+        // get the bytecode at the host
+        let code = self.host.get_code(&code_address).cloned().ok_or_else(|| {
+            eyre::eyre!("there should be a smart contract at the destination address")
+        })?;
+        let bytecode = AnalyzedCode::analyze(code.as_ref());
+
+        // the value is transferred before the execution, and back if it fails. delegated calls
+        // keep the value of the call which delegated, without transferring it again.
+        self.clear_logs();
+        let snapshot = self.host.clone();
+        if !matches!(kind, CallKind::DelegateCall) {
+            let balance = self.host.get_balance(from);
+            if balance < value {
+                self.gas_used = None;
+                self.state_changes = self.state_changes.as_ref().map(|_| StateDiff::new());
+                return Ok((Bytes::default(), StatusCode::InsufficientBalance, 0))
+            }
+            self.host.set_balance(from, balance - value);
+            let balance = self.host.get_balance(to);
+            self.host.set_balance(to, balance + value);
+        }
+
+        self.host.start_call(to);
+        let output =
+            bytecode.execute(&mut self.host, &mut self.tracer, None, message, self.revision);
+        // the changes of the calls which fail are all rolled back
+        let success = matches!(output.status_code, StatusCode::Success);
+        if self.state_changes.is_some() {
+            let changes =
+                if success { state_diff(&snapshot, &self.host) } else { StateDiff::new() };
+            self.state_changes = Some(changes);
+        }
+        if !success {
+            self.host = snapshot;
+        }
+        self.collect_logs(&output);
+        if matches!(output.status_code, StatusCode::UndefinedInstruction) {
+            check_opcodes(&code, self.revision)?;
+        }
+
+        // evmodin doesn't take the intrinsic cost of the transaction into account
+        let execution = gas_limit - output.gas_left as u64;
+        let gas =
+            self.adjust_gas(GAS_TRANSACTION_CALL, execution, output.gas_refund, calldata.as_ref());
+        self.gas_used = Some(gas);
+
+        Ok((output.output_data.to_vec().into(), output.status_code, gas.adjusted))
+    }
+
+    /// Runs the init code and stores the code it returns at `address`. Failed deployments only
+    /// increase the nonce of the deployer, the changes made by the init code are rolled back.
+    fn create(
+        &mut self,
+        from: Address,
+        address: Address,
+        kind: CallKind,
+        bytecode: Bytes,
+        value: U256,
+    ) -> Result<(Address, StatusCode, u64)> {
+        let before = self.state_changes.as_ref().map(|_| self.host.clone());
+        let nonce = self.host.get_nonce(&from);
+        self.host.set_nonce(from, nonce + 1);
+
+        let occupied = self.host.get_code(&address).map_or(false, |code| !code.is_empty());
+        if occupied || self.host.get_nonce(&address) != 0 {
+            eyre::bail!("there is already a contract at {:?}", address)
+        }
+        let balance = self.host.get_balance(from);
+        if balance < value {
+            eyre::bail!("the deployer cannot afford to send {} wei", value)
+        }
+
+        self.clear_logs();
+        let snapshot = self.host.clone();
+        self.host.set_balance(from, balance - value);
+        let balance = self.host.get_balance(address);
+        self.host.set_balance(address, balance + value);
+        // the account must exist for the constructor to write to its storage
+        self.host.set_code(address, Default::default());
+        if self.revision >= Revision::Spurious {
+            self.host.set_nonce(address, 1);
+        }
+
+        #[allow(deprecated)]
+        let message = Message {
+            sender: from,
+            destination: address,
+            depth: 0,
+            kind,
+            input_data: Default::default(),
+            value,
+            gas: self.gas_limit as i64,
+            is_static: false,
+        };
+        let init_code = AnalyzedCode::analyze(bytecode.as_ref());
+        self.host.start_call(address);
+        let output =
+            init_code.execute(&mut self.host, &mut self.tracer, None, message, self.revision);
+        self.collect_logs(&output);
+        let base = if self.revision >= Revision::Homestead {
+            GAS_TRANSACTION_CREATE
+        } else {
+            GAS_TRANSACTION_CALL
+        };
+        let execution = self.gas_limit - output.gas_left as u64;
+        let gas = self.adjust_gas(base, execution, output.gas_refund, &bytecode);
+        self.gas_used = Some(gas);
+
+        match output.status_code {
+            StatusCode::Success => self.host.set_code(address, output.output_data.clone()),
+            _ => self.host = snapshot,
+        }
+        if let Some(before) = before {
+            self.state_changes = Some(state_diff(&before, &self.host));
+        }
+        if matches!(output.status_code, StatusCode::UndefinedInstruction) {
+            check_opcodes(&bytecode, self.revision)?;
+        }
+        match output.status_code {
+            StatusCode::Success => Ok((address, output.status_code, gas.adjusted)),
+            StatusCode::Revert => Err(eyre::eyre!(
+                "constructor reverted: {}",
+                dapp_utils::decode_revert(&output.output_data)
+            )),
+            status => Err(eyre::eyre!("deployment failed: {:?}", status)),
+        }
+    }
+}
+
+impl<S> EvmOdin<S, RecordingTracer> {
+    /// Takes the call tree recorded since the last time it was taken
+    pub fn take_traces(&mut self) -> Vec<CallTraceNode> {
+        self.tracer.take_traces()
+    }
+
+    /// Takes the steps recorded since the last time they were taken
+    pub fn take_steps(&mut self) -> Vec<DebugStep> {
+        self.tracer.take_steps()
+    }
+}
+
+impl<S> EvmOdin<S, SharedTracer> {
+    /// Takes the call tree recorded since the last time it was taken
+    pub fn take_traces(&mut self) -> Vec<CallTraceNode> {
+        self.tracer.take_traces()
+    }
+
+    /// Takes the steps recorded since the last time they were taken
+    pub fn take_steps(&mut self) -> Vec<DebugStep> {
+        self.tracer.take_steps()
     }
 }
 
-/// Helper trait for exposing additional functionality over EVMOdin Hosts
-pub trait HostExt: Host {
-    /// Gets the bytecode at the specified address. `None` if the specified address
-    /// is not a contract account.
-    fn get_code(&self, address: &Address) -> Option<&bytes::Bytes>;
-    /// Sets the bytecode at the specified address to the provided value.
-    fn set_code(&mut self, address: Address, code: bytes::Bytes);
+/// A [`RecordingTracer`] whose clones record to the same call tree, so that it can be given to
+/// both an [`EvmOdin`] and its [`CallExecutingHost`], to record the nested frames too
+#[derive(Clone, Debug, Default)]
+pub struct SharedTracer(Rc<RefCell<RecordingTracer>>);
+
+impl SharedTracer {
+    pub fn new(tracer: RecordingTracer) -> Self {
+        Self(Rc::new(RefCell::new(tracer)))
+    }
+
+    /// Takes the call tree recorded since the last time it was taken
+    pub fn take_traces(&self) -> Vec<CallTraceNode> {
+        self.0.borrow_mut().take_traces()
+    }
+
+    /// Takes the steps recorded since the last time they were taken
+    pub fn take_steps(&self) -> Vec<DebugStep> {
+        self.0.borrow_mut().take_steps()
+    }
 }
 
-impl<S: HostExt, Tr: Tracer> Evm<S> for EvmOdin<S, Tr> {
-    type ReturnReason = StatusCode;
+impl Tracer for SharedTracer {
+    fn notify_execution_start(&mut self, revision: Revision, message: Message, code: bytes::Bytes) {
+        self.0.borrow_mut().notify_execution_start(revision, message, code)
+    }
 
-    fn is_success(reason: &Self::ReturnReason) -> bool {
-        matches!(reason, StatusCode::Success)
+    fn notify_instruction_start(&mut self, pc: usize, opcode: OpCode, state: &ExecutionState) {
+        self.0.borrow_mut().notify_instruction_start(pc, opcode, state)
     }
 
-    fn is_fail(reason: &Self::ReturnReason) -> bool {
-        matches!(reason, StatusCode::Revert)
+    fn notify_execution_end(&mut self, output: &Output) {
+        self.0.borrow_mut().notify_execution_end(output)
     }
+}
 
-    fn reset(&mut self, state: S) {
-        self.host = state;
+/// A tracer which records the frames executed by the interpreter as a call tree, along with
+/// their steps, in the same shape as the sputnik executor with cheatcodes does. The frames
+/// executed by a host (e.g. [`CallExecutingHost`]) are only recorded if it passes the tracer
+/// along, see [`SharedTracer`].
+#[derive(Clone, Debug)]
+pub struct RecordingTracer {
+    tracer: CallTracer,
+    steps: StepRecorder,
+    /// The maximum depth of the frames which are recorded, or `None` to record all of them
+    max_depth: Option<usize>,
+    /// The number of frames which are currently executing
+    depth: usize,
+}
+
+impl Default for RecordingTracer {
+    fn default() -> Self {
+        Self::new(None, DebugConfig::default())
     }
+}
 
-    fn initialize_contracts<I: IntoIterator<Item = (Address, Bytes)>>(&mut self, contracts: I) {
-        contracts.into_iter().for_each(|(address, bytecode)| {
-            self.host.set_code(address, bytecode.0);
-        })
+impl RecordingTracer {
+    /// Records the frames up to `max_depth`, the top-level call being at depth 0, and their
+    /// steps as per `config`, which bounds the memory the tracer uses
+    pub fn new(max_depth: Option<usize>, config: DebugConfig) -> Self {
+        let tracer = CallTracer { enabled: true, ..Default::default() };
+        let steps = StepRecorder { config: Some(config), ..Default::default() };
+        Self { tracer, steps, max_depth, depth: 0 }
     }
 
-    fn state(&self) -> &S {
-        &self.host
+    /// Takes the call tree recorded since the last time it was taken
+    pub fn take_traces(&mut self) -> Vec<CallTraceNode> {
+        let nodes = self.tracer.nodes().to_vec();
+        self.tracer.clear();
+        nodes
     }
 
-    /// Runs the selected function
-    fn call_raw(
-        &mut self,
-        from: Address,
-        to: Address,
-        calldata: Bytes,
-        value: U256,
-        is_static: bool,
-    ) -> Result<(Bytes, Self::ReturnReason, u64)> {
-        // For the `func.constant` field usage
-        #[allow(deprecated)]
-        let message = Message {
-            sender: from,
-            destination: to,
-            // What should this be?
-            depth: 0,
-            kind: self.call_kind.unwrap_or(CallKind::Call),
-            input_data: calldata.0,
-            value,
-            gas: self.gas_limit as i64,
-            is_static,
-        };
+    /// Takes the steps recorded since the last time they were taken
+    pub fn take_steps(&mut self) -> Vec<DebugStep> {
+        let steps = self.steps.steps();
+        self.steps.clear();
+        steps
+    }
 
-        // get the bytecode at the host
-        let bytecode = self.host.get_code(&to).ok_or_else(|| {
-            eyre::eyre!("there should be a smart contract at the destination address")
-        })?;
-        let bytecode = AnalyzedCode::analyze(bytecode.as_ref());
-        let output =
-            bytecode.execute(&mut self.host, &mut self.tracer, None, message, self.revision);
+    /// Whether the frames at `depth` are recorded
+    fn records(&self, depth: usize) -> bool {
+        self.max_depth.map_or(true, |max_depth| depth <= max_depth)
+    }
+}
+
+impl Tracer for RecordingTracer {
+    fn notify_execution_start(
+        &mut self,
+        _revision: Revision,
+        message: Message,
+        code: bytes::Bytes,
+    ) {
+        if self.records(self.depth) {
+            // deployments have no calldata, their init code is executed instead
+            let (kind, data) = match message.kind {
+                CallKind::Create | CallKind::Create2 { .. } => {
+                    (call_tracing::CallKind::Create, &code)
+                }
+                _ => (call_tracing::CallKind::Call, &message.input_data),
+            };
+            self.tracer.start(
+                kind,
+                message.sender,
+                message.destination,
+                data,
+                message.value,
+                message.gas as u64,
+            );
+        }
+        self.depth += 1;
+    }
 
-        // evmodin doesn't take the BASE_TX_COST and the calldata into account
-        let gas = self.gas_limit - output.gas_left as u64;
+    fn notify_instruction_start(&mut self, pc: usize, opcode: OpCode, state: &ExecutionState) {
+        let depth = self.depth.saturating_sub(1);
+        if !self.records(depth) {
+            return
+        }
+        let items = self.steps.config.map_or(0, |config| config.stack_items);
+        // the recorder expects the topmost item last, like sputnik's stack
+        let stack = (0..items.min(state.stack.len()))
+            .rev()
+            .map(|position| {
+                let mut word = [0u8; 32];
+                state.stack.get(position).to_big_endian(&mut word);
+                H256(word)
+            })
+            .collect::<Vec<_>>();
+        self.steps.record(
+            pc,
+            opcode.0,
+            state.gas_left as u64,
+            &stack,
+            depth,
+            self.tracer.current(),
+        );
+    }
 
-        Ok((output.output_data.to_vec().into(), output.status_code, gas))
+    fn notify_execution_end(&mut self, output: &Output) {
+        self.depth = self.depth.saturating_sub(1);
+        if !self.records(self.depth) {
+            return
+        }
+        let gas_limit =
+            self.tracer.current().map_or(0, |index| self.tracer.nodes()[index].trace.gas_limit);
+        let success = matches!(output.status_code, StatusCode::Success);
+        let gas_used = gas_limit.saturating_sub(output.gas_left.max(0) as u64);
+        self.tracer.finish(success, &output.output_data, gas_used);
     }
 }
 
@@ -108,16 +1229,68 @@ mod helpers {
 
         fn set_code(&mut self, address: Address, bytecode: bytes::Bytes) {
             let hash = keccak256(&bytecode);
-            self.accounts.insert(
-                address,
-                Account {
-                    nonce: 0,
-                    balance: 0.into(),
-                    code: bytecode,
-                    code_hash: hash.into(),
-                    storage: Default::default(),
-                },
-            );
+            let account = self.accounts.entry(address).or_insert_with(empty_account);
+            account.code = bytecode;
+            account.code_hash = hash.into();
+        }
+
+        fn get_nonce(&self, address: &Address) -> u64 {
+            self.accounts.get(address).map(|acc| acc.nonce).unwrap_or_default()
+        }
+
+        fn set_nonce(&mut self, address: Address, nonce: u64) {
+            self.accounts.entry(address).or_insert_with(empty_account).nonce = nonce;
+        }
+
+        fn set_balance(&mut self, address: Address, balance: U256) {
+            self.accounts.entry(address).or_insert_with(empty_account).balance = balance;
+        }
+
+        fn start_call(&mut self, _destination: Address) {
+            // the slots written and accessed by the previous transactions are clean and cold
+            // again, for the refunds and the costs of the new one
+            for value in self.accounts.values_mut().flat_map(|acc| acc.storage.values_mut()) {
+                value.dirty = false;
+                value.access_status = AccessStatus::Cold;
+            }
+        }
+
+        fn set_tx_context(&mut self, context: TxContext) {
+            self.tx_context = context;
+        }
+
+        fn addresses(&self) -> Vec<Address> {
+            self.accounts.keys().copied().collect()
+        }
+
+        fn storage(&self, address: &Address) -> BTreeMap<H256, StorageSlot> {
+            // the slots which were only read or accessed are tracked too, with a zero value
+            let slots = self.accounts.get(address).into_iter().flat_map(|acc| &acc.storage);
+            slots
+                .map(|(slot, value)| {
+                    (*slot, StorageSlot { value: value.value, dirty: value.dirty })
+                })
+                .filter(|(_, slot)| !slot.value.is_zero() || slot.dirty)
+                .collect()
+        }
+
+        fn take_logs(&mut self) -> Vec<RawLog> {
+            self.recorded
+                .lock()
+                .logs
+                .drain(..)
+                .map(|log| RawLog { topics: log.topics, data: log.data.to_vec() })
+                .collect()
+        }
+    }
+
+    fn empty_account() -> Account {
+        Account {
+            nonce: 0,
+            balance: 0.into(),
+            code: Default::default(),
+            code_hash: Default::default(),
+            storage: Default::default(),
         }
     }
 }
@@ -125,8 +1298,13 @@ mod helpers {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_helpers::{can_call_vm_directly, solidity_unit_test, COMPILED};
-    use evmodin::{tracing::NoopTracer, util::mocked_host::MockedHost};
+    use crate::test_helpers::{
+        can_call_vm_directly, evaluated_outcomes, failing_assertion_logs, greeter_state_changes,
+        solidity_unit_test, state_cheatcodes, unreadable_failure_state, COMPILED,
+    };
+    use dapp_utils::get_func;
+    use ethers::abi::{Detokenize, Token, Tokenize};
+    use evmodin::util::mocked_host::MockedHost;
 
     #[test]
     fn evmodin_can_call_vm_directly() {
@@ -143,16 +1321,663 @@ mod tests {
     }
 
     #[test]
-    // TODO: This fails because the cross-contract host does not work.
-    #[ignore]
-    fn evmodin_can_call_solidity_unit_test() {
-        let revision = Revision::Istanbul;
+    fn evmodin_state_accessors() {
+        let host = MockedHost::default();
+        let mut evm = EvmOdin::new(host, 12_000_000, Revision::Istanbul, NoopTracer);
+        let addr = Address::repeat_byte(1);
+
+        evm.set_balance(addr, 100.into());
+        evm.set_nonce(addr, 5).unwrap();
+        assert_eq!(evm.get_balance(addr), 100.into());
+        assert_eq!(evm.get_nonce(addr), 5.into());
+        assert!(evm.get_code(addr).as_ref().is_empty());
+
+        // setting the code keeps the rest of the account
+        evm.initialize_contracts(vec![(addr, vec![0x00].into())]);
+        assert_eq!(evm.get_code(addr).as_ref(), &[0x00]);
+        assert_eq!(evm.get_nonce(addr), 5.into());
+    }
+
+    #[test]
+    #[cfg(feature = "sputnik")]
+    fn gas_matches_sputnik() {
+        use crate::sputnik::{
+            helpers::{new_backend, new_vicinity},
+            Executor,
+        };
+        use dapp_utils::get_func;
+        use sputnik::Config;
+
         let compiled = COMPILED.get("Greeter").expect("could not find contract");
         let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let greet = get_func("function greet(string)").unwrap();
+
         let host = MockedHost::default();
+        let mut evmodin = EvmOdin::new(host, 12_000_000, Revision::Istanbul, NoopTracer);
+        evmodin.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut sputnik = Executor::new(12_000_000, &config, &backend);
+        sputnik.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        // clearing the greeting afterwards is refunded
+        for greeting in ["hi", ""].iter() {
+            let (_, status, evmodin_gas) = evmodin
+                .call::<(), _>(Address::zero(), addr, &greet, greeting.to_string(), 0.into(), None)
+                .unwrap();
+            assert_eq!(status, StatusCode::Success);
+            let (_, _, sputnik_gas) = sputnik
+                .call::<(), _>(Address::zero(), addr, &greet, greeting.to_string(), 0.into(), None)
+                .unwrap();
+
+            // the hosts may price some accesses differently, but not by much
+            let close = |a: u64, b: u64| (a as i64 - b as i64).abs() as u64 <= b / 100;
+            assert!(close(evmodin_gas, sputnik_gas), "{} != {}", evmodin_gas, sputnik_gas);
+            let (evmodin_raw, sputnik_raw) =
+                (evmodin.gas_used().unwrap().raw, sputnik.gas_used().unwrap().raw);
+            assert!(close(evmodin_raw, sputnik_raw), "{} != {}", evmodin_raw, sputnik_raw);
+        }
+    }
+
+    #[test]
+    fn evmodin_can_call_solidity_unit_test() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("GreeterTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        // the test deploys and calls the greeter
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
         let gas_limit = 12_000_000;
         let evm = EvmOdin::new(host, gas_limit, revision, NoopTracer);
 
         solidity_unit_test(evm, addr, compiled);
     }
+
+    #[test]
+    fn evmodin_can_apply_state_cheatcodes() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("CheatCodes").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+
+        state_cheatcodes(evm, addr, compiled);
+    }
+
+    #[test]
+    fn evmodin_reports_unreadable_failure_state() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("FailureTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+
+        unreadable_failure_state(evm, addr, compiled);
+    }
+
+    #[test]
+    fn evmodin_logs_failing_assertions() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("FailureTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        let logs = failing_assertion_logs(evm, addr, compiled);
+        assert_eq!(logs.len(), 3);
+
+        // the logs are decoded the same way as the ones of the sputnik executor
+        #[cfg(feature = "sputnik")]
+        {
+            use crate::sputnik::{
+                helpers::{new_backend, new_vicinity},
+                Executor,
+            };
+            let cfg = sputnik::Config::istanbul();
+            let vicinity = new_vicinity();
+            let backend = new_backend(&vicinity, Default::default());
+            let evm = Executor::new_with_cheatcodes(backend, 12_000_000, &cfg, false);
+            assert_eq!(failing_assertion_logs(evm, addr, compiled), logs);
+        }
+    }
+
+    #[test]
+    fn evmodin_evaluates_test_outcomes() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("FailureTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        evaluated_outcomes(evm, addr, compiled);
+    }
+
+    #[test]
+    fn records_state_changes() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let mut evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+
+        // nothing is recorded when disabled
+        evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap();
+        assert!(evm.state_changes().is_empty());
+        let changes = greeter_state_changes(evm, compiled);
+        // the deployer and the deployed contract
+        assert_eq!(changes[0].len(), 2);
+
+        // the changes are the same as the ones of the sputnik executor
+        #[cfg(feature = "sputnik")]
+        {
+            use crate::sputnik::{
+                helpers::{new_backend, new_vicinity},
+                Executor,
+            };
+            let cfg = sputnik::Config::istanbul();
+            let vicinity = new_vicinity();
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = Executor::new(12_000_000, &cfg, &backend);
+            evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap();
+            assert_eq!(greeter_state_changes(evm, compiled), changes);
+        }
+    }
+
+    /// Stores the calldata's first word in the first slot of an account, in separate calls, and
+    /// returns the changes made by each call along with their gas
+    fn store_calldata<S, E: Evm<S>>(mut evm: E, values: &[u64]) -> Vec<(StateDiff, u64)> {
+        let from = Address::repeat_byte(1);
+        let addr = Address::repeat_byte(0x11);
+        // PUSH1 0 CALLDATALOAD PUSH1 0 SSTORE STOP
+        let code = hex::decode("60003560005500").unwrap();
+        evm.initialize_contracts(vec![(addr, code.into())]);
+        evm.set_state_diff_enabled(true);
+        values
+            .iter()
+            .map(|value| {
+                let calldata = H256::from_low_u64_be(*value).as_bytes().to_vec().into();
+                evm.call_raw(from, addr, calldata, 0.into(), false, None).unwrap();
+                let mut diff = evm.state_changes();
+                // only some EVMs increase the nonce of the sender of a call
+                diff.remove(&from);
+                (diff, evm.gas_used().unwrap().adjusted)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn storage_is_clean_at_the_start_of_calls() {
+        let revision = Revision::Istanbul;
+        let evm = EvmOdin::new(MockedHost::default(), 12_000_000, revision, NoopTracer);
+        let slot = H256::zero();
+
+        let changes = store_calldata(evm, &[1, 2]);
+        let diffs = changes.iter().map(|(diff, _)| &diff[&Address::repeat_byte(0x11)].storage);
+        let expected = [(0, 1), (1, 2)]
+            .iter()
+            .map(|(from, to)| Change {
+                from: H256::from_low_u64_be(*from),
+                to: H256::from_low_u64_be(*to),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(diffs.map(|storage| storage[&slot]).collect::<Vec<_>>(), expected);
+        // EIP-2200: the slot written by the first call is clean again in the second one, so it
+        // is modified rather than modified again
+        let gas = changes.iter().map(|(_, gas)| *gas).collect::<Vec<_>>();
+        assert_eq!(gas, vec![3 * 3 + 20_000, 3 * 3 + 5_000]);
+    }
+
+    #[test]
+    #[cfg(feature = "sputnik")]
+    fn state_changes_match_sputnik() {
+        use crate::sputnik::{
+            helpers::{new_backend, new_vicinity},
+            Executor,
+        };
+        // the slot is set, modified, then cleared
+        let values = [1, 2, 0];
+        let revision = Revision::Istanbul;
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let changes = store_calldata(EvmOdin::new(host, 12_000_000, revision, NoopTracer), &values);
+
+        let cfg = sputnik::Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let evm = Executor::new(12_000_000, &cfg, &backend);
+        assert_eq!(store_calldata(evm, &values), changes);
+    }
+
+    #[test]
+    fn unsupported_cheatcodes_revert() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("CheatCodes").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let mut evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let calldata = ethers::utils::id("testSnapshot()").to_vec();
+        let (retdata, status, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(matches!(status, StatusCode::Revert));
+        assert_eq!(dapp_utils::decode_revert(&retdata), "`snapshot()` is not supported by evmodin");
+    }
+
+    #[test]
+    fn records_traces() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let config = DebugConfig { stack_items: 2, max_steps: None };
+        let tracer = RecordingTracer::new(Some(0), config);
+        let mut evm = EvmOdin::new(MockedHost::default(), 12_000_000, revision, tracer);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let greet = get_func("function greet(string)").unwrap();
+        evm.call::<(), _>(Address::zero(), addr, &greet, "hi".to_owned(), 0.into(), None).unwrap();
+
+        let nodes = evm.take_traces();
+        assert_eq!(nodes.len(), 1);
+        let trace = &nodes[0].trace;
+        assert_eq!(trace.kind, crate::call_tracing::CallKind::Call);
+        assert_eq!((trace.depth, trace.address), (0, addr));
+        assert_eq!(trace.data[..4], greet.short_signature());
+        assert!(trace.success && trace.gas_used > 0);
+
+        let steps = evm.take_steps();
+        // the solidity preamble pushes the free memory pointer
+        assert_eq!((steps[0].pc, steps[0].opcode, steps[0].frame), (0, 0x60, Some(0)));
+        assert!(steps.iter().all(|step| step.depth == 0 && step.stack.len() <= 2));
+        assert_eq!(steps[1].stack, vec![H256::from_low_u64_be(0x80)]);
+
+        // taking the traces clears them
+        assert!(evm.take_traces().is_empty() && evm.take_steps().is_empty());
+    }
+
+    #[test]
+    fn records_traces_of_nested_frames() {
+        let revision = Revision::Istanbul;
+        let compiled = COMPILED.get("GreeterTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        for (max_depth, frames) in [(None, 2), (Some(0), 1)] {
+            let tracer = SharedTracer::new(RecordingTracer::new(max_depth, Default::default()));
+            let host =
+                CallExecutingHost::new(MockedHost::default(), revision).with_tracer(tracer.clone());
+            let mut evm = EvmOdin::new(host, 12_000_000, revision, tracer);
+            evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+            // `setUp` deploys the greeter
+            evm.setup(addr).unwrap();
+
+            let nodes = evm.take_traces();
+            assert_eq!(nodes.len(), frames);
+            assert_eq!((nodes[0].trace.depth, nodes[0].trace.address), (0, addr));
+            if let Some(create) = nodes.get(1) {
+                assert_eq!(create.trace.kind, crate::call_tracing::CallKind::Create);
+                assert_eq!((create.trace.depth, create.parent), (1, Some(0)));
+                assert!(create.trace.success);
+            }
+            assert!(evm.take_steps().iter().all(|step| step.depth < frames));
+        }
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let revision = Revision::Istanbul;
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let mut evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        let from = Address::repeat_byte(1);
+
+        evm.set_balance(from, 100.into());
+        let first = evm.snapshot();
+        evm.set_nonce(from, 1).unwrap();
+        let second = evm.snapshot();
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let (deployed, _, _) = evm.deploy(from, compiled.bytecode.clone(), 0.into()).unwrap();
+        evm.call::<(), _>(
+            from,
+            deployed,
+            &get_func("function greet(string)").unwrap(),
+            "hi".to_owned(),
+            0.into(),
+            None,
+        )
+        .unwrap();
+        assert_ne!(evm.host.get_storage(deployed, H256::zero()), H256::zero());
+
+        // the accounts created after the snapshot are gone, storage included
+        evm.restore(second).unwrap();
+        assert_eq!(evm.get_nonce(from), 1.into());
+        assert!(evm.get_code(deployed).as_ref().is_empty());
+        assert_eq!(evm.host.get_storage(deployed, H256::zero()), H256::zero());
+
+        // restoring an older snapshot discards the newer ones, but can be repeated
+        evm.set_balance(from, 5.into());
+        evm.restore(first).unwrap();
+        assert_eq!(evm.get_nonce(from), 0.into());
+        assert_eq!(evm.get_balance(from), 100.into());
+        assert!(evm.restore(second).is_err());
+        evm.restore(first).unwrap();
+
+        evm.clear_snapshots();
+        assert!(evm.restore(first).is_err());
+    }
+
+    #[test]
+    fn gas_depends_on_revision() {
+        let mut evm =
+            EvmOdin::new(MockedHost::default(), 12_000_000, Revision::Istanbul, NoopTracer);
+        let addr = Address::repeat_byte(0x11);
+        // PUSH1 1 PUSH1 0 SSTORE PUSH1 2 PUSH1 0 SSTORE STOP
+        let code = hex::decode("600160005560026000550000").unwrap();
+        evm.initialize_contracts(vec![(addr, code.into())]);
+        let snapshot = evm.snapshot();
+
+        // 4 pushes, a zero to non-zero store and a store to the slot it made dirty
+        let (_, status, gas) =
+            evm.call_raw(Address::zero(), addr, Bytes::default(), 0.into(), false, None).unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        // EIP-2200: the dirty store costs as much as a `SLOAD`
+        assert_eq!(gas, 4 * 3 + 20_000 + 800);
+
+        // EIP-2929: the slot is cold on the first access, then warm
+        evm.restore(snapshot).unwrap();
+        let (_, _, gas) = evm
+            .call_with_revision(Address::zero(), addr, Bytes::default(), 0.into(), Revision::London)
+            .unwrap();
+        assert_eq!(gas, 4 * 3 + 20_000 + 2_100 + 100);
+        assert_eq!(evm.revision, Revision::Istanbul);
+    }
+
+    #[test]
+    fn sets_block_environment() {
+        let revision = Revision::London;
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let mut evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        evm.set_block_timestamp(1_000);
+        evm.set_block_number(5);
+        evm.set_block_coinbase(Address::repeat_byte(0xcb));
+        evm.set_chain_id(10.into());
+        evm.set_block_base_fee(7.into());
+        evm.set_block_difficulty(3.into());
+        evm.set_gas_price(2.into());
+
+        // returns TIMESTAMP, NUMBER, COINBASE, CHAINID, BASEFEE, DIFFICULTY and GASPRICE
+        let opcodes = [0x42, 0x43, 0x41, 0x46, 0x48, 0x44, 0x3a];
+        let mut code = Vec::new();
+        for (i, opcode) in opcodes.iter().enumerate() {
+            // <opcode> PUSH1 <offset> MSTORE
+            code.extend([*opcode, 0x60, i as u8 * 32, 0x52]);
+        }
+        // PUSH1 <size> PUSH1 0 RETURN
+        code.extend([0x60, opcodes.len() as u8 * 32, 0x60, 0x00, 0xf3]);
+        let addr = Address::repeat_byte(0x11);
+        evm.initialize_contracts(vec![(addr, code.into())]);
+
+        let (retdata, status, _) =
+            evm.call_raw(Address::zero(), addr, Bytes::default(), 0.into(), false, None).unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        let words = retdata.as_ref().chunks(32).map(U256::from_big_endian).collect::<Vec<_>>();
+        let coinbase = U256::from_big_endian(Address::repeat_byte(0xcb).as_bytes());
+        assert_eq!(
+            words,
+            vec![1_000.into(), 5.into(), coinbase, 10.into(), 7.into(), 3.into(), 2.into()]
+        );
+
+        // the contracts see the same environment
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let (greeter, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap();
+        let time: U256 = call(&mut evm, greeter, "function time() returns (uint256)", ());
+        assert_eq!(time, 1_000.into());
+    }
+
+    #[test]
+    fn names_unavailable_opcodes() {
+        let revision = Revision::Istanbul;
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let mut evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        let addr = Address::repeat_byte(0x11);
+        // PUSH1 0x48 POP BASEFEE POP STOP: the push data is not an opcode
+        let code = hex::decode("604850485000").unwrap();
+        evm.initialize_contracts(vec![(addr, code.into())]);
+
+        let err = evm
+            .call_raw(Address::zero(), addr, Bytes::default(), 0.into(), false, None)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`BASEFEE` (0x48) at pc 3 is not available before London, the code ran with Istanbul"
+        );
+
+        evm.set_revision(Revision::London);
+        let (_, status, _) =
+            evm.call_raw(Address::zero(), addr, Bytes::default(), 0.into(), false, None).unwrap();
+        assert!(matches!(status, StatusCode::Success));
+    }
+
+    #[test]
+    fn fails_the_calls_made_once_interrupted() {
+        let mut evm =
+            EvmOdin::new(MockedHost::default(), 12_000_000, Revision::Istanbul, NoopTracer);
+        let addr = Address::repeat_byte(1);
+        evm.initialize_contracts(vec![(addr, vec![0x00].into())]);
+        let call = |evm: &mut EvmOdin<_, _>| {
+            evm.call_raw(Address::zero(), addr, Bytes::default(), 0.into(), false, None).unwrap().1
+        };
+
+        let interrupt = Interrupt::default();
+        evm.set_interrupt(Some(interrupt.clone()));
+        assert!(matches!(call(&mut evm), StatusCode::Success));
+        interrupt.raise();
+        assert!(evm.is_interrupted());
+        assert!(matches!(call(&mut evm), StatusCode::Failure));
+        assert_eq!(evm.gas_used(), None);
+
+        evm.set_interrupt(None);
+        assert!(matches!(call(&mut evm), StatusCode::Success));
+    }
+
+    #[test]
+    fn deploys_contracts() {
+        let revision = Revision::Istanbul;
+        let mut evm = EvmOdin::new(MockedHost::default(), 12_000_000, revision, NoopTracer);
+        let from = Address::repeat_byte(1);
+
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let (addr, status, _) = evm.deploy(from, compiled.bytecode.clone(), 0.into()).unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        assert_eq!(addr, ethers::utils::get_contract_address(from, 0u64));
+        assert_eq!(evm.get_code(addr), compiled.runtime_bytecode);
+        assert_eq!(evm.get_nonce(from), 1.into());
+        assert_eq!(evm.get_nonce(addr), 1.into());
+
+        evm.call::<(), _>(
+            from,
+            addr,
+            &get_func("function greet(string)").unwrap(),
+            "hi".to_owned(),
+            0.into(),
+            None,
+        )
+        .unwrap();
+        let (greeting, _, _) = evm
+            .call::<String, _>(
+                from,
+                addr,
+                &get_func("function greeting() returns (string)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(greeting, "hi");
+
+        // the address of `CREATE2` deployments does not depend on the nonce
+        let salt = H256::repeat_byte(7);
+        let (addr2, _, _) = evm.deploy2(from, compiled.bytecode.clone(), 0.into(), salt).unwrap();
+        let expected = ethers::utils::get_create2_address(
+            from,
+            salt.as_bytes().to_vec(),
+            compiled.bytecode.to_vec(),
+        );
+        assert_eq!(addr2, expected);
+        assert_eq!(evm.get_code(addr2), compiled.runtime_bytecode);
+        assert!(evm.deploy2(from, compiled.bytecode.clone(), 0.into(), salt).is_err());
+
+        // failed deployments leave no account behind
+        let compiled = COMPILED.get("RevertingConstructor").expect("could not find contract");
+        let nonce = evm.get_nonce(from);
+        let err = evm.deploy(from, compiled.bytecode.clone(), 0.into()).unwrap_err();
+        assert_eq!(err.to_string(), "constructor reverted: constructor reverted");
+        let failed = ethers::utils::get_contract_address(from, nonce);
+        assert!(!evm.host.account_exists(failed));
+        assert_eq!(evm.get_nonce(from), nonce + 1);
+    }
+
+    #[test]
+    fn transfers_value() {
+        let compiled = COMPILED.get("ValueRecorder").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let from = Address::repeat_byte(1);
+        let mut evm =
+            EvmOdin::new(MockedHost::default(), 12_000_000, Revision::Istanbul, NoopTracer);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        evm.set_balance(from, 100.into());
+
+        let record = get_func("function record() returns (uint256)").unwrap();
+        let (_, status, _) = evm.call::<U256, _>(from, addr, &record, (), 40.into(), None).unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        assert_eq!(evm.get_balance(from), 60.into());
+        assert_eq!(evm.get_balance(addr), 40.into());
+        for (getter, expected) in [("value", 40), ("balance", 40)] {
+            let func = get_func(&format!("function {}() returns (uint256)", getter)).unwrap();
+            let (value, _, _) = evm.call::<U256, _>(from, addr, &func, (), 0.into(), None).unwrap();
+            assert_eq!(value, expected.into(), "{}", getter);
+        }
+
+        // the value is sent back when the call reverts
+        let fail = get_func("function fail()").unwrap().encode_input(&[]).unwrap();
+        let (_, status, _) = evm.call_raw(from, addr, fail.into(), 10.into(), false, None).unwrap();
+        assert!(matches!(status, StatusCode::Revert));
+        assert_eq!(evm.get_balance(from), 60.into());
+        assert_eq!(evm.get_balance(addr), 40.into());
+
+        // the calls whose value the sender cannot afford fail
+        let record = record.encode_input(&[]).unwrap();
+        let (_, status, _) =
+            evm.call_raw(from, addr, record.into(), 61.into(), false, None).unwrap();
+        assert!(matches!(status, StatusCode::InsufficientBalance));
+        assert_eq!(evm.get_balance(from), 60.into());
+    }
+
+    #[test]
+    fn delegates_to_implementations() {
+        let revision = Revision::Istanbul;
+        let host = CallExecutingHost::new(MockedHost::default(), revision);
+        let mut evm = EvmOdin::new(host, 12_000_000, revision, NoopTracer);
+        let user = Address::repeat_byte(1);
+        evm.set_balance(user, 100.into());
+
+        let compiled = COMPILED.get("Implementation").expect("could not find contract");
+        let (implementation, _, _) = evm.deploy(user, compiled.bytecode.clone(), 0.into()).unwrap();
+        let compiled = COMPILED.get("Proxy").expect("could not find contract");
+        let mut init_code = compiled.bytecode.to_vec();
+        init_code.extend(ethers::abi::encode(&[Token::Address(implementation)]));
+        let (proxy, _, _) = evm.deploy(user, init_code.into(), 0.into()).unwrap();
+
+        let set = get_func("function set(uint256)").unwrap();
+        let getter = |evm: &mut CallKindsEvm, who: Address, name: &str| {
+            let func = get_func(&format!("function {}() returns (uint256)", name)).unwrap();
+            evm.call::<U256, _>(Address::zero(), who, &func, (), 0.into(), None).unwrap().0
+        };
+        let sender = |evm: &mut CallKindsEvm, who: Address| {
+            let func = get_func("function sender() returns (address)").unwrap();
+            evm.call::<Address, _>(Address::zero(), who, &func, (), 0.into(), None).unwrap().0
+        };
+
+        // the proxy delegates to the implementation, which writes to the proxy's storage
+        let (_, status, _) =
+            evm.call::<(), _>(user, proxy, &set, U256::from(5), 3.into(), None).unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        assert_eq!(getter(&mut evm, proxy, "value"), 5.into());
+        assert_eq!(getter(&mut evm, proxy, "received"), 3.into());
+        assert_eq!(sender(&mut evm, proxy), user);
+        assert_eq!(getter(&mut evm, implementation, "value"), 0.into());
+        assert_eq!(evm.get_balance(proxy), 3.into());
+
+        // top-level delegated calls keep the sender and value, without transferring it
+        let calldata: Bytes = set.encode_input(&[Token::Uint(7.into())]).unwrap().into();
+        let (_, status, _) = evm
+            .call_with_kind(user, proxy, implementation, calldata, 2.into(), CallKind::DelegateCall)
+            .unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        assert_eq!(getter(&mut evm, proxy, "value"), 7.into());
+        assert_eq!(getter(&mut evm, proxy, "received"), 2.into());
+        assert_eq!(getter(&mut evm, implementation, "value"), 0.into());
+        assert_eq!(evm.get_balance(proxy), 3.into());
+
+        // `CALLCODE`s transfer the value to the account whose code they run in the context of
+        let calldata: Bytes = set.encode_input(&[Token::Uint(9.into())]).unwrap().into();
+        let (_, status, _) = evm
+            .call_with_kind(user, proxy, implementation, calldata, 2.into(), CallKind::CallCode)
+            .unwrap();
+        assert!(matches!(status, StatusCode::Success));
+        assert_eq!(getter(&mut evm, proxy, "value"), 9.into());
+        assert_eq!(sender(&mut evm, proxy), user);
+        assert_eq!(getter(&mut evm, implementation, "value"), 0.into());
+        assert_eq!(evm.get_balance(proxy), 5.into());
+        assert_eq!(evm.get_balance(user), 95.into());
+    }
+
+    type CallKindsEvm = EvmOdin<CallExecutingHost<MockedHost>, NoopTracer>;
+
+    fn call<D: Detokenize, T: Tokenize>(
+        evm: &mut CallKindsEvm,
+        to: Address,
+        sig: &str,
+        args: T,
+    ) -> D {
+        let func = get_func(sig).unwrap();
+        let (value, status, _) =
+            evm.call::<D, _>(Address::zero(), to, &func, args, 0.into(), None).unwrap();
+        assert!(matches!(status, StatusCode::Success), "{} failed: {:?}", func.name, status);
+        value
+    }
+
+    #[test]
+    fn executes_every_call_kind() {
+        let compiled = COMPILED.get("CallKinds").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let host = CallExecutingHost::new(MockedHost::default(), Revision::Istanbul);
+        let mut evm = EvmOdin::new(host, 12_000_000, Revision::Istanbul, NoopTracer);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let value = |evm: &mut CallKindsEvm, who| -> U256 {
+            call(evm, who, "function value() returns (uint256)", ())
+        };
+
+        call::<(), _>(&mut evm, addr, "function setUp()", ());
+        let store: Address = call(&mut evm, addr, "function store() returns (address)", ());
+        assert!(!evm.get_code(store).as_ref().is_empty());
+
+        // calls act on the callee's storage
+        call::<(), _>(&mut evm, addr, "function callSet(uint256)", U256::from(5));
+        assert_eq!(value(&mut evm, store), 5.into());
+        assert_eq!(value(&mut evm, addr), 0.into());
+
+        // the delegated calls act on the caller's storage
+        call::<(), _>(&mut evm, addr, "function delegateSet(uint256)", U256::from(7));
+        assert_eq!(value(&mut evm, addr), 7.into());
+        call::<(), _>(&mut evm, addr, "function callcodeSet(uint256)", U256::from(9));
+        assert_eq!(value(&mut evm, addr), 9.into());
+        assert_eq!(value(&mut evm, store), 5.into());
+
+        // static calls can read but not write
+        let read: U256 = call(&mut evm, addr, "function staticGet() returns (uint256)", ());
+        assert_eq!(read, 5.into());
+        assert!(!call::<bool, _>(&mut evm, addr, "function staticSet() returns (bool)", ()));
+
+        // the writes of reverted calls are rolled back
+        let sig = "function revertingCall(uint256) returns (bool)";
+        assert!(!call::<bool, _>(&mut evm, addr, sig, U256::from(11)));
+        assert_eq!(value(&mut evm, store), 5.into());
+    }
 }
@@ -0,0 +1,730 @@
+//! Property testing of the functions with parameters, by calling them with inputs generated
+//! by [proptest](https://docs.rs/proptest) and shrinking the failing ones. The fuzzing itself
+//! needs the `fuzz` feature, while the dictionary is harvested by the EVMs regardless.
+#[cfg(feature = "fuzz")]
+use crate::{Evm, CHEATCODE_ADDRESS, INTERRUPTED};
+#[cfg(feature = "fuzz")]
+use dapp_utils::decode_revert;
+use dapp_utils::format_log_token;
+#[cfg(feature = "fuzz")]
+use ethers::{
+    abi::ParamType,
+    types::{I256, U256},
+};
+use ethers::{
+    abi::{Function, Token},
+    types::{Address, Bytes},
+};
+use eyre::Result;
+#[cfg(feature = "fuzz")]
+use proptest::{
+    prelude::*,
+    strategy::Union,
+    test_runner::{Config, RngAlgorithm, TestCaseError, TestError, TestRng, TestRunner},
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "fuzz")]
+use std::cell::RefCell;
+use std::{cmp::min, collections::BTreeSet, fmt};
+
+/// The revert data of an `assume` whose condition was false
+pub const ASSUME_MAGIC_REVERT: &[u8] = b"dapptools-rs::assume";
+
+/// The maximum length of the generated dynamic `bytes` and `string` values
+#[cfg(feature = "fuzz")]
+const MAX_DYNAMIC_LEN: usize = 256;
+
+/// The seed of the deterministic configuration
+const DETERMINISTIC_SEED: u64 = 0x6461_7070;
+
+const PUSH1: u8 = 0x60;
+const PUSH32: u8 = 0x7f;
+
+/// Configures how the values of the parameters are generated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StrategyConfig {
+    /// The maximum length of the generated dynamic arrays
+    pub max_array_len: usize,
+    /// The maximum nesting of arrays and tuples, past which dynamic arrays are generated empty,
+    /// so that the encoding of the values stays small
+    pub max_depth: usize,
+    /// The percentage of the addresses, integers and fixed bytes which are picked from the
+    /// dictionary, if it is not empty
+    pub dictionary_weight: u32,
+}
+
+impl Default for StrategyConfig {
+    fn default() -> Self {
+        StrategyConfig { max_array_len: 8, max_depth: 4, dictionary_weight: 40 }
+    }
+}
+
+/// Configures the fuzz and invariant tests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FuzzConfig {
+    /// The number of inputs per fuzz test, or of sequences of calls per invariant test
+    pub runs: u32,
+    /// The seed of the generated inputs, which is random if unset. A seed generates the same
+    /// inputs for the same ABI, so that failures can be reproduced.
+    pub seed: Option<u64>,
+    /// The maximum number of values which the strategies may reject while generating inputs
+    pub max_local_rejects: u32,
+    /// The maximum length of the generated dynamic arrays
+    pub max_array_len: usize,
+    /// The maximum number of attempts at shrinking a failing input
+    pub max_shrink_iters: u32,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        FuzzConfig {
+            runs: 256,
+            seed: None,
+            max_local_rejects: 65_536,
+            max_array_len: 8,
+            max_shrink_iters: u32::MAX,
+        }
+    }
+}
+
+impl FuzzConfig {
+    /// The configuration with a fixed seed, so that the same inputs are generated on every run,
+    /// e.g. for tests which must not flake
+    pub fn deterministic() -> Self {
+        FuzzConfig { seed: Some(DETERMINISTIC_SEED), ..Default::default() }
+    }
+
+    /// Builds a test runner generating the inputs from the configured seed, or from a random one,
+    /// which is returned so that it can be reported. Unlike the proptest defaults, none of the
+    /// configuration is read from the environment.
+    #[cfg(feature = "fuzz")]
+    pub fn runner(&self) -> (TestRunner, u64) {
+        let seed = self.seed.unwrap_or_else(|| TestRunner::default().rng().next_u64());
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        let rng = TestRng::from_seed(RngAlgorithm::ChaCha, &bytes);
+
+        let config = Config {
+            cases: self.runs,
+            max_local_rejects: self.max_local_rejects,
+            max_global_rejects: 1024,
+            max_flat_map_regens: 1_000_000,
+            failure_persistence: None,
+            max_shrink_time: 0,
+            max_shrink_iters: self.max_shrink_iters,
+            verbose: 0,
+            rng_algorithm: RngAlgorithm::ChaCha,
+            ..Config::default()
+        };
+        (TestRunner::new_with_rng(config, rng), seed)
+    }
+
+    /// The configuration of the strategies generating the values of the parameters
+    pub fn strategy_config(&self) -> StrategyConfig {
+        StrategyConfig { max_array_len: self.max_array_len, ..Default::default() }
+    }
+}
+
+/// Interesting values harvested before fuzzing, e.g. the storage values written during
+/// `setUp()`, the constants of the deployed code and the addresses of the deployed contracts.
+/// Random values rarely hit them, so the strategies pick them some of the time. Harvesting
+/// during execution has a cost, so the EVMs only do it when enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FuzzDictionary {
+    pub enabled: bool,
+    values: BTreeSet<[u8; 32]>,
+    /// The addresses inserted via [`FuzzDictionary::insert_address`], e.g. the deployed
+    /// contracts which the invariant tests target
+    addresses: BTreeSet<Address>,
+}
+
+impl FuzzDictionary {
+    /// Inserts a 32 bytes word, e.g. a storage slot or value
+    pub fn insert(&mut self, word: [u8; 32]) {
+        if self.enabled {
+            self.values.insert(word);
+        }
+    }
+
+    /// Inserts an address, left-padded to a word
+    pub fn insert_address(&mut self, address: Address) {
+        if self.enabled {
+            self.addresses.insert(address);
+        }
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(address.as_bytes());
+        self.insert(word);
+    }
+
+    /// Inserts the constants pushed by the `PUSH1` to `PUSH32` instructions of the code
+    pub fn insert_push_constants(&mut self, code: &[u8]) {
+        let mut pc = 0;
+        while pc < code.len() {
+            let opcode = code[pc];
+            pc += 1;
+            if (PUSH1..=PUSH32).contains(&opcode) {
+                let len = (opcode - PUSH1 + 1) as usize;
+                // the code may end in the middle of the pushed bytes
+                let data = &code[pc..min(pc + len, code.len())];
+                let mut word = [0u8; 32];
+                word[32 - data.len()..].copy_from_slice(data);
+                self.insert(word);
+                pc += len;
+            }
+        }
+    }
+
+    /// Removes the harvested values
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.addresses.clear();
+    }
+
+    /// The harvested values
+    pub fn values(&self) -> &BTreeSet<[u8; 32]> {
+        &self.values
+    }
+
+    /// The harvested addresses
+    pub fn addresses(&self) -> &BTreeSet<Address> {
+        &self.addresses
+    }
+}
+
+/// The minimal input found to make a fuzz test fail
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CounterExample {
+    pub calldata: Bytes,
+    // Token does not implement Serde (lol), so we just serialize the calldata
+    #[serde(skip)]
+    pub args: Vec<Token>,
+    /// The names of the function's parameters, in the order of `args`
+    #[serde(skip)]
+    pub names: Vec<String>,
+}
+
+impl CounterExample {
+    /// Decodes the arguments of a call of `func`, failing if the calldata is not one, e.g.
+    /// because the function changed since the calldata was generated
+    pub fn decode(func: &Function, calldata: Bytes) -> Result<Self> {
+        if calldata.len() < 4 || calldata[..4] != func.short_signature() {
+            eyre::bail!("the calldata is not a call of {}", func.name)
+        }
+        let args = func.decode_input(&calldata[4..])?;
+        let names = func.inputs.iter().map(|input| input.name.clone()).collect();
+        Ok(Self { calldata, args, names })
+    }
+}
+
+impl fmt::Display for CounterExample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match self.names.get(i).filter(|name| !name.is_empty()) {
+                Some(name) => format!("{} = {}", name, format_value(arg, 0)),
+                None => format_value(arg, 0),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", args.join(", "))
+    }
+}
+
+/// Formats a value for a counterexample, with one field per line for tuples so that nested
+/// structs stay legible
+pub(crate) fn format_value(token: &Token, indent: usize) -> String {
+    match token {
+        Token::Tuple(fields) => {
+            let padding = "  ".repeat(indent + 1);
+            let fields = fields
+                .iter()
+                .map(|field| format!("{}{},\n", padding, format_value(field, indent + 1)))
+                .collect::<String>();
+            format!("(\n{}{})", fields, "  ".repeat(indent))
+        }
+        Token::Array(values) | Token::FixedArray(values) => {
+            let values = values.iter().map(|value| format_value(value, indent)).collect::<Vec<_>>();
+            format!("[{}]", values.join(", "))
+        }
+        token => format_log_token(token),
+    }
+}
+
+/// The result of a fuzz test
+#[derive(Clone, Debug)]
+pub struct FuzzTestResult {
+    pub success: bool,
+    /// The minimal failing input, if the test failed
+    pub counterexample: Option<CounterExample>,
+    /// Why the test failed, e.g. the decoded revert reason of the minimal failing input
+    pub reason: Option<String>,
+    /// The seed the inputs were generated from, to reproduce the failure
+    pub seed: u64,
+    /// The gas used by the runs of the test, if it passed
+    pub gas: Option<FuzzGas>,
+}
+
+/// The mean and median gas used by the runs of a fuzz test
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuzzGas {
+    pub mean: u64,
+    pub median: u64,
+}
+
+impl FuzzGas {
+    /// Summarizes the gas used by each run, unless none ran
+    pub fn new(mut gas: Vec<u64>) -> Option<Self> {
+        if gas.is_empty() {
+            return None
+        }
+        gas.sort_unstable();
+        let mean = gas.iter().map(|&gas| gas as u128).sum::<u128>() / gas.len() as u128;
+        let mid = gas.len() / 2;
+        let median = if gas.len() % 2 == 0 {
+            ((gas[mid - 1] as u128 + gas[mid] as u128) / 2) as u64
+        } else {
+            gas[mid]
+        };
+        Some(Self { mean: mean as u64, median })
+    }
+}
+
+/// Calls `func` at `address` with as many inputs as configured, each time starting from the
+/// state the EVM was in before fuzzing. Some of the inputs are picked from the `dictionary`.
+/// The inputs which are rejected via the `assume` cheatcode are discarded, up to 1024 of them,
+/// after which the test fails. The run stops early if the EVM is interrupted.
+#[cfg(feature = "fuzz")]
+pub fn fuzz<S, E: Evm<S>>(
+    evm: &mut E,
+    config: &FuzzConfig,
+    func: &Function,
+    address: Address,
+    should_fail: bool,
+    dictionary: &FuzzDictionary,
+) -> Result<FuzzTestResult> {
+    let snapshot = evm.snapshot();
+    let evm = RefCell::new(evm);
+    let (mut runner, seed) = config.runner();
+    let strat = fuzz_calldata(func, config.strategy_config(), dictionary);
+    // the gas used by the runs which passed
+    let gas = RefCell::new(Vec::new());
+
+    let result = runner.run(&strat, |calldata| {
+        let mut evm = evm.borrow_mut();
+        // the remaining inputs are rejected once interrupted, which aborts the run quickly
+        if evm.is_interrupted() {
+            return Err(TestCaseError::reject(INTERRUPTED))
+        }
+        evm.restore(snapshot).expect("could not restore the state");
+        let gas_used = run_case(&mut **evm, address, calldata, should_fail)?;
+        gas.borrow_mut().push(gas_used);
+        Ok(())
+    });
+
+    if evm.borrow().is_interrupted() {
+        let reason = Some(INTERRUPTED.to_owned());
+        return Ok(FuzzTestResult { success: false, counterexample: None, reason, seed, gas: None })
+    }
+
+    let result = match result {
+        Ok(()) => FuzzTestResult {
+            success: true,
+            counterexample: None,
+            reason: None,
+            seed,
+            gas: FuzzGas::new(gas.into_inner()),
+        },
+        Err(TestError::Fail(reason, calldata)) => {
+            tracing::info!(seed, "Found minimal failing case: {}", hex::encode(&calldata));
+            let counterexample = CounterExample::decode(func, calldata)?;
+            FuzzTestResult {
+                success: false,
+                counterexample: Some(counterexample),
+                reason: Some(reason.message().to_owned()),
+                seed,
+                gas: None,
+            }
+        }
+        // too many inputs were rejected
+        Err(TestError::Abort(reason)) => FuzzTestResult {
+            success: false,
+            counterexample: None,
+            reason: Some(reason.message().to_owned()),
+            seed,
+            gas: None,
+        },
+    };
+    Ok(result)
+}
+
+/// Runs `func` with a single input from the current state, which is restored afterwards, e.g.
+/// to check whether the counterexample found by an earlier run with `seed` still fails.
+/// Returns the failure if it does.
+#[cfg(feature = "fuzz")]
+pub fn replay<S, E: Evm<S>>(
+    evm: &mut E,
+    func: &Function,
+    address: Address,
+    should_fail: bool,
+    calldata: &Bytes,
+    seed: u64,
+) -> Result<Option<FuzzTestResult>> {
+    let snapshot = evm.snapshot();
+    let outcome = run_case(evm, address, calldata.clone(), should_fail);
+    evm.restore(snapshot)?;
+    match outcome {
+        Err(TestCaseError::Fail(reason)) => Ok(Some(FuzzTestResult {
+            success: false,
+            counterexample: Some(CounterExample::decode(func, calldata.clone())?),
+            reason: Some(reason.message().to_owned()),
+            seed,
+            gas: None,
+        })),
+        // the input passes, or was rejected
+        _ => Ok(None),
+    }
+}
+
+/// Calls the function at `address` with the calldata, returning the gas used if the test
+/// passes. The inputs rejected via the `assume` cheatcode, or made once the EVM is
+/// interrupted, are rejected.
+#[cfg(feature = "fuzz")]
+fn run_case<S, E: Evm<S>>(
+    evm: &mut E,
+    address: Address,
+    calldata: Bytes,
+    should_fail: bool,
+) -> Result<u64, TestCaseError> {
+    let (retdata, reason, gas_used) = evm
+        .call_raw(Address::zero(), address, calldata, 0.into(), false, None)
+        .expect("could not make raw evm call");
+    if evm.is_interrupted() {
+        return Err(TestCaseError::reject(INTERRUPTED))
+    }
+
+    if E::is_fail(&reason) && retdata.as_ref() == ASSUME_MAGIC_REVERT {
+        return Err(TestCaseError::reject("assumption failed"))
+    }
+    let success = evm
+        .try_check_success(address, &reason, should_fail)
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+    if !success {
+        let reason = if E::is_fail(&reason) {
+            decode_revert(&retdata)
+        } else if should_fail {
+            "expected a failure".to_owned()
+        } else {
+            "assertion failed".to_owned()
+        };
+        return Err(TestCaseError::fail(reason))
+    }
+    Ok(gas_used)
+}
+
+/// Builds the strategy generating the calldata of `func`, with values for all its parameters
+#[cfg(feature = "fuzz")]
+pub fn fuzz_calldata<'a>(
+    func: &'a Function,
+    config: StrategyConfig,
+    dictionary: &FuzzDictionary,
+) -> impl Strategy<Value = Bytes> + 'a {
+    // We need to compose all the strategies generated for each parameter in all
+    // possible combinations
+    let strats = func
+        .inputs
+        .iter()
+        .map(|input| fuzz_param(&input.kind, config, dictionary))
+        .collect::<Vec<_>>();
+
+    strats.prop_map(move |tokens| func.encode_input(&tokens).unwrap().into())
+}
+
+/// Builds the strategy generating the values of a parameter type
+#[cfg(feature = "fuzz")]
+pub fn fuzz_param(
+    param: &ParamType,
+    config: StrategyConfig,
+    dictionary: &FuzzDictionary,
+) -> BoxedStrategy<Token> {
+    fuzz_param_at(param, config, dictionary, 0)
+}
+
+/// Builds the strategy of a parameter type nested in `depth` arrays or tuples
+#[cfg(feature = "fuzz")]
+fn fuzz_param_at(
+    param: &ParamType,
+    config: StrategyConfig,
+    dictionary: &FuzzDictionary,
+    depth: usize,
+) -> BoxedStrategy<Token> {
+    match param {
+        // The key to making this work is the `boxed()` call which type erases everything
+        // https://altsysrq.github.io/proptest-book/proptest/tutorial/transforming-strategies.html
+        ParamType::Address => {
+            let random = any::<[u8; 20]>().prop_map(|x| Token::Address(x.into())).boxed();
+            with_dictionary(random, config, dictionary, |word| {
+                Token::Address(Address::from_slice(&word[12..]))
+            })
+            // calls from the test contract to these addresses would not behave as expected
+            .prop_filter("excluded address", |token| match token {
+                Token::Address(addr) => !addr.is_zero() && *addr != *CHEATCODE_ADDRESS,
+                _ => true,
+            })
+            .boxed()
+        }
+        ParamType::Uint(n) => {
+            let random = if *n <= 128 {
+                let mask = if *n == 128 { u128::MAX } else { (1u128 << *n) - 1 };
+                any::<u128>().prop_map(move |x| Token::Uint((x & mask).into())).boxed()
+            } else {
+                let mask = uint_mask(*n);
+                any::<[u8; 32]>().prop_map(move |x| Token::Uint(U256::from(&x) & mask)).boxed()
+            };
+            let mask = uint_mask(*n);
+            with_dictionary(random, config, dictionary, move |word| {
+                Token::Uint(U256::from(&word) & mask)
+            })
+        }
+        ParamType::Int(n) => {
+            let n = *n;
+            let random = if n <= 128 {
+                // the arithmetic shift keeps the sign while bounding the value to the width
+                let shift = 128 - n;
+                any::<i128>()
+                    .prop_map(move |x| Token::Int(I256::from(x >> shift).into_raw()))
+                    .boxed()
+            } else {
+                any::<[u8; 32]>().prop_map(move |x| Token::Int(sign_extend(&x, n))).boxed()
+            };
+            with_dictionary(random, config, dictionary, move |word| {
+                Token::Int(sign_extend(&word, n))
+            })
+        }
+        ParamType::FixedBytes(n) => {
+            let n = *n;
+            let random =
+                proptest::collection::vec(any::<u8>(), n).prop_map(Token::FixedBytes).boxed();
+            with_dictionary(random, config, dictionary, move |word| {
+                Token::FixedBytes(word[..n].to_vec())
+            })
+        }
+        ParamType::Bool => any::<bool>().prop_map(Token::Bool).boxed(),
+        ParamType::Bytes => proptest::collection::vec(any::<u8>(), 0..=MAX_DYNAMIC_LEN)
+            .prop_map(Token::Bytes)
+            .boxed(),
+        ParamType::String => proptest::collection::vec(any::<char>(), 0..=MAX_DYNAMIC_LEN)
+            .prop_map(|chars| Token::String(chars.into_iter().collect()))
+            .boxed(),
+        // shrinking removes elements before shrinking the remaining ones
+        ParamType::Array(param) => {
+            let max_len = if depth < config.max_depth { config.max_array_len } else { 0 };
+            let element = fuzz_param_at(param, config, dictionary, depth + 1);
+            proptest::collection::vec(element, 0..=max_len).prop_map(Token::Array).boxed()
+        }
+        ParamType::FixedArray(param, len) => {
+            let element = fuzz_param_at(param, config, dictionary, depth + 1);
+            proptest::collection::vec(element, *len).prop_map(Token::FixedArray).boxed()
+        }
+        ParamType::Tuple(params) => params
+            .iter()
+            .map(|param| fuzz_param_at(param, config, dictionary, depth + 1))
+            .collect::<Vec<_>>()
+            .prop_map(Token::Tuple)
+            .boxed(),
+    }
+}
+
+/// Picks the values of the dictionary, converted to tokens by `from_word`, for the configured
+/// percentage of the values, and the values of the `random` strategy otherwise
+#[cfg(feature = "fuzz")]
+fn with_dictionary(
+    random: BoxedStrategy<Token>,
+    config: StrategyConfig,
+    dictionary: &FuzzDictionary,
+    from_word: impl Fn([u8; 32]) -> Token + 'static,
+) -> BoxedStrategy<Token> {
+    if dictionary.values.is_empty() || config.dictionary_weight == 0 {
+        return random
+    }
+    let words = dictionary.values.iter().copied().collect::<Vec<_>>();
+    let picked = proptest::sample::select(words).prop_map(from_word).boxed();
+    if config.dictionary_weight >= 100 {
+        return picked
+    }
+    Union::new_weighted(vec![
+        (config.dictionary_weight, picked),
+        (100 - config.dictionary_weight, random),
+    ])
+    .boxed()
+}
+
+/// The mask of the bits of an `n` bits wide integer
+#[cfg(feature = "fuzz")]
+fn uint_mask(n: usize) -> U256 {
+    if n == 256 {
+        U256::MAX
+    } else {
+        (U256::one() << n) - 1
+    }
+}
+
+/// Truncates a word to an `n` bits wide signed integer, sign-extended back to 256 bits
+#[cfg(feature = "fuzz")]
+fn sign_extend(word: &[u8; 32], n: usize) -> U256 {
+    let mask = uint_mask(n);
+    let value = U256::from(word) & mask;
+    if value.bit(n - 1) {
+        value | !mask
+    } else {
+        value
+    }
+}
+
+#[cfg(all(test, feature = "fuzz"))]
+mod tests {
+    use super::*;
+    use proptest::strategy::ValueTree;
+
+    fn sample(param: &ParamType) -> Vec<Token> {
+        let mut runner = TestRunner::deterministic();
+        let strat = fuzz_param(param, StrategyConfig::default(), &FuzzDictionary::default());
+        (0..256).map(|_| strat.new_tree(&mut runner).unwrap().current()).collect()
+    }
+
+    #[test]
+    fn summarizes_gas() {
+        assert_eq!(FuzzGas::new(Vec::new()), None);
+        assert_eq!(FuzzGas::new(vec![30, 10, 20]), Some(FuzzGas { mean: 20, median: 20 }));
+        assert_eq!(FuzzGas::new(vec![100, 1, 2, 5]), Some(FuzzGas { mean: 27, median: 3 }));
+        let max = FuzzGas::new(vec![u64::MAX, u64::MAX]).unwrap();
+        assert_eq!(max, FuzzGas { mean: u64::MAX, median: u64::MAX });
+    }
+
+    #[test]
+    fn values_are_bounded_by_their_type() {
+        for token in sample(&ParamType::Uint(24)) {
+            assert!(token.into_uint().unwrap() < U256::from(1u64 << 24));
+        }
+        let max = U256::one() << 199;
+        for token in sample(&ParamType::Int(200)) {
+            let value = I256::from_raw(token.into_int().unwrap());
+            assert!(value < I256::from_raw(max) && value >= -I256::from_raw(max));
+        }
+        for token in sample(&ParamType::Int(8)) {
+            let value = I256::from_raw(token.into_int().unwrap());
+            assert!(value < I256::from(128) && value >= I256::from(-128));
+        }
+        for token in sample(&ParamType::FixedBytes(4)) {
+            assert_eq!(token.into_fixed_bytes().unwrap().len(), 4);
+        }
+        for token in sample(&ParamType::Bytes) {
+            assert!(token.into_bytes().unwrap().len() <= MAX_DYNAMIC_LEN);
+        }
+        for token in sample(&ParamType::Address) {
+            let addr = token.into_address().unwrap();
+            assert!(!addr.is_zero() && addr != *CHEATCODE_ADDRESS);
+        }
+    }
+
+    #[test]
+    fn nested_values_are_bounded() {
+        let config = StrategyConfig::default();
+        let entry = ParamType::Tuple(vec![ParamType::Address, ParamType::Uint(256)]);
+        for token in sample(&ParamType::Array(Box::new(entry))) {
+            let entries = token.into_array().unwrap();
+            assert!(entries.len() <= config.max_array_len);
+            for entry in entries {
+                assert_eq!(entry.into_tuple().unwrap().len(), 2);
+            }
+        }
+
+        // dynamic arrays past the maximum depth are empty
+        let mut nested = ParamType::Uint(8);
+        for _ in 0..=config.max_depth {
+            nested = ParamType::Array(Box::new(nested));
+        }
+        // the number of nested non-empty arrays
+        fn depth(token: Token) -> usize {
+            match token {
+                Token::Array(values) if !values.is_empty() => {
+                    1 + values.into_iter().map(depth).max().unwrap_or_default()
+                }
+                _ => 0,
+            }
+        }
+        assert!(sample(&nested).into_iter().all(|token| depth(token) <= config.max_depth));
+
+        for token in sample(&ParamType::FixedArray(Box::new(ParamType::Bool), 3)) {
+            assert_eq!(token.into_fixed_array().unwrap().len(), 3);
+        }
+    }
+
+    #[test]
+    fn picks_values_from_dictionary() {
+        let mut dictionary = FuzzDictionary::default();
+        // nothing is harvested unless enabled
+        dictionary.insert([1u8; 32]);
+        assert!(dictionary.values().is_empty());
+
+        // PUSH2 0x1234, ADD, PUSH1 0x05, and a PUSH32 cut short by the end of the code
+        dictionary.enabled = true;
+        dictionary.insert_push_constants(&[0x61, 0x12, 0x34, 0x01, 0x60, 0x05, 0x7f, 0xff]);
+        let values = dictionary.values().iter().map(U256::from).collect::<Vec<_>>();
+        assert_eq!(values, vec![U256::from(5), U256::from(0xff), U256::from(0x1234)]);
+
+        let mut runner = TestRunner::deterministic();
+        let strat = fuzz_param(&ParamType::Uint(256), StrategyConfig::default(), &dictionary);
+        let picked = (0..256)
+            .map(|_| strat.new_tree(&mut runner).unwrap().current().into_uint().unwrap())
+            .filter(|value| values.contains(value))
+            .count();
+        assert!(picked > 50, "only {} values were picked from the dictionary", picked);
+    }
+
+    #[test]
+    fn seed_reproduces_inputs() {
+        let func = dapp_utils::get_func("function f(uint256[] x, address y, bytes z)").unwrap();
+        let generate = |config: FuzzConfig| {
+            let (mut runner, seed) = config.runner();
+            let strat = fuzz_calldata(&func, config.strategy_config(), &FuzzDictionary::default());
+            let inputs =
+                (0..32).map(|_| strat.new_tree(&mut runner).unwrap().current()).collect::<Vec<_>>();
+            (inputs, seed)
+        };
+
+        let (inputs, seed) = generate(FuzzConfig::deterministic());
+        assert_eq!(seed, DETERMINISTIC_SEED);
+        assert_eq!(generate(FuzzConfig::deterministic()).0, inputs);
+
+        // the seed of a random run can be pasted back to reproduce it
+        let (random, seed) = generate(FuzzConfig::default());
+        assert_ne!(random, inputs);
+        let reproduced = generate(FuzzConfig { seed: Some(seed), ..Default::default() }).0;
+        assert_eq!(reproduced, random);
+    }
+
+    #[test]
+    fn formats_counterexample() {
+        let counterexample = CounterExample {
+            calldata: Bytes::default(),
+            args: vec![Token::Uint(3.into()), Token::Address(Address::repeat_byte(1))],
+            names: vec!["amount".to_owned(), "to".to_owned()],
+        };
+        assert_eq!(
+            counterexample.to_string(),
+            format!("amount = 3, to = {:?}", Address::repeat_byte(1))
+        );
+
+        let entry = Token::Tuple(vec![Token::Address(Address::zero()), Token::Uint(1000.into())]);
+        let counterexample = CounterExample {
+            calldata: Bytes::default(),
+            args: vec![Token::Array(vec![entry])],
+            names: vec!["entries".to_owned()],
+        };
+        assert_eq!(
+            counterexample.to_string(),
+            format!("entries = [(\n  {:?},\n  1000,\n)]", Address::zero())
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Aggregation of the gas used by the calls to the deployed contracts, per function
+use crate::known_contracts::KnownContracts;
+use ethers::{
+    types::{Bytes, H256},
+    utils::keccak256,
+};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// Collects the gas used by the calls made during a test run, keyed by the code hash of the
+/// callee, so that calls to different deployments of the same contract are aggregated.
+/// Collecting requires hashing the code of every callee, so it does nothing unless enabled.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GasReport {
+    pub enabled: bool,
+    /// The gas used by every call, by code hash of the callee and function selector
+    calls: BTreeMap<H256, BTreeMap<[u8; 4], Vec<u64>>>,
+    /// The code of the callees by hash, to identify their contract
+    codes: BTreeMap<H256, Bytes>,
+}
+
+/// The statistics of the gas used by the calls to a function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasStats {
+    pub calls: usize,
+    pub min: u64,
+    pub max: u64,
+    pub mean: u64,
+    pub median: u64,
+}
+
+impl GasStats {
+    /// Computes the statistics of the provided samples, which must not be empty
+    fn new(samples: &[u64]) -> Self {
+        let mut samples = samples.to_vec();
+        samples.sort_unstable();
+        let len = samples.len();
+        let median = if len % 2 == 0 {
+            (samples[len / 2 - 1] + samples[len / 2]) / 2
+        } else {
+            samples[len / 2]
+        };
+        GasStats {
+            calls: len,
+            min: samples[0],
+            max: samples[len - 1],
+            mean: samples.iter().sum::<u64>() / len as u64,
+            median,
+        }
+    }
+}
+
+/// The gas statistics of the functions of a deployed contract
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContractGasReport {
+    pub name: String,
+    /// The statistics by function name, or by hex-encoded selector for unknown functions
+    pub functions: BTreeMap<String, GasStats>,
+}
+
+impl GasReport {
+    /// Records the gas used by a call to the contract with the provided code. Calls without a
+    /// selector (e.g. plain value transfers) are not recorded.
+    pub fn record(&mut self, code: &[u8], calldata: &[u8], gas_used: u64) {
+        if !self.enabled || calldata.len() < 4 {
+            return
+        }
+        let mut selector = [0u8; 4];
+        selector.copy_from_slice(&calldata[..4]);
+        let code_hash = H256::from(keccak256(code));
+        self.codes.entry(code_hash).or_insert_with(|| code.to_vec().into());
+        self.calls.entry(code_hash).or_default().entry(selector).or_default().push(gas_used);
+    }
+
+    /// Adds the calls recorded by another report, e.g. the one of a test run in parallel
+    pub fn merge(&mut self, other: GasReport) {
+        self.enabled |= other.enabled;
+        for (code_hash, functions) in other.calls {
+            let calls = self.calls.entry(code_hash).or_default();
+            for (selector, gas_used) in functions {
+                calls.entry(selector).or_default().extend(gas_used);
+            }
+        }
+        self.codes.extend(other.codes);
+    }
+
+    /// Aggregates the calls to the contracts identified by `known`, including the ones whose
+    /// code differs from their artifact's, e.g. by the values of their immutables. Calls to
+    /// unknown contracts and to test contracts (i.e. the ones with `test` or `invariant`
+    /// functions) are left out, so that the report is about the contracts under test
+    pub fn contracts(&self, known: &KnownContracts) -> Vec<ContractGasReport> {
+        let mut by_contract = BTreeMap::<_, BTreeMap<_, Vec<u64>>>::new();
+        for (code_hash, calls) in &self.calls {
+            let name = match known.identify(&self.codes[code_hash]) {
+                Some(name) => name,
+                None => continue,
+            };
+            let merged = by_contract.entry(name).or_default();
+            for (selector, samples) in calls {
+                merged.entry(*selector).or_default().extend(samples);
+            }
+        }
+        by_contract
+            .into_iter()
+            .filter_map(|(name, calls)| {
+                let abi = known.abi(name)?;
+                if abi
+                    .functions()
+                    .any(|func| func.name.starts_with("test") || func.name.starts_with("invariant"))
+                {
+                    return None
+                }
+                let functions = calls
+                    .iter()
+                    .map(|(selector, samples)| {
+                        let name = abi
+                            .functions()
+                            .find(|func| func.short_signature() == *selector)
+                            .map(|func| func.name.clone())
+                            .unwrap_or_else(|| format!("0x{}", hex::encode(selector)));
+                        (name, GasStats::new(samples))
+                    })
+                    .collect();
+                Some(ContractGasReport { name: name.clone(), functions })
+            })
+            .collect()
+    }
+
+    /// Formats the report of the known contracts as a table per contract, as dapptools does
+    pub fn format(&self, known: &KnownContracts) -> String {
+        let mut out = String::new();
+        for contract in self.contracts(known) {
+            let width = contract.functions.keys().map(String::len).max().unwrap_or_default();
+            let _ = writeln!(out, "{}", contract.name);
+            let _ = writeln!(
+                out,
+                "  {:<width$} {:>10} {:>10} {:>10} {:>10} {:>8}",
+                "function",
+                "min",
+                "avg",
+                "median",
+                "max",
+                "calls",
+                width = width.max("function".len())
+            );
+            for (name, stats) in &contract.functions {
+                let _ = writeln!(
+                    out,
+                    "  {:<width$} {:>10} {:>10} {:>10} {:>10} {:>8}",
+                    name,
+                    stats.min,
+                    stats.mean,
+                    stats.median,
+                    stats.max,
+                    stats.calls,
+                    width = width.max("function".len())
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{abi::parse_abi, utils::CompiledContract};
+
+    #[test]
+    fn aggregates_known_contracts() {
+        // PUSH32 <immutable> POP
+        let mut token = vec![0x7f];
+        token.extend([0; 32]);
+        token.push(0x50);
+        let test = vec![0x00];
+        let token_abi = parse_abi(&["function transfer(address,uint256)"]).unwrap();
+        let transfer = token_abi.function("transfer").unwrap().short_signature();
+        let test_abi = parse_abi(&["function testTransfer()"]).unwrap();
+        let compiled = vec![
+            (
+                "Token".to_owned(),
+                CompiledContract {
+                    abi: token_abi,
+                    bytecode: Default::default(),
+                    runtime_bytecode: token.clone().into(),
+                },
+            ),
+            (
+                "TokenTest".to_owned(),
+                CompiledContract {
+                    abi: test_abi.clone(),
+                    bytecode: Default::default(),
+                    runtime_bytecode: test.clone().into(),
+                },
+            ),
+        ];
+        let known = KnownContracts::new(compiled.iter().map(|(name, contract)| (name, contract)));
+
+        let mut report = GasReport::default();
+        // nothing is recorded unless enabled
+        report.record(&token, &transfer, 1);
+        assert_eq!(report, GasReport::default());
+
+        report.enabled = true;
+        for gas in [300, 100, 200] {
+            report.record(&token, &transfer, gas);
+        }
+        // another deployment, with a different value of the immutable
+        let mut deployed = token.clone();
+        deployed[32] = 1;
+        report.record(&deployed, &transfer, 400);
+        report.record(&token, &[0xde, 0xad, 0xbe, 0xef], 50);
+        let test_selector = test_abi.function("testTransfer").unwrap().short_signature();
+        report.record(&test, &test_selector, 1000);
+        report.record(&[0x01, 0x02], &transfer, 1000);
+
+        let contracts = report.contracts(&known);
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[0].name, "Token");
+        assert_eq!(
+            contracts[0].functions["transfer"],
+            GasStats { calls: 4, min: 100, max: 400, mean: 250, median: 250 }
+        );
+        assert_eq!(contracts[0].functions["0xdeadbeef"].calls, 1);
+        assert!(report.format(&known).starts_with("Token\n  function"));
+
+        // the calls of merged reports are aggregated together
+        let mut other = GasReport { enabled: true, ..Default::default() };
+        other.record(&token, &transfer, 500);
+        let mut merged = GasReport::default();
+        merged.merge(report.clone());
+        merged.merge(other);
+        assert!(merged.enabled);
+        let contracts = merged.contracts(&known);
+        assert_eq!(
+            contracts[0].functions["transfer"],
+            GasStats { calls: 5, min: 100, max: 500, mean: 300, median: 300 }
+        );
+    }
+}
@@ -0,0 +1,513 @@
+//! The cheatcodes ABI, shared by the EVM adapters, along with the cheatcodes which only
+//! override the block context, mutate accounts or rewrite callers, so that every adapter
+//! applies them the same way. The adapters intercept the calls to
+//! [`crate::CHEATCODE_ADDRESS`], decode them as [`HevmCall`]s and pass them to
+//! [`apply_state_cheatcode`], applying the cheatcodes which need access to their internals
+//! (e.g. expectations or snapshots) themselves.
+use ethers::{
+    abi::{parse_abi, Abi, Token},
+    types::{Address, H256, U256},
+};
+use once_cell::sync::Lazy;
+
+/// The code reported for the cheatcode address, which has none as calls to it are intercepted.
+/// It is non-empty so that Solidity's checks that the target of a high-level call has code
+/// pass. It consists of a single `STOP`.
+pub const CHEATCODE_STUB: [u8; 1] = [0x00];
+
+/// ABI-encodes the provided message as `Error(string)`, so that the caller can decode it via
+/// the same means as a regular Solidity `revert(reason)`
+pub fn encode_error(reason: &str) -> Vec<u8> {
+    let mut data = ethers::utils::id("Error(string)").to_vec();
+    data.extend_from_slice(&ethers::abi::encode(&[Token::String(reason.to_owned())]));
+    data
+}
+
+/// The ABI of all the cheatcodes which can be called at [`crate::CHEATCODE_ADDRESS`]
+pub static HEVM_ABI: Lazy<Abi> = Lazy::new(|| {
+    parse_abi(&[
+        "function warp(uint256)",
+        "function roll(uint256)",
+        "function store(address,bytes32,bytes32)",
+        "function load(address,bytes32) returns (bytes32)",
+        "function prank(address)",
+        "function startPrank(address)",
+        "function stopPrank()",
+        "function deal(address,uint256)",
+        "function etch(address,bytes)",
+        "function sign(uint256,bytes32) returns (uint8,bytes32,bytes32)",
+        "function addr(uint256) returns (address)",
+        "function ffi(string[]) returns (bytes)",
+        "function expectRevert()",
+        "function expectRevert(bytes)",
+        "function expectRevert(bytes4)",
+        "function expectEmit(bool,bool,bool,bool)",
+        "function expectCall(address,bytes)",
+        "function expectCall(address,uint256,bytes)",
+        "function mockCall(address,bytes,bytes)",
+        "function clearMockedCalls()",
+        "function record()",
+        "function accesses(address) returns (bytes32[],bytes32[])",
+        "function snapshot() returns (uint256)",
+        "function revertTo(uint256) returns (bool)",
+        "function fee(uint256)",
+        "function difficulty(uint256)",
+        "function coinbase(address)",
+        "function chainId(uint256)",
+        "function getNonce(address) returns (uint64)",
+        "function setNonce(address,uint64)",
+        "function label(address,string)",
+        "function assume(bool)",
+        "function readFile(string) returns (string)",
+        "function writeFile(string,string)",
+        "function envString(string) returns (string)",
+        "function envUint(string) returns (uint256)",
+        "function envAddress(string) returns (address)",
+        "function envBool(string) returns (bool)",
+        "function setBlockhash(uint256,bytes32)",
+        "function txGasPrice(uint256)",
+        "function transact(address,address,uint256,bytes) returns (bool,bytes)",
+        "function clearPendingExpectations()",
+    ])
+    .expect("could not parse the cheatcodes ABI")
+});
+
+/// A decoded call to the cheatcode address
+#[derive(Clone, Debug, PartialEq)]
+pub enum HevmCall {
+    /// `warp(uint256)`: sets `block.timestamp`
+    Warp(U256),
+    /// `roll(uint256)`: sets `block.number`
+    Roll(U256),
+    /// `store(address,bytes32,bytes32)`: sets the storage slot of an account
+    Store(Address, H256, H256),
+    /// `load(address,bytes32)`: reads the storage slot of an account
+    Load(Address, H256),
+    /// `prank(address)`: sets `msg.sender` for the next call
+    Prank(Address),
+    /// `startPrank(address)`: sets `msg.sender` for all calls until `stopPrank`
+    StartPrank(Address),
+    /// `stopPrank()`: stops an active prank
+    StopPrank,
+    /// `deal(address,uint256)`: sets the balance of an account
+    Deal(Address, U256),
+    /// `etch(address,bytes)`: sets the runtime bytecode of an account
+    Etch(Address, Vec<u8>),
+    /// `sign(uint256,bytes32)`: signs a digest with a private key
+    Sign(U256, H256),
+    /// `addr(uint256)`: derives the address of a private key
+    Addr(U256),
+    /// `ffi(string[])`: runs a command on the host and returns its output
+    Ffi(Vec<String>),
+    /// `expectRevert(bytes)`: expects the next call to revert with the provided data, or with
+    /// anything if it is empty (`expectRevert()`)
+    ExpectRevert(Vec<u8>),
+    /// `expectRevert(bytes4)`: expects the next call to revert with the provided selector
+    ExpectRevertSelector([u8; 4]),
+    /// `expectEmit(bool,bool,bool,bool)`: expects the next call to emit a log matching the
+    /// next one emitted by the caller
+    ExpectEmit([bool; 4]),
+    /// `expectCall(address,bytes)` and `expectCall(address,uint256,bytes)`: expects a call to
+    /// be made to the address with the calldata prefix (and value)
+    ExpectCall(Address, Vec<u8>, Option<U256>),
+    /// `mockCall(address,bytes,bytes)`: makes calls to the address with the calldata prefix
+    /// return the provided data
+    MockCall(Address, Vec<u8>, Vec<u8>),
+    /// `clearMockedCalls()`: removes all the mocks
+    ClearMockedCalls,
+    /// `record()`: starts recording storage accesses
+    Record,
+    /// `accesses(address)`: returns the storage slots read and written by an address
+    Accesses(Address),
+    /// `snapshot()`: snapshots the state, returning the snapshot's id
+    Snapshot,
+    /// `revertTo(uint256)`: reverts the state to a snapshot, returning whether it existed
+    RevertTo(U256),
+    /// `fee(uint256)`: sets the base fee
+    Fee(U256),
+    /// `difficulty(uint256)`: sets `block.difficulty`
+    Difficulty(U256),
+    /// `coinbase(address)`: sets `block.coinbase`
+    Coinbase(Address),
+    /// `chainId(uint256)`: sets the chain id returned by `CHAINID`
+    ChainId(U256),
+    /// `getNonce(address)`: returns the nonce of an account
+    GetNonce(Address),
+    /// `setNonce(address,uint64)`: increases the nonce of an account
+    SetNonce(Address, u64),
+    /// `label(address,string)`: sets a human-readable name for an address
+    Label(Address, String),
+    /// `assume(bool)`: rejects the input of the call if the condition is false
+    Assume(bool),
+    /// `readFile(string)`: reads a file below the filesystem root
+    ReadFile(String),
+    /// `writeFile(string,string)`: writes a file below the filesystem root
+    WriteFile(String, String),
+    /// `envString(string)`: reads an environment variable
+    EnvString(String),
+    /// `envUint(string)`: reads an environment variable as a decimal or `0x`-hex number
+    EnvUint(String),
+    /// `envAddress(string)`: reads an environment variable as an address
+    EnvAddress(String),
+    /// `envBool(string)`: reads an environment variable as `true` or `false`
+    EnvBool(String),
+    /// `setBlockhash(uint256,bytes32)`: sets the hash returned by `BLOCKHASH` for a block
+    SetBlockhash(U256, H256),
+    /// `txGasPrice(uint256)`: sets `tx.gasprice`
+    TxGasPrice(U256),
+    /// `transact(address,address,uint256,bytes)`: makes a call with an arbitrary sender and
+    /// value
+    Transact(Address, Address, U256, Vec<u8>),
+    /// `clearPendingExpectations()`: drops all the expected reverts, logs and calls
+    ClearPendingExpectations,
+}
+
+impl HevmCall {
+    /// Whether the cheatcode leaves the state untouched, so that it can be used from a static
+    /// context, e.g. from a `view` function
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            HevmCall::Load(..) |
+                HevmCall::Sign(..) |
+                HevmCall::Addr(_) |
+                HevmCall::Accesses(_) |
+                HevmCall::GetNonce(_) |
+                HevmCall::Assume(_) |
+                HevmCall::ReadFile(_) |
+                HevmCall::EnvString(_) |
+                HevmCall::EnvUint(_) |
+                HevmCall::EnvAddress(_) |
+                HevmCall::EnvBool(_)
+        )
+    }
+
+    /// Decodes the calldata of a call made to the cheatcode address by matching its selector
+    /// against [`HEVM_ABI`]. Errors if the selector is not a known cheatcode or if the arguments
+    /// are not properly ABI-encoded, which can happen e.g. when fuzzing.
+    pub fn decode(input: &[u8]) -> eyre::Result<Self> {
+        if input.len() < 4 {
+            eyre::bail!("cheatcode calldata too short: 0x{}", hex::encode(input))
+        }
+        let (selector, data) = input.split_at(4);
+
+        let func = HEVM_ABI
+            .functions()
+            .find(|func| func.short_signature() == selector)
+            .ok_or_else(|| eyre::eyre!("unknown cheatcode 0x{}", hex::encode(selector)))?;
+        let args = func
+            .decode_input(data)
+            .map_err(|err| eyre::eyre!("invalid arguments for `{}`: {}", func.signature(), err))?;
+
+        Ok(match (func.name.as_str(), args.as_slice()) {
+            ("warp", [Token::Uint(timestamp)]) => HevmCall::Warp(*timestamp),
+            ("roll", [Token::Uint(number)]) => HevmCall::Roll(*number),
+            ("store", [Token::Address(who), Token::FixedBytes(slot), Token::FixedBytes(value)]) => {
+                HevmCall::Store(*who, H256::from_slice(slot), H256::from_slice(value))
+            }
+            ("load", [Token::Address(who), Token::FixedBytes(slot)]) => {
+                HevmCall::Load(*who, H256::from_slice(slot))
+            }
+            ("prank", [Token::Address(who)]) => HevmCall::Prank(*who),
+            ("startPrank", [Token::Address(who)]) => HevmCall::StartPrank(*who),
+            ("stopPrank", []) => HevmCall::StopPrank,
+            ("deal", [Token::Address(who), Token::Uint(balance)]) => HevmCall::Deal(*who, *balance),
+            ("etch", [Token::Address(who), Token::Bytes(code)]) => {
+                HevmCall::Etch(*who, code.clone())
+            }
+            ("sign", [Token::Uint(private_key), Token::FixedBytes(digest)]) => {
+                HevmCall::Sign(*private_key, H256::from_slice(digest))
+            }
+            ("addr", [Token::Uint(private_key)]) => HevmCall::Addr(*private_key),
+            ("ffi", [Token::Array(args)]) => {
+                let args = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Token::String(arg) => Ok(arg.clone()),
+                        _ => Err(eyre::eyre!("invalid arguments for `{}`", func.signature())),
+                    })
+                    .collect::<eyre::Result<Vec<_>>>()?;
+                HevmCall::Ffi(args)
+            }
+            ("expectRevert", []) => HevmCall::ExpectRevert(Vec::new()),
+            ("expectRevert", [Token::Bytes(data)]) => HevmCall::ExpectRevert(data.clone()),
+            ("expectRevert", [Token::FixedBytes(selector)]) => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(&selector[..4]);
+                HevmCall::ExpectRevertSelector(bytes)
+            }
+            (
+                "expectEmit",
+                [Token::Bool(topic1), Token::Bool(topic2), Token::Bool(topic3), Token::Bool(data)],
+            ) => HevmCall::ExpectEmit([*topic1, *topic2, *topic3, *data]),
+            ("expectCall", [Token::Address(target), Token::Bytes(data)]) => {
+                HevmCall::ExpectCall(*target, data.clone(), None)
+            }
+            ("expectCall", [Token::Address(target), Token::Uint(value), Token::Bytes(data)]) => {
+                HevmCall::ExpectCall(*target, data.clone(), Some(*value))
+            }
+            ("mockCall", [Token::Address(target), Token::Bytes(data), Token::Bytes(retdata)]) => {
+                HevmCall::MockCall(*target, data.clone(), retdata.clone())
+            }
+            ("clearMockedCalls", []) => HevmCall::ClearMockedCalls,
+            ("record", []) => HevmCall::Record,
+            ("accesses", [Token::Address(who)]) => HevmCall::Accesses(*who),
+            ("snapshot", []) => HevmCall::Snapshot,
+            ("revertTo", [Token::Uint(id)]) => HevmCall::RevertTo(*id),
+            ("fee", [Token::Uint(fee)]) => HevmCall::Fee(*fee),
+            ("difficulty", [Token::Uint(difficulty)]) => HevmCall::Difficulty(*difficulty),
+            ("coinbase", [Token::Address(coinbase)]) => HevmCall::Coinbase(*coinbase),
+            ("chainId", [Token::Uint(chain_id)]) => HevmCall::ChainId(*chain_id),
+            ("getNonce", [Token::Address(who)]) => HevmCall::GetNonce(*who),
+            ("setNonce", [Token::Address(who), Token::Uint(nonce)]) => {
+                // the words of `uint64`s are decoded without checking that they fit
+                if *nonce > U256::from(u64::MAX) {
+                    eyre::bail!(
+                        "invalid arguments for `{}`: {} is not a uint64",
+                        func.signature(),
+                        nonce
+                    )
+                }
+                HevmCall::SetNonce(*who, nonce.as_u64())
+            }
+            ("label", [Token::Address(who), Token::String(label)]) => {
+                HevmCall::Label(*who, label.clone())
+            }
+            ("assume", [Token::Bool(condition)]) => HevmCall::Assume(*condition),
+            ("readFile", [Token::String(path)]) => HevmCall::ReadFile(path.clone()),
+            ("writeFile", [Token::String(path), Token::String(data)]) => {
+                HevmCall::WriteFile(path.clone(), data.clone())
+            }
+            ("envString", [Token::String(name)]) => HevmCall::EnvString(name.clone()),
+            ("envUint", [Token::String(name)]) => HevmCall::EnvUint(name.clone()),
+            ("envAddress", [Token::String(name)]) => HevmCall::EnvAddress(name.clone()),
+            ("envBool", [Token::String(name)]) => HevmCall::EnvBool(name.clone()),
+            ("setBlockhash", [Token::Uint(number), Token::FixedBytes(hash)]) => {
+                HevmCall::SetBlockhash(*number, H256::from_slice(hash))
+            }
+            ("txGasPrice", [Token::Uint(gas_price)]) => HevmCall::TxGasPrice(*gas_price),
+            (
+                "transact",
+                [Token::Address(from), Token::Address(to), Token::Uint(value), Token::Bytes(data)],
+            ) => HevmCall::Transact(*from, *to, *value, data.clone()),
+            ("clearPendingExpectations", []) => HevmCall::ClearPendingExpectations,
+            _ => eyre::bail!("invalid arguments for `{}`", func.signature()),
+        })
+    }
+}
+
+/// A `msg.sender` override which is applied to the calls made by `prank_caller`, either only
+/// to the next one (`prank`) or to all of them until `stopPrank` is called (`startPrank`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prank {
+    /// The address which called `prank`
+    pub prank_caller: Address,
+    /// The address which will be used as the caller instead
+    pub new_caller: Address,
+    /// The call depth at which `prank` was called, so that only calls made at this depth
+    /// get their sender rewritten
+    pub depth: usize,
+    /// Whether the prank is cleared after the first call it applies to
+    pub single_call: bool,
+}
+
+/// If there is an active prank and the call is made by the pranking contract at the depth it
+/// started the prank from, returns the pranked address instead of the caller, consuming the
+/// prank if it was a single-call one. Calls made by the callee are one level deeper and are
+/// left untouched.
+pub fn prank_caller(prank: &mut Option<Prank>, caller: Address, depth: usize) -> Address {
+    match *prank {
+        Some(active) if active.prank_caller == caller && active.depth == depth => {
+            if active.single_call {
+                *prank = None;
+            }
+            active.new_caller
+        }
+        _ => caller,
+    }
+}
+
+/// The state mutations needed by the cheatcodes which every adapter supports
+pub trait HevmState {
+    /// Overrides `block.timestamp`
+    fn warp(&mut self, timestamp: U256);
+
+    /// Overrides `block.number`
+    fn roll(&mut self, number: U256);
+
+    /// Writes a storage slot, creating the account if it does not exist yet. The write must be
+    /// visible to the rest of the transaction.
+    fn store(&mut self, who: Address, slot: H256, value: H256);
+
+    /// Reads a storage slot, including the writes made earlier in the transaction. The read
+    /// must not be recorded as an access of the account.
+    fn load(&self, who: Address, slot: H256) -> H256;
+
+    /// Sets the balance of an account, creating it if it does not exist yet
+    fn deal(&mut self, who: Address, balance: U256);
+
+    /// Sets the code of an account, creating it if it does not exist yet. Empty code makes it
+    /// an EOA again.
+    fn etch(&mut self, who: Address, code: Vec<u8>);
+
+    /// The active prank, which the adapter applies to the calls via [`prank_caller`]
+    fn prank_mut(&mut self) -> &mut Option<Prank>;
+}
+
+/// Applies the cheatcodes which only need the [`HevmState`] mutations, returning their
+/// ABI-encoded output or the reason to revert with. Returns `None` for the other cheatcodes,
+/// which the adapters apply themselves. `depth` is the depth of the frame which called the
+/// cheatcode, as the adapter measures it when applying pranks.
+pub fn apply_state_cheatcode<S: HevmState>(
+    state: &mut S,
+    call: &HevmCall,
+    msg_sender: Address,
+    depth: usize,
+) -> Option<Result<Vec<u8>, String>> {
+    let retdata = match *call {
+        HevmCall::Warp(timestamp) => {
+            state.warp(timestamp);
+            Vec::new()
+        }
+        HevmCall::Roll(number) => {
+            state.roll(number);
+            Vec::new()
+        }
+        HevmCall::Store(who, slot, value) => {
+            state.store(who, slot, value);
+            Vec::new()
+        }
+        HevmCall::Load(who, slot) => {
+            let value = state.load(who, slot);
+            ethers::abi::encode(&[Token::FixedBytes(value.as_bytes().to_vec())])
+        }
+        HevmCall::Deal(who, balance) => {
+            state.deal(who, balance);
+            Vec::new()
+        }
+        HevmCall::Etch(who, ref code) => {
+            state.etch(who, code.clone());
+            Vec::new()
+        }
+        HevmCall::Prank(new_caller) | HevmCall::StartPrank(new_caller) => {
+            let single_call = matches!(call, HevmCall::Prank(_));
+            let prank = state.prank_mut();
+            if prank.is_some() {
+                let reason = if single_call {
+                    "cannot `prank` again before the pranked call was made"
+                } else {
+                    "cannot `startPrank` while another prank is active"
+                };
+                return Some(Err(reason.to_owned()))
+            }
+            *prank = Some(Prank { prank_caller: msg_sender, new_caller, depth, single_call });
+            Vec::new()
+        }
+        HevmCall::StopPrank => {
+            if state.prank_mut().take().is_none() {
+                return Some(Err("no active prank to stop".to_owned()))
+            }
+            Vec::new()
+        }
+        _ => return None,
+    };
+    Some(Ok(retdata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Default)]
+    struct State {
+        timestamp: U256,
+        storage: BTreeMap<(Address, H256), H256>,
+        prank: Option<Prank>,
+    }
+
+    impl HevmState for State {
+        fn warp(&mut self, timestamp: U256) {
+            self.timestamp = timestamp;
+        }
+
+        fn roll(&mut self, _number: U256) {}
+
+        fn store(&mut self, who: Address, slot: H256, value: H256) {
+            self.storage.insert((who, slot), value);
+        }
+
+        fn load(&self, who: Address, slot: H256) -> H256 {
+            self.storage.get(&(who, slot)).copied().unwrap_or_default()
+        }
+
+        fn deal(&mut self, _who: Address, _balance: U256) {}
+
+        fn etch(&mut self, _who: Address, _code: Vec<u8>) {}
+
+        fn prank_mut(&mut self) -> &mut Option<Prank> {
+            &mut self.prank
+        }
+    }
+
+    fn apply(state: &mut State, sig: &str, args: &[Token]) -> Option<Result<Vec<u8>, String>> {
+        let func = HEVM_ABI.functions().find(|func| func.signature() == sig).unwrap();
+        let call = HevmCall::decode(&func.encode_input(args).unwrap()).unwrap();
+        apply_state_cheatcode(state, &call, Address::repeat_byte(1), 1)
+    }
+
+    #[test]
+    fn applies_state_cheatcodes() {
+        let mut state = State::default();
+        apply(&mut state, "warp(uint256)", &[Token::Uint(100.into())]).unwrap().unwrap();
+        assert_eq!(state.timestamp, 100.into());
+
+        let (who, slot) = (Token::Address(Address::repeat_byte(2)), Token::FixedBytes(vec![1; 32]));
+        let value = Token::FixedBytes(vec![3; 32]);
+        apply(&mut state, "store(address,bytes32,bytes32)", &[who.clone(), slot.clone(), value])
+            .unwrap()
+            .unwrap();
+        let loaded = apply(&mut state, "load(address,bytes32)", &[who, slot]).unwrap().unwrap();
+        assert_eq!(loaded, vec![3; 32]);
+
+        // the cheatcodes which need the adapter's internals are left to it
+        assert!(apply(&mut state, "snapshot()", &[]).is_none());
+    }
+
+    #[test]
+    fn pranks() {
+        let mut state = State::default();
+        let new_caller = Address::repeat_byte(9);
+        apply(&mut state, "prank(address)", &[Token::Address(new_caller)]).unwrap().unwrap();
+        let err = apply(&mut state, "startPrank(address)", &[Token::Address(new_caller)]);
+        assert_eq!(err, Some(Err("cannot `startPrank` while another prank is active".to_owned())));
+
+        // only the calls made by the pranking contract at its depth are pranked, once
+        let caller = Address::repeat_byte(1);
+        assert_eq!(prank_caller(&mut state.prank, caller, 2), caller);
+        assert_eq!(prank_caller(&mut state.prank, Address::zero(), 1), Address::zero());
+        assert_eq!(prank_caller(&mut state.prank, caller, 1), new_caller);
+        assert_eq!(prank_caller(&mut state.prank, caller, 1), caller);
+
+        let err = apply(&mut state, "stopPrank()", &[]);
+        assert_eq!(err, Some(Err("no active prank to stop".to_owned())));
+    }
+
+    #[test]
+    fn rejects_nonces_above_uint64() {
+        let func = HEVM_ABI.function("setNonce").unwrap();
+        let who = Address::repeat_byte(2);
+        let calldata =
+            func.encode_input(&[Token::Address(who), Token::Uint(u64::MAX.into())]).unwrap();
+        assert_eq!(HevmCall::decode(&calldata).unwrap(), HevmCall::SetNonce(who, u64::MAX));
+
+        // the calldata is valid, but the nonce cannot be set
+        let nonce = Token::Uint(U256::from(u64::MAX) + 1);
+        let calldata = func.encode_input(&[Token::Address(who), nonce]).unwrap();
+        let err = HevmCall::decode(&calldata).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid arguments for `setNonce(address,uint64)`: 18446744073709551616 is not a uint64"
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Invariant testing, by calling the functions of the contracts deployed during `setUp()` in
+//! random sequences and checking the `invariant` functions of the test contract along the way
+use crate::{
+    fuzz::{format_value, fuzz_param, FuzzConfig, FuzzDictionary},
+    Evm, INTERRUPTED,
+};
+use dapp_utils::decode_revert;
+use ethers::{
+    abi::{Abi, Function, StateMutability, Token},
+    types::{Address, Bytes},
+};
+use eyre::Result;
+use proptest::{
+    prelude::*,
+    strategy::Union,
+    test_runner::{TestCaseError, TestError},
+};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, fmt};
+
+/// Configures the sequences of calls made by the invariant tests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantConfig {
+    /// The maximum number of calls of a sequence
+    pub depth: usize,
+    /// The addresses the calls are made from, which must not be empty
+    pub senders: Vec<Address>,
+    /// Whether the invariants are checked after every call of a sequence, rather than only
+    /// after its last call
+    pub check_every_call: bool,
+}
+
+impl Default for InvariantConfig {
+    fn default() -> Self {
+        InvariantConfig {
+            depth: 15,
+            senders: vec![
+                Address::repeat_byte(0x10),
+                Address::repeat_byte(0x20),
+                Address::repeat_byte(0x30),
+            ],
+            check_every_call: true,
+        }
+    }
+}
+
+/// A contract whose functions are called by the invariant tests
+#[derive(Clone, Debug)]
+pub struct InvariantTarget {
+    pub address: Address,
+    pub name: String,
+    pub abi: Abi,
+}
+
+/// A call of the sequence which broke an invariant
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvariantCall {
+    pub sender: Address,
+    pub target: Address,
+    pub calldata: Bytes,
+    /// The name of the target contract
+    #[serde(skip)]
+    pub contract: String,
+    /// The called function with its decoded arguments, e.g. `transfer(0x.., 3)`
+    #[serde(skip)]
+    pub call: String,
+}
+
+impl InvariantCall {
+    /// Decodes a call of one of the functions of the target, failing if the calldata is not
+    /// one, e.g. because the target changed since the calldata was generated
+    pub fn decode(sender: Address, target: &InvariantTarget, calldata: Bytes) -> Result<Self> {
+        let func = target
+            .abi
+            .functions()
+            .find(|func| calldata.len() >= 4 && calldata[..4] == func.short_signature())
+            .ok_or_else(|| eyre::eyre!("the calldata is not a call of {}", target.name))?;
+        let args = func.decode_input(&calldata[4..])?;
+        Ok(Self {
+            sender,
+            target: target.address,
+            calldata,
+            contract: target.name.clone(),
+            call: format_call(func, &args),
+        })
+    }
+}
+
+impl fmt::Display for InvariantCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "from {:?} to {}({:?}): {}", self.sender, self.contract, self.target, self.call)
+    }
+}
+
+/// The result of an invariant test
+#[derive(Clone, Debug)]
+pub struct InvariantTestResult {
+    pub success: bool,
+    /// The name of the invariant which was broken, if any
+    pub broken: Option<String>,
+    /// The minimal sequence of calls found to break the invariant, which is empty if it was
+    /// already broken after `setUp()`
+    pub sequence: Option<Vec<InvariantCall>>,
+    /// Why the invariant was broken, e.g. the decoded revert reason of the invariant function
+    pub reason: Option<String>,
+    /// The seed the sequences were generated from, to reproduce the failure
+    pub seed: u64,
+}
+
+/// Calls the non-view functions of the `targets` in as many random sequences as configured,
+/// each time starting from the state the EVM was in before, and checks that
+/// the `invariants` of the test contract at `address` return true. The individual calls may
+/// revert. The sequences which break an invariant are shrunk by removing calls. The run stops
+/// early if the EVM is interrupted. Errors if no senders are configured.
+#[allow(clippy::too_many_arguments)]
+pub fn invariant<S, E: Evm<S>>(
+    evm: &mut E,
+    fuzz_config: &FuzzConfig,
+    address: Address,
+    invariants: &[Function],
+    targets: &[InvariantTarget],
+    config: &InvariantConfig,
+    dictionary: &FuzzDictionary,
+) -> Result<InvariantTestResult> {
+    if config.senders.is_empty() {
+        eyre::bail!("the invariant tests need at least one sender to make the calls from")
+    }
+    let snapshot = evm.snapshot();
+    let (mut runner, seed) = fuzz_config.runner();
+
+    // the invariants may not even hold in the initial state
+    if let Some((_, broken, reason)) = execute(evm, address, invariants, &[], config)? {
+        return Ok(InvariantTestResult {
+            success: false,
+            broken: Some(broken),
+            sequence: Some(Vec::new()),
+            reason: Some(reason),
+            seed,
+        })
+    }
+    let call = match call_strategy(targets, fuzz_config, config, dictionary) {
+        Some(call) => call,
+        // there is nothing to call
+        None => {
+            return Ok(InvariantTestResult {
+                success: true,
+                broken: None,
+                sequence: None,
+                reason: None,
+                seed,
+            })
+        }
+    };
+    let strat = proptest::collection::vec(call, 1..=config.depth.max(1));
+
+    let evm = RefCell::new(evm);
+    let result = runner.run(&strat, |calls| {
+        let mut evm = evm.borrow_mut();
+        // the remaining sequences are rejected once interrupted, which aborts the run quickly
+        if evm.is_interrupted() {
+            return Err(TestCaseError::reject(INTERRUPTED))
+        }
+        evm.restore(snapshot).expect("could not restore the state");
+        let result = execute(&mut **evm, address, invariants, &calls, config);
+        if evm.is_interrupted() {
+            return Err(TestCaseError::reject(INTERRUPTED))
+        }
+        match result {
+            Ok(None) => Ok(()),
+            Ok(Some((_, broken, reason))) => {
+                Err(TestCaseError::fail(format!("{}: {}", broken, reason)))
+            }
+            Err(err) => Err(TestCaseError::fail(err.to_string())),
+        }
+    });
+    let evm = evm.into_inner();
+    if evm.is_interrupted() {
+        evm.restore(snapshot)?;
+        return Ok(InvariantTestResult {
+            success: false,
+            broken: None,
+            sequence: None,
+            reason: Some(INTERRUPTED.to_owned()),
+            seed,
+        })
+    }
+
+    let result = match result {
+        Ok(()) => {
+            InvariantTestResult { success: true, broken: None, sequence: None, reason: None, seed }
+        }
+        Err(TestError::Fail(reason, mut calls)) => {
+            // replay the minimal sequence to find out which call broke which invariant
+            evm.restore(snapshot)?;
+            match execute(evm, address, invariants, &calls, config)? {
+                Some((len, broken, reason)) => {
+                    calls.truncate(len);
+                    InvariantTestResult {
+                        success: false,
+                        broken: Some(broken),
+                        sequence: Some(calls),
+                        reason: Some(reason),
+                        seed,
+                    }
+                }
+                None => InvariantTestResult {
+                    success: false,
+                    broken: None,
+                    sequence: Some(calls),
+                    reason: Some(reason.message().to_owned()),
+                    seed,
+                },
+            }
+        }
+        Err(TestError::Abort(reason)) => InvariantTestResult {
+            success: false,
+            broken: None,
+            sequence: None,
+            reason: Some(reason.message().to_owned()),
+            seed,
+        },
+    };
+    evm.restore(snapshot)?;
+    Ok(result)
+}
+
+/// Makes the calls from the current state, which is restored afterwards, checking the
+/// invariants as configured, e.g. to check whether the sequence which broke an invariant in an
+/// earlier run still does. Returns the calls made until the first broken invariant, along with
+/// its name and why it was broken.
+pub fn replay<S, E: Evm<S>>(
+    evm: &mut E,
+    address: Address,
+    invariants: &[Function],
+    calls: &[InvariantCall],
+    config: &InvariantConfig,
+) -> Result<Option<(Vec<InvariantCall>, String, String)>> {
+    let snapshot = evm.snapshot();
+    let result = execute(evm, address, invariants, calls, config);
+    evm.restore(snapshot)?;
+    Ok(result?.map(|(len, broken, reason)| (calls[..len].to_vec(), broken, reason)))
+}
+
+/// Makes the calls, checking the invariants as configured. Returns the number of calls made
+/// before the first broken invariant, along with its name and why it was broken.
+fn execute<S, E: Evm<S>>(
+    evm: &mut E,
+    address: Address,
+    invariants: &[Function],
+    calls: &[InvariantCall],
+    config: &InvariantConfig,
+) -> Result<Option<(usize, String, String)>> {
+    if calls.is_empty() {
+        return Ok(
+            check_invariants(evm, address, invariants)?.map(|(broken, reason)| (0, broken, reason))
+        )
+    }
+    for (i, call) in calls.iter().enumerate() {
+        // the calls are expected to revert sometimes, e.g. with random arguments
+        evm.call_raw(call.sender, call.target, call.calldata.clone(), 0.into(), false, None)?;
+        if config.check_every_call || i + 1 == calls.len() {
+            if let Some((broken, reason)) = check_invariants(evm, address, invariants)? {
+                return Ok(Some((i + 1, broken, reason)))
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Calls the invariants, returning the name of the first one which does not return true along
+/// with why
+fn check_invariants<S, E: Evm<S>>(
+    evm: &mut E,
+    address: Address,
+    invariants: &[Function],
+) -> Result<Option<(String, String)>> {
+    for func in invariants {
+        let calldata = func.encode_input(&[])?;
+        let (retdata, status, _) =
+            evm.call_raw(Address::zero(), address, calldata.into(), 0.into(), false, None)?;
+        let holds = E::is_success(&status) &&
+            matches!(func.decode_output(&retdata).as_deref(), Ok([Token::Bool(true)]));
+        if !holds {
+            let reason = if E::is_fail(&status) {
+                decode_revert(&retdata)
+            } else {
+                "invariant broken".to_owned()
+            };
+            return Ok(Some((func.name.clone(), reason)))
+        }
+    }
+    Ok(None)
+}
+
+/// Formats a call with its decoded arguments, e.g. `transfer(0x.., 3)`
+fn format_call(func: &Function, args: &[Token]) -> String {
+    let formatted = args.iter().map(|arg| format_value(arg, 0)).collect::<Vec<_>>();
+    format!("{}({})", func.name, formatted.join(", "))
+}
+
+/// Builds the strategy generating a call of one of the non-view functions of the targets, or
+/// `None` if there are none
+fn call_strategy(
+    targets: &[InvariantTarget],
+    fuzz_config: &FuzzConfig,
+    config: &InvariantConfig,
+    dictionary: &FuzzDictionary,
+) -> Option<BoxedStrategy<InvariantCall>> {
+    let senders = proptest::sample::select(config.senders.clone());
+    let strats = targets
+        .iter()
+        .flat_map(|target| target.abi.functions().map(move |func| (target, func)))
+        .filter(|(_, func)| {
+            !matches!(func.state_mutability, StateMutability::View | StateMutability::Pure)
+        })
+        .map(|(target, func)| {
+            let params = func
+                .inputs
+                .iter()
+                .map(|input| fuzz_param(&input.kind, fuzz_config.strategy_config(), dictionary))
+                .collect::<Vec<_>>();
+            let (address, contract, func) = (target.address, target.name.clone(), func.clone());
+            (senders.clone(), params)
+                .prop_map(move |(sender, args)| InvariantCall {
+                    sender,
+                    target: address,
+                    calldata: func.encode_input(&args).unwrap().into(),
+                    contract: contract.clone(),
+                    call: format_call(&func, &args),
+                })
+                .boxed()
+        })
+        .collect::<Vec<_>>();
+
+    if strats.is_empty() {
+        None
+    } else {
+        Some(Union::new(strats).boxed())
+    }
+}
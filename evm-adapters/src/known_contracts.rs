@@ -0,0 +1,246 @@
+//! Identification of deployed contracts by matching their code against the compiled artifacts,
+//! e.g. to name the addresses of traces
+use ethers::{
+    abi::Abi,
+    types::H256,
+    utils::{keccak256, CompiledContract},
+};
+use std::{collections::BTreeMap, ops::Range};
+
+/// The name of a compiled contract
+pub type ArtifactId = String;
+
+/// The runtime code of a compiled contract, along with the regions which differ once deployed
+#[derive(Clone, Debug)]
+struct Artifact {
+    id: ArtifactId,
+    abi: Abi,
+    /// The runtime code, without its metadata hash
+    code: Vec<u8>,
+    /// The regions of the code which are only filled at deployment, in order: the values of
+    /// immutables and the addresses of libraries
+    masked: Vec<Range<usize>>,
+}
+
+impl Artifact {
+    fn matches(&self, code: &[u8]) -> bool {
+        if code.len() != self.code.len() {
+            return false
+        }
+        let mut start = 0;
+        for range in &self.masked {
+            if code[start..range.start] != self.code[start..range.start] {
+                return false
+            }
+            start = range.end;
+        }
+        code[start..] == self.code[start..]
+    }
+}
+
+/// The compiled contracts, to identify the contracts deployed from them.
+///
+/// Deployed code rarely equals the runtime code of its artifact: the metadata hash appended by
+/// solc depends on the sources' paths, and the values of immutables and the address of
+/// libraries are only known at deployment. They are all ignored when matching code.
+#[derive(Clone, Debug, Default)]
+pub struct KnownContracts {
+    artifacts: Vec<Artifact>,
+    /// The indices of the artifacts by the hash of their exact runtime code, for the contracts
+    /// deployed as they were compiled
+    by_hash: BTreeMap<H256, usize>,
+}
+
+impl KnownContracts {
+    /// Indexes the contracts by name, leaving out the ones without runtime code, e.g. the
+    /// interfaces and abstract contracts
+    pub fn new<'a>(
+        contracts: impl IntoIterator<Item = (&'a String, &'a CompiledContract)>,
+    ) -> Self {
+        let mut contracts = contracts
+            .into_iter()
+            .filter(|(_, contract)| !contract.runtime_bytecode.as_ref().is_empty())
+            .collect::<Vec<_>>();
+        // the first of the artifacts with the same code is the one reported
+        contracts.sort_by_key(|(name, _)| *name);
+
+        let mut known = Self::default();
+        for (index, (name, contract)) in contracts.into_iter().enumerate() {
+            let code = contract.runtime_bytecode.as_ref();
+            known.by_hash.entry(H256::from(keccak256(code))).or_insert(index);
+            let code = strip_metadata(code).to_vec();
+            known.artifacts.push(Artifact {
+                id: name.clone(),
+                abi: contract.abi.clone(),
+                masked: placeholders(&code),
+                code,
+            });
+        }
+        known
+    }
+
+    /// Identifies the artifact the deployed `code` was compiled from
+    pub fn identify(&self, code: &[u8]) -> Option<&ArtifactId> {
+        if code.is_empty() {
+            return None
+        }
+        if let Some(&index) = self.by_hash.get(&H256::from(keccak256(code))) {
+            return Some(&self.artifacts[index].id)
+        }
+        let code = strip_metadata(code);
+        self.artifacts.iter().find(|artifact| artifact.matches(code)).map(|artifact| &artifact.id)
+    }
+
+    /// The ABI of an identified artifact
+    pub fn abi(&self, id: &str) -> Option<&Abi> {
+        self.artifacts.iter().find(|artifact| artifact.id == id).map(|artifact| &artifact.abi)
+    }
+}
+
+/// Strips the CBOR-encoded metadata which solc appends to the code, whose length is given by
+/// the last 2 bytes
+fn strip_metadata(code: &[u8]) -> &[u8] {
+    let len = match code {
+        [.., high, low] => u16::from_be_bytes([*high, *low]) as usize,
+        _ => return code,
+    };
+    match code.len().checked_sub(len + 2) {
+        // the metadata is a CBOR map of 1 to 5 entries
+        Some(start) if (0xa1..=0xa5).contains(&code[start]) => &code[..start],
+        _ => code,
+    }
+}
+
+const PUSH1: u8 = 0x60;
+const PUSH20: u8 = 0x73;
+const PUSH32: u8 = 0x7f;
+
+/// The immediates of the `PUSH20`s and `PUSH32`s which are zero in the runtime code of an
+/// artifact, as solc leaves them for the values of immutables and for the address of the
+/// library, which are filled at deployment
+fn placeholders(code: &[u8]) -> Vec<Range<usize>> {
+    let mut placeholders = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        if (PUSH1..=PUSH32).contains(&opcode) {
+            let size = (opcode - PUSH1 + 1) as usize;
+            let immediate = pc + 1..(pc + 1 + size).min(code.len());
+            let zero = code[immediate.clone()].iter().all(|byte| *byte == 0);
+            if (opcode == PUSH20 || opcode == PUSH32) && immediate.len() == size && zero {
+                placeholders.push(immediate);
+            }
+            pc += size;
+        }
+        pc += 1;
+    }
+    placeholders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Bytes;
+
+    /// Solc's metadata, with the given bytes as hash
+    fn metadata(hash: u8) -> Vec<u8> {
+        let mut metadata = vec![0xa2, 0x64];
+        metadata.extend(b"ipfs");
+        metadata.extend([0x58, 0x22]);
+        metadata.extend([hash; 34]);
+        metadata.push(0x64);
+        metadata.extend(b"solc");
+        metadata.extend([0x43, 0, 7, 6]);
+        let len = metadata.len() as u16;
+        metadata.extend(len.to_be_bytes());
+        metadata
+    }
+
+    fn compiled(code: Vec<u8>) -> CompiledContract {
+        CompiledContract {
+            abi: Abi::default(),
+            bytecode: Bytes::default(),
+            runtime_bytecode: code.into(),
+        }
+    }
+
+    #[test]
+    fn masks_metadata_immutables_and_library_addresses() {
+        // PUSH32 <immutable> PUSH1 0 MSTORE, PUSH20 <library address> POP
+        let mut code = vec![PUSH32];
+        code.extend([0; 32]);
+        code.extend([PUSH1, 0, 0x52, PUSH20]);
+        code.extend([0; 20]);
+        code.push(0x50);
+        let mut other = code.clone();
+        other[35] = 0x53;
+
+        let contracts = vec![
+            ("Immutables".to_owned(), compiled([code.clone(), metadata(1)].concat())),
+            ("Other".to_owned(), compiled([other.clone(), metadata(1)].concat())),
+            ("Interface".to_owned(), compiled(Vec::new())),
+        ];
+        let known = KnownContracts::new(contracts.iter().map(|(name, contract)| (name, contract)));
+
+        // as compiled
+        assert_eq!(known.identify(&[code.clone(), metadata(1)].concat()).unwrap(), "Immutables");
+        // with a different metadata hash, values of immutables and library address
+        let mut deployed = code.clone();
+        deployed[1..33].copy_from_slice(&[0xff; 32]);
+        deployed[37..57].copy_from_slice(&[0xaa; 20]);
+        assert_eq!(
+            known.identify(&[deployed.clone(), metadata(2)].concat()).unwrap(),
+            "Immutables"
+        );
+        assert_eq!(known.identify(&deployed).unwrap(), "Immutables");
+
+        // the code outside of the placeholders has to match
+        deployed[34] = 0x20;
+        assert_eq!(known.identify(&deployed), None);
+        assert_eq!(known.identify(&[]), None);
+        assert_eq!(known.identify(&code[..10]), None);
+        assert!(known.abi("Other").is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "sputnik")]
+    fn identifies_deployed_contracts() {
+        use crate::{
+            sputnik::{
+                helpers::{new_backend, new_vicinity},
+                Executor,
+            },
+            test_helpers::COMPILED,
+            Evm,
+        };
+        use ethers::{abi::Token, types::Address};
+        use sputnik::Config;
+
+        let known = KnownContracts::new(COMPILED.iter());
+        let cfg = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        let from = Address::repeat_byte(1);
+
+        // the immutables are set from the constructor's arguments
+        let immutables = COMPILED.get("Immutables").expect("could not find contract");
+        for value in [1u64, 42] {
+            let args = ethers::abi::encode(&[Token::Uint(value.into())]);
+            let bytecode = [immutables.bytecode.as_ref(), args.as_slice()].concat();
+            let (addr, _, _) = evm.deploy(from, bytecode.into(), 0.into()).unwrap();
+            let code = evm.get_code(addr);
+            assert_ne!(code, immutables.runtime_bytecode);
+            assert_eq!(known.identify(&code).unwrap(), "Immutables");
+        }
+
+        // libraries guard against being called directly with their own address
+        let library = COMPILED.get("DoubleLib").expect("could not find contract");
+        let (addr, _, _) = evm.deploy(from, library.bytecode.clone(), 0.into()).unwrap();
+        assert_eq!(known.identify(&evm.get_code(addr)).unwrap(), "DoubleLib");
+
+        let greeter = COMPILED.get("Greeter").expect("could not find contract");
+        let (addr, _, _) = evm.deploy(from, greeter.bytecode.clone(), 0.into()).unwrap();
+        assert_eq!(known.identify(&evm.get_code(addr)).unwrap(), "Greeter");
+    }
+}
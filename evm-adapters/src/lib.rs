@@ -6,17 +6,135 @@ pub mod sputnik;
 #[cfg(feature = "evmodin")]
 pub mod evmodin;
 
+pub mod access_list;
+
 mod blocking_provider;
 pub use blocking_provider::BlockingProvider;
 
+pub mod call_tracing;
+use call_tracing::CallTraceNode;
+
+#[cfg(feature = "coverage")]
+pub mod coverage;
+#[cfg(feature = "coverage")]
+use coverage::BitSet;
+#[cfg(feature = "coverage")]
+use std::collections::HashMap;
+
+#[cfg(any(feature = "debug-steps", feature = "evmodin"))]
+pub mod debug_steps;
+#[cfg(feature = "debug-steps")]
+use debug_steps::{DebugConfig, DebugStep};
+
+pub mod dstest_logs;
+use dstest_logs::{decode_logs, TestLogEntry};
+
+pub mod either;
+
+pub mod fuzz;
+use fuzz::FuzzDictionary;
+
+pub mod gas_report;
+use gas_report::GasReport;
+
+pub mod hevm;
+
+#[cfg(feature = "fuzz")]
+pub mod invariant;
+
+pub mod known_contracts;
+
+pub mod linker;
+
+pub mod state_diff;
+use state_diff::StateDiff;
+
 use ethers::{
-    abi::{Detokenize, Function, Tokenize},
-    core::types::{Address, U256},
+    abi::{Detokenize, Function, RawLog, Tokenize},
+    core::types::{Address, H256, U256},
     prelude::{decode_function_data, encode_function_data, Bytes},
 };
 
-use dapp_utils::get_func;
+use dapp_utils::{decode_revert, get_func};
 use eyre::Result;
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Address where the cheatcode handler intercepts calls, same as in HEVM:
+/// `address(bytes20(uint160(uint256(keccak256('hevm cheat code')))))`
+pub static CHEATCODE_ADDRESS: Lazy<Address> = Lazy::new(|| {
+    "0x7109709ecfa91a80626ff3989d68f67f5b1dd12d".parse().expect("invalid cheatcode address")
+});
+
+/// The gas used by a call
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GasUsed {
+    /// The gas consumed by the transaction, including its intrinsic cost and before refunds
+    pub raw: u64,
+    /// The gas consumed by the execution of the call only, i.e. without the intrinsic cost of
+    /// the transaction and after refunds, as reported by dapptools
+    pub adjusted: u64,
+}
+
+/// Where and how a call ran out of gas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfGas {
+    /// The gas limit of the call
+    pub gas_limit: u64,
+    /// The gas used by the call, without its intrinsic cost
+    pub gas_used: u64,
+    /// The callee and depth of the deepest frame which exhausted its gas, if traces were
+    /// recorded
+    pub frame: Option<(Address, usize)>,
+}
+
+impl std::fmt::Display for OutOfGas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "out of gas: used {} of the {} gas provided", self.gas_used, self.gas_limit)?;
+        if let Some((address, depth)) = self.frame {
+            write!(f, ", exhausted by {:?} at depth {}", address, depth)?;
+        }
+        Ok(())
+    }
+}
+
+/// The reason of the calls aborted by an [`Interrupt`]
+pub const INTERRUPTED: &str = "interrupted";
+
+/// A flag which aborts the calls of an EVM once raised, e.g. by a watchdog thread when a test
+/// runs for too long. Its clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn raise(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The outcome of a test call, see [`Evm::evaluate`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestOutcome {
+    /// Whether the test passed, i.e. it failed if and only if it was expected to, and it met
+    /// all of its expectations
+    pub success: bool,
+    /// Why the test failed, e.g. its decoded revert reason, or `None` if it passed
+    pub reason: Option<String>,
+    /// The decoded logs emitted by the test call, e.g. DS-Test's assertion failure details
+    pub logs: Vec<TestLogEntry>,
+    /// The gas used by the test call, without its intrinsic cost, if the EVM reports it
+    pub gas_used: Option<u64>,
+}
 
 // TODO: Any reason this should be an async trait?
 /// Low-level abstraction layer for interfacing with various EVMs. Once instantiated, one
@@ -25,6 +143,9 @@ pub trait Evm<State> {
     /// The returned reason type from an EVM (Success / Revert/ Stopped etc.)
     type ReturnReason: std::fmt::Debug + PartialEq;
 
+    /// Identifies a snapshot of the state taken via [`Evm::snapshot`]
+    type SnapshotId: Copy + std::fmt::Debug;
+
     /// Whether a return reason should be considered successful
     fn is_success(reason: &Self::ReturnReason) -> bool;
     /// Whether a return reason should be considered failing
@@ -39,7 +160,137 @@ pub trait Evm<State> {
     /// Resets the EVM's state to the provided value
     fn reset(&mut self, state: State);
 
-    /// Executes the specified EVM call against the state
+    /// Takes a snapshot of the current state, e.g. after a test's `setUp()`
+    fn snapshot(&mut self) -> Self::SnapshotId;
+
+    /// Restores the state (including nonces and logs) to a snapshot. The snapshot can be
+    /// restored again, but the ones taken after it are discarded.
+    ///
+    /// The state after `setUp()` is restored before each run of the fuzz tests, so restoring
+    /// should cost far less than setting the state up again, e.g. by only copying the changes
+    /// made on top of the backend.
+    fn restore(&mut self, id: Self::SnapshotId) -> Result<()>;
+
+    /// Discards all the snapshots taken so far, which can no longer be restored afterwards,
+    /// e.g. once all the tests of a contract have run
+    fn clear_snapshots(&mut self);
+
+    /// Returns the human-readable names of addresses (e.g. set via the `label` cheatcode), so
+    /// that they can be used when formatting results
+    fn labels(&self) -> BTreeMap<Address, String> {
+        BTreeMap::new()
+    }
+
+    /// Returns the logs emitted during the last call, excluding the ones of calls which
+    /// reverted. They are replaced by the next call.
+    fn logs(&self) -> Vec<RawLog> {
+        Vec::new()
+    }
+
+    /// Returns the number of expectations (e.g. set via the `expectRevert` cheatcode) which the
+    /// last call left unsatisfied
+    fn pending_expectations(&self) -> usize {
+        0
+    }
+
+    /// Returns both the raw and the adjusted gas used by the last call, if the EVM can tell
+    /// them apart. The calls themselves return the adjusted gas.
+    fn gas_used(&self) -> Option<GasUsed> {
+        None
+    }
+
+    /// Returns where and how the last call ran out of gas, if it did and the EVM can tell
+    fn out_of_gas(&self) -> Option<OutOfGas> {
+        None
+    }
+
+    /// Sets the flag which, once raised, makes the calls fail with [`INTERRUPTED`]: the ones
+    /// made afterwards immediately, and the one being executed at its next instruction if the
+    /// EVM supports it. It is a no-op for EVMs which cannot be interrupted.
+    fn set_interrupt(&mut self, _interrupt: Option<Interrupt>) {}
+
+    /// Whether the flag set via [`Evm::set_interrupt`] was raised
+    fn is_interrupted(&self) -> bool {
+        false
+    }
+
+    /// Sets whether the test contracts are DSTests, whose `failed()` is called after their
+    /// successful tests to see whether one of their assertions failed. The runner decides it
+    /// from the ABI of the test contract, since the other contracts have no `failed()` to call.
+    fn set_dstest(&mut self, _dstest: bool) {}
+
+    /// Whether the test contracts are DSTests, see [`Evm::set_dstest`]
+    fn is_dstest(&self) -> bool {
+        false
+    }
+
+    /// Enables or disables the recording of call traces, which has a cost on every call. It
+    /// is a no-op for EVMs which do not support tracing.
+    fn set_tracing_enabled(&mut self, _enabled: bool) {}
+
+    /// Returns the call tree of the last call, if tracing is supported and enabled
+    fn traces(&self) -> Vec<CallTraceNode> {
+        Vec::new()
+    }
+
+    /// Enables or disables the collection of the gas used by every call to the deployed
+    /// contracts, which has a cost on every call. It is a no-op for EVMs which do not support
+    /// it.
+    fn set_gas_reporting_enabled(&mut self, _enabled: bool) {}
+
+    /// Returns the gas used by the calls made so far, if gas reporting is supported and enabled
+    fn gas_report(&self) -> GasReport {
+        GasReport::default()
+    }
+
+    /// Enables or disables the harvesting of the values for fuzzing (e.g. the storage values
+    /// written during `setUp()`), which has a cost on every storage write. Enabling it clears
+    /// the values harvested so far. It is a no-op for EVMs which do not support it.
+    fn set_fuzz_dictionary_enabled(&mut self, _enabled: bool) {}
+
+    /// Returns the values harvested for fuzzing, if harvesting is supported and enabled
+    fn fuzz_dictionary(&self) -> FuzzDictionary {
+        FuzzDictionary::default()
+    }
+
+    /// Enables or disables the recording of the changes made to the state by every call, which
+    /// requires keeping a copy of the state from before the call. It is a no-op for EVMs which
+    /// do not support it.
+    fn set_state_diff_enabled(&mut self, _enabled: bool) {}
+
+    /// Returns the changes made to the state by the last call, if recording is supported and
+    /// enabled
+    fn state_changes(&self) -> StateDiff {
+        StateDiff::default()
+    }
+
+    /// Sets what to record of the steps executed by every call, or disables their recording
+    /// with `None`, as it has a large cost on every step. It is a no-op for EVMs which do not
+    /// support it.
+    #[cfg(feature = "debug-steps")]
+    fn set_debug_config(&mut self, _config: Option<DebugConfig>) {}
+
+    /// Returns the steps executed by the last call, if recording is supported and enabled
+    #[cfg(feature = "debug-steps")]
+    fn debug_steps(&self) -> Vec<DebugStep> {
+        Vec::new()
+    }
+
+    /// Enables or disables the recording of the program counters executed by every call, which
+    /// has a cost on every step. Enabling it clears the ones recorded so far. It is a no-op for
+    /// EVMs which do not support it.
+    #[cfg(feature = "coverage")]
+    fn set_coverage_enabled(&mut self, _enabled: bool) {}
+
+    /// Returns the program counters executed since the recording was enabled, by hash of the
+    /// executed code (the init code for deployments), if recording is supported and enabled
+    #[cfg(feature = "coverage")]
+    fn coverage(&self) -> HashMap<H256, BitSet> {
+        HashMap::new()
+    }
+
+    /// Executes the specified EVM call against the state. `gas_limit` overrides the gas limit
+    /// the EVM was instantiated with for this call only.
     // TODO: Should we just make this take a `TransactionRequest` or other more
     // ergonomic type?
     fn call<D: Detokenize, T: Tokenize>(
@@ -49,6 +300,24 @@ pub trait Evm<State> {
         func: &Function,
         args: T, // derive arbitrary for Tokenize?
         value: U256,
+        gas_limit: Option<u64>,
+    ) -> Result<(D, Self::ReturnReason, u64)> {
+        self.call_with_access_list(from, to, func, args, value, gas_limit, Vec::new())
+    }
+
+    /// Executes the specified EVM call against the state, with the provided accounts and
+    /// storage slots warm from the start (EIP-2930), e.g. as generated by
+    /// `eth_createAccessList`
+    #[allow(clippy::too_many_arguments)]
+    fn call_with_access_list<D: Detokenize, T: Tokenize>(
+        &mut self,
+        from: Address,
+        to: Address,
+        func: &Function,
+        args: T,
+        value: U256,
+        gas_limit: Option<u64>,
+        access_list: Vec<(Address, Vec<H256>)>,
     ) -> Result<(D, Self::ReturnReason, u64)> {
         let calldata = encode_function_data(func, args)?;
         #[allow(deprecated)]
@@ -57,11 +326,23 @@ pub trait Evm<State> {
                 func.state_mutability,
                 ethers::abi::StateMutability::View | ethers::abi::StateMutability::Pure
             );
-        let (retdata, status, gas) = self.call_raw(from, to, calldata, value, is_static)?;
+        let (retdata, status, gas) = self.call_raw_with_access_list(
+            from,
+            to,
+            calldata,
+            value,
+            is_static,
+            gas_limit,
+            access_list,
+        )?;
         let retdata = decode_function_data(func, retdata, false)?;
         Ok((retdata, status, gas))
     }
 
+    /// Executes a call with arbitrary calldata, e.g. to functions which are not in any ABI.
+    /// The return data is passed through as is, including the revert data of failed calls so
+    /// that callers can decode it themselves. The return data and gas used are meaningful for
+    /// failed calls as well.
     fn call_raw(
         &mut self,
         from: Address,
@@ -69,57 +350,163 @@ pub trait Evm<State> {
         calldata: Bytes,
         value: U256,
         is_static: bool,
+        gas_limit: Option<u64>,
     ) -> Result<(Bytes, Self::ReturnReason, u64)>;
 
-    /// Runs the `setUp()` function call to instantiate the contract's state
+    /// Executes a call with arbitrary calldata and an access list, see
+    /// [`Evm::call_with_access_list`]. The access list is ignored by EVMs which do not support
+    /// it.
+    #[allow(clippy::too_many_arguments)]
+    fn call_raw_with_access_list(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        is_static: bool,
+        gas_limit: Option<u64>,
+        _access_list: Vec<(Address, Vec<H256>)>,
+    ) -> Result<(Bytes, Self::ReturnReason, u64)> {
+        self.call_raw(from, to, calldata, value, is_static, gas_limit)
+    }
+
+    /// Deploys a contract by running its constructor, returning the address it was deployed
+    /// at, which is derived from the nonce of `from`. The constructor arguments, if any, must
+    /// be ABI-encoded and appended to the creation `bytecode`. Constructors which revert make
+    /// the deployment fail with their decoded reason.
+    fn deploy(
+        &mut self,
+        from: Address,
+        bytecode: Bytes,
+        value: U256,
+    ) -> Result<(Address, Self::ReturnReason, u64)>;
+
+    /// Sets the balance of an account
+    fn set_balance(&mut self, address: Address, balance: U256);
+
+    /// Gets the balance of an account, including the changes made by the previous calls
+    fn get_balance(&self, address: Address) -> U256;
+
+    /// Sets the nonce of an account. Some implementations cannot lower nonces and fail instead.
+    fn set_nonce(&mut self, address: Address, nonce: u64) -> Result<()>;
+
+    /// Gets the nonce of an account, including the changes made by the previous calls
+    fn get_nonce(&self, address: Address) -> U256;
+
+    /// Gets the code of an account, which is empty if it is not a contract
+    fn get_code(&self, address: Address) -> Bytes;
+
+    /// Runs the `setUp()` function call to instantiate the contract's state, failing with the
+    /// decoded revert reason if it does not succeed
     fn setup(&mut self, address: Address) -> Result<()> {
-        let (_, _, _) = self.call::<(), _>(
-            Address::zero(),
-            address,
-            &get_func("function setUp() external").unwrap(),
-            (),
-            0.into(),
-        )?;
-        // debug_assert_eq!(status, ExitReason::Succeed(ExitSucceed::Stopped));
+        let calldata = get_func("function setUp() external").unwrap().encode_input(&[])?;
+        let (retdata, status, _) =
+            self.call_raw(Address::zero(), address, calldata.into(), 0.into(), false, None)?;
+        if !Self::is_success(&status) {
+            eyre::bail!("{}", decode_revert(&retdata))
+        }
         Ok(())
     }
 
     /// Runs the `failed()` function call to inspect the test contract's state and
     /// see whether the `failed` state var is set. This is to allow compatibility
-    /// with dapptools-style DSTest smart contracts to preserve emiting of logs
+    /// with dapptools-style DSTest smart contracts to preserve emiting of logs.
+    /// It errors if there is no contract at all, or if `failed()` reverts or does not return a
+    /// boolean, e.g. if the contract is not a DSTest, see [`Evm::set_dstest`].
     fn failed(&mut self, address: Address) -> Result<bool> {
-        let (failed, _, _) = self.call::<bool, _>(
-            Address::zero(),
-            address,
-            &get_func("function failed() returns (bool)").unwrap(),
-            (),
-            0.into(),
-        )?;
-        Ok(failed)
+        let func = get_func("function failed() returns (bool)").unwrap();
+        if self.get_code(address).as_ref().is_empty() {
+            eyre::bail!("could not determine failure state: the test contract has no code")
+        }
+        let calldata = func.encode_input(&[])?;
+        let (retdata, status, _) = self
+            .call_raw(Address::zero(), address, calldata.into(), 0.into(), false, None)
+            .map_err(|err| eyre::eyre!("could not determine failure state: {}", err))?;
+        if !Self::is_success(&status) {
+            eyre::bail!("could not determine failure state: `failed()` reverted")
+        }
+        if retdata.as_ref().is_empty() {
+            eyre::bail!("could not determine failure state: `failed()` returned nothing")
+        }
+        decode_function_data(&func, retdata, false).map_err(|err| {
+            eyre::eyre!("could not determine failure state: invalid `failed()` output: {}", err)
+        })
     }
 
     /// Given a smart contract address, the result type and whether it's expected to fail,
-    /// it returns the test's success status
-    fn check_success(
+    /// it returns the test's success status. Errors if the status cannot be determined, e.g.
+    /// if the test contract's `failed()` cannot be called.
+    fn try_check_success(
         &mut self,
         address: Address,
         reason: &Self::ReturnReason,
         should_fail: bool,
-    ) -> bool {
-        if should_fail {
-            if Self::is_success(reason) {
-                self.failed(address).unwrap_or(false)
-            } else if Self::is_fail(reason) {
-                true
-            } else {
-                tracing::error!(?reason);
-                false
-            }
+    ) -> Result<bool> {
+        // tests which are not expected to fail must satisfy all their expectations
+        if !should_fail && self.pending_expectations() > 0 {
+            return Ok(false)
+        }
+
+        // the tests which succeed may still have failed a DSTest assertion
+        if Self::is_success(reason) {
+            Ok((self.is_dstest() && self.failed(address)?) == should_fail)
+        } else if Self::is_fail(reason) {
+            Ok(should_fail)
         } else {
-            Self::is_success(reason)
+            tracing::error!(?reason);
+            Ok(false)
         }
     }
 
+    /// Evaluates the outcome of a test call which returned `retdata` with `reason`, given
+    /// whether it is expected to fail. It must be called right after the test call, whose logs
+    /// and gas used it reports. Tests whose status cannot be determined fail.
+    fn evaluate(
+        &mut self,
+        address: Address,
+        retdata: &[u8],
+        reason: &Self::ReturnReason,
+        should_fail: bool,
+    ) -> TestOutcome {
+        // they have to be read before checking for success, which may make a call
+        let logs = decode_logs(&self.logs());
+        let gas_used = self.gas_used().map(|gas| gas.adjusted);
+        let out_of_gas = self.out_of_gas();
+        let pending = self.pending_expectations();
+
+        let failure = match self.try_check_success(address, reason, should_fail) {
+            Ok(true) => None,
+            Err(err) => Some(err.to_string()),
+            Ok(false) if Self::is_fail(reason) => Some(decode_revert(retdata)),
+            // tests which errored (e.g. ran out of gas) have no revert data
+            Ok(false) if !Self::is_success(reason) => {
+                Some(out_of_gas.map_or_else(|| format!("{:?}", reason), |oog| oog.to_string()))
+            }
+            Ok(false) if !should_fail && pending > 0 => {
+                Some(format!("{} expectation(s) were not met", pending))
+            }
+            Ok(false) if should_fail => Some("expected a failure, but the test passed".to_owned()),
+            Ok(false) => Some("assertion failed".to_owned()),
+        };
+        TestOutcome { success: failure.is_none(), reason: failure, logs, gas_used }
+    }
+
+    /// Like [`Evm::try_check_success`], but tests whose status cannot be determined are
+    /// considered failed.
+    ///
+    /// Deprecated: use [`Evm::evaluate`], which also tells why tests failed.
+    fn check_success(
+        &mut self,
+        address: Address,
+        reason: &Self::ReturnReason,
+        should_fail: bool,
+    ) -> bool {
+        self.try_check_success(address, reason, should_fail).unwrap_or_else(|err| {
+            tracing::error!(%err);
+            false
+        })
+    }
+
     // TODO: Should we add a "deploy contract" function as well, or should we assume that
     // the EVM is instantiated with a DB that includes any needed contracts?
 }
@@ -149,6 +536,7 @@ mod test_helpers {
                 &get_func("function greet(string greeting) external").unwrap(),
                 "hi".to_owned(),
                 0.into(),
+                None,
             )
             .unwrap();
 
@@ -159,6 +547,7 @@ mod test_helpers {
                 &get_func("function greeting() public view returns (string)").unwrap(),
                 (),
                 0.into(),
+                None,
             )
             .unwrap();
         assert_eq!(retdata, "hi");
@@ -184,6 +573,7 @@ mod test_helpers {
                 &get_func("function setUp() external").unwrap(),
                 (),
                 0.into(),
+                None,
             )
             .unwrap();
 
@@ -194,6 +584,7 @@ mod test_helpers {
                 &get_func("function testGreeting()").unwrap(),
                 (),
                 0.into(),
+                None,
             )
             .unwrap();
 
@@ -204,4 +595,174 @@ mod test_helpers {
 
         // TODO: Add testFail
     }
+
+    /// Checks that the tests whose `failed()` cannot be read fail with an error instead of
+    /// passing, using the `FailureTest` fixture
+    pub fn unreadable_failure_state<S, E: Evm<S>>(
+        mut evm: E,
+        addr: Address,
+        compiled: &CompiledContract,
+    ) {
+        evm.set_dstest(true);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let calldata = ethers::utils::id("succeed()").to_vec().into();
+        let (_, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(evm.try_check_success(addr, &reason, false).unwrap());
+
+        // e.g. if the test contract self-destructed
+        evm.initialize_contracts(vec![(addr, Bytes::default())]);
+        for should_fail in [false, true] {
+            let err = evm.try_check_success(addr, &reason, should_fail).unwrap_err();
+            assert!(err.to_string().starts_with("could not determine failure state"), "{}", err);
+        }
+        assert!(!evm.check_success(addr, &reason, true));
+
+        // nor can the state of the ones whose `failed()` reverts or returns nothing
+        for code in ["60006000fd", "00"] {
+            evm.initialize_contracts(vec![(addr, hex::decode(code).unwrap().into())]);
+            let err = evm.try_check_success(addr, &reason, false).unwrap_err();
+            assert!(err.to_string().starts_with("could not determine failure state"), "{}", err);
+        }
+
+        // unless they are not DSTests, whose `failed()` is not called
+        evm.set_dstest(false);
+        assert!(evm.try_check_success(addr, &reason, false).unwrap());
+        assert!(!evm.try_check_success(addr, &reason, true).unwrap());
+    }
+
+    /// Runs a failing DS-Test assertion of the `FailureTest` fixture, checks that the logs
+    /// of the calls which reverted are excluded, and returns the decoded logs
+    pub fn failing_assertion_logs<S, E: Evm<S>>(
+        mut evm: E,
+        addr: Address,
+        compiled: &CompiledContract,
+    ) -> Vec<String> {
+        evm.set_dstest(true);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let calldata = ethers::utils::id("failWithLogs()").to_vec().into();
+        let (_, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(!evm.check_success(addr, &reason, false));
+        let logs = dstest_logs::decode_logs(&evm.logs())
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            logs,
+            vec!["Error: a == b not satisfied [uint]", "  Expected: 1", "    Actual: 2"]
+        );
+
+        // the logs of calls which revert are excluded altogether
+        let calldata = ethers::utils::id("revertWithLogs()").to_vec().into();
+        evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(evm.logs().is_empty());
+
+        logs
+    }
+
+    /// Evaluates tests of the `FailureTest` contract which pass or fail in different ways,
+    /// checking the reason they failed
+    pub fn evaluated_outcomes<S, E: Evm<S>>(
+        mut evm: E,
+        addr: Address,
+        compiled: &CompiledContract,
+    ) {
+        evm.set_dstest(true);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let mut evaluate = |sig: &str, should_fail: bool| {
+            let calldata = ethers::utils::id(sig).to_vec().into();
+            let (retdata, reason, _) =
+                evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+            evm.evaluate(addr, &retdata, &reason, should_fail)
+        };
+
+        let outcome = evaluate("succeed()", false);
+        assert!(outcome.success);
+        assert_eq!(outcome.reason, None);
+        assert!(outcome.gas_used.is_some());
+
+        let outcome = evaluate("succeed()", true);
+        assert!(!outcome.success);
+        assert_eq!(outcome.reason.as_deref(), Some("expected a failure, but the test passed"));
+
+        let outcome = evaluate("revertNow()", false);
+        assert!(!outcome.success);
+        assert_eq!(outcome.reason.as_deref(), Some("reverted"));
+
+        // the failed assertions carry their logs
+        let outcome = evaluate("failWithLogs()", false);
+        assert!(!outcome.success);
+        assert_eq!(outcome.reason.as_deref(), Some("assertion failed"));
+        assert_eq!(outcome.logs.len(), 3);
+    }
+
+    /// Deploys the greeter, then greets directly and from a frame which reverts, with the
+    /// recording of the state changes enabled. Returns the changes made by each call.
+    pub fn greeter_state_changes<S, E: Evm<S>>(
+        mut evm: E,
+        compiled: &CompiledContract,
+    ) -> Vec<StateDiff> {
+        let from = Address::repeat_byte(1);
+        evm.set_state_diff_enabled(true);
+        let (addr, _, _) = evm.deploy(from, compiled.bytecode.clone(), 0.into()).unwrap();
+        let mut changes = vec![evm.state_changes()];
+        for sig in ["function greet(string)", "function tryGreet(string)"] {
+            let func = get_func(sig).unwrap();
+            evm.call::<(), _>(from, addr, &func, "hi".to_owned(), 0.into(), None).unwrap();
+            let mut diff = evm.state_changes();
+            // only some EVMs increase the nonce of the sender of a call
+            diff.remove(&from);
+            changes.push(diff);
+        }
+
+        let code = changes[0][&addr].code.clone().unwrap();
+        assert_eq!(code.to, compiled.runtime_bytecode);
+        assert_eq!(changes[1][&addr].storage.len(), 1);
+        // the writes of the frames which reverted are left out
+        assert!(changes[2].is_empty());
+        changes
+    }
+
+    /// Runs the tests of the cheatcodes which every adapter supports against the `CheatCodes`
+    /// fixture, so that they behave the same way under all of them
+    pub fn state_cheatcodes<S, E: Evm<S>>(mut evm: E, addr: Address, compiled: &CompiledContract) {
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let tests = [
+            ("testWarp", false),
+            ("testRoll", false),
+            ("testRollAndWarp", false),
+            ("testPrank", false),
+            ("testPrankNested", false),
+            ("testStartPrank", false),
+            ("testDeal", false),
+            ("testDealSelfAndSend", false),
+            ("testEtch", false),
+            ("testFailPrankTwice", true),
+            ("testFailStopPrank", true),
+        ];
+        for (test, should_fail) in tests {
+            let func = get_func(&format!("function {}()", test)).unwrap();
+            let (_, reason, _) =
+                evm.call::<(), _>(Address::zero(), addr, &func, (), 0.into(), None).unwrap();
+            assert!(evm.check_success(addr, &reason, should_fail), "{} failed", test);
+        }
+
+        let (who, slot, value) =
+            (Address::repeat_byte(0x42), H256::repeat_byte(1), H256::repeat_byte(2));
+        let (loaded, reason, _) = evm
+            .call::<H256, _>(
+                Address::zero(),
+                addr,
+                &get_func("function storeAndLoad(address,bytes32,bytes32) returns (bytes32)")
+                    .unwrap(),
+                (who, slot, value),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(loaded, value);
+    }
 }
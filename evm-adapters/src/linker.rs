@@ -0,0 +1,150 @@
+//! Linking of the compiled code of contracts against the addresses of the libraries they call,
+//! and deployment of these libraries in dependency order
+use crate::Evm;
+
+use dapp_solc::LinkReferences;
+use ethers::{
+    types::{Address, Bytes},
+    utils::CompiledContract,
+};
+use eyre::{Result, WrapErr};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Writes the addresses of the libraries at their offsets in the code
+pub fn link_code(
+    code: &Bytes,
+    references: &BTreeMap<String, Vec<usize>>,
+    libraries: &BTreeMap<String, Address>,
+) -> Result<Bytes> {
+    let mut code = code.to_vec();
+    for (library, offsets) in references {
+        let address = libraries
+            .get(library)
+            .ok_or_else(|| eyre::eyre!("the address of library {} is unknown", library))?;
+        for &offset in offsets {
+            let placeholder = code.get_mut(offset..offset + 20).ok_or_else(|| {
+                eyre::eyre!("the reference to library {} at {} is out of bounds", library, offset)
+            })?;
+            placeholder.copy_from_slice(address.as_bytes());
+        }
+    }
+    Ok(code.into())
+}
+
+/// Links the compiled contracts against the libraries they call
+#[derive(Clone, Debug)]
+pub struct Linker<'a> {
+    contracts: &'a HashMap<String, CompiledContract>,
+    /// The link references of the contracts which call libraries, by contract name
+    references: &'a HashMap<String, LinkReferences>,
+}
+
+impl<'a> Linker<'a> {
+    /// Links the contracts with the link references of the same compilation, as returned by
+    /// `SolcBuilder::link_references`
+    pub fn new(
+        contracts: &'a HashMap<String, CompiledContract>,
+        references: &'a HashMap<String, LinkReferences>,
+    ) -> Self {
+        Self { contracts, references }
+    }
+
+    /// The libraries called by the contract, which must be deployed before it
+    pub fn dependencies(&self, name: &str) -> BTreeSet<&'a String> {
+        self.references.get(name).map(LinkReferences::libraries).unwrap_or_default()
+    }
+
+    /// Returns the creation and runtime code of the contract, with the addresses of the
+    /// libraries it calls filled in
+    pub fn link(
+        &self,
+        name: &str,
+        libraries: &BTreeMap<String, Address>,
+    ) -> Result<(Bytes, Bytes)> {
+        let contract =
+            self.contracts.get(name).ok_or_else(|| eyre::eyre!("unknown contract {}", name))?;
+        match self.references.get(name) {
+            Some(references) => Ok((
+                link_code(&contract.bytecode, &references.bytecode, libraries)?,
+                link_code(&contract.runtime_bytecode, &references.runtime_bytecode, libraries)?,
+            )),
+            None => Ok((contract.bytecode.clone(), contract.runtime_bytecode.clone())),
+        }
+    }
+
+    /// Deploys the libraries the contract depends on, directly or via other libraries, which
+    /// are not in `libraries` yet, adding their addresses to it. The libraries are deployed
+    /// after the ones they call.
+    pub fn deploy_libraries<E: Evm<S>, S>(
+        &self,
+        evm: &mut E,
+        from: Address,
+        name: &str,
+        libraries: &mut BTreeMap<String, Address>,
+    ) -> Result<()> {
+        self.deploy_dependencies(evm, from, name, libraries, &mut Vec::new())
+    }
+
+    fn deploy_dependencies<E: Evm<S>, S>(
+        &self,
+        evm: &mut E,
+        from: Address,
+        name: &str,
+        libraries: &mut BTreeMap<String, Address>,
+        // the libraries being deployed, to detect cycles
+        path: &mut Vec<String>,
+    ) -> Result<()> {
+        for library in self.dependencies(name) {
+            if libraries.contains_key(library) {
+                continue
+            }
+            if path.contains(library) {
+                eyre::bail!("libraries {} and {} depend on each other", path.join(", "), library)
+            }
+
+            path.push(library.clone());
+            self.deploy_dependencies(evm, from, library, libraries, path)?;
+            path.pop();
+
+            let (bytecode, _) = self.link(library, libraries)?;
+            let (address, reason, _) = evm
+                .deploy(from, bytecode, 0.into())
+                .wrap_err_with(|| format!("could not deploy library {}", library))?;
+            if !E::is_success(&reason) {
+                eyre::bail!("could not deploy library {}: {:?}", library, reason)
+            }
+            libraries.insert(library.clone(), address);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_library_addresses() {
+        let code = Bytes::from(vec![0x73; 45]);
+        let references = vec![("Math".to_owned(), vec![1, 24]), ("Quad".to_owned(), vec![])]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let math = Address::repeat_byte(0x11);
+        let mut libraries =
+            vec![("Quad".to_owned(), Address::repeat_byte(0x22))].into_iter().collect();
+
+        let err = link_code(&code, &references, &libraries).unwrap_err();
+        assert_eq!(err.to_string(), "the address of library Math is unknown");
+
+        libraries.insert("Math".to_owned(), math);
+        let linked = link_code(&code, &references, &libraries).unwrap();
+        assert_eq!(linked[0], 0x73);
+        assert_eq!(&linked[1..21], math.as_bytes());
+        assert_eq!(&linked[21..24], [0x73; 3]);
+        assert_eq!(&linked[24..44], math.as_bytes());
+        assert_eq!(linked[44], 0x73);
+
+        let references = std::iter::once(("Math".to_owned(), vec![30])).collect();
+        assert!(link_code(&code, &references, &libraries).is_err());
+    }
+}
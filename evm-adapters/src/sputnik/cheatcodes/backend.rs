@@ -0,0 +1,98 @@
+use super::Cheatcodes;
+
+use ethers::types::{H160, H256, U256};
+use sputnik::backend::{Backend, Basic};
+use std::sync::Arc;
+
+#[derive(Debug)]
+/// A [`Backend`] which overrides the values of its inner backend with the ones set via
+/// cheatcodes
+pub struct CheatcodeBackend<B> {
+    /// The inner backend type, which is never written to: the changes made by the calls are
+    /// kept in the state on top of it. It is shared by the clones of the backend, so that
+    /// snapshotting the state does not copy it, however many accounts it holds.
+    pub backend: Arc<B>,
+    /// The enabled cheatcodes
+    pub cheats: Cheatcodes,
+}
+
+impl<B> Clone for CheatcodeBackend<B> {
+    fn clone(&self) -> Self {
+        Self { backend: Arc::clone(&self.backend), cheats: self.cheats.clone() }
+    }
+}
+
+impl<B: Backend> CheatcodeBackend<B> {
+    /// Wraps the provided backend with no cheatcodes applied
+    pub fn new(backend: B) -> Self {
+        Self { backend: Arc::new(backend), cheats: Default::default() }
+    }
+}
+
+impl<B: Backend> Backend for CheatcodeBackend<B> {
+    fn gas_price(&self) -> U256 {
+        self.cheats.tx_gas_price.unwrap_or_else(|| self.backend.gas_price())
+    }
+
+    fn origin(&self) -> H160 {
+        self.backend.origin()
+    }
+
+    /// Overriden hashes are returned even for blocks outside of the 256 most recent ones, which
+    /// the `BLOCKHASH` opcode could otherwise not access
+    fn block_hash(&self, number: U256) -> H256 {
+        self.cheats
+            .block_hashes
+            .get(&number)
+            .copied()
+            .unwrap_or_else(|| self.backend.block_hash(number))
+    }
+
+    fn block_number(&self) -> U256 {
+        self.cheats.block_number.unwrap_or_else(|| self.backend.block_number())
+    }
+
+    fn block_coinbase(&self) -> H160 {
+        self.cheats.block_coinbase.unwrap_or_else(|| self.backend.block_coinbase())
+    }
+
+    fn block_timestamp(&self) -> U256 {
+        self.cheats.block_timestamp.unwrap_or_else(|| self.backend.block_timestamp())
+    }
+
+    fn block_difficulty(&self) -> U256 {
+        self.cheats.block_difficulty.unwrap_or_else(|| self.backend.block_difficulty())
+    }
+
+    fn block_gas_limit(&self) -> U256 {
+        self.backend.block_gas_limit()
+    }
+
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.cheats.block_base_fee_per_gas.unwrap_or_else(|| self.backend.block_base_fee_per_gas())
+    }
+
+    fn chain_id(&self) -> U256 {
+        self.cheats.chain_id.unwrap_or_else(|| self.backend.chain_id())
+    }
+
+    fn exists(&self, address: H160) -> bool {
+        self.backend.exists(address)
+    }
+
+    fn basic(&self, address: H160) -> Basic {
+        self.backend.basic(address)
+    }
+
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.backend.code(address)
+    }
+
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        self.backend.storage(address, index)
+    }
+
+    fn original_storage(&self, address: H160, index: H256) -> Option<H256> {
+        self.backend.original_storage(address, index)
+    }
+}
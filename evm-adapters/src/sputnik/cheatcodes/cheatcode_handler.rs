@@ -0,0 +1,3725 @@
+use super::{
+    backend::CheatcodeBackend, decode_console_log, memory_stackstate_owned::MemoryStackStateOwned,
+    ExpectedCall, ExpectedEmit, ExpectedRevert, HevmCall, Prank, ASSUME_MAGIC_REVERT,
+    CHEATCODE_ADDRESS, CONSOLE_ADDRESS,
+};
+#[cfg(feature = "coverage")]
+use crate::coverage::{BitSet, CoverageRecorder};
+#[cfg(feature = "debug-steps")]
+use crate::debug_steps::{DebugConfig, DebugStep, StepRecorder};
+use crate::{
+    access_list::AccessListTracker,
+    call_tracing::{CallKind, CallTraceNode, CallTracer},
+    fuzz::FuzzDictionary,
+    gas_report::GasReport,
+    hevm::{apply_state_cheatcode, encode_error, prank_caller, HevmState, CHEATCODE_STUB},
+    sputnik::{
+        precompiles::{precompiles, Precompiles},
+        Executor, SputnikExecutor, MAX_NONCE_INCREASE,
+    },
+    Interrupt, INTERRUPTED,
+};
+#[cfg(feature = "coverage")]
+use std::collections::HashMap;
+
+use std::{
+    cmp::min,
+    collections::BTreeMap,
+    convert::Infallible,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use sputnik::{
+    backend::{Backend, Log},
+    executor::{PrecompileOutput, StackExecutor, StackExitKind, StackState, StackSubstateMetadata},
+    gasometer, Capture, Config, Context, CreateScheme, ExitError, ExitReason, ExitRevert,
+    ExitSucceed, Handler, Opcode, Runtime, Stack, Transfer,
+};
+
+use ethers::{
+    abi::{RawLog, Token},
+    core::k256::ecdsa::SigningKey,
+    signers::{LocalWallet, Signer},
+    types::{Bytes, H160, H256, U256},
+};
+
+/// Hooks on live EVM execution and forwards everything else to a Sputnik [`Handler`].
+///
+/// It allows:
+/// 1. Logging of values for debugging, via hardhat-style `console.log`
+/// 2. Modifying chain state live with cheatcodes
+///
+/// The `call_inner` and `create_inner` functions are copy-pasted from upstream, so that they
+/// can hook in the runtime.
+/// It may eventually be removed if Sputnik allows bringing in your own runtime handler.
+// TODO: Should this be called `HookedHandler`? Maybe we could implement other hooks
+// here, e.g. dapptools logs, some ad-hoc method for tracing etc.
+pub struct CheatcodeHandler<H> {
+    handler: H,
+    /// The messages logged via `console.log`
+    console_logs: Vec<String>,
+    /// The number of logs in the state before the last call, so that the ones it emitted can
+    /// be told apart
+    logs_start: usize,
+    /// Records the call tree of the last call, if enabled
+    tracer: CallTracer,
+    /// Aggregates the gas used by the calls made since the executor was created, if enabled
+    gas_report: GasReport,
+    /// Harvests the values written to the storage and the deployed contracts, if enabled
+    dictionary: FuzzDictionary,
+    /// Records the accounts and storage slots accessed by the last call, if enabled
+    access_list: AccessListTracker,
+    /// Records the steps executed by the last call, if enabled
+    #[cfg(feature = "debug-steps")]
+    steps: StepRecorder,
+    /// Aggregates the program counters executed by the calls, if enabled
+    #[cfg(feature = "coverage")]
+    coverage: CoverageRecorder,
+    /// The precompiles of the hard fork, which calls are dispatched to
+    precompiles: Precompiles,
+    /// Aborts the call being executed once raised, see [`crate::Evm::set_interrupt`]
+    interrupt: Option<Interrupt>,
+}
+
+/// The state type used by the cheatcode-enabled executor
+pub type CheatcodeStackState<'a, B> = MemoryStackStateOwned<'a, CheatcodeBackend<B>>;
+
+/// A Sputnik [`StackExecutor`] wrapped with the cheatcode handler
+pub type CheatcodeStackExecutor<'a, B> =
+    CheatcodeHandler<StackExecutor<'a, CheatcodeStackState<'a, B>>>;
+
+impl<'a, B: Backend> SputnikExecutor<CheatcodeStackState<'a, B>> for CheatcodeStackExecutor<'a, B> {
+    fn config(&self) -> &Config {
+        self.handler.config()
+    }
+
+    fn state(&self) -> &CheatcodeStackState<'a, B> {
+        self.handler.state()
+    }
+
+    fn state_mut(&mut self) -> &mut CheatcodeStackState<'a, B> {
+        self.handler.state_mut()
+    }
+
+    fn gas_left(&self) -> U256 {
+        // NB: We do this to avoid `function cannot return without recursing`
+        U256::from(self.state().metadata().gasometer().gas())
+    }
+
+    fn labels(&self) -> BTreeMap<H160, String> {
+        self.state().backend.cheats.labels.clone()
+    }
+
+    fn pending_expectations(&self) -> usize {
+        self.state().backend.cheats.pending_expectations
+    }
+
+    fn logs(&self) -> Vec<RawLog> {
+        let logs = self.state().substate.logs();
+        logs.get(self.logs_start..)
+            .unwrap_or_default()
+            .iter()
+            .map(|log| RawLog { topics: log.topics.clone(), data: log.data.clone() })
+            .collect()
+    }
+
+    fn reset_cheatcodes(&mut self) {
+        self.state_mut().snapshots.clear();
+        self.state_mut().backend.cheats.reset();
+    }
+
+    fn set_interrupt(&mut self, interrupt: Option<Interrupt>) {
+        self.interrupt = interrupt;
+    }
+
+    fn set_tracing_enabled(&mut self, enabled: bool) {
+        self.tracer.enabled = enabled;
+    }
+
+    fn traces(&self) -> Vec<CallTraceNode> {
+        self.tracer.nodes().to_vec()
+    }
+
+    fn set_gas_reporting_enabled(&mut self, enabled: bool) {
+        self.gas_report.enabled = enabled;
+    }
+
+    fn gas_report(&self) -> GasReport {
+        self.gas_report.clone()
+    }
+
+    fn set_fuzz_dictionary_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.dictionary.clear();
+        }
+        self.dictionary.enabled = enabled;
+    }
+
+    fn fuzz_dictionary(&self) -> FuzzDictionary {
+        self.dictionary.clone()
+    }
+
+    #[cfg(feature = "debug-steps")]
+    fn set_debug_config(&mut self, config: Option<DebugConfig>) {
+        self.steps.config = config;
+    }
+
+    #[cfg(feature = "debug-steps")]
+    fn debug_steps(&self) -> Vec<DebugStep> {
+        self.steps.steps()
+    }
+
+    #[cfg(feature = "coverage")]
+    fn set_coverage_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.coverage.clear();
+        }
+        self.coverage.enabled = enabled;
+    }
+
+    #[cfg(feature = "coverage")]
+    fn coverage(&self) -> HashMap<H256, BitSet> {
+        self.coverage.coverage().clone()
+    }
+
+    /// Copied from [upstream](https://github.com/rust-blockchain/evm/blob/5ecf36ce393380a89c6f1b09ef79f686fe043624/src/executor/stack/executor.rs#L474-L543),
+    /// so that the top-level call is executed with the hooked handler
+    fn transact_call(
+        &mut self,
+        caller: H160,
+        address: H160,
+        value: U256,
+        data: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        let transaction_cost = gasometer::call_transaction_cost(&data, &access_list);
+        match self.state_mut().metadata_mut().gasometer_mut().record_transaction(transaction_cost) {
+            Ok(()) => (),
+            Err(e) => return (e.into(), Vec::new()),
+        }
+
+        // Initialize initial addresses for EIP-2929. Unlike upstream, the precompiles are
+        // warm as well, as they are on mainnet.
+        if self.config().increase_state_access_gas {
+            let precompiles: Vec<_> = self.precompiles.keys().copied().collect();
+            let addresses = core::iter::once(caller)
+                .chain(core::iter::once(address))
+                .chain(precompiles.into_iter());
+            self.state_mut().metadata_mut().access_addresses(addresses);
+
+            self.initialize_access_list(access_list);
+        }
+
+        self.state_mut().inc_nonce(caller);
+
+        // the logs of the previous call are not returned anymore
+        self.logs_start = self.state().substate.logs().len();
+
+        let context = Context { caller, address, apparent_value: value };
+
+        self.tracer.clear();
+        self.access_list.clear();
+        #[cfg(feature = "debug-steps")]
+        self.steps.clear();
+        let gas_before = self.state().metadata().gasometer().gas();
+        self.tracer.start(CallKind::Call, caller, address, &data, value, gas_limit);
+
+        let (reason, retdata) = match self.call_inner(
+            address,
+            Some(Transfer { source: caller, target: address, value }),
+            data,
+            Some(gas_limit),
+            false,
+            false,
+            false,
+            context,
+        ) {
+            Capture::Exit((s, v)) => (s, v),
+            Capture::Trap(_) => unreachable!(),
+        };
+        self.finish_trace(gas_before, &reason, &retdata);
+        self.state_mut().finalize_deletes();
+
+        // pranks and expectations are not part of the journaled state, so we clear them
+        // manually to ensure that they cannot leak into the next transaction
+        // snapshots are only valid within the transaction they were taken in, because
+        // reverting to them restores the parent substates as well
+        self.state_mut().snapshots.clear();
+        let cheats = &mut self.state_mut().backend.cheats;
+        cheats.prank = None;
+        let expected_revert = cheats.expected_revert.take();
+        let expected_emits = std::mem::take(&mut cheats.expected_emits);
+        let expected_calls = std::mem::take(&mut cheats.expected_calls);
+        cheats.clear_expectations();
+
+        let mut unsatisfied = Vec::new();
+        if let Some(expected) = expected_revert {
+            unsatisfied.push(format!(
+                "expected {}, but no call was made",
+                format_expected_revert(&expected)
+            ));
+        }
+        for expected in expected_emits.iter().filter(|expected| expected.log.is_some()) {
+            unsatisfied.push(format!(
+                "expected log {}, but no call was made",
+                format_expected_log(expected)
+            ));
+        }
+        for expected in expected_calls.iter().filter(|expected| !expected.found) {
+            let value = expected.value.map(|value| format!(" and value {}", value));
+            unsatisfied.push(format!(
+                "expected call to {} with data 0x{}{} was not made",
+                cheats.format_address(expected.target),
+                hex::encode(&expected.data),
+                value.unwrap_or_default()
+            ));
+        }
+        cheats.pending_expectations = unsatisfied.len();
+        // a successful test which left expectations unsatisfied fails with all of them
+        if !unsatisfied.is_empty() && matches!(reason, ExitReason::Succeed(_)) {
+            return (ExitReason::Revert(ExitRevert::Reverted), encode_error(&unsatisfied.join("; ")))
+        }
+
+        (reason, retdata)
+    }
+
+    fn transact_create(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        let scheme = CreateScheme::Legacy { caller };
+        let (reason, _, retdata) =
+            self.transact_create_with(caller, scheme, value, init_code, gas_limit, access_list);
+        (reason, retdata)
+    }
+}
+
+impl<'a, B: Backend> Executor<CheatcodeStackState<'a, B>, CheatcodeStackExecutor<'a, B>> {
+    /// Instantiates a cheatcode-enabled executor given a backend, a gas limit and the
+    /// chain configuration. `enable_ffi` must be explicitly set for the `ffi` cheatcode to be
+    /// allowed to execute commands on the host.
+    pub fn new_with_cheatcodes(
+        backend: B,
+        gas_limit: u64,
+        config: &'a Config,
+        enable_ffi: bool,
+    ) -> Self {
+        // make this a cheatcode-enabled backend
+        let mut backend = CheatcodeBackend::new(backend);
+        backend.cheats.ffi_enabled = enable_ffi;
+
+        // create the memory stack state (owned, so that we can modify the backend via
+        // self.state_mut on the transact_call fn)
+        let metadata = StackSubstateMetadata::new(gas_limit, config);
+        let state = MemoryStackStateOwned::new(metadata, backend);
+
+        // create the executor and wrap it with the cheatcode handler
+        let precompiles = precompiles(config);
+        let executor = StackExecutor::new_with_precompile(state, config, precompiles.clone());
+        let executor = CheatcodeHandler {
+            handler: executor,
+            console_logs: Vec::new(),
+            logs_start: 0,
+            tracer: CallTracer::default(),
+            gas_report: GasReport::default(),
+            dictionary: FuzzDictionary::default(),
+            access_list: AccessListTracker::default(),
+            #[cfg(feature = "debug-steps")]
+            steps: StepRecorder::default(),
+            #[cfg(feature = "coverage")]
+            coverage: CoverageRecorder::default(),
+            precompiles,
+            interrupt: None,
+        };
+
+        Executor::from_executor(executor, gas_limit)
+    }
+
+    /// Discards all the changes made on top of the backend, along with the snapshots and the
+    /// cheatcodes, as `Evm::reset` would with a fresh state. Only the overlay and its metadata
+    /// are rebuilt: the backend is kept, so that e.g. the accounts a forked backend has already
+    /// fetched do not have to be fetched again.
+    pub fn reset_to_backend(&mut self) {
+        let metadata = StackSubstateMetadata::new(
+            self.gas_limit,
+            StackExecutor::config(&self.executor.handler),
+        );
+        self.executor.state_mut().reset(metadata);
+        self.executor.reset_cheatcodes();
+        crate::Evm::clear_snapshots(self);
+    }
+
+    /// Dry-runs a call, returning the accounts and storage slots it accessed in the shape of
+    /// `eth_createAccessList`, so that they can be passed to `Evm::call_with_access_list`. The
+    /// state is left as it was before the call.
+    pub fn generate_access_list(
+        &mut self,
+        from: H160,
+        to: H160,
+        calldata: Bytes,
+    ) -> Vec<(H160, Vec<H256>)> {
+        let state = self.executor.state().clone();
+        self.executor.access_list.enabled = true;
+        let _ = self.executor.transact_call(
+            from,
+            to,
+            U256::zero(),
+            calldata.to_vec(),
+            self.gas_limit,
+            Vec::new(),
+        );
+        self.executor.access_list.enabled = false;
+        *self.executor.state_mut() = state;
+
+        // the sender, the callee and the precompiles are warm anyway
+        let mut excluded = vec![from, to];
+        excluded.extend(self.executor.precompiles.keys().copied());
+        self.executor.access_list.access_list(&excluded)
+    }
+}
+
+/// Returned instead of the revert data when a call reverts as expected. It is zeroed and long
+/// enough so that the caller can still decode the return values it expected from the call.
+const DUMMY_OUTPUT: [u8; 320] = [0u8; 320];
+
+/// Reverts with the provided message, encoded as via [`encode_error`]
+fn evm_error(retdata: &str) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+    Capture::Exit((ExitReason::Revert(ExitRevert::Reverted), encode_error(retdata)))
+}
+
+/// Decodes the reason of an `Error(string)`-encoded revert
+fn decode_error(data: &[u8]) -> Option<String> {
+    if data.len() < 4 || data[..4] != ethers::utils::id("Error(string)") {
+        return None
+    }
+    match ethers::abi::decode(&[ethers::abi::ParamType::String], &data[4..]).ok()?.pop()? {
+        Token::String(reason) => Some(reason),
+        _ => None,
+    }
+}
+
+/// Formats revert data for error messages: `Error(string)`-encoded reasons and printable
+/// strings are shown as text, panics by their cause and anything else is hex-encoded
+fn format_revert_data(data: &[u8]) -> String {
+    let reason = decode_error(data).or_else(|| {
+        std::str::from_utf8(data)
+            .ok()
+            .filter(|s| !s.is_empty() && !s.chars().any(char::is_control))
+            .map(str::to_owned)
+    });
+    match reason {
+        Some(reason) => format!("`{}`", reason),
+        None => {
+            dapp_utils::decode_panic(data).unwrap_or_else(|| format!("0x{}", hex::encode(data)))
+        }
+    }
+}
+
+/// Formats a log for error messages
+fn format_log(topics: &[H256], data: &[u8]) -> String {
+    let topics = topics.iter().map(|topic| format!("{:?}", topic)).collect::<Vec<_>>();
+    format!("{{ topics: [{}], data: 0x{} }}", topics.join(", "), hex::encode(data))
+}
+
+/// Formats the template of an expected log for error messages
+fn format_expected_log(expected: &ExpectedEmit) -> String {
+    match expected.log {
+        Some(ref log) => format_log(&log.topics, &log.data),
+        None => "<none>".to_owned(),
+    }
+}
+
+/// Formats an expected revert for error messages
+fn format_expected_revert(expected: &ExpectedRevert) -> String {
+    if expected.is_any() {
+        return "a revert".to_owned()
+    }
+    format!("revert {}", format_revert_data(&expected.data))
+}
+
+/// Whether the data of a revert satisfies the expectation. Data expected via
+/// `expectRevert(bytes)` may either match the raw revert data, or the reason of an
+/// `Error(string)` revert.
+fn revert_matches(expected: &ExpectedRevert, data: &[u8]) -> bool {
+    if expected.is_any() {
+        return true
+    }
+    if expected.selector_only {
+        return data.get(..4) == Some(&expected.data[..])
+    }
+    data == &expected.data[..] ||
+        decode_error(data).map_or(false, |reason| reason.as_bytes() == &expected.data[..])
+}
+
+/// Instantiates a wallet from a private key passed to a cheatcode. Errors if the key is zero or
+/// not smaller than the secp256k1 curve order.
+fn wallet_from_private_key(private_key: U256) -> eyre::Result<LocalWallet> {
+    let mut bytes = [0u8; 32];
+    private_key.to_big_endian(&mut bytes);
+    let key = SigningKey::from_bytes(&bytes).map_err(|_| {
+        eyre::eyre!("invalid private key: must be non-zero and smaller than the curve order")
+    })?;
+    Ok(key.into())
+}
+
+/// Runs the command in `args` with the remaining elements as its arguments and returns its
+/// stdout, hex-decoded if it is `0x`-prefixed hex. Errors if the command exits unsuccessfully.
+fn ffi(args: &[String]) -> eyre::Result<Vec<u8>> {
+    let (program, args) = args.split_first().ok_or_else(|| eyre::eyre!("ffi: empty command"))?;
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|err| eyre::eyre!("ffi: could not run `{}`: {}", program, err))?;
+    if !output.status.success() {
+        eyre::bail!(
+            "ffi: `{}` failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if let Some(decoded) = stdout.trim().strip_prefix("0x").and_then(|s| hex::decode(s).ok()) {
+        return Ok(decoded)
+    }
+    Ok(output.stdout)
+}
+
+/// Resolves a path passed to `readFile` or `writeFile` against the filesystem root. Errors if
+/// the path is absolute or contains `..`, so that it cannot point outside of the root.
+fn jailed_path(root: &Path, path: &str) -> eyre::Result<PathBuf> {
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        eyre::bail!("path `{}` must be relative and stay within the filesystem root", path)
+    }
+    Ok(root.join(relative))
+}
+
+/// Reads the environment variable `name` and parses it via `parse`. Errors if the variable is
+/// not set or cannot be parsed as a `kind`.
+fn env_var<T>(name: &str, kind: &str, parse: impl FnOnce(&str) -> Option<T>) -> eyre::Result<T> {
+    let value = std::env::var(name)
+        .map_err(|err| eyre::eyre!("could not read environment variable `{}`: {}", name, err))?;
+    parse(&value).ok_or_else(|| {
+        eyre::eyre!("environment variable `{}` is not a valid {}: `{}`", name, kind, value)
+    })
+}
+
+/// Parses a decimal or `0x`-prefixed hex number
+fn parse_uint(value: &str) -> Option<U256> {
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_dec_str(value).ok(),
+    }
+}
+
+impl<'a, B: Backend> CheatcodeStackExecutor<'a, B> {
+    /// The messages logged via `console.log` since the executor was instantiated or the logs
+    /// were last cleared
+    pub fn console_logs(&self) -> &[String] {
+        &self.console_logs
+    }
+
+    /// Clears the messages logged via `console.log`
+    pub fn clear_console_logs(&mut self) {
+        self.console_logs.clear();
+    }
+
+    /// Deploys a contract at the address derived from the deployer and its nonce, going through
+    /// the cheatcode handler, so that the constructor can use cheatcodes. Returns the address
+    /// of the deployed contract, if the deployment succeeded.
+    pub fn transact_create(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Option<H160>, Vec<u8>) {
+        let scheme = CreateScheme::Legacy { caller };
+        self.transact_create_with(caller, scheme, value, init_code, gas_limit, access_list)
+    }
+
+    /// Like [`transact_create`](Self::transact_create), but deploys at the address derived from
+    /// the deployer, the salt and the hash of the init code, as via `CREATE2`
+    pub fn transact_create2(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+        salt: H256,
+    ) -> (ExitReason, Option<H160>, Vec<u8>) {
+        let code_hash = H256::from(ethers::utils::keccak256(&init_code));
+        let scheme = CreateScheme::Create2 { caller, code_hash, salt };
+        self.transact_create_with(caller, scheme, value, init_code, gas_limit, access_list)
+    }
+
+    /// Copied from [upstream](https://github.com/rust-blockchain/evm/blob/5ecf36ce393380a89c6f1b09ef79f686fe043624/src/executor/stack/executor.rs#L396-L422),
+    /// so that the constructor is executed with the hooked handler and its revert data is
+    /// returned along with the address of the deployed contract
+    fn transact_create_with(
+        &mut self,
+        caller: H160,
+        scheme: CreateScheme,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Option<H160>, Vec<u8>) {
+        let transaction_cost = gasometer::create_transaction_cost(&init_code, &access_list);
+        match self.state_mut().metadata_mut().gasometer_mut().record_transaction(transaction_cost) {
+            Ok(()) => (),
+            Err(e) => return (e.into(), None, Vec::new()),
+        }
+
+        if self.config().increase_state_access_gas {
+            let precompiles: Vec<_> = self.precompiles.keys().copied().collect();
+            self.state_mut().metadata_mut().access_addresses(precompiles.into_iter());
+            self.initialize_access_list(access_list);
+        }
+
+        self.logs_start = self.state().substate.logs().len();
+
+        let address = self.handler.create_address(scheme);
+        self.tracer.clear();
+        #[cfg(feature = "debug-steps")]
+        self.steps.clear();
+        let gas_before = self.state().metadata().gasometer().gas();
+        self.tracer.start(CallKind::Create, caller, address, &init_code, value, gas_limit);
+
+        let (reason, created, retdata) =
+            match self.create_inner(caller, address, value, init_code, Some(gas_limit), false) {
+                Capture::Exit(inner) => inner,
+                Capture::Trap(_) => unreachable!(),
+            };
+        self.finish_trace(gas_before, &reason, &retdata);
+        self.state_mut().finalize_deletes();
+
+        // the cheatcodes used by the constructor do not outlive the deployment either
+        self.state_mut().snapshots.clear();
+        let cheats = &mut self.state_mut().backend.cheats;
+        cheats.prank = None;
+        cheats.clear_expectations();
+
+        (reason, created, retdata)
+    }
+
+    /// Decodes the provided calldata as a function call to the cheatcodes ABI and, if it
+    /// matches a known cheatcode, applies it to the state. Unknown or malformed calls revert
+    /// with a human-readable reason instead, as do state-changing cheatcodes called from a
+    /// static context.
+    fn apply_cheatcode(
+        &mut self,
+        input: Vec<u8>,
+        msg_sender: H160,
+        is_static: bool,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        let call = match HevmCall::decode(&input) {
+            Ok(call) => call,
+            Err(err) => return evm_error(&err.to_string()),
+        };
+        if is_static && !call.is_read_only() {
+            return evm_error("cannot use state-changing cheatcode in static context")
+        }
+        let depth = self.state().metadata().depth().unwrap_or_default();
+        match apply_state_cheatcode(self, &call, msg_sender, depth) {
+            Some(Ok(retdata)) => {
+                return Capture::Exit((ExitReason::Succeed(ExitSucceed::Returned), retdata))
+            }
+            Some(Err(reason)) => return evm_error(&reason),
+            None => {}
+        }
+
+        let state = self.state_mut();
+        let retdata = match call {
+            HevmCall::Warp(_) |
+            HevmCall::Roll(_) |
+            HevmCall::Store(..) |
+            HevmCall::Load(..) |
+            HevmCall::Prank(_) |
+            HevmCall::StartPrank(_) |
+            HevmCall::StopPrank |
+            HevmCall::Deal(..) |
+            HevmCall::Etch(..) => unreachable!("applied by `apply_state_cheatcode`"),
+            HevmCall::Fee(fee) => {
+                state.backend.cheats.block_base_fee_per_gas = Some(fee);
+                Vec::new()
+            }
+            HevmCall::Difficulty(difficulty) => {
+                state.backend.cheats.block_difficulty = Some(difficulty);
+                Vec::new()
+            }
+            HevmCall::Coinbase(coinbase) => {
+                state.backend.cheats.block_coinbase = Some(coinbase);
+                Vec::new()
+            }
+            HevmCall::TxGasPrice(gas_price) => {
+                state.backend.cheats.tx_gas_price = Some(gas_price);
+                Vec::new()
+            }
+            HevmCall::Transact(from, to, value, data) => {
+                // credit the sender with the value it lacks, so that neither it nor the test
+                // contract need to hold it beforehand
+                let balance = state.basic(from).balance;
+                if balance < value {
+                    state.touch(from);
+                    state.deposit(from, value - balance);
+                }
+                let transfer = Transfer { source: from, target: to, value };
+                let context = Context { caller: from, address: to, apparent_value: value };
+                let (reason, retdata) = match self.call_inner(
+                    to,
+                    Some(transfer),
+                    data,
+                    None,
+                    false,
+                    true,
+                    false,
+                    context,
+                ) {
+                    Capture::Exit(res) => res,
+                    Capture::Trap(_) => unreachable!("Trap is Infallible"),
+                };
+                ethers::abi::encode(&[
+                    Token::Bool(matches!(reason, ExitReason::Succeed(_))),
+                    Token::Bytes(retdata),
+                ])
+            }
+            HevmCall::SetBlockhash(number, hash) => {
+                // a zero hash clears the override
+                if hash.is_zero() {
+                    state.backend.cheats.block_hashes.remove(&number);
+                } else {
+                    state.backend.cheats.block_hashes.insert(number, hash);
+                }
+                Vec::new()
+            }
+            HevmCall::ChainId(chain_id) => {
+                state.backend.cheats.chain_id = Some(chain_id);
+                Vec::new()
+            }
+            HevmCall::GetNonce(who) => {
+                let nonce = state.basic(who).nonce;
+                if nonce > U256::from(u64::MAX) {
+                    return evm_error(&format!(
+                        "the nonce of {} does not fit in a uint64",
+                        state.backend.cheats.format_address(who)
+                    ))
+                }
+                ethers::abi::encode(&[Token::Uint(nonce)])
+            }
+            HevmCall::SetNonce(who, nonce) => {
+                let current = state.basic(who).nonce;
+                if U256::from(nonce) < current {
+                    return evm_error(&format!(
+                        "cannot lower the nonce of {} from {} to {}",
+                        state.backend.cheats.format_address(who),
+                        current,
+                        nonce
+                    ))
+                }
+                // the current nonce may not fit in a `u64`, if it was set by the backend
+                let increase = U256::from(nonce) - current;
+                if increase > U256::from(MAX_NONCE_INCREASE) {
+                    return evm_error(&format!(
+                        "cannot increase a nonce by more than {} at once",
+                        MAX_NONCE_INCREASE
+                    ))
+                }
+                for _ in 0..increase.as_u64() {
+                    state.inc_nonce(who);
+                }
+                Vec::new()
+            }
+            HevmCall::Sign(private_key, digest) => {
+                let wallet = match wallet_from_private_key(private_key) {
+                    Ok(wallet) => wallet,
+                    Err(err) => return evm_error(&err.to_string()),
+                };
+                // signs the digest as-is, without any EIP-191 prefix or EIP-155 replay
+                // protection, so `v` is 27 or 28
+                let sig = wallet.sign_hash(digest, false);
+                let mut r = [0u8; 32];
+                sig.r.to_big_endian(&mut r);
+                let mut s = [0u8; 32];
+                sig.s.to_big_endian(&mut s);
+                ethers::abi::encode(&[
+                    Token::Uint(sig.v.into()),
+                    Token::FixedBytes(r.to_vec()),
+                    Token::FixedBytes(s.to_vec()),
+                ])
+            }
+            HevmCall::Addr(private_key) => {
+                let wallet = match wallet_from_private_key(private_key) {
+                    Ok(wallet) => wallet,
+                    Err(err) => return evm_error(&err.to_string()),
+                };
+                ethers::abi::encode(&[Token::Address(wallet.address())])
+            }
+            HevmCall::Ffi(args) => {
+                if !state.backend.cheats.ffi_enabled {
+                    return evm_error("ffi disabled")
+                }
+                match ffi(&args) {
+                    Ok(output) => ethers::abi::encode(&[Token::Bytes(output)]),
+                    Err(err) => return evm_error(&err.to_string()),
+                }
+            }
+            HevmCall::ReadFile(path) => {
+                if !state.backend.cheats.fs_enabled {
+                    return evm_error("filesystem access disabled")
+                }
+                let contents = jailed_path(&state.backend.cheats.fs_root, &path).and_then(|file| {
+                    std::fs::read_to_string(file)
+                        .map_err(|err| eyre::eyre!("readFile: could not read `{}`: {}", path, err))
+                });
+                match contents {
+                    Ok(contents) => ethers::abi::encode(&[Token::String(contents)]),
+                    Err(err) => return evm_error(&err.to_string()),
+                }
+            }
+            HevmCall::WriteFile(path, data) => {
+                if !state.backend.cheats.fs_enabled {
+                    return evm_error("filesystem access disabled")
+                }
+                let res = jailed_path(&state.backend.cheats.fs_root, &path).and_then(|file| {
+                    std::fs::write(file, data).map_err(|err| {
+                        eyre::eyre!("writeFile: could not write `{}`: {}", path, err)
+                    })
+                });
+                if let Err(err) = res {
+                    return evm_error(&err.to_string())
+                }
+                Vec::new()
+            }
+            HevmCall::EnvString(name) => {
+                match env_var(&name, "string", |value| Some(value.to_owned())) {
+                    Ok(value) => ethers::abi::encode(&[Token::String(value)]),
+                    Err(err) => return evm_error(&err.to_string()),
+                }
+            }
+            HevmCall::EnvUint(name) => match env_var(&name, "uint", parse_uint) {
+                Ok(value) => ethers::abi::encode(&[Token::Uint(value)]),
+                Err(err) => return evm_error(&err.to_string()),
+            },
+            HevmCall::EnvAddress(name) => {
+                match env_var(&name, "address", |value| value.parse().ok()) {
+                    Ok(value) => ethers::abi::encode(&[Token::Address(value)]),
+                    Err(err) => return evm_error(&err.to_string()),
+                }
+            }
+            HevmCall::EnvBool(name) => match env_var(&name, "bool", |value| value.parse().ok()) {
+                Ok(value) => ethers::abi::encode(&[Token::Bool(value)]),
+                Err(err) => return evm_error(&err.to_string()),
+            },
+            HevmCall::ExpectRevert(data) => {
+                if state.backend.cheats.expected_revert.is_some() {
+                    return evm_error("already expecting a revert from the next call")
+                }
+                let depth = state.metadata().depth().unwrap_or_default();
+                state.backend.cheats.expected_revert =
+                    Some(ExpectedRevert { data, selector_only: false, caller: msg_sender, depth });
+                Vec::new()
+            }
+            HevmCall::ExpectEmit(checks) => {
+                let depth = state.metadata().depth().unwrap_or_default();
+                state.backend.cheats.expected_emits.push(ExpectedEmit {
+                    caller: msg_sender,
+                    depth,
+                    checks,
+                    log: None,
+                    emitter: None,
+                    found: false,
+                });
+                Vec::new()
+            }
+            HevmCall::ExpectCall(target, data, value) => {
+                state.backend.cheats.expected_calls.push(ExpectedCall {
+                    target,
+                    data,
+                    value,
+                    found: false,
+                });
+                Vec::new()
+            }
+            HevmCall::MockCall(target, data, retdata) => {
+                if target == *CHEATCODE_ADDRESS {
+                    return evm_error("cannot mock calls to the cheatcode address")
+                }
+                state.backend.cheats.mocked_calls.insert((target, data), retdata);
+                Vec::new()
+            }
+            HevmCall::ClearPendingExpectations => {
+                state.backend.cheats.clear_expectations();
+                Vec::new()
+            }
+            HevmCall::ClearMockedCalls => {
+                state.backend.cheats.mocked_calls.clear();
+                Vec::new()
+            }
+            HevmCall::Record => {
+                // restarts recording from scratch if it was already started
+                state.backend.cheats.accesses = Some(Default::default());
+                Vec::new()
+            }
+            HevmCall::Accesses(who) => {
+                let (reads, writes) = match state.backend.cheats.accesses {
+                    Some(ref accesses) => (
+                        accesses.reads.borrow().get(&who).cloned().unwrap_or_default(),
+                        accesses.writes.get(&who).cloned().unwrap_or_default(),
+                    ),
+                    None => (Vec::new(), Vec::new()),
+                };
+                let to_token = |slots: Vec<H256>| {
+                    Token::Array(
+                        slots
+                            .into_iter()
+                            .map(|slot| Token::FixedBytes(slot.as_bytes().to_vec()))
+                            .collect(),
+                    )
+                };
+                ethers::abi::encode(&[to_token(reads), to_token(writes)])
+            }
+            HevmCall::Snapshot => {
+                // cheatcode overrides (e.g. `warp`) are not part of the snapshot
+                let depth = state.metadata().depth().unwrap_or_default();
+                state.snapshots.push((depth, state.substate.clone()));
+                ethers::abi::encode(&[Token::Uint((state.snapshots.len() - 1).into())])
+            }
+            HevmCall::RevertTo(id) => {
+                let depth = state.metadata().depth().unwrap_or_default();
+                let snapshot = if id < state.snapshots.len().into() {
+                    state.snapshots.get(id.as_usize()).filter(|(d, _)| *d == depth).cloned()
+                } else {
+                    None
+                };
+                // restores storage, balances, nonces and code, and discards the logs emitted
+                // since the snapshot. the snapshot is kept, so that it can be reverted to again.
+                // the gas accounting of the current call is left untouched.
+                let reverted = match snapshot {
+                    Some((_, mut substate)) => {
+                        *substate.metadata_mut() = state.substate.metadata().clone();
+                        state.substate = substate;
+                        true
+                    }
+                    None => false,
+                };
+                ethers::abi::encode(&[Token::Bool(reverted)])
+            }
+            HevmCall::Label(who, label) => {
+                // relabeling overwrites the previous label
+                state.backend.cheats.labels.insert(who, label);
+                Vec::new()
+            }
+            HevmCall::Assume(condition) => {
+                if !condition {
+                    // outside of fuzzing, this is a regular revert which fails the test
+                    state.backend.cheats.assume_rejections += 1;
+                    return Capture::Exit((
+                        ExitReason::Revert(ExitRevert::Reverted),
+                        ASSUME_MAGIC_REVERT.to_vec(),
+                    ))
+                }
+                Vec::new()
+            }
+            HevmCall::ExpectRevertSelector(selector) => {
+                if state.backend.cheats.expected_revert.is_some() {
+                    return evm_error("already expecting a revert from the next call")
+                }
+                let depth = state.metadata().depth().unwrap_or_default();
+                state.backend.cheats.expected_revert = Some(ExpectedRevert {
+                    data: selector.to_vec(),
+                    selector_only: true,
+                    caller: msg_sender,
+                    depth,
+                });
+                Vec::new()
+            }
+        };
+
+        Capture::Exit((ExitReason::Succeed(ExitSucceed::Returned), retdata))
+    }
+
+    /// If there is an active prank and this call or deployment is made by the pranking
+    /// contract at the depth it started the prank from, returns the pranked address instead of
+    /// the caller, consuming the prank if it was a single-call one. Calls made by the callee are
+    /// one level deeper and are left untouched.
+    fn prank_caller(&mut self, caller: H160) -> H160 {
+        let depth = self.state().metadata().depth().unwrap_or_default();
+        prank_caller(&mut self.state_mut().backend.cheats.prank, caller, depth)
+    }
+
+    /// Applies the active prank to a call, replacing its caller (and the source of any value
+    /// transfer) with the pranked address
+    fn apply_prank(
+        &mut self,
+        mut transfer: Option<Transfer>,
+        mut context: Context,
+    ) -> (Option<Transfer>, Context) {
+        let caller = self.prank_caller(context.caller);
+        if caller != context.caller {
+            context.caller = caller;
+            if let Some(transfer) = transfer.as_mut() {
+                transfer.source = caller;
+            }
+        }
+        (transfer, context)
+    }
+
+    /// Marks the addresses and storage slots of an access list as warm, as per EIP-2930
+    fn initialize_access_list(&mut self, access_list: Vec<(H160, Vec<H256>)>) {
+        let addresses = access_list.iter().map(|(address, _)| *address).collect::<Vec<_>>();
+        self.state_mut().metadata_mut().access_addresses(addresses.into_iter());
+        let storage_keys = access_list
+            .into_iter()
+            .flat_map(|(address, keys)| keys.into_iter().map(move |key| (address, key)));
+        self.state_mut().metadata_mut().access_storages(storage_keys);
+    }
+
+    /// Records the result of the current frame in the call trace, given the gas which was left
+    /// before it was entered
+    fn finish_trace(&mut self, gas_before: u64, reason: &ExitReason, retdata: &[u8]) {
+        let gas_used = gas_before.saturating_sub(self.state().metadata().gasometer().gas());
+        self.tracer.finish(matches!(reason, ExitReason::Succeed(_)), retdata, gas_used);
+    }
+
+    /// Takes the expected revert if this call or deployment made by `caller` is the one it
+    /// applies to, i.e. the next one made by the contract which called `expectRevert`, at the
+    /// same depth
+    fn take_expected_revert(&mut self, caller: H160) -> Option<ExpectedRevert> {
+        let depth = self.state().metadata().depth().unwrap_or_default();
+        let cheats = &mut self.state_mut().backend.cheats;
+        let applies = matches!(
+            cheats.expected_revert,
+            Some(ref expected) if expected.caller == caller && expected.depth == depth
+        );
+        if applies {
+            cheats.expected_revert.take()
+        } else {
+            None
+        }
+    }
+
+    /// Sets the callee as the emitter of the expected logs if this call is the one they apply
+    /// to, i.e. the next call made by the contract which called `expectEmit`, at the same depth
+    fn arm_expected_emits(&mut self, context: &Context) -> bool {
+        let depth = self.state().metadata().depth().unwrap_or_default();
+        let mut armed = false;
+        for expected in self.state_mut().backend.cheats.expected_emits.iter_mut() {
+            if expected.log.is_some() &&
+                expected.emitter.is_none() &&
+                expected.caller == context.caller &&
+                expected.depth == depth
+            {
+                expected.emitter = Some(context.address);
+                armed = true;
+            }
+        }
+        armed
+    }
+
+    /// Checks that all the logs expected from a call were emitted, making it revert with the
+    /// first missing log and the logs which were emitted instead otherwise
+    fn check_expected_emits(
+        &mut self,
+        res: Capture<(ExitReason, Vec<u8>), Infallible>,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        let cheats = &mut self.state_mut().backend.cheats;
+        let (armed, pending) = std::mem::take(&mut cheats.expected_emits)
+            .into_iter()
+            .partition::<Vec<_>, _>(|expected| expected.emitter.is_some());
+        cheats.expected_emits = pending;
+        let emitted = std::mem::take(&mut cheats.emitted_logs);
+
+        // a reverting call fails regardless of its logs
+        if !matches!(res, Capture::Exit((ExitReason::Succeed(_), _))) {
+            return res
+        }
+        match armed.iter().find(|expected| !expected.found) {
+            Some(missing) => {
+                let emitted = emitted
+                    .iter()
+                    .map(|log| {
+                        let address = cheats.format_address(log.address);
+                        format!("{}: {}", address, format_log(&log.topics, &log.data))
+                    })
+                    .collect::<Vec<_>>();
+                evm_error(&format!(
+                    "expected log {} was not emitted, emitted logs: [{}]",
+                    format_expected_log(missing),
+                    emitted.join(", ")
+                ))
+            }
+            None => res,
+        }
+    }
+
+    /// Checks the result of a call against the revert it was expected to return. Matching
+    /// reverts are swallowed so that the test can continue, anything else makes the call
+    /// revert with a message explaining the mismatch.
+    fn check_expected_revert(
+        &self,
+        expected: ExpectedRevert,
+        res: Capture<(ExitReason, Vec<u8>), Infallible>,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        let (reason, retdata) = match res {
+            Capture::Exit(inner) => inner,
+            Capture::Trap(_) => unreachable!("Trap is Infallible"),
+        };
+        let expected_data = format_expected_revert(&expected);
+        match reason {
+            ExitReason::Revert(_) if revert_matches(&expected, &retdata) => {
+                Capture::Exit((ExitReason::Succeed(ExitSucceed::Returned), DUMMY_OUTPUT.to_vec()))
+            }
+            // without expected data, failing in any way satisfies the expectation
+            ExitReason::Error(_) | ExitReason::Fatal(_) if expected.is_any() => {
+                Capture::Exit((ExitReason::Succeed(ExitSucceed::Returned), DUMMY_OUTPUT.to_vec()))
+            }
+            ExitReason::Revert(_) => evm_error(&format!(
+                "expected {}, got {}",
+                expected_data,
+                format_revert_data(&retdata)
+            )),
+            ExitReason::Succeed(_) if expected.is_any() => evm_error(&format!(
+                "expected {}, but the call succeeded and returned {}",
+                expected_data,
+                format_revert_data(&retdata)
+            )),
+            ExitReason::Succeed(_) => {
+                evm_error(&format!("expected {}, but the call succeeded", expected_data))
+            }
+            reason => evm_error(&format!("expected {}, got {:?}", expected_data, reason)),
+        }
+    }
+
+    // NB: This function is copy-pasted from uptream's call_inner
+    #[allow(clippy::too_many_arguments)]
+    fn call_inner(
+        &mut self,
+        code_address: H160,
+        transfer: Option<Transfer>,
+        input: Vec<u8>,
+        target_gas: Option<u64>,
+        is_static: bool,
+        take_l64: bool,
+        take_stipend: bool,
+        context: Context,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        macro_rules! try_or_fail {
+            ( $e:expr ) => {
+                match $e {
+                    Ok(v) => v,
+                    Err(e) => return Capture::Exit((e.into(), Vec::new())),
+                }
+            };
+        }
+
+        fn l64(gas: u64) -> u64 {
+            gas - gas / 64
+        }
+
+        let after_gas = if take_l64 && self.config().call_l64_after_gas {
+            if self.config().estimate {
+                let initial_after_gas = self.state().metadata().gasometer().gas();
+                let diff = initial_after_gas - l64(initial_after_gas);
+                try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(diff));
+                self.state().metadata().gasometer().gas()
+            } else {
+                l64(self.state().metadata().gasometer().gas())
+            }
+        } else {
+            self.state().metadata().gasometer().gas()
+        };
+
+        let target_gas = target_gas.unwrap_or(after_gas);
+        let mut gas_limit = min(target_gas, after_gas);
+
+        try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(gas_limit));
+
+        if let Some(transfer) = transfer.as_ref() {
+            if take_stipend && transfer.value != U256::zero() {
+                gas_limit = gas_limit.saturating_add(self.config().call_stipend);
+            }
+        }
+
+        let code = self.code(code_address);
+
+        self.handler.enter_substate(gas_limit, is_static);
+        self.state_mut().touch(context.address);
+
+        if let Some(depth) = self.state().metadata().depth() {
+            if depth > self.config().call_stack_limit {
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                return Capture::Exit((ExitError::CallTooDeep.into(), Vec::new()))
+            }
+        }
+
+        if let Some(transfer) = transfer {
+            match self.state_mut().transfer(transfer) {
+                Ok(()) => (),
+                Err(e) => {
+                    let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                    return Capture::Exit((ExitReason::Error(e), Vec::new()))
+                }
+            }
+        }
+
+        if let Some(precompile) = self.precompiles.get(&code_address).copied() {
+            return match precompile(&input, Some(gas_limit), &context, is_static) {
+                Ok(PrecompileOutput { exit_status, output, cost, logs }) => {
+                    for Log { address, topics, data } in logs {
+                        self.state_mut().log(address, topics, data);
+                    }
+                    let _ = self.state_mut().metadata_mut().gasometer_mut().record_cost(cost);
+                    let _ = self.handler.exit_substate(StackExitKind::Succeeded);
+                    Capture::Exit((ExitReason::Succeed(exit_status), output))
+                }
+                Err(e) => {
+                    let _ = self.handler.exit_substate(StackExitKind::Failed);
+                    Capture::Exit((ExitReason::Error(e), Vec::new()))
+                }
+            }
+        }
+
+        // NB: we get the config from the inner handler, so that its lifetime is not tied to the
+        // borrow of `self` which is passed to the runtime below
+        let config = self.handler.config();
+        let code = Rc::new(code);
+        let mut runtime = Runtime::new(code.clone(), Rc::new(input), context, config);
+
+        let reason = self.run(&mut runtime, &code);
+
+        match reason {
+            ExitReason::Succeed(s) => {
+                let _ = self.handler.exit_substate(StackExitKind::Succeeded);
+                Capture::Exit((ExitReason::Succeed(s), runtime.machine().return_value()))
+            }
+            ExitReason::Error(e) => {
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Error(e), Vec::new()))
+            }
+            ExitReason::Revert(e) => {
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                Capture::Exit((ExitReason::Revert(e), runtime.machine().return_value()))
+            }
+            ExitReason::Fatal(e) => {
+                self.state_mut().metadata_mut().gasometer_mut().fail();
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Fatal(e), Vec::new()))
+            }
+        }
+    }
+
+    /// Executes the code of a frame until it exits
+    #[cfg(not(any(feature = "debug-steps", feature = "coverage")))]
+    fn run(&mut self, runtime: &mut Runtime, _code: &[u8]) -> ExitReason {
+        match runtime.run(self) {
+            Capture::Exit(s) => s,
+            Capture::Trap(_) => unreachable!("Trap is Infallible"),
+        }
+    }
+
+    /// Executes the code of a frame until it exits, step by step if the steps or the coverage
+    /// are recorded
+    #[cfg(any(feature = "debug-steps", feature = "coverage"))]
+    #[cfg_attr(not(all(feature = "debug-steps", feature = "coverage")), allow(unused_variables))]
+    fn run(&mut self, runtime: &mut Runtime, code: &[u8]) -> ExitReason {
+        #[cfg(feature = "coverage")]
+        let mut hits = if self.coverage.enabled { Some(BitSet::default()) } else { None };
+        #[cfg(feature = "coverage")]
+        let records_coverage = hits.is_some();
+        #[cfg(not(feature = "coverage"))]
+        let records_coverage = false;
+        #[cfg(feature = "debug-steps")]
+        let records_steps = self.steps.config.is_some();
+        #[cfg(not(feature = "debug-steps"))]
+        let records_steps = false;
+
+        if !records_coverage && !records_steps {
+            return match runtime.run(self) {
+                Capture::Exit(s) => s,
+                Capture::Trap(_) => unreachable!("Trap is Infallible"),
+            }
+        }
+
+        let reason = loop {
+            let machine = runtime.machine();
+            if let (Ok(pc), Some((opcode, stack))) = (machine.position(), machine.inspect()) {
+                #[cfg(feature = "coverage")]
+                if let Some(hits) = &mut hits {
+                    hits.insert(*pc);
+                }
+                #[cfg(feature = "debug-steps")]
+                if records_steps {
+                    let metadata = self.state().metadata();
+                    let gas_left = metadata.gasometer().gas();
+                    let depth = metadata.depth().unwrap_or_default();
+                    let frame = self.tracer.current();
+                    self.steps.record(*pc, opcode.0, gas_left, stack.data(), depth, frame);
+                }
+            }
+            match runtime.step(self) {
+                Ok(()) => {}
+                Err(Capture::Exit(s)) => break s,
+                Err(Capture::Trap(_)) => unreachable!("Trap is Infallible"),
+            }
+        };
+
+        #[cfg(feature = "coverage")]
+        if let Some(hits) = hits {
+            self.coverage.record(H256::from(ethers::utils::keccak256(code)), &hits);
+        }
+        reason
+    }
+
+    // NB: This function is copy-pasted from uptream's create_inner, except that the address is
+    // derived by the caller, so that it can be derived from the pranked deployer
+    fn create_inner(
+        &mut self,
+        caller: H160,
+        address: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        target_gas: Option<u64>,
+        take_l64: bool,
+    ) -> Capture<(ExitReason, Option<H160>, Vec<u8>), Infallible> {
+        macro_rules! try_or_fail {
+            ( $e:expr ) => {
+                match $e {
+                    Ok(v) => v,
+                    Err(e) => return Capture::Exit((e.into(), None, Vec::new())),
+                }
+            };
+        }
+
+        fn l64(gas: u64) -> u64 {
+            gas - gas / 64
+        }
+
+        self.state_mut().metadata_mut().access_address(caller);
+        self.state_mut().metadata_mut().access_address(address);
+
+        if let Some(depth) = self.state().metadata().depth() {
+            if depth > self.config().call_stack_limit {
+                return Capture::Exit((ExitError::CallTooDeep.into(), None, Vec::new()))
+            }
+        }
+
+        if self.balance(caller) < value {
+            return Capture::Exit((ExitError::OutOfFund.into(), None, Vec::new()))
+        }
+
+        let after_gas = if take_l64 && self.config().call_l64_after_gas {
+            if self.config().estimate {
+                let initial_after_gas = self.state().metadata().gasometer().gas();
+                let diff = initial_after_gas - l64(initial_after_gas);
+                try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(diff));
+                self.state().metadata().gasometer().gas()
+            } else {
+                l64(self.state().metadata().gasometer().gas())
+            }
+        } else {
+            self.state().metadata().gasometer().gas()
+        };
+
+        let target_gas = target_gas.unwrap_or(after_gas);
+        let gas_limit = min(after_gas, target_gas);
+        try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(gas_limit));
+
+        self.state_mut().inc_nonce(caller);
+
+        self.handler.enter_substate(gas_limit, false);
+
+        {
+            if self.code_size(address) != U256::zero() {
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                return Capture::Exit((ExitError::CreateCollision.into(), None, Vec::new()))
+            }
+
+            if self.handler.nonce(address) > U256::zero() {
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                return Capture::Exit((ExitError::CreateCollision.into(), None, Vec::new()))
+            }
+
+            self.state_mut().reset_storage(address);
+        }
+
+        let context = Context { address, caller, apparent_value: value };
+        let transfer = Transfer { source: caller, target: address, value };
+        match self.state_mut().transfer(transfer) {
+            Ok(()) => (),
+            Err(e) => {
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                return Capture::Exit((ExitReason::Error(e), None, Vec::new()))
+            }
+        }
+
+        if self.config().create_increase_nonce {
+            self.state_mut().inc_nonce(address);
+        }
+
+        let config = self.handler.config();
+        let init_code = Rc::new(init_code);
+        let mut runtime = Runtime::new(init_code.clone(), Rc::new(Vec::new()), context, config);
+
+        let reason = self.run(&mut runtime, &init_code);
+
+        match reason {
+            ExitReason::Succeed(s) => {
+                let out = runtime.machine().return_value();
+
+                if let Some(limit) = self.config().create_contract_limit {
+                    if out.len() > limit {
+                        self.state_mut().metadata_mut().gasometer_mut().fail();
+                        let _ = self.handler.exit_substate(StackExitKind::Failed);
+                        return Capture::Exit((
+                            ExitError::CreateContractLimit.into(),
+                            None,
+                            Vec::new(),
+                        ))
+                    }
+                }
+
+                match self.state_mut().metadata_mut().gasometer_mut().record_deposit(out.len()) {
+                    Ok(()) => {
+                        let e = self.handler.exit_substate(StackExitKind::Succeeded);
+                        self.state_mut().set_code(address, out);
+                        try_or_fail!(e);
+                        Capture::Exit((ExitReason::Succeed(s), Some(address), Vec::new()))
+                    }
+                    Err(e) => {
+                        let _ = self.handler.exit_substate(StackExitKind::Failed);
+                        Capture::Exit((ExitReason::Error(e), None, Vec::new()))
+                    }
+                }
+            }
+            ExitReason::Error(e) => {
+                self.state_mut().metadata_mut().gasometer_mut().fail();
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Error(e), None, Vec::new()))
+            }
+            ExitReason::Revert(e) => {
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                Capture::Exit((ExitReason::Revert(e), None, runtime.machine().return_value()))
+            }
+            ExitReason::Fatal(e) => {
+                self.state_mut().metadata_mut().gasometer_mut().fail();
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Fatal(e), None, Vec::new()))
+            }
+        }
+    }
+}
+
+// Delegates everything internally, except the `call` and `create` calls, which are hooked
+// so that we can modify
+impl<'a, B: Backend> HevmState for CheatcodeStackExecutor<'a, B> {
+    fn warp(&mut self, timestamp: U256) {
+        self.state_mut().backend.cheats.block_timestamp = Some(timestamp);
+    }
+
+    fn roll(&mut self, number: U256) {
+        self.state_mut().backend.cheats.block_number = Some(number);
+    }
+
+    fn store(&mut self, who: H160, slot: H256, value: H256) {
+        let state = self.state_mut();
+        // touch the account so that it gets created if it does not exist yet
+        state.touch(who);
+        // write to the executor's state, so that the write is visible to any SLOADs in the
+        // rest of the transaction
+        state.set_storage(who, slot, value);
+    }
+
+    fn load(&self, who: H160, slot: H256) -> H256 {
+        // reads the pending state, so that any writes which happened earlier in the
+        // transaction are observed. never-written slots are zero.
+        // reads via the inner handler, so that this is not recorded as an access
+        self.handler.storage(who, slot)
+    }
+
+    fn deal(&mut self, who: H160, balance: U256) {
+        let state = self.state_mut();
+        state.touch(who);
+        // zero out the balance before depositing, so that it can be lowered as well
+        state.reset_balance(who);
+        state.deposit(who, balance);
+    }
+
+    fn etch(&mut self, who: H160, code: Vec<u8>) {
+        let state = self.state_mut();
+        // fresh accounts get created with a zero nonce. etching empty code makes the account
+        // an EOA again.
+        state.touch(who);
+        state.set_code(who, code);
+    }
+
+    fn prank_mut(&mut self) -> &mut Option<Prank> {
+        // the cheatcode call does not enter a substate, so pranks are started at the depth of
+        // the frame which called `prank`
+        &mut self.state_mut().backend.cheats.prank
+    }
+}
+
+impl<'a, B: Backend> Handler for CheatcodeStackExecutor<'a, B> {
+    type CreateInterrupt = Infallible;
+    type CreateFeedback = Infallible;
+    type CallInterrupt = Infallible;
+    type CallFeedback = Infallible;
+
+    fn call(
+        &mut self,
+        code_address: H160,
+        transfer: Option<Transfer>,
+        input: Vec<u8>,
+        target_gas: Option<u64>,
+        is_static: bool,
+        context: Context,
+    ) -> Capture<(ExitReason, Vec<u8>), Self::CallInterrupt> {
+        // We intercept calls to the `CHEATCODE_ADDRESS` to apply the cheatcode directly
+        // to the state.
+        if code_address == *CHEATCODE_ADDRESS {
+            // the call is static either if it is a `STATICCALL`, or if it is made from a frame
+            // which is static
+            let is_static = is_static || self.state().metadata().is_static();
+            self.apply_cheatcode(input, context.caller, is_static)
+        } else if code_address == *CONSOLE_ADDRESS {
+            // unknown overloads are ignored, so that tests do not break with other versions of
+            // `console.sol`
+            if let Some(message) = decode_console_log(&input) {
+                self.console_logs.push(message);
+            }
+            Capture::Exit((ExitReason::Succeed(ExitSucceed::Stopped), Vec::new()))
+        } else {
+            // calls are expected at any depth
+            let value = transfer.as_ref().map(|transfer| transfer.value).unwrap_or_default();
+            for expected in self.state_mut().backend.cheats.expected_calls.iter_mut() {
+                if expected.matches(code_address, &input, value) {
+                    expected.found = true;
+                }
+            }
+
+            // the expectation is matched against the caller before any prank is applied
+            let expected_revert = self.take_expected_revert(context.caller);
+            let expects_emits = self.arm_expected_emits(&context);
+            let (transfer, context) = self.apply_prank(transfer, context);
+            let gas_before = self.state().metadata().gasometer().gas();
+            let gas_limit = target_gas.map_or(gas_before, |gas| min(gas, gas_before));
+            self.tracer.start(
+                CallKind::Call,
+                context.caller,
+                code_address,
+                &input,
+                value,
+                gas_limit,
+            );
+            // mocked calls return without executing the callee's code, at any depth
+            let mocked =
+                self.state().backend.cheats.mocked_call(code_address, &input).map(<[u8]>::to_vec);
+            let mut res = match mocked {
+                Some(retdata) => {
+                    Capture::Exit((ExitReason::Succeed(ExitSucceed::Returned), retdata))
+                }
+                None => {
+                    // the code is read before the call, as the callee may self-destruct
+                    let reported = if self.gas_report.enabled {
+                        let selector = input.iter().take(4).copied().collect::<Vec<_>>();
+                        Some((self.handler.code(code_address), selector))
+                    } else {
+                        None
+                    };
+                    let res = self.call_inner(
+                        code_address,
+                        transfer,
+                        input,
+                        target_gas,
+                        is_static,
+                        true,
+                        true,
+                        context,
+                    );
+                    if let Some((code, selector)) = reported {
+                        let gas_used =
+                            gas_before.saturating_sub(self.state().metadata().gasometer().gas());
+                        self.gas_report.record(&code, &selector, gas_used);
+                    }
+                    res
+                }
+            };
+            if expects_emits {
+                res = self.check_expected_emits(res);
+            }
+            if let Some(expected) = expected_revert {
+                res = self.check_expected_revert(expected, res);
+            }
+            if let Capture::Exit((ref reason, ref retdata)) = res {
+                self.finish_trace(gas_before, reason, retdata);
+            }
+            res
+        }
+    }
+
+    // Everything else is left the same
+    fn balance(&self, address: H160) -> U256 {
+        self.access_list.access_address(address);
+        self.handler.balance(address)
+    }
+
+    fn code_size(&self, address: H160) -> U256 {
+        self.access_list.access_address(address);
+        if address == *CHEATCODE_ADDRESS {
+            return CHEATCODE_STUB.len().into()
+        }
+        self.handler.code_size(address)
+    }
+
+    fn code_hash(&self, address: H160) -> H256 {
+        self.access_list.access_address(address);
+        if address == *CHEATCODE_ADDRESS {
+            return H256::from(ethers::utils::keccak256(CHEATCODE_STUB))
+        }
+        self.handler.code_hash(address)
+    }
+
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.access_list.access_address(address);
+        if address == *CHEATCODE_ADDRESS {
+            return CHEATCODE_STUB.to_vec()
+        }
+        self.handler.code(address)
+    }
+
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        self.access_list.access_slot(address, index);
+        if let Some(ref accesses) = self.state().backend.cheats.accesses {
+            accesses.reads.borrow_mut().entry(address).or_default().push(index);
+        }
+        self.handler.storage(address, index)
+    }
+
+    fn original_storage(&self, address: H160, index: H256) -> H256 {
+        self.handler.original_storage(address, index)
+    }
+
+    fn gas_left(&self) -> U256 {
+        // Need to disambiguate type, because the same method exists in the `SputnikExecutor`
+        // trait and the `Handler` trait.
+        Handler::gas_left(&self.handler)
+    }
+
+    fn gas_price(&self) -> U256 {
+        self.handler.gas_price()
+    }
+
+    fn origin(&self) -> H160 {
+        self.handler.origin()
+    }
+
+    fn block_hash(&self, number: U256) -> H256 {
+        self.handler.block_hash(number)
+    }
+
+    fn block_number(&self) -> U256 {
+        self.handler.block_number()
+    }
+
+    fn block_coinbase(&self) -> H160 {
+        self.handler.block_coinbase()
+    }
+
+    fn block_timestamp(&self) -> U256 {
+        self.handler.block_timestamp()
+    }
+
+    fn block_difficulty(&self) -> U256 {
+        self.handler.block_difficulty()
+    }
+
+    fn block_gas_limit(&self) -> U256 {
+        self.handler.block_gas_limit()
+    }
+
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.handler.block_base_fee_per_gas()
+    }
+
+    fn chain_id(&self) -> U256 {
+        self.handler.chain_id()
+    }
+
+    fn exists(&self, address: H160) -> bool {
+        self.handler.exists(address)
+    }
+
+    fn deleted(&self, address: H160) -> bool {
+        self.handler.deleted(address)
+    }
+
+    fn is_cold(&self, address: H160, index: Option<H256>) -> bool {
+        match index {
+            Some(index) => self.access_list.access_slot(address, index),
+            None => self.access_list.access_address(address),
+        }
+        self.handler.is_cold(address, index)
+    }
+
+    fn set_storage(&mut self, address: H160, index: H256, value: H256) -> Result<(), ExitError> {
+        if let Some(ref mut accesses) = self.state_mut().backend.cheats.accesses {
+            accesses.writes.entry(address).or_default().push(index);
+        }
+        self.access_list.access_slot(address, index);
+        self.dictionary.insert(index.0);
+        self.dictionary.insert(value.0);
+        self.handler.set_storage(address, index, value)
+    }
+
+    fn log(&mut self, address: H160, topics: Vec<H256>, data: Vec<u8>) -> Result<(), ExitError> {
+        let depth = self.state().metadata().depth().unwrap_or_default();
+        let cheats = &mut self.state_mut().backend.cheats;
+
+        // the first log emitted after `expectEmit` by its caller is the template to match
+        // against, so it is not emitted
+        if let Some(expected) = cheats.expected_emits.iter_mut().find(|e| e.log.is_none()) {
+            if expected.caller == address && expected.depth == depth {
+                expected.log = Some(RawLog { topics, data });
+                return Ok(())
+            }
+        }
+
+        // logs of the callee are matched against the expected ones in order
+        if cheats.expected_emits.iter().any(|expected| expected.emitter.is_some()) {
+            if let Some(expected) = cheats
+                .expected_emits
+                .iter_mut()
+                .find(|expected| expected.emitter.is_some() && !expected.found)
+            {
+                if expected.emitter == Some(address) && expected.matches(&topics, &data) {
+                    expected.found = true;
+                }
+            }
+            cheats.emitted_logs.push(Log { address, topics: topics.clone(), data: data.clone() });
+        }
+
+        if self.tracer.enabled {
+            self.tracer.log(RawLog { topics: topics.clone(), data: data.clone() });
+        }
+
+        self.handler.log(address, topics, data)
+    }
+
+    fn mark_delete(&mut self, address: H160, target: H160) -> Result<(), ExitError> {
+        self.handler.mark_delete(address, target)
+    }
+
+    fn create(
+        &mut self,
+        caller: H160,
+        scheme: CreateScheme,
+        value: U256,
+        init_code: Vec<u8>,
+        target_gas: Option<u64>,
+    ) -> Capture<(ExitReason, Option<H160>, Vec<u8>), Self::CreateInterrupt> {
+        // the expectations are matched against the deployer before any prank is applied, and
+        // the created contract is the emitter of the expected logs
+        let expected_revert = self.take_expected_revert(caller);
+        let caller = self.prank_caller(caller);
+        // the address is derived from the (pranked) deployer, and from its nonce for `CREATE`
+        let scheme = match scheme {
+            CreateScheme::Legacy { .. } => CreateScheme::Legacy { caller },
+            CreateScheme::Create2 { code_hash, salt, .. } => {
+                CreateScheme::Create2 { caller, code_hash, salt }
+            }
+            scheme => scheme,
+        };
+        let address = self.handler.create_address(scheme);
+        let expects_emits =
+            self.arm_expected_emits(&Context { address, caller, apparent_value: value });
+        let gas_before = self.state().metadata().gasometer().gas();
+        let gas_limit = target_gas.map_or(gas_before, |gas| min(gas, gas_before));
+        self.tracer.start(CallKind::Create, caller, address, &init_code, value, gas_limit);
+
+        let (reason, created, retdata) =
+            match self.create_inner(caller, address, value, init_code, target_gas, true) {
+                Capture::Exit(inner) => inner,
+                Capture::Trap(_) => unreachable!("Trap is Infallible"),
+            };
+        if let Some(created) = created.filter(|_| self.dictionary.enabled) {
+            self.dictionary.insert_address(created);
+            let code = self.handler.code(created);
+            self.dictionary.insert_push_constants(&code);
+        }
+        let mut res = Capture::Exit((reason, retdata));
+        if expects_emits {
+            res = self.check_expected_emits(res);
+        }
+        if let Some(expected) = expected_revert {
+            res = self.check_expected_revert(expected, res);
+        }
+        if let Capture::Exit((ref reason, ref retdata)) = res {
+            self.finish_trace(gas_before, reason, retdata);
+        }
+        match res {
+            // a swallowed revert returns the address the contract would have been deployed at,
+            // because Solidity reverts if the created address is zero
+            Capture::Exit((reason @ ExitReason::Succeed(_), _)) => {
+                Capture::Exit((reason, created.or(Some(address)), Vec::new()))
+            }
+            Capture::Exit((reason, retdata)) => Capture::Exit((reason, None, retdata)),
+            Capture::Trap(_) => unreachable!("Trap is Infallible"),
+        }
+    }
+
+    fn pre_validate(
+        &mut self,
+        context: &Context,
+        opcode: Opcode,
+        stack: &Stack,
+    ) -> Result<(), ExitError> {
+        // polled before every instruction, so that endless executions can be stopped
+        if self.interrupt.as_ref().map_or(false, Interrupt::is_raised) {
+            return Err(ExitError::Other(INTERRUPTED.into()))
+        }
+        self.handler.pre_validate(context, opcode, stack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        call_tracing::TraceItem,
+        known_contracts::KnownContracts,
+        sputnik::{
+            cheatcodes::{is_assume_rejection, HEVM_ABI},
+            helpers::{new_backend, new_vicinity},
+        },
+        state_diff::Change,
+        test_helpers::{state_cheatcodes, COMPILED},
+        Evm,
+    };
+    use dapp_utils::{decode_revert, get_func};
+    use ethers::{
+        abi::{Function, Tokenize},
+        types::{Address, Bytes, Signature},
+    };
+    use sputnik::backend::{MemoryAccount, MemoryBackend, MemoryState, MemoryVicinity};
+    use std::{sync::Arc, time::Instant};
+
+    type TestExecutor<'a> = Executor<
+        CheatcodeStackState<'a, MemoryBackend<'a>>,
+        CheatcodeStackExecutor<'a, MemoryBackend<'a>>,
+    >;
+
+    /// Instantiates a cheatcode-enabled EVM with the `CheatCodes` fixture deployed
+    fn cheatcodes_evm<'a>(
+        config: &'a Config,
+        vicinity: &'a MemoryVicinity,
+    ) -> (TestExecutor<'a>, Address) {
+        let compiled = COMPILED.get("CheatCodes").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let backend = new_backend(vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, config, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        (evm, addr)
+    }
+
+    fn warp_calldata(args: Vec<u8>) -> Vec<u8> {
+        let mut calldata = ethers::utils::id("warp(uint256)").to_vec();
+        calldata.extend_from_slice(&args);
+        calldata
+    }
+
+    #[test]
+    fn applies_state_cheatcodes() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("CheatCodes").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let backend = new_backend(&vicinity, Default::default());
+        let evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        state_cheatcodes(evm, addr, compiled);
+    }
+
+    #[test]
+    fn applies_known_cheatcode() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        let calldata = warp_calldata(ethers::abi::encode(&[Token::Uint(100.into())]));
+        let (reason, _) = match evm.executor.apply_cheatcode(calldata, H160::zero(), false) {
+            Capture::Exit(inner) => inner,
+            Capture::Trap(_) => unreachable!(),
+        };
+        assert_eq!(reason, ExitReason::Succeed(ExitSucceed::Returned));
+        assert_eq!(evm.executor.state().backend.cheats.block_timestamp, Some(100.into()));
+    }
+
+    #[test]
+    fn unknown_cheatcode_reverts_with_reason() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        let (reason, retdata) =
+            match evm.executor.apply_cheatcode(vec![0xde, 0xad, 0xbe, 0xef], H160::zero(), false) {
+                Capture::Exit(inner) => inner,
+                Capture::Trap(_) => unreachable!(),
+            };
+        assert_eq!(reason, ExitReason::Revert(ExitRevert::Reverted));
+        assert_eq!(decode_revert(&retdata), "unknown cheatcode 0xdeadbeef");
+    }
+
+    #[test]
+    fn malformed_cheatcode_args_revert() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        // `warp` expects a full 32 byte word
+        let (reason, retdata) =
+            match evm.executor.apply_cheatcode(warp_calldata(vec![1, 2, 3]), H160::zero(), false) {
+                Capture::Exit(inner) => inner,
+                Capture::Trap(_) => unreachable!(),
+            };
+        assert_eq!(reason, ExitReason::Revert(ExitRevert::Reverted));
+        assert!(decode_revert(&retdata).starts_with("invalid arguments for `warp(uint256)`"));
+        assert_eq!(evm.executor.state().backend.cheats.block_timestamp, None);
+
+        // calldata shorter than a selector
+        let (reason, _) = match evm.executor.apply_cheatcode(vec![1], H160::zero(), false) {
+            Capture::Exit(inner) => inner,
+            Capture::Trap(_) => unreachable!(),
+        };
+        assert_eq!(reason, ExitReason::Revert(ExitRevert::Reverted));
+    }
+
+    #[test]
+    fn warp() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testWarp()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        // the override persists for subsequent calls
+        let (timestamp, _, _) = evm
+            .call::<U256, _>(
+                Address::zero(),
+                addr,
+                &get_func("function timestamp() returns (uint256)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(timestamp, 1641070800.into());
+    }
+
+    #[test]
+    fn warp_backwards() {
+        let config = Config::istanbul();
+        let mut vicinity = new_vicinity();
+        vicinity.block_timestamp = 1641070800.into();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        // rewinding to before the original timestamp must work as well
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function warp(uint256 time)").unwrap(),
+                U256::from(100),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().block_timestamp(), 100.into());
+    }
+
+    #[test]
+    fn roll() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in ["function testRoll()", "function testRollAndWarp()"] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+        assert_eq!(evm.state().block_number(), 10.into());
+    }
+
+    #[test]
+    fn store() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let greeter = COMPILED.get("Greeter").expect("could not find contract");
+        let greeter_addr: Address = "0x2000000000000000000000000000000000000000".parse().unwrap();
+        evm.initialize_contracts(vec![(greeter_addr, greeter.runtime_bytecode.clone())]);
+
+        // overwrites the greeting and reads it back via the getter in the same call
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function storeGreeting(address greeter)").unwrap(),
+                greeter_addr,
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        let (greeting, _, _) = evm
+            .call::<String, _>(
+                Address::zero(),
+                greeter_addr,
+                &get_func("function greeting() returns (string)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(greeting, "gm");
+
+        // also works on accounts which do not exist yet
+        let fresh: Address = "0x3000000000000000000000000000000000000000".parse().unwrap();
+        let slot = H256::from_low_u64_be(1);
+        let value = H256::from_low_u64_be(1337);
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function store(address who, bytes32 slot, bytes32 value)").unwrap(),
+                (fresh, slot, value),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert!(evm.state().exists(fresh));
+        assert_eq!(evm.state().storage(fresh, slot), value);
+    }
+
+    #[test]
+    fn load() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let who: Address = "0x3000000000000000000000000000000000000000".parse().unwrap();
+        let slot = H256::from_low_u64_be(1);
+        let value = H256::from_low_u64_be(1337);
+
+        // stores and loads the slot within the same transaction
+        let store_and_load = get_func(
+            "function storeAndLoad(address who, bytes32 slot, bytes32 value) returns (bytes32)",
+        )
+        .unwrap();
+        let (loaded, reason, _) = evm
+            .call::<H256, _>(
+                Address::zero(),
+                addr,
+                &store_and_load,
+                (who, slot, value),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(loaded, value);
+
+        // never-written slots are zero
+        let (loaded, reason, _) = evm
+            .call::<H256, _>(
+                Address::zero(),
+                addr,
+                &get_func("function load(address who, bytes32 slot) returns (bytes32)").unwrap(),
+                (who, H256::from_low_u64_be(2)),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(loaded, H256::zero());
+    }
+
+    #[test]
+    fn prank() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in ["function testPrank()", "function testPrankNested()"] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+        assert_eq!(evm.state().backend.cheats.prank, None);
+    }
+
+    #[test]
+    fn prank_twice_reverts() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let calldata = ethers::utils::id("testFailPrankTwice()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(evm.check_success(addr, &reason, true));
+        assert_eq!(
+            decode_revert(retdata.as_ref()),
+            "cannot `prank` again before the pranked call was made"
+        );
+        // the prank which was set before reverting does not outlive the transaction
+        assert_eq!(evm.state().backend.cheats.prank, None);
+    }
+
+    #[test]
+    fn start_prank() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testStartPrank()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        // a prank which is never stopped is cleared when the transaction ends
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function startPrank(address who)").unwrap(),
+                Address::from_low_u64_be(1337),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().backend.cheats.prank, None);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testPrank()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+    }
+
+    #[test]
+    fn stop_prank_without_prank_reverts() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let calldata = ethers::utils::id("testFailStopPrank()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(evm.check_success(addr, &reason, true));
+        assert_eq!(decode_revert(retdata.as_ref()), "no active prank to stop");
+    }
+
+    #[test]
+    fn transact_create_applies_cheatcodes() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, _) = cheatcodes_evm(&config, &vicinity);
+        let compiled = COMPILED.get("WarpingConstructor").expect("could not find contract");
+        let timestamp = get_func("function timestamp() returns (uint256)").unwrap();
+        let deployer = Address::repeat_byte(1);
+        let init_code = |time: u64| {
+            let mut init_code = compiled.bytecode.to_vec();
+            init_code.extend(ethers::abi::encode(&[Token::Uint(time.into())]));
+            init_code
+        };
+
+        let (reason, created, _) =
+            evm.executor.transact_create(deployer, 0.into(), init_code(100), evm.gas_limit, vec![]);
+        assert_eq!(reason, ExitReason::Succeed(ExitSucceed::Returned));
+        let created = created.unwrap();
+        assert_eq!(created, ethers::utils::get_contract_address(deployer, 0u64));
+        // the timestamp warped by the constructor outlives the deployment
+        assert_eq!(evm.state().backend.cheats.block_timestamp, Some(100.into()));
+        let (time, _, _) =
+            evm.call::<U256, _>(deployer, created, &timestamp, (), 0.into(), None).unwrap();
+        assert_eq!(time, 100.into());
+
+        let salt = H256::repeat_byte(7);
+        let (reason, created, _) = evm.executor.transact_create2(
+            deployer,
+            0.into(),
+            init_code(200),
+            evm.gas_limit,
+            vec![],
+            salt,
+        );
+        assert_eq!(reason, ExitReason::Succeed(ExitSucceed::Returned));
+        let created = created.unwrap();
+        let expected =
+            ethers::utils::get_create2_address(deployer, salt.as_bytes().to_vec(), init_code(200));
+        assert_eq!(created, expected);
+        assert_eq!(evm.state().backend.cheats.block_timestamp, Some(200.into()));
+        let (time, _, _) =
+            evm.call::<U256, _>(deployer, created, &timestamp, (), 0.into(), None).unwrap();
+        assert_eq!(time, 200.into());
+
+        // the same salt and init code cannot be deployed twice
+        let (reason, created, _) = evm.executor.transact_create2(
+            deployer,
+            0.into(),
+            init_code(200),
+            evm.gas_limit,
+            vec![],
+            salt,
+        );
+        assert!(matches!(reason, ExitReason::Error(ExitError::CreateCollision)));
+        assert!(created.is_none());
+    }
+
+    #[test]
+    fn deal() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in ["function testDeal()", "function testDealSelfAndSend()"] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+
+        let eth = U256::exp10(18);
+        assert_eq!(evm.state().basic(Address::from_low_u64_be(1337)).balance, U256::zero());
+        assert_eq!(evm.state().basic(Address::from_low_u64_be(1338)).balance, eth / 4);
+        assert_eq!(evm.state().basic(addr).balance, eth * 3 / 4);
+    }
+
+    #[test]
+    fn etch() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testEtch()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        let compiled = COMPILED.get("ReturnsConstant").expect("could not find contract");
+        let etched = Address::from_low_u64_be(0x4000);
+        assert_eq!(evm.state().code(etched), compiled.runtime_bytecode.as_ref().to_vec());
+        assert_eq!(evm.state().basic(etched).nonce, U256::zero());
+    }
+
+    #[test]
+    fn sign() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let digest = H256::from(ethers::utils::keccak256("gm"));
+        let ((v, r, s), reason, _) = evm
+            .call::<(u8, H256, H256), _>(
+                Address::zero(),
+                addr,
+                &get_func(
+                    "function sign(uint256 pk, bytes32 digest) returns (uint8,bytes32,bytes32)",
+                )
+                .unwrap(),
+                (U256::one(), digest),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        let sig = Signature {
+            r: U256::from_big_endian(r.as_bytes()),
+            s: U256::from_big_endian(s.as_bytes()),
+            v: v.into(),
+        };
+        let expected: Address = "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap();
+        assert_eq!(sig.recover(digest).unwrap(), expected);
+    }
+
+    #[test]
+    fn ecrecover_recovers_signer() {
+        for config in [Config::istanbul(), Config::berlin()] {
+            let vicinity = new_vicinity();
+            let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+            let (_, reason, _) = evm
+                .call::<(), _>(
+                    Address::zero(),
+                    addr,
+                    &get_func("function testEcrecover()").unwrap(),
+                    (),
+                    0.into(),
+                    None,
+                )
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false));
+        }
+    }
+
+    #[test]
+    fn precompile_gas_matches_mainnet() {
+        let config = Config::berlin();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        // sha256 costs 60 gas plus 12 per word
+        let sha256 = Address::from_low_u64_be(2);
+        let (retdata, reason, gas) = evm
+            .call_raw(Address::zero(), sha256, vec![1; 64].into(), 0.into(), false, None)
+            .unwrap();
+        assert_eq!(reason, ExitReason::Succeed(ExitSucceed::Returned));
+        assert_eq!(
+            hex::encode(retdata),
+            "7c8975e1e60a5c8337f28edf8c33c3b180360b7279644a9bc1af3c51e6220bf5"
+        );
+        assert_eq!(gas, 84);
+    }
+
+    #[test]
+    fn sign_with_invalid_key_reverts() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        let func = HEVM_ABI.function("sign").unwrap();
+        // zero and the secp256k1 curve order are both out of range
+        let order = U256::from_str_radix(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            16,
+        )
+        .unwrap();
+        for private_key in [U256::zero(), order, U256::MAX] {
+            let calldata = func
+                .encode_input(&[Token::Uint(private_key), Token::FixedBytes(vec![1; 32])])
+                .unwrap();
+            let (reason, retdata) =
+                match evm.executor.apply_cheatcode(calldata, H160::zero(), false) {
+                    Capture::Exit(inner) => inner,
+                    Capture::Trap(_) => unreachable!(),
+                };
+            assert_eq!(reason, ExitReason::Revert(ExitRevert::Reverted));
+            assert!(decode_revert(&retdata).starts_with("invalid private key"));
+        }
+    }
+
+    #[test]
+    fn addr() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let func = get_func("function addr(uint256 pk) returns (address)").unwrap();
+        let (derived, reason, _) = evm
+            .call::<Address, _>(Address::zero(), addr, &func, U256::one(), 0.into(), None)
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        let expected: Address = "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap();
+        assert_eq!(derived, expected);
+
+        // agrees with the signer of `sign`
+        let pk = U256::from(1337);
+        let (derived, _, _) =
+            evm.call::<Address, _>(Address::zero(), addr, &func, pk, 0.into(), None).unwrap();
+        let digest = H256::from(ethers::utils::keccak256("gm"));
+        let ((v, r, s), _, _) = evm
+            .call::<(u8, H256, H256), _>(
+                Address::zero(),
+                addr,
+                &get_func(
+                    "function sign(uint256 pk, bytes32 digest) returns (uint8,bytes32,bytes32)",
+                )
+                .unwrap(),
+                (pk, digest),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        let sig = Signature {
+            r: U256::from_big_endian(r.as_bytes()),
+            s: U256::from_big_endian(s.as_bytes()),
+            v: v.into(),
+        };
+        assert_eq!(sig.recover(digest).unwrap(), derived);
+
+        // invalid keys revert instead of panicking
+        let calldata = ethers::prelude::encode_function_data(&func, U256::zero()).unwrap();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(matches!(reason, ExitReason::Revert(_)));
+        assert!(decode_revert(retdata.as_ref()).starts_with("invalid private key"));
+    }
+
+    #[test]
+    fn ffi() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        let func = get_func("function ffi(string[] args) returns (bytes)").unwrap();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        // must be explicitly enabled
+        let calldata = ethers::prelude::encode_function_data(&func, args(&["echo", "gm"])).unwrap();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(matches!(reason, ExitReason::Revert(_)));
+        assert_eq!(decode_revert(retdata.as_ref()), "ffi disabled");
+
+        evm.executor.state_mut().backend.cheats.ffi_enabled = true;
+
+        // hex output gets decoded, anything else is returned as-is
+        let (output, reason, _) = evm
+            .call::<ethers::types::Bytes, _>(
+                Address::zero(),
+                addr,
+                &func,
+                args(&["echo", "0x1337"]),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(output.as_ref(), &[0x13, 0x37]);
+
+        let (output, _, _) = evm
+            .call::<ethers::types::Bytes, _>(
+                Address::zero(),
+                addr,
+                &func,
+                args(&["echo", "gm"]),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(output.as_ref(), b"gm\n");
+
+        // failing commands revert with their stderr
+        let calldata = ethers::prelude::encode_function_data(
+            &func,
+            args(&["sh", "-c", "echo oops >&2; exit 1"]),
+        )
+        .unwrap();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(matches!(reason, ExitReason::Revert(_)));
+        let reason = decode_revert(retdata.as_ref());
+        assert!(reason.starts_with("ffi: `sh` failed"), "{}", reason);
+        assert!(reason.ends_with("oops"), "{}", reason);
+    }
+
+    #[test]
+    fn read_write_file() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        let read = get_func("function readFile(string path) returns (string)").unwrap();
+        let write = get_func("function writeFile(string path, string data)").unwrap();
+        fn revert_reason(evm: &mut TestExecutor, addr: Address, calldata: Bytes) -> String {
+            let (retdata, reason, _) =
+                evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+            assert!(matches!(reason, ExitReason::Revert(_)));
+            decode_revert(retdata.as_ref())
+        }
+
+        // must be explicitly enabled
+        let calldata =
+            ethers::prelude::encode_function_data(&write, ("out.txt".to_owned(), "gm".to_owned()))
+                .unwrap();
+        assert_eq!(revert_reason(&mut evm, addr, calldata), "filesystem access disabled");
+
+        let root = std::env::temp_dir().join(format!("dapptools-fs-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        evm.executor.state_mut().backend.cheats.fs_enabled = true;
+        evm.executor.state_mut().backend.cheats.fs_root = root.clone();
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &write,
+                ("out.txt".to_owned(), "gm".to_owned()),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(std::fs::read_to_string(root.join("out.txt")).unwrap(), "gm");
+
+        let (contents, reason, _) = evm
+            .call::<String, _>(Address::zero(), addr, &read, "./out.txt".to_owned(), 0.into(), None)
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(contents, "gm");
+
+        // missing files revert with the os error
+        let calldata =
+            ethers::prelude::encode_function_data(&read, "missing.txt".to_owned()).unwrap();
+        let reason = revert_reason(&mut evm, addr, calldata);
+        assert!(reason.starts_with("readFile: could not read `missing.txt`"), "{}", reason);
+
+        // paths cannot escape the root
+        for path in ["/etc/passwd", "../out.txt", "sub/../../out.txt"] {
+            let calldata = ethers::prelude::encode_function_data(&read, path.to_owned()).unwrap();
+            let reason = revert_reason(&mut evm, addr, calldata);
+            assert!(reason.contains("must be relative"), "{}", reason);
+        }
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn env() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        std::env::set_var("DAPPTOOLS_TEST_ENV_STRING", "gm");
+        std::env::set_var("DAPPTOOLS_TEST_ENV_UINT", "1337");
+        std::env::set_var("DAPPTOOLS_TEST_ENV_HEX_UINT", "0x539");
+        std::env::set_var(
+            "DAPPTOOLS_TEST_ENV_ADDRESS",
+            "0x0000000000000000000000000000000000001337",
+        );
+        std::env::set_var("DAPPTOOLS_TEST_ENV_BOOL", "true");
+        std::env::set_var("DAPPTOOLS_TEST_ENV_BAD_UINT", "lots");
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testEnv()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        // unparseable and missing variables revert
+        let func = get_func("function envUint(string name) returns (uint256)").unwrap();
+        for (name, expected) in [
+            (
+                "DAPPTOOLS_TEST_ENV_BAD_UINT",
+                "environment variable `DAPPTOOLS_TEST_ENV_BAD_UINT` is not a valid uint: `lots`",
+            ),
+            (
+                "DAPPTOOLS_TEST_ENV_MISSING",
+                "could not read environment variable `DAPPTOOLS_TEST_ENV_MISSING`",
+            ),
+        ] {
+            let calldata = ethers::prelude::encode_function_data(&func, name.to_owned()).unwrap();
+            let (retdata, reason, _) =
+                evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+            assert!(matches!(reason, ExitReason::Revert(_)));
+            let reason = decode_revert(retdata.as_ref());
+            assert!(reason.starts_with(expected), "{}", reason);
+        }
+    }
+
+    #[test]
+    fn expect_revert() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in [
+            "function testExpectRevertString()",
+            "function testExpectRevertData()",
+            "function testExpectRevertSelector()",
+            "function testExpectRevertAny()",
+        ] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+
+        for (func, expected) in [
+            ("testFailExpectRevertWrongReason()", "expected revert `nope`, got `other`"),
+            ("testFailExpectRevertSucceeds()", "expected revert `nope`, but the call succeeded"),
+            ("testFailExpectRevertNested()", "expected revert `nested`, but the call succeeded"),
+            ("testFailExpectRevertNotCalled()", "expected revert `nope`, but no call was made"),
+            (
+                "testFailExpectRevertAnySucceeds()",
+                "expected a revert, but the call succeeded and returned \
+                 0x000000000000000000000000000000000000000000000000000000000000002a",
+            ),
+        ] {
+            let calldata = ethers::utils::id(func).to_vec();
+            let (retdata, reason, _) = evm
+                .call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, true), "{} did not fail", func);
+            assert_eq!(decode_revert(retdata.as_ref()), expected);
+        }
+        assert_eq!(evm.state().backend.cheats.expected_revert, None);
+    }
+
+    #[test]
+    fn expect_emit() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in [
+            "function testExpectEmit()",
+            "function testExpectEmitIgnoresData()",
+            "function testExpectEmitMultiple()",
+        ] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+
+        for func in [
+            "testFailExpectEmitWrongData()",
+            "testFailExpectEmitOutOfOrder()",
+            "testFailExpectEmitNested()",
+            "testFailExpectEmitNotCalled()",
+        ] {
+            let calldata = ethers::utils::id(func).to_vec();
+            let (retdata, reason, _) = evm
+                .call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, true), "{} did not fail", func);
+            assert!(decode_revert(retdata.as_ref()).starts_with("expected log"));
+        }
+        assert!(evm.state().backend.cheats.expected_emits.is_empty());
+        assert!(evm.state().backend.cheats.emitted_logs.is_empty());
+    }
+
+    #[test]
+    fn expect_call() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in [
+            "function testExpectCall()",
+            "function testExpectCallNested()",
+            "function testExpectCallWithValue()",
+        ] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+
+        for func in ["testFailExpectCallNotMade()", "testFailExpectCallWrongValue()"] {
+            let calldata = ethers::utils::id(func).to_vec();
+            let (retdata, reason, _) = evm
+                .call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, true), "{} did not fail", func);
+            let reason = decode_revert(retdata.as_ref());
+            assert!(reason.starts_with("expected call to"), "{}", reason);
+        }
+        assert!(evm.state().backend.cheats.expected_calls.is_empty());
+    }
+
+    #[test]
+    fn mock_call() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in [
+            "function testMockCall()",
+            "function testMockCallLongestPrefix()",
+            "function testMockCallNested()",
+            "function testClearMockedCalls()",
+        ] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+
+        let calldata = ethers::utils::id("testFailMockCheatcode()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(evm.check_success(addr, &reason, true));
+        assert_eq!(decode_revert(retdata.as_ref()), "cannot mock calls to the cheatcode address");
+    }
+
+    #[test]
+    fn record_accesses() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testRecordAccesses()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        // recording is not stopped by `accesses`
+        let accesses = evm.state().backend.cheats.accesses.as_ref().unwrap();
+        assert_eq!(accesses.reads.borrow().values().map(Vec::len).sum::<usize>(), 2);
+        assert_eq!(accesses.writes.values().map(Vec::len).sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn generated_access_list_warms_storage() {
+        let config = Config::berlin();
+        let vicinity = new_vicinity();
+        let (mut evm, _) = cheatcodes_evm(&config, &vicinity);
+        let compiled = COMPILED.get("StorageSummer").expect("could not find contract");
+        let addr = Address::repeat_byte(2);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let sum = get_func("function sum() returns (uint256)").unwrap();
+        let calldata = Bytes::from(sum.encode_input(&[]).unwrap());
+
+        let access_list = evm.generate_access_list(Address::zero(), addr, calldata);
+        let slots = (0..16).map(H256::from_low_u64_be).collect::<Vec<_>>();
+        assert_eq!(access_list, vec![(addr, slots)]);
+
+        // the dry run leaves the slots cold
+        let id = evm.snapshot();
+        let (_, _, cold) =
+            evm.call::<U256, _>(Address::zero(), addr, &sum, (), 0.into(), None).unwrap();
+        evm.restore(id).unwrap();
+        let (_, _, warm) = evm
+            .call_with_access_list::<U256, _>(
+                Address::zero(),
+                addr,
+                &sum,
+                (),
+                0.into(),
+                None,
+                access_list,
+            )
+            .unwrap();
+        // every `SLOAD` is warm, and the cost of the access list is intrinsic
+        assert_eq!(cold - warm, 16 * (2100 - 100));
+    }
+
+    #[test]
+    fn snapshot() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testSnapshot()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        // the nonce bump of the deployment was reverted, and so was the emitted log
+        assert_eq!(evm.state().basic(addr).nonce, U256::zero());
+        assert!(evm.state().substate.logs().is_empty());
+        assert!(evm.state().snapshots.is_empty());
+    }
+
+    #[test]
+    fn fee() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        assert_eq!(evm.state().backend.block_base_fee_per_gas(), U256::zero());
+
+        let fee = U256::from(25) * U256::exp10(9);
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function fee(uint256 baseFee)").unwrap(),
+                fee,
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().backend.block_base_fee_per_gas(), fee);
+    }
+
+    #[test]
+    fn difficulty_and_coinbase() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in ["function testDifficulty()", "function testCoinbase()"] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+        assert_eq!(evm.state().block_difficulty(), 1337.into());
+        assert_eq!(evm.state().block_coinbase(), Address::from_low_u64_be(0x6000));
+    }
+
+    #[test]
+    fn chain_id() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (separator, reason, _) = evm
+            .call::<H256, _>(
+                Address::zero(),
+                addr,
+                &get_func("function testChainId() returns (bytes32)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().chain_id(), 10.into());
+
+        let keccak = |s: &str| Token::FixedBytes(ethers::utils::keccak256(s).to_vec());
+        let domain = concat!(
+            "EIP712Domain(string name,string version,uint256 chainId,",
+            "address verifyingContract)"
+        );
+        let expected = ethers::utils::keccak256(ethers::abi::encode(&[
+            keccak(domain),
+            keccak("CheatCodes"),
+            keccak("1"),
+            Token::Uint(10.into()),
+            Token::Address(addr),
+        ]));
+        assert_eq!(separator, H256::from(expected));
+    }
+
+    #[test]
+    fn nonce() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testSetNonce()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().basic(addr).nonce, 301.into());
+
+        let calldata = ethers::utils::id("testFailSetNonceLower()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(evm.check_success(addr, &reason, true));
+        assert_eq!(
+            decode_revert(retdata.as_ref()),
+            "cannot lower the nonce of 0x0000000000000000000000000000000000007000 from 10 to 5"
+        );
+    }
+
+    #[test]
+    fn label() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let calldata = ethers::utils::id("testFailExpectCallLabeled()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(evm.check_success(addr, &reason, true));
+        let reason = decode_revert(retdata.as_ref());
+        assert!(reason.starts_with("expected call to emitter with data"), "{}", reason);
+
+        // the label was overwritten and is available outside of the executor
+        let labels = evm.labels();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels.values().next().unwrap(), "emitter");
+        // the addresses without a label are named by their hex representation
+        let cheats = &evm.state().backend.cheats;
+        assert_eq!(cheats.format_address(*labels.keys().next().unwrap()), "emitter");
+        assert_eq!(cheats.format_address(addr), format!("{:?}", addr));
+    }
+
+    #[test]
+    fn assume() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        let func = get_func("function testAssume(uint256 x)").unwrap();
+
+        let calldata = ethers::prelude::encode_function_data(&func, U256::from(5)).unwrap();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(is_assume_rejection(&reason, retdata.as_ref()));
+        // not a success when not fuzzing
+        assert!(!evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().backend.cheats.assume_rejections, 1);
+
+        let calldata = ethers::prelude::encode_function_data(&func, U256::from(20)).unwrap();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        assert!(!is_assume_rejection(&reason, retdata.as_ref()));
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().backend.cheats.assume_rejections, 1);
+
+        // regular reverts are not rejections
+        let calldata = ethers::utils::id("testFailExpectRevertNotCalled()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(!is_assume_rejection(&reason, retdata.as_ref()));
+    }
+
+    #[test]
+    #[cfg(feature = "fuzz")]
+    fn fuzz_discards_assume_rejections() {
+        use crate::fuzz::{fuzz, FuzzConfig};
+
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        let fuzz_config = FuzzConfig::deterministic();
+
+        let func = get_func("function testAssume(uint256 x)").unwrap();
+        let res = fuzz(&mut evm, &fuzz_config, &func, addr, false, &Default::default()).unwrap();
+        assert!(res.success);
+        let gas = res.gas.unwrap();
+        assert!(gas.mean > 0 && gas.median > 0);
+
+        // too many rejections fail the test
+        let func = get_func("function assumeNever(uint256 x)").unwrap();
+        let res = fuzz(&mut evm, &fuzz_config, &func, addr, false, &Default::default()).unwrap();
+        assert!(!res.success);
+        assert!(res.counterexample.is_none());
+        assert!(res.reason.is_some());
+        assert!(res.gas.is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "fuzz")]
+    fn fuzz_dictionary_finds_stored_threshold() {
+        use crate::fuzz::{fuzz, FuzzConfig};
+
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("DictionaryTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        // nothing is harvested unless enabled
+        evm.setup(addr).unwrap();
+        assert!(evm.fuzz_dictionary().values().is_empty());
+
+        evm.set_fuzz_dictionary_enabled(true);
+        evm.setup(addr).unwrap();
+        evm.set_fuzz_dictionary_enabled(false);
+        let mut dictionary = evm.fuzz_dictionary();
+        assert!(!dictionary.values().is_empty());
+        dictionary.enabled = true;
+
+        let func = compiled.abi.function("testThreshold").unwrap();
+        let fuzz_config = FuzzConfig::deterministic();
+        // random values do not hit the threshold
+        let res = fuzz(&mut evm, &fuzz_config, func, addr, false, &Default::default()).unwrap();
+        assert!(res.success);
+
+        let res = fuzz(&mut evm, &fuzz_config, func, addr, false, &dictionary).unwrap();
+        assert!(!res.success);
+        assert_eq!(res.reason.as_deref(), Some("found the threshold"));
+    }
+
+    #[test]
+    fn set_blockhash() {
+        let config = Config::istanbul();
+        let mut vicinity = new_vicinity();
+        vicinity.block_number = 10.into();
+        vicinity.block_hashes = vec![H256::repeat_byte(0xaa)];
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testSetBlockhash()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.state().block_hash(5.into()), H256::from_low_u64_be(1337));
+        // overrides which were cleared or never set return the value of the underlying backend
+        assert_eq!(evm.state().block_hash(1000.into()), H256::zero());
+        assert_eq!(evm.state().block_hash(9.into()), H256::repeat_byte(0xaa));
+    }
+
+    #[test]
+    fn tx_gas_price() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testTxGasPrice()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        // the gas price and the base fee are overriden independently
+        assert_eq!(evm.state().gas_price(), 42.into());
+        assert_eq!(evm.state().backend.block_base_fee_per_gas(), 7.into());
+    }
+
+    #[test]
+    fn transact() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testTransact()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        // the sender was credited with exactly the value it sent
+        assert_eq!(evm.state().basic(Address::from_low_u64_be(0x5000)).balance, U256::zero());
+    }
+
+    #[test]
+    fn clear_pending_expectations() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testClearPendingExpectations()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(evm.executor.pending_expectations(), 0);
+
+        // all the unsatisfied expectations are reported
+        let calldata = ethers::utils::id("testFailPendingExpectations()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert_eq!(evm.executor.pending_expectations(), 2);
+        assert!(!evm.check_success(addr, &reason, false));
+        assert_eq!(
+            decode_revert(retdata.as_ref()),
+            "expected revert `nope`, but no call was made; expected call to \
+             0x0000000000000000000000000000000000001234 with data 0x was not made"
+        );
+    }
+
+    #[test]
+    fn reset_cheatcodes() {
+        let config = Config::istanbul();
+        let mut vicinity = new_vicinity();
+        vicinity.block_timestamp = 100.into();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        fn timestamp(evm: &mut TestExecutor, addr: Address) -> U256 {
+            let func = get_func("function timestamp() returns (uint256)").unwrap();
+            evm.call::<U256, _>(Address::zero(), addr, &func, (), 0.into(), None).unwrap().0
+        }
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testWarp()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(timestamp(&mut evm, addr), 1641070800.into());
+
+        // resetting to a state with cheatcodes applied clears them
+        let id = evm.snapshot();
+        let state = evm.state().clone();
+        evm.reset(state);
+        assert_eq!(timestamp(&mut evm, addr), 100.into());
+        // along with the snapshots of the previous state
+        assert!(evm.restore(id).is_err());
+    }
+
+    #[test]
+    fn reset_to_backend() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let funded = Address::repeat_byte(1);
+        let state = vec![(funded, MemoryAccount { balance: 100.into(), ..Default::default() })]
+            .into_iter()
+            .collect();
+        let backend = new_backend(&vicinity, state);
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        evm.executor.state_mut().deposit(funded, 1.into());
+        let id = evm.snapshot();
+
+        // the changes made on top of the backend are discarded, but its accounts are kept
+        evm.reset_to_backend();
+        assert!(evm.executor.state().code(addr).is_empty());
+        assert_eq!(evm.executor.state().basic(funded).balance, 100.into());
+        assert!(evm.restore(id).is_err());
+    }
+
+    /// Compares resetting the executor between calls to rebuilding it from the same state, on
+    /// 1,000 empty calls. Rebuilding has to copy the whole state into a new backend (or, for a
+    /// forked backend, to fetch the accounts again), so resetting should be faster by an order
+    /// of magnitude. Run with `cargo test --release -- --ignored --nocapture reset_benchmark`.
+    #[test]
+    #[ignore]
+    fn reset_benchmark() {
+        const CALLS: usize = 1_000;
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        // as many accounts as a forked backend may have cached
+        let state: MemoryState = (1..=10_000)
+            .map(|i| {
+                (
+                    Address::from_low_u64_be(i),
+                    MemoryAccount { balance: i.into(), ..Default::default() },
+                )
+            })
+            .collect();
+        let empty = Address::repeat_byte(0xee);
+        fn call_empty(evm: &mut TestExecutor, empty: Address) {
+            let (_, reason, _) =
+                evm.call_raw(Address::zero(), empty, Bytes::new(), 0.into(), false, None).unwrap();
+            assert!(matches!(reason, ExitReason::Succeed(_)));
+        }
+
+        let start = Instant::now();
+        for _ in 0..CALLS {
+            let backend = new_backend(&vicinity, state.clone());
+            let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+            call_empty(&mut evm, empty);
+        }
+        let rebuilt = start.elapsed();
+
+        let backend = new_backend(&vicinity, state);
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+        let start = Instant::now();
+        for _ in 0..CALLS {
+            evm.reset_to_backend();
+            call_empty(&mut evm, empty);
+        }
+        let reset = start.elapsed();
+
+        println!(
+            "{} empty calls: {:?} rebuilding the executor, {:?} resetting it ({:.1}x faster)",
+            CALLS,
+            rebuilt,
+            reset,
+            rebuilt.as_secs_f64() / reset.as_secs_f64()
+        );
+        assert!(reset < rebuilt);
+    }
+
+    #[test]
+    fn snapshots_share_the_backend() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, _) = cheatcodes_evm(&config, &vicinity);
+        assert_eq!(Arc::strong_count(&evm.executor.state().backend.backend), 1);
+
+        let id = evm.snapshot();
+        evm.snapshot();
+        assert_eq!(Arc::strong_count(&evm.executor.state().backend.backend), 3);
+        evm.restore(id).unwrap();
+        assert_eq!(Arc::strong_count(&evm.executor.state().backend.backend), 2);
+    }
+
+    /// Compares restoring a snapshot taken after `GreeterTest`'s `setUp()` before each call, as
+    /// the fuzz tests do, to rebuilding the executor, deploying the contract and running the
+    /// setup again, on 1,000 calls. Restoring has to be faster by an order of magnitude. Run
+    /// with `cargo test --release -- --ignored snapshot_benchmark`.
+    #[test]
+    #[ignore]
+    fn snapshot_benchmark() {
+        const CALLS: usize = 1_000;
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("GreeterTest").expect("could not find contract");
+        let setup = get_func("function setUp()").unwrap();
+        let greet = get_func("function greet(string)").unwrap();
+        fn call(evm: &mut TestExecutor, addr: Address, func: &Function, args: impl Tokenize) {
+            let (_, reason, _) =
+                evm.call::<(), _>(Address::zero(), addr, func, args, 0.into(), None).unwrap();
+            assert!(matches!(reason, ExitReason::Succeed(_)));
+        }
+
+        let start = Instant::now();
+        for i in 0..CALLS {
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+            let (addr, _, _) =
+                evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap();
+            call(&mut evm, addr, &setup, ());
+            call(&mut evm, addr, &greet, (i.to_string(),));
+        }
+        let rebuilt = start.elapsed();
+
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+        let (addr, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap();
+        call(&mut evm, addr, &setup, ());
+        let snapshot = evm.snapshot();
+        let start = Instant::now();
+        for i in 0..CALLS {
+            evm.restore(snapshot).unwrap();
+            call(&mut evm, addr, &greet, (i.to_string(),));
+        }
+        let restored = start.elapsed();
+
+        let speedup = rebuilt.as_secs_f64() / restored.as_secs_f64();
+        assert!(
+            speedup >= 10.0,
+            "{:?} rebuilding the executor, {:?} restoring a snapshot ({:.1}x faster)",
+            rebuilt,
+            restored,
+            speedup
+        );
+    }
+
+    #[test]
+    fn selfdestruct() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("SelfDestructTest").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        // every function is called in a transaction of its own
+        fn call(evm: &mut TestExecutor, addr: Address, func: &str) {
+            let calldata = ethers::utils::id(func).to_vec().into();
+            let (retdata, reason, _) =
+                evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+            assert!(
+                matches!(reason, ExitReason::Succeed(_)),
+                "{} failed: {}",
+                func,
+                decode_revert(retdata.as_ref())
+            );
+        }
+
+        call(&mut evm, addr, "setUp()");
+        let child = evm
+            .call::<Address, _>(
+                Address::zero(),
+                addr,
+                &get_func("function child() returns (address)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap()
+            .0;
+        let alive = evm.snapshot();
+
+        // reverting to a snapshot taken before the destruction brings the contract back
+        call(&mut evm, addr, "destroyAndRevert()");
+        call(&mut evm, addr, "checkAlive()");
+
+        evm.set_state_diff_enabled(true);
+        call(&mut evm, addr, "destroy()");
+        let changes = evm.state_changes();
+        let destroyed = &changes[&child];
+        assert!(destroyed.destroyed);
+        assert!(destroyed.code.as_ref().unwrap().to.as_ref().is_empty());
+        assert_eq!(destroyed.nonce, Some(Change { from: 1.into(), to: 0.into() }));
+        let one_ether = U256::exp10(18);
+        assert_eq!(destroyed.balance, Some(Change { from: one_ether, to: 0.into() }));
+        // both the slot written by the constructor and the one written later are cleared
+        assert_eq!(
+            destroyed.storage.keys().copied().collect::<Vec<_>>(),
+            vec![H256::zero(), H256::from_low_u64_be(1)]
+        );
+        assert_eq!(
+            changes[&Address::from_low_u64_be(0xbeef)].balance,
+            Some(Change { from: 0.into(), to: one_ether })
+        );
+        evm.set_state_diff_enabled(false);
+
+        call(&mut evm, addr, "checkDestroyed()");
+        assert!(!evm.executor.state().deleted(child));
+        assert!(evm.executor.state().is_empty(child));
+
+        // as does restoring a snapshot of the executor taken before the transaction
+        evm.restore(alive).unwrap();
+        call(&mut evm, addr, "checkAlive()");
+
+        // once destroyed, contracts can be deployed again at the same address
+        call(&mut evm, addr, "destroy()");
+        call(&mut evm, addr, "resurrect()");
+        assert_eq!(evm.executor.state().basic(child).nonce, 1.into());
+        assert_eq!(evm.executor.state().destroyed[&child].times, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-steps")]
+    fn records_debug_steps() {
+        use crate::debug_steps::DebugConfig;
+
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let gm = get_func("function gm()").unwrap();
+
+        // nothing is recorded unless configured
+        evm.call::<(), _>(Address::zero(), addr, &gm, (), 0.into(), None).unwrap();
+        assert!(evm.debug_steps().is_empty());
+
+        evm.set_tracing_enabled(true);
+        evm.set_debug_config(Some(DebugConfig::default()));
+        evm.call::<(), _>(Address::zero(), addr, &gm, (), 0.into(), None).unwrap();
+        let steps = evm.debug_steps();
+        assert!(steps.iter().all(|step| step.depth == 0 && step.frame == Some(0)));
+        assert!(steps.windows(2).all(|steps| steps[0].gas_left >= steps[1].gas_left));
+
+        // the dispatcher jumps to the function, which stores the greeting in slot 0
+        let jumps_and_stores = steps
+            .iter()
+            .filter(|step| step.opcode == Opcode::JUMPDEST.0 || step.opcode == Opcode::SSTORE.0)
+            .collect::<Vec<_>>();
+        let store = jumps_and_stores.iter().position(|step| step.opcode == Opcode::SSTORE.0);
+        assert!(matches!(store, Some(index) if index > 0), "{:?}", jumps_and_stores);
+        assert_eq!(
+            jumps_and_stores.iter().filter(|step| step.opcode == Opcode::SSTORE.0).count(),
+            1
+        );
+        let store = jumps_and_stores[store.unwrap()];
+        let mut greeting = [0u8; 32];
+        greeting[..2].copy_from_slice(b"gm");
+        greeting[31] = 4;
+        assert_eq!(store.stack[..2], [H256::zero(), greeting.into()]);
+
+        // only the last steps are kept when they are bounded
+        evm.set_debug_config(Some(DebugConfig { max_steps: Some(10), ..Default::default() }));
+        evm.call::<(), _>(Address::zero(), addr, &gm, (), 0.into(), None).unwrap();
+        let last = evm.debug_steps();
+        assert_eq!(last.len(), 10);
+        assert_eq!(
+            last.iter().map(|step| step.pc).collect::<Vec<_>>(),
+            steps[steps.len() - 10..].iter().map(|step| step.pc).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "coverage")]
+    fn records_coverage() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("Branches").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &config, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        let code_hash = H256::from(ethers::utils::keccak256(&compiled.runtime_bytecode));
+        let branch = get_func("function branch(bool condition)").unwrap();
+
+        // the positions of the `SSTORE`s, one per branch, skipping the data of the pushes and
+        // the metadata appended after the first `INVALID`
+        let mut stores = Vec::new();
+        let mut pc = 0;
+        let code = compiled.runtime_bytecode.as_ref();
+        while pc < code.len() && code[pc] != Opcode::INVALID.0 {
+            if code[pc] == Opcode::SSTORE.0 {
+                stores.push(pc);
+            }
+            pc += match code[pc] {
+                push @ 0x60..=0x7f => (push - 0x5e) as usize,
+                _ => 1,
+            };
+        }
+        assert_eq!(stores.len(), 2);
+
+        evm.call::<(), _>(Address::zero(), addr, &branch, true, 0.into(), None).unwrap();
+        assert!(evm.coverage().is_empty());
+
+        evm.set_coverage_enabled(true);
+        evm.call::<(), _>(Address::zero(), addr, &branch, true, 0.into(), None).unwrap();
+        let hits = evm.coverage()[&code_hash].clone();
+        let covered = stores.iter().filter(|pc| hits.contains(**pc)).count();
+        assert_eq!(covered, 1, "untaken branch covered: {:?}", hits.iter().collect::<Vec<_>>());
+
+        // the coverage of the calls is aggregated
+        evm.call::<(), _>(Address::zero(), addr, &branch, false, 0.into(), None).unwrap();
+        let aggregated = &evm.coverage()[&code_hash];
+        assert!(stores.iter().all(|pc| aggregated.contains(*pc)));
+        assert!(hits.iter().all(|pc| aggregated.contains(pc)));
+        assert!(aggregated.len() > hits.len());
+    }
+
+    #[test]
+    fn static_context() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        // read-only cheatcodes can be used from view functions
+        let (_, reason, _) = evm
+            .call::<H256, _>(
+                Address::zero(),
+                addr,
+                &get_func("function loadView(address who, bytes32 slot) view returns (bytes32)")
+                    .unwrap(),
+                (addr, H256::zero()),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+
+        // state-changing ones revert
+        let calldata = ethers::utils::id("warpView()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(matches!(reason, ExitReason::Revert(_)));
+        assert_eq!(
+            decode_revert(retdata.as_ref()),
+            "cannot use state-changing cheatcode in static context"
+        );
+        assert_eq!(evm.state().backend.cheats.block_timestamp, None);
+    }
+
+    #[test]
+    fn cheatcode_address_code() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let ((size, hash), reason, _) = evm
+            .call::<(U256, H256), _>(
+                Address::zero(),
+                addr,
+                &get_func("function cheatcodeAddressCode() returns (uint256, bytes32)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(size, U256::one());
+        assert_eq!(hash, H256::from(ethers::utils::keccak256([0x00u8])));
+    }
+
+    #[test]
+    fn console_log() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testConsoleLog()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert_eq!(
+            evm.executor.console_logs(),
+            [
+                "gm".to_owned(),
+                "balance 1337".to_owned(),
+                "0x0000000000000000000000000000000000001337 true".to_owned(),
+            ]
+        );
+
+        evm.executor.clear_console_logs();
+        assert!(evm.executor.console_logs().is_empty());
+    }
+
+    #[test]
+    fn logs() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        let (_, reason, _) = evm
+            .call::<(), _>(
+                Address::zero(),
+                addr,
+                &get_func("function testEmitLogs()").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        // the log of the reverted call is dropped
+        let logs = evm.logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].topics[0], H256::from(ethers::utils::keccak256("Other(uint256)")));
+        assert_eq!(
+            logs[1].topics[0],
+            H256::from(ethers::utils::keccak256("Transfer(address,address,uint256)"))
+        );
+
+        // each call only returns its own logs
+        let (_, reason, _) = evm
+            .call::<U256, _>(
+                Address::zero(),
+                addr,
+                &get_func("function timestamp() returns (uint256)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert!(evm.logs().is_empty());
+    }
+
+    #[test]
+    fn create() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+
+        for func in [
+            "function testPrankCreate()",
+            "function testPrankCreate2()",
+            "function testExpectRevertCreate()",
+        ] {
+            let (_, reason, _) = evm
+                .call::<(), _>(Address::zero(), addr, &get_func(func).unwrap(), (), 0.into(), None)
+                .unwrap();
+            assert!(evm.check_success(addr, &reason, false), "{} failed", func);
+        }
+
+        let calldata = ethers::utils::id("testFailExpectRevertCreateSucceeds()").to_vec();
+        let (retdata, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert!(evm.check_success(addr, &reason, true));
+        assert_eq!(
+            decode_revert(retdata.as_ref()),
+            "expected revert `constructor reverted`, but the call succeeded"
+        );
+    }
+
+    #[test]
+    fn traces() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        let func = get_func("function testEmitLogs()").unwrap();
+
+        // nothing is recorded unless enabled
+        let (_, reason, _) =
+            evm.call::<(), _>(Address::zero(), addr, &func, (), 0.into(), None).unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        assert!(evm.traces().is_empty());
+
+        evm.set_tracing_enabled(true);
+        let (_, reason, _) =
+            evm.call::<(), _>(Address::zero(), addr, &func, (), 0.into(), None).unwrap();
+        assert!(evm.check_success(addr, &reason, false));
+        let traces = evm.traces();
+        let kinds = traces.iter().map(|node| node.trace.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![CallKind::Call, CallKind::Create, CallKind::Call, CallKind::Call]);
+        assert_eq!(traces[0].trace.address, addr);
+        assert!(traces[0].trace.success);
+        assert!(traces[1].trace.success);
+        assert!(traces[2].trace.success);
+        // the reverted call is traced as well
+        assert!(!traces[3].trace.success);
+        assert!(traces[1..].iter().all(|node| node.parent == Some(0) && node.trace.depth == 1));
+        // the logs are interleaved with the calls of the frame which emitted them
+        assert!(matches!(
+            traces[0].items.as_slice(),
+            [TraceItem::Call(1), TraceItem::Log(_), TraceItem::Call(2), TraceItem::Call(3)]
+        ));
+        assert!(matches!(traces[2].items.as_slice(), [TraceItem::Log(_)]));
+        assert!(matches!(traces[3].items.as_slice(), [TraceItem::Log(_)]));
+    }
+
+    #[test]
+    fn gas_report() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let (mut evm, addr) = cheatcodes_evm(&config, &vicinity);
+        let func = get_func("function testEmitLogs()").unwrap();
+        let known = KnownContracts::new(COMPILED.iter());
+
+        evm.set_gas_reporting_enabled(true);
+        for _ in 0..2 {
+            let (_, reason, _) =
+                evm.call::<(), _>(Address::zero(), addr, &func, (), 0.into(), None).unwrap();
+            assert!(evm.check_success(addr, &reason, false));
+        }
+
+        // the calls to the test contract itself are not reported
+        let report = evm.gas_report().contracts(&known);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].name, "Emitter");
+        let functions = report[0].functions.keys().collect::<Vec<_>>();
+        assert_eq!(functions, vec!["emitAndRevert", "emitTransfer"]);
+        assert!(report[0].functions.values().all(|stats| stats.calls == 2 && stats.min > 0));
+    }
+
+    #[test]
+    fn interrupt() {
+        let config = Config::istanbul();
+        let vicinity = new_vicinity();
+        let compiled = COMPILED.get("Loop").expect("could not find contract");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let backend = new_backend(&vicinity, Default::default());
+        // enough gas for the loop to run for hours
+        let mut evm = Executor::new_with_cheatcodes(backend, 1 << 60, &config, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        // the call being executed is stopped
+        let interrupt = Interrupt::default();
+        evm.set_interrupt(Some(interrupt.clone()));
+        let watchdog = {
+            let interrupt = interrupt.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                interrupt.raise()
+            })
+        };
+        let start = Instant::now();
+        let calldata = ethers::utils::id("spin()").to_vec().into();
+        let (_, reason, _) =
+            evm.call_raw(Address::zero(), addr, calldata, 0.into(), false, None).unwrap();
+        watchdog.join().unwrap();
+        assert!(start.elapsed().as_secs() < 10);
+        assert_eq!(reason, ExitReason::Error(ExitError::Other(INTERRUPTED.into())));
+        assert!(evm.is_interrupted());
+
+        // as are the calls made afterwards, e.g. the remaining runs of a fuzz test
+        let count = get_func("function count(uint256)").unwrap();
+        #[cfg(feature = "fuzz")]
+        {
+            use crate::fuzz::{fuzz, FuzzConfig};
+            let res = fuzz(
+                &mut evm,
+                &FuzzConfig::deterministic(),
+                &count,
+                addr,
+                false,
+                &Default::default(),
+            )
+            .unwrap();
+            assert!(!res.success);
+            assert!(res.counterexample.is_none());
+            assert_eq!(res.reason.as_deref(), Some(INTERRUPTED));
+        }
+
+        // until the interrupt is unset
+        evm.set_interrupt(None);
+        assert!(!evm.is_interrupted());
+        let (_, reason, _) = evm
+            .call::<(), _>(Address::zero(), addr, &count, (U256::one(),), 0.into(), None)
+            .unwrap();
+        assert!(matches!(reason, ExitReason::Succeed(_)));
+    }
+}
@@ -0,0 +1,120 @@
+//! Decoding of hardhat-style `console.log` calls
+use dapp_utils::format_log_token;
+use ethers::{abi::ParamType, types::Address};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+
+/// Address where the cheatcode handler intercepts `console.log` calls, same as in hardhat:
+/// `address(bytes20(bytes("console.log")))`
+pub static CONSOLE_ADDRESS: Lazy<Address> = Lazy::new(|| {
+    "0x000000000000000000636F6e736F6c652e6c6f67".parse().expect("invalid console address")
+});
+
+/// The parameter types of all the `console.log` overloads, keyed by their selector.
+///
+/// `console.sol` computes its selectors from signatures which spell `uint256` as `uint` (and
+/// `int256` as `int`), so both spellings are registered.
+static CONSOLE_SIGNATURES: Lazy<BTreeMap<[u8; 4], Vec<ParamType>>> = Lazy::new(|| {
+    let mut signatures: Vec<(String, Vec<&str>)> = vec![
+        ("log".to_owned(), vec![]),
+        ("logInt".to_owned(), vec!["int"]),
+        ("logUint".to_owned(), vec!["uint"]),
+        ("logString".to_owned(), vec!["string"]),
+        ("logBool".to_owned(), vec!["bool"]),
+        ("logAddress".to_owned(), vec!["address"]),
+        ("logBytes".to_owned(), vec!["bytes"]),
+    ];
+    let fixed_bytes = (1..=32).map(|size| format!("bytes{}", size)).collect::<Vec<_>>();
+    for (size, kind) in fixed_bytes.iter().enumerate() {
+        signatures.push((format!("logBytes{}", size + 1), vec![kind.as_str()]));
+    }
+
+    // `log` is overloaded for all the combinations of up to 4 of these types
+    let kinds = ["uint", "string", "bool", "address"];
+    let mut combinations: Vec<Vec<&str>> = vec![vec![]];
+    for _ in 0..4 {
+        combinations = combinations
+            .iter()
+            .flat_map(|params| {
+                kinds.iter().map(move |kind| {
+                    let mut params = params.clone();
+                    params.push(*kind);
+                    params
+                })
+            })
+            .collect();
+        signatures.extend(combinations.iter().map(|params| ("log".to_owned(), params.clone())));
+    }
+
+    let mut selectors = BTreeMap::new();
+    for (name, params) in signatures {
+        let types = params.iter().map(|kind| param_type(kind)).collect::<Vec<_>>();
+        let canonical = params
+            .iter()
+            .map(|kind| match *kind {
+                "uint" => "uint256",
+                "int" => "int256",
+                kind => kind,
+            })
+            .collect::<Vec<_>>();
+        let short = format!("{}({})", name, params.join(","));
+        let full = format!("{}({})", name, canonical.join(","));
+        for signature in [short, full] {
+            selectors.insert(ethers::utils::id(&signature), types.clone());
+        }
+    }
+    selectors
+});
+
+/// Maps a type used in `console.sol` signatures to its ABI type
+fn param_type(kind: &str) -> ParamType {
+    match kind {
+        "uint" => ParamType::Uint(256),
+        "int" => ParamType::Int(256),
+        "string" => ParamType::String,
+        "bool" => ParamType::Bool,
+        "address" => ParamType::Address,
+        "bytes" => ParamType::Bytes,
+        kind => {
+            let size = kind.trim_start_matches("bytes").parse().expect("invalid console type");
+            ParamType::FixedBytes(size)
+        }
+    }
+}
+
+/// Decodes the calldata of a `console.log` call and formats its arguments separated by spaces.
+/// Returns `None` if the selector is not a known `console.log` overload or if the arguments
+/// are malformed.
+pub fn decode_console_log(input: &[u8]) -> Option<String> {
+    if input.len() < 4 {
+        return None
+    }
+    let (selector, data) = input.split_at(4);
+    let types = CONSOLE_SIGNATURES.get(selector)?;
+    let tokens = ethers::abi::decode(types, data).ok()?;
+    Some(tokens.iter().map(format_log_token).collect::<Vec<_>>().join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{abi::Token, types::I256};
+
+    #[test]
+    fn decodes_console_logs() {
+        let mut input = ethers::utils::id("log(string,uint)").to_vec();
+        input.extend(ethers::abi::encode(&[Token::String("gm".to_owned()), Token::Uint(1.into())]));
+        assert_eq!(decode_console_log(&input).unwrap(), "gm 1");
+
+        let mut input = ethers::utils::id("logInt(int)").to_vec();
+        input.extend(ethers::abi::encode(&[Token::Int(I256::minus_one().into_raw())]));
+        assert_eq!(decode_console_log(&input).unwrap(), "-1");
+
+        // both the `console.sol` and the canonical spelling of `uint256` are recognized
+        let mut input = ethers::utils::id("log(uint256)").to_vec();
+        input.extend(ethers::abi::encode(&[Token::Uint(42.into())]));
+        assert_eq!(decode_console_log(&input).unwrap(), "42");
+
+        assert_eq!(decode_console_log(&ethers::utils::id("log(bytes32,bytes32)")), None);
+    }
+}
@@ -0,0 +1,266 @@
+use sputnik::{
+    backend::{Apply, Backend, Basic, Log},
+    executor::{MemoryStackSubstate, StackState, StackSubstateMetadata},
+    ExitError, Transfer,
+};
+
+use ethers::types::{H160, H256, U256};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// This struct implementation is copied from [upstream](https://github.com/rust-blockchain/evm/blob/5ecf36ce393380a89c6f1b09ef79f686fe043624/src/executor/stack/state.rs#L412) and modified to own the Backend type.
+///
+/// We had to copy it so that we can modify the Stack's internal backend, because
+/// the upstream MemoryStackState only has an immutable reference to `Backend` which
+/// does not allow us to do so.
+///
+/// It also holds the snapshots taken via the `snapshot` cheatcode. They are kept here instead
+/// of in the `Cheatcodes`, because the substates borrow the config.
+///
+/// Unlike upstream, the substate outlives the transactions, so the accounts which
+/// self-destructed have to be removed from it once the transaction is over, see
+/// [`MemoryStackStateOwned::finalize_deletes`].
+#[derive(Clone)]
+pub struct MemoryStackStateOwned<'config, B> {
+    pub backend: B,
+    pub substate: MemoryStackSubstate<'config>,
+    /// The substates snapshotted via `snapshot`, along with the call depth they were taken at
+    pub snapshots: Vec<(usize, MemoryStackSubstate<'config>)>,
+    /// The accounts destroyed by the previous transactions
+    pub destroyed: BTreeMap<H160, DestroyedAccount>,
+    /// The accounts marked for deletion during the current transaction, including by the
+    /// frames which reverted since
+    pending_deletes: BTreeSet<H160>,
+}
+
+/// An account destroyed by a previous transaction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DestroyedAccount {
+    /// The nonce the account had in the substate when it was last destroyed. The substate can
+    /// only increase nonces, so the nonce of the account is offset by it instead of reset.
+    pub nonce: U256,
+    /// The number of times the account was destroyed
+    pub times: usize,
+}
+
+impl<'config, B: Backend> MemoryStackStateOwned<'config, B> {
+    pub fn new(metadata: StackSubstateMetadata<'config>, backend: B) -> Self {
+        Self {
+            backend,
+            substate: MemoryStackSubstate::new(metadata),
+            snapshots: Vec::new(),
+            destroyed: BTreeMap::new(),
+            pending_deletes: BTreeSet::new(),
+        }
+    }
+
+    /// Discards all the changes made on top of the backend, starting over with the provided
+    /// metadata
+    pub fn reset(&mut self, metadata: StackSubstateMetadata<'config>) {
+        self.substate = MemoryStackSubstate::new(metadata);
+        self.snapshots.clear();
+        self.destroyed.clear();
+        self.pending_deletes.clear();
+    }
+
+    /// Removes the code, storage and balance of the accounts which self-destructed during the
+    /// transaction, and resets their nonce, as the EVM does once a transaction is over. Until
+    /// then, they can still be called. Must be called at the end of every transaction.
+    ///
+    /// NB: The substate keeps the accounts marked for deletion, so an account destroyed again
+    /// after being re-deployed (e.g. via `CREATE2`) is removed even if the frame which
+    /// destroyed it reverted.
+    pub fn finalize_deletes(&mut self) {
+        for address in std::mem::take(&mut self.pending_deletes) {
+            if !self.substate.deleted(address) {
+                continue
+            }
+            let nonce = self
+                .substate
+                .known_basic(address)
+                .unwrap_or_else(|| self.backend.basic(address))
+                .nonce;
+            let destroyed = self.destroyed.entry(address).or_default();
+            destroyed.nonce = nonce;
+            destroyed.times += 1;
+
+            self.substate.set_code(address, Vec::new(), &self.backend);
+            self.substate.reset_storage(address, &self.backend);
+            self.substate.reset_balance(address, &self.backend);
+        }
+    }
+
+    #[must_use]
+    pub fn deconstruct(
+        self,
+    ) -> (
+        impl IntoIterator<Item = Apply<impl IntoIterator<Item = (H256, H256)>>>,
+        impl IntoIterator<Item = Log>,
+    ) {
+        self.substate.deconstruct(&self.backend)
+    }
+
+    pub fn withdraw(&mut self, address: H160, value: U256) -> Result<(), ExitError> {
+        self.substate.withdraw(address, value, &self.backend)
+    }
+
+    pub fn deposit(&mut self, address: H160, value: U256) {
+        self.substate.deposit(address, value, &self.backend)
+    }
+}
+
+impl<'config, B: Backend> Backend for MemoryStackStateOwned<'config, B> {
+    fn gas_price(&self) -> U256 {
+        self.backend.gas_price()
+    }
+    fn origin(&self) -> H160 {
+        self.backend.origin()
+    }
+    fn block_hash(&self, number: U256) -> H256 {
+        self.backend.block_hash(number)
+    }
+    fn block_number(&self) -> U256 {
+        self.backend.block_number()
+    }
+    fn block_coinbase(&self) -> H160 {
+        self.backend.block_coinbase()
+    }
+    fn block_timestamp(&self) -> U256 {
+        self.backend.block_timestamp()
+    }
+    fn block_difficulty(&self) -> U256 {
+        self.backend.block_difficulty()
+    }
+    fn block_gas_limit(&self) -> U256 {
+        self.backend.block_gas_limit()
+    }
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.backend.block_base_fee_per_gas()
+    }
+    fn chain_id(&self) -> U256 {
+        self.backend.chain_id()
+    }
+
+    fn exists(&self, address: H160) -> bool {
+        self.substate.known_account(address).is_some() || self.backend.exists(address)
+    }
+
+    fn basic(&self, address: H160) -> Basic {
+        let mut basic =
+            self.substate.known_basic(address).unwrap_or_else(|| self.backend.basic(address));
+        if let Some(destroyed) = self.destroyed.get(&address) {
+            basic.nonce = basic.nonce.saturating_sub(destroyed.nonce);
+        }
+        basic
+    }
+
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.substate.known_code(address).unwrap_or_else(|| self.backend.code(address))
+    }
+
+    fn storage(&self, address: H160, key: H256) -> H256 {
+        self.substate
+            .known_storage(address, key)
+            .unwrap_or_else(|| self.backend.storage(address, key))
+    }
+
+    fn original_storage(&self, address: H160, key: H256) -> Option<H256> {
+        if let Some(value) = self.substate.known_original_storage(address, key) {
+            return Some(value)
+        }
+
+        self.backend.original_storage(address, key)
+    }
+}
+
+impl<'config, B: Backend> StackState<'config> for MemoryStackStateOwned<'config, B> {
+    fn metadata(&self) -> &StackSubstateMetadata<'config> {
+        self.substate.metadata()
+    }
+
+    fn metadata_mut(&mut self) -> &mut StackSubstateMetadata<'config> {
+        self.substate.metadata_mut()
+    }
+
+    fn enter(&mut self, gas_limit: u64, is_static: bool) {
+        self.substate.enter(gas_limit, is_static)
+    }
+
+    fn exit_commit(&mut self) -> Result<(), ExitError> {
+        self.substate.exit_commit()
+    }
+
+    fn exit_revert(&mut self) -> Result<(), ExitError> {
+        self.substate.exit_revert()
+    }
+
+    fn exit_discard(&mut self) -> Result<(), ExitError> {
+        self.substate.exit_discard()
+    }
+
+    fn is_empty(&self, address: H160) -> bool {
+        // the nonces of the destroyed accounts are offset, so the substate cannot tell
+        if self.destroyed.contains_key(&address) {
+            let basic = self.basic(address);
+            return basic.balance.is_zero() && basic.nonce.is_zero() && self.code(address).is_empty()
+        }
+
+        if let Some(known_empty) = self.substate.known_empty(address) {
+            return known_empty
+        }
+
+        self.backend.basic(address).balance == U256::zero() &&
+            self.backend.basic(address).nonce == U256::zero() &&
+            self.backend.code(address).is_empty()
+    }
+
+    fn deleted(&self, address: H160) -> bool {
+        // the accounts destroyed by the previous transactions stay marked in the substate
+        self.substate.deleted(address) &&
+            (self.pending_deletes.contains(&address) || !self.destroyed.contains_key(&address))
+    }
+
+    fn is_cold(&self, address: H160) -> bool {
+        self.substate.is_cold(address)
+    }
+
+    fn is_storage_cold(&self, address: H160, key: H256) -> bool {
+        self.substate.is_storage_cold(address, key)
+    }
+
+    fn inc_nonce(&mut self, address: H160) {
+        self.substate.inc_nonce(address, &self.backend);
+    }
+
+    fn set_storage(&mut self, address: H160, key: H256, value: H256) {
+        self.substate.set_storage(address, key, value)
+    }
+
+    fn reset_storage(&mut self, address: H160) {
+        self.substate.reset_storage(address, &self.backend);
+    }
+
+    fn log(&mut self, address: H160, topics: Vec<H256>, data: Vec<u8>) {
+        self.substate.log(address, topics, data);
+    }
+
+    fn set_deleted(&mut self, address: H160) {
+        self.pending_deletes.insert(address);
+        self.substate.set_deleted(address)
+    }
+
+    fn set_code(&mut self, address: H160, code: Vec<u8>) {
+        self.substate.set_code(address, code, &self.backend)
+    }
+
+    fn transfer(&mut self, transfer: Transfer) -> Result<(), ExitError> {
+        self.substate.transfer(transfer, &self.backend)
+    }
+
+    fn reset_balance(&mut self, address: H160) {
+        self.substate.reset_balance(address, &self.backend)
+    }
+
+    fn touch(&mut self, address: H160) {
+        self.substate.touch(address, &self.backend)
+    }
+}
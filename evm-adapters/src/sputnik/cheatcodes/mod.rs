@@ -0,0 +1,213 @@
+//! Hooks to EVM execution
+mod cheatcode_handler;
+pub use cheatcode_handler::{CheatcodeHandler, CheatcodeStackExecutor, CheatcodeStackState};
+
+mod backend;
+pub use backend::CheatcodeBackend;
+
+mod memory_stackstate_owned;
+pub use memory_stackstate_owned::{DestroyedAccount, MemoryStackStateOwned};
+
+mod console;
+pub use console::{decode_console_log, CONSOLE_ADDRESS};
+
+pub use crate::{
+    fuzz::ASSUME_MAGIC_REVERT,
+    hevm::{HevmCall, Prank, HEVM_ABI},
+    CHEATCODE_ADDRESS,
+};
+
+use ethers::{
+    abi::RawLog,
+    types::{Address, H256, U256},
+};
+use sputnik::{backend::Log, ExitReason};
+use std::{cell::RefCell, collections::BTreeMap, path::PathBuf};
+
+#[derive(Clone, Debug, Default)]
+/// Cheatcodes can be used to control the EVM context during setup or runtime,
+/// which can be useful for simulations or specialized unit tests
+pub struct Cheatcodes {
+    /// The overriden timestamp
+    pub block_timestamp: Option<U256>,
+    /// The overriden block number
+    pub block_number: Option<U256>,
+    /// The overriden base fee
+    pub block_base_fee_per_gas: Option<U256>,
+    /// The overriden block difficulty
+    pub block_difficulty: Option<U256>,
+    /// The overriden block coinbase
+    pub block_coinbase: Option<Address>,
+    /// The overriden chain id
+    pub chain_id: Option<U256>,
+    /// The overriden gas price of the transaction
+    pub tx_gas_price: Option<U256>,
+    /// The overriden block hashes, keyed by block number
+    pub block_hashes: BTreeMap<U256, H256>,
+    /// The sender override for the next call, if any
+    pub prank: Option<Prank>,
+    /// Whether `ffi` is allowed to execute arbitrary commands on the host
+    pub ffi_enabled: bool,
+    /// Whether `readFile` and `writeFile` are allowed to access the host filesystem
+    pub fs_enabled: bool,
+    /// The directory which the paths passed to `readFile` and `writeFile` are relative to.
+    /// They cannot escape it. Defaults to the working directory.
+    pub fs_root: PathBuf,
+    /// The revert expected from the next call, if any
+    pub expected_revert: Option<ExpectedRevert>,
+    /// The logs expected to be emitted during the next call, in order
+    pub expected_emits: Vec<ExpectedEmit>,
+    /// The logs emitted during a call which is checked against `expected_emits`
+    pub emitted_logs: Vec<Log>,
+    /// The calls expected to be made during the rest of the transaction
+    pub expected_calls: Vec<ExpectedCall>,
+    /// The number of expectations which the last transaction left unsatisfied
+    pub pending_expectations: usize,
+    /// The return data of mocked calls, keyed by the callee and the calldata prefix
+    pub mocked_calls: BTreeMap<(Address, Vec<u8>), Vec<u8>>,
+    /// The storage accesses recorded since `record` was called, if it was
+    pub accesses: Option<RecordedAccesses>,
+    /// The human-readable names of the addresses, set via `label`
+    pub labels: BTreeMap<Address, String>,
+    /// The number of calls rejected via `assume` so far, which is kept across tests
+    pub assume_rejections: usize,
+}
+
+/// The storage slots read and written per address, in the order they were accessed
+#[derive(Clone, Debug, Default)]
+pub struct RecordedAccesses {
+    /// The slots read via `SLOAD`. This is a `RefCell` because reads are recorded from
+    /// `Handler::storage`, which only has access to `&self`.
+    pub reads: RefCell<BTreeMap<Address, Vec<H256>>>,
+    /// The slots written via `SSTORE`
+    pub writes: BTreeMap<Address, Vec<H256>>,
+}
+
+impl Cheatcodes {
+    /// Clears all the overrides, pranks, mocks, expectations and recorded accesses, so that
+    /// they do not leak from one test into the next. Whether `ffi` and filesystem access are
+    /// enabled, the labels and the `assume` rejection counter are left untouched.
+    pub fn reset(&mut self) {
+        *self = Self {
+            ffi_enabled: self.ffi_enabled,
+            fs_enabled: self.fs_enabled,
+            fs_root: std::mem::take(&mut self.fs_root),
+            labels: std::mem::take(&mut self.labels),
+            assume_rejections: self.assume_rejections,
+            ..Default::default()
+        };
+    }
+
+    /// Names the address by its label if it has one, e.g. in the messages of the failed
+    /// expectations
+    pub fn format_address(&self, addr: Address) -> String {
+        self.labels.get(&addr).cloned().unwrap_or_else(|| format!("{:?}", addr))
+    }
+
+    /// Drops all the expectations which have not been checked yet
+    pub fn clear_expectations(&mut self) {
+        self.expected_revert = None;
+        self.expected_emits.clear();
+        self.emitted_logs.clear();
+        self.expected_calls.clear();
+    }
+
+    /// Returns the return data of the mock with the longest calldata prefix which matches
+    /// the call, if any
+    pub fn mocked_call(&self, target: Address, data: &[u8]) -> Option<&[u8]> {
+        self.mocked_calls
+            .iter()
+            .filter(|((address, prefix), _)| *address == target && data.starts_with(prefix))
+            .max_by_key(|((_, prefix), _)| prefix.len())
+            .map(|(_, retdata)| retdata.as_slice())
+    }
+}
+
+/// A revert expected via `expectRevert`, which is checked against the next call made by
+/// `caller` at `depth`, similarly to a [`Prank`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedRevert {
+    /// The expected revert data, or the revert reason if the call reverts with `Error(string)`.
+    /// If it is empty (`expectRevert()`), any revert is accepted.
+    pub data: Vec<u8>,
+    /// Whether only the selector of the revert data has to match (`expectRevert(bytes4)`)
+    pub selector_only: bool,
+    /// The address which called `expectRevert`
+    pub caller: Address,
+    /// The call depth at which `expectRevert` was called
+    pub depth: usize,
+}
+
+impl ExpectedRevert {
+    /// Whether any revert satisfies the expectation (`expectRevert()`)
+    pub fn is_any(&self) -> bool {
+        self.data.is_empty() && !self.selector_only
+    }
+}
+
+/// A log expected via `expectEmit`. The first log emitted by `caller` after calling
+/// `expectEmit` is used as the template, which must be matched by a log emitted by the callee
+/// of the next call it makes
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedEmit {
+    /// The address which called `expectEmit`
+    pub caller: Address,
+    /// The call depth at which `expectEmit` was called
+    pub depth: usize,
+    /// Whether topics 1 to 3 and the data have to match the template, in that order (topic 0
+    /// always has to match)
+    pub checks: [bool; 4],
+    /// The template log, once emitted
+    pub log: Option<RawLog>,
+    /// The address which must emit the log, set when the next call is made
+    pub emitter: Option<Address>,
+    /// Whether a matching log was emitted
+    pub found: bool,
+}
+
+impl ExpectedEmit {
+    /// Whether the provided log matches the template
+    pub fn matches(&self, topics: &[H256], data: &[u8]) -> bool {
+        let template = match self.log {
+            Some(ref log) => log,
+            None => return false,
+        };
+        if template.topics.get(0) != topics.get(0) {
+            return false
+        }
+        let topics_match = self.checks[..3]
+            .iter()
+            .enumerate()
+            .all(|(i, check)| !check || template.topics.get(i + 1) == topics.get(i + 1));
+        topics_match && (!self.checks[3] || template.data == data)
+    }
+}
+
+/// A call expected via `expectCall`, which must be made at any depth before the end of the
+/// transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedCall {
+    /// The address which must be called
+    pub target: Address,
+    /// The prefix the calldata must start with
+    pub data: Vec<u8>,
+    /// The value which must be transferred, if any
+    pub value: Option<U256>,
+    /// Whether a matching call was made
+    pub found: bool,
+}
+
+impl ExpectedCall {
+    /// Whether the provided call matches the expectation
+    pub fn matches(&self, target: Address, data: &[u8], value: U256) -> bool {
+        self.target == target &&
+            data.starts_with(&self.data) &&
+            self.value.map_or(true, |expected| expected == value)
+    }
+}
+
+/// Whether the result of a call means that its input was rejected via `assume`, in which case
+/// fuzzers should discard the input instead of considering the call failed
+pub fn is_assume_rejection(reason: &ExitReason, retdata: &[u8]) -> bool {
+    matches!(reason, ExitReason::Revert(_)) && retdata == ASSUME_MAGIC_REVERT
+}
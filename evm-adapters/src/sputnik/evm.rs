@@ -1,44 +1,113 @@
-use crate::Evm;
+#[cfg(feature = "coverage")]
+use crate::coverage::BitSet;
+#[cfg(feature = "debug-steps")]
+use crate::debug_steps::{DebugConfig, DebugStep};
+use crate::{
+    call_tracing::CallTraceNode,
+    fuzz::FuzzDictionary,
+    gas_report::GasReport,
+    sputnik::{precompiles::precompiles, DepositState, SputnikExecutor, WrittenState},
+    state_diff::{AccountDiff, Change, StateDiff},
+    Evm, GasUsed, Interrupt, OutOfGas, CHEATCODE_ADDRESS, INTERRUPTED,
+};
+#[cfg(feature = "coverage")]
+use std::collections::HashMap;
 
-use ethers::types::{Address, Bytes, U256};
+use ethers::{
+    abi::RawLog,
+    types::{Address, Bytes, H256, U256},
+};
 
 use sputnik::{
     backend::{Backend, MemoryAccount},
     executor::{MemoryStackState, StackExecutor, StackState, StackSubstateMetadata},
-    Config, ExitReason, Handler,
+    Config, ExitError, ExitReason,
 };
-use std::collections::BTreeMap;
+use std::{cmp::min, collections::BTreeMap, marker::PhantomData};
 
+use dapp_utils::{calldata_cost, decode_revert};
 use eyre::Result;
+use once_cell::sync::Lazy;
 
 pub type MemoryState = BTreeMap<Address, MemoryAccount>;
 
+/// The largest increase `set_nonce` (and the `setNonce` cheatcode) can apply at once. The
+/// substate has no setter for the nonce, so it is increased one by one.
+pub(crate) const MAX_NONCE_INCREASE: u64 = 1_000_000;
+
+/// The slot of the cheatcode address' storage in which newer versions of DSTest record that an
+/// assertion failed: `bytes32("failed")`
+static DSTEST_FAILED_SLOT: Lazy<H256> = Lazy::new(|| {
+    let mut slot = H256::zero();
+    slot.as_bytes_mut()[..6].copy_from_slice(b"failed");
+    slot
+});
+
 // TODO: Check if we can implement this as the base layer of an ethers-provider
 // Middleware stack instead of doing RPC calls.
-pub struct Executor<'a, S> {
-    pub executor: StackExecutor<'a, S>,
+pub struct Executor<S, E> {
+    pub executor: E,
     pub gas_limit: u64,
+    /// The gas used by the last call
+    gas_used: Option<GasUsed>,
+    /// Where and how the last call ran out of gas, if it did
+    out_of_gas: Option<OutOfGas>,
+    /// The states snapshotted via `snapshot`, in the order they were taken
+    snapshots: Vec<S>,
+    /// The changes made by the last call, if their recording is enabled
+    state_changes: Option<StateDiff>,
+    /// Makes the calls fail once raised, see [`Evm::set_interrupt`]
+    interrupt: Option<Interrupt>,
+    /// Whether `failed()` is called on the test contracts, see [`Evm::set_dstest`]
+    dstest: bool,
+    marker: PhantomData<S>,
+}
+
+impl<S, E> Executor<S, E> {
+    /// Wraps an already instantiated Sputnik executor (e.g. one with cheatcodes enabled)
+    pub fn from_executor(executor: E, gas_limit: u64) -> Self {
+        Self {
+            executor,
+            gas_limit,
+            gas_used: None,
+            out_of_gas: None,
+            snapshots: Vec::new(),
+            state_changes: None,
+            interrupt: None,
+            dstest: false,
+            marker: PhantomData,
+        }
+    }
 }
 
 // Manual implementation of `Clone` for Clone-able StackStates (typically when the Backend
 // behind them is also clone-able). This is useful to have e.g. when running fuzz
 // tests which we need to take ownership of the EVM and clone it for each run in the
 // test runner's closure.
-impl<'a, S: StackState<'a> + Clone> Clone for Executor<'a, S> {
+impl<'a, S: StackState<'a> + Clone> Clone for Executor<S, StackExecutor<'a, S>> {
     fn clone(&self) -> Self {
         Self {
             gas_limit: self.gas_limit,
+            gas_used: self.gas_used,
+            out_of_gas: self.out_of_gas,
+            snapshots: self.snapshots.clone(),
+            state_changes: self.state_changes.clone(),
+            interrupt: self.interrupt.clone(),
+            dstest: self.dstest,
             executor: StackExecutor::new_with_precompile(
                 self.executor.state().clone(),
                 self.executor.config(),
-                Default::default(),
+                precompiles(self.executor.config()),
             ),
+            marker: PhantomData,
         }
     }
 }
 
 // Concrete implementation over the in-memory backend
-impl<'a, B: Backend> Executor<'a, MemoryStackState<'a, 'a, B>> {
+impl<'a, B: Backend>
+    Executor<MemoryStackState<'a, 'a, B>, StackExecutor<'a, MemoryStackState<'a, 'a, B>>>
+{
     /// Given a gas limit, vm version, initial chain configuration and initial state
     // TOOD: See if we can make lifetimes better here
     pub fn new(gas_limit: u64, config: &'a Config, backend: &'a B) -> Self {
@@ -47,9 +116,9 @@ impl<'a, B: Backend> Executor<'a, MemoryStackState<'a, 'a, B>> {
         // setup state
         let state = MemoryStackState::new(metadata, backend);
         // setup executor
-        let executor = StackExecutor::new_with_precompile(state, config, Default::default());
+        let executor = StackExecutor::new_with_precompile(state, config, precompiles(config));
 
-        Self { executor, gas_limit }
+        Self::from_executor(executor, gas_limit)
     }
 }
 
@@ -58,11 +127,13 @@ impl<'a, B: Backend> Executor<'a, MemoryStackState<'a, 'a, B>> {
 // We use StackState as a trait and not as an associated type because we want to
 // allow the developer what the db type should be. Whereas for ReturnReason, we want it
 // to be generic across implementations, but we don't want to make it a user-controlled generic.
-impl<'a, S> Evm<S> for Executor<'a, S>
+impl<'a, S, E> Evm<S> for Executor<S, E>
 where
-    S: StackState<'a>,
+    E: SputnikExecutor<S>,
+    S: StackState<'a> + DepositState + WrittenState + Clone,
 {
     type ReturnReason = ExitReason;
+    type SnapshotId = usize;
 
     fn is_success(reason: &Self::ReturnReason) -> bool {
         matches!(reason, ExitReason::Succeed(_))
@@ -72,9 +143,57 @@ where
         matches!(reason, ExitReason::Revert(_))
     }
 
+    /// Tests which succeed may still have failed a DSTest assertion, which is recorded in its
+    /// `failed()` variable or, by newer versions of DSTest, in the storage of the cheatcode
+    /// address. Tests which error (e.g. run out of gas) fail even if they are expected to.
+    fn try_check_success(
+        &mut self,
+        address: Address,
+        reason: &Self::ReturnReason,
+        should_fail: bool,
+    ) -> Result<bool> {
+        // tests which are not expected to fail must satisfy all their expectations
+        if !should_fail && self.pending_expectations() > 0 {
+            return Ok(false)
+        }
+
+        match reason {
+            ExitReason::Succeed(_) => {
+                let global = self.executor.state().storage(*CHEATCODE_ADDRESS, *DSTEST_FAILED_SLOT);
+                let failed = !global.is_zero() || (self.is_dstest() && self.failed(address)?);
+                Ok(failed == should_fail)
+            }
+            ExitReason::Revert(_) => Ok(should_fail),
+            ExitReason::Error(_) | ExitReason::Fatal(_) => {
+                tracing::error!(?reason, "test errored");
+                Ok(false)
+            }
+        }
+    }
+
     fn reset(&mut self, state: S) {
         let mut _state = self.executor.state_mut();
         *_state = state;
+        self.executor.reset_cheatcodes();
+        // the snapshots were taken on top of the previous state
+        self.snapshots.clear();
+    }
+
+    fn snapshot(&mut self) -> usize {
+        self.snapshots.push(self.executor.state().clone());
+        self.snapshots.len() - 1
+    }
+
+    fn restore(&mut self, id: usize) -> Result<()> {
+        let state =
+            self.snapshots.get(id).ok_or_else(|| eyre::eyre!("no snapshot with id {}", id))?;
+        *self.executor.state_mut() = state.clone();
+        self.snapshots.truncate(id + 1);
+        Ok(())
+    }
+
+    fn clear_snapshots(&mut self) {
+        self.snapshots.clear();
     }
 
     /// given an iterator of contract address to contract bytecode, initializes
@@ -90,27 +209,298 @@ where
         self.executor.state()
     }
 
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        let state = self.executor.state_mut();
+        state.touch(address);
+        // zero out the balance before depositing, so that it can be lowered as well
+        state.reset_balance(address);
+        state.deposit(address, balance);
+    }
+
+    fn get_balance(&self, address: Address) -> U256 {
+        self.executor.state().basic(address).balance
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) -> Result<()> {
+        let current = self.get_nonce(address);
+        if U256::from(nonce) < current {
+            eyre::bail!("cannot lower the nonce of {:?} from {} to {}", address, current, nonce)
+        }
+        // the current nonce may not fit in a `u64`, if it was set by the backend
+        let increase = U256::from(nonce) - current;
+        if increase > U256::from(MAX_NONCE_INCREASE) {
+            eyre::bail!("cannot increase a nonce by more than {} at once", MAX_NONCE_INCREASE)
+        }
+        // the state can only increment nonces
+        let state = self.executor.state_mut();
+        for _ in 0..increase.as_u64() {
+            state.inc_nonce(address);
+        }
+        Ok(())
+    }
+
+    fn get_nonce(&self, address: Address) -> U256 {
+        self.executor.state().basic(address).nonce
+    }
+
+    fn get_code(&self, address: Address) -> Bytes {
+        self.executor.state().code(address).into()
+    }
+
+    fn labels(&self) -> BTreeMap<Address, String> {
+        self.executor.labels()
+    }
+
+    fn pending_expectations(&self) -> usize {
+        self.executor.pending_expectations()
+    }
+
+    fn logs(&self) -> Vec<RawLog> {
+        self.executor.logs()
+    }
+
+    fn gas_used(&self) -> Option<GasUsed> {
+        self.gas_used
+    }
+
+    fn out_of_gas(&self) -> Option<OutOfGas> {
+        self.out_of_gas
+    }
+
+    fn set_interrupt(&mut self, interrupt: Option<Interrupt>) {
+        // only the cheatcode-enabled executor stops the call being executed, the calls made
+        // afterwards are stopped here regardless
+        self.executor.set_interrupt(interrupt.clone());
+        self.interrupt = interrupt;
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.interrupt.as_ref().map_or(false, Interrupt::is_raised)
+    }
+
+    fn set_dstest(&mut self, dstest: bool) {
+        self.dstest = dstest;
+    }
+
+    fn is_dstest(&self) -> bool {
+        self.dstest
+    }
+
+    fn set_tracing_enabled(&mut self, enabled: bool) {
+        self.executor.set_tracing_enabled(enabled)
+    }
+
+    fn traces(&self) -> Vec<CallTraceNode> {
+        self.executor.traces()
+    }
+
+    fn set_gas_reporting_enabled(&mut self, enabled: bool) {
+        self.executor.set_gas_reporting_enabled(enabled)
+    }
+
+    fn gas_report(&self) -> GasReport {
+        self.executor.gas_report()
+    }
+
+    fn set_fuzz_dictionary_enabled(&mut self, enabled: bool) {
+        self.executor.set_fuzz_dictionary_enabled(enabled)
+    }
+
+    fn fuzz_dictionary(&self) -> FuzzDictionary {
+        self.executor.fuzz_dictionary()
+    }
+
+    fn set_state_diff_enabled(&mut self, enabled: bool) {
+        self.state_changes = if enabled { Some(StateDiff::default()) } else { None };
+    }
+
+    fn state_changes(&self) -> StateDiff {
+        self.state_changes.clone().unwrap_or_default()
+    }
+
+    #[cfg(feature = "debug-steps")]
+    fn set_debug_config(&mut self, config: Option<DebugConfig>) {
+        self.executor.set_debug_config(config)
+    }
+
+    #[cfg(feature = "debug-steps")]
+    fn debug_steps(&self) -> Vec<DebugStep> {
+        self.executor.debug_steps()
+    }
+
+    #[cfg(feature = "coverage")]
+    fn set_coverage_enabled(&mut self, enabled: bool) {
+        self.executor.set_coverage_enabled(enabled)
+    }
+
+    #[cfg(feature = "coverage")]
+    fn coverage(&self) -> HashMap<H256, BitSet> {
+        self.executor.coverage()
+    }
+
     /// Runs the selected function
     fn call_raw(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        is_static: bool,
+        gas_limit: Option<u64>,
+    ) -> Result<(Bytes, ExitReason, u64)> {
+        self.call_raw_with_access_list(from, to, calldata, value, is_static, gas_limit, Vec::new())
+    }
+
+    fn call_raw_with_access_list(
         &mut self,
         from: Address,
         to: Address,
         calldata: Bytes,
         value: U256,
         _is_static: bool,
+        gas_limit: Option<u64>,
+        access_list: Vec<(Address, Vec<H256>)>,
     ) -> Result<(Bytes, ExitReason, u64)> {
-        let gas_before = self.executor.gas_left();
+        if self.is_interrupted() {
+            self.gas_used = None;
+            self.out_of_gas = None;
+            return Ok((Bytes::new(), ExitReason::Error(ExitError::Other(INTERRUPTED.into())), 0))
+        }
+        let gas_limit = gas_limit.unwrap_or(self.gas_limit);
+        let gasometer = self.executor.state().metadata().gasometer();
+        let (gas_before, refunded_before) = (gasometer.gas(), gasometer.refunded_gas());
+        let before = self.state_changes.as_ref().map(|_| self.executor.state().clone());
+
+        let list_cost = access_list_cost(self.executor.config(), &access_list);
+        let (status, retdata) =
+            self.executor.transact_call(from, to, value, calldata.to_vec(), gas_limit, access_list);
+        if let Some(before) = before {
+            self.state_changes = Some(state_diff(&before, self.executor.state()));
+        }
+
+        let gasometer = self.executor.state().metadata().gasometer();
+        let raw = gas_before - gasometer.gas();
+        let refunded = (gasometer.refunded_gas() - refunded_before).max(0) as u64;
+        let config = self.executor.config();
+        let base = config.gas_transaction_call + list_cost;
+        let gas = adjust_gas(config, base, raw, refunded, calldata.as_ref());
+        self.gas_used = Some(gas);
+        self.out_of_gas = if status == ExitReason::Error(ExitError::OutOfGas) {
+            let frame = exhausted_frame(&self.executor.traces());
+            Some(OutOfGas { gas_limit, gas_used: gas.adjusted, frame })
+        } else {
+            None
+        };
+
+        Ok((retdata.into(), status, gas.adjusted))
+    }
+
+    fn deploy(
+        &mut self,
+        from: Address,
+        bytecode: Bytes,
+        value: U256,
+    ) -> Result<(Address, ExitReason, u64)> {
+        let nonce = self.executor.state().basic(from).nonce;
+        let address = ethers::utils::get_contract_address(from, nonce);
+
+        let gasometer = self.executor.state().metadata().gasometer();
+        let (gas_before, refunded_before) = (gasometer.gas(), gasometer.refunded_gas());
+        let before = self.state_changes.as_ref().map(|_| self.executor.state().clone());
 
         let (status, retdata) =
-            self.executor.transact_call(from, to, value, calldata.to_vec(), self.gas_limit, vec![]);
+            self.executor.transact_create(from, value, bytecode.to_vec(), self.gas_limit, vec![]);
+        if let Some(before) = before {
+            self.state_changes = Some(state_diff(&before, self.executor.state()));
+        }
 
-        let gas_after = self.executor.gas_left();
-        let gas = dapp_utils::remove_extra_costs(gas_before - gas_after, calldata.as_ref());
+        let gasometer = self.executor.state().metadata().gasometer();
+        let raw = gas_before - gasometer.gas();
+        let refunded = (gasometer.refunded_gas() - refunded_before).max(0) as u64;
+        let config = self.executor.config();
+        let gas = adjust_gas(config, config.gas_transaction_create, raw, refunded, &bytecode);
+        self.gas_used = Some(gas);
 
-        Ok((retdata.into(), status, gas.as_u64()))
+        match status {
+            ExitReason::Succeed(_) => Ok((address, status, gas.adjusted)),
+            ExitReason::Revert(_) => {
+                Err(eyre::eyre!("constructor reverted: {}", decode_revert(&retdata)))
+            }
+            reason => Err(eyre::eyre!("deployment failed: {:?}", reason)),
+        }
     }
 }
 
+/// The values which differ between two states of an executor, among the accounts and storage
+/// slots written to in the later one. Since the changes of reverted frames are discarded and
+/// reads are not writes, neither shows up in the diff.
+fn state_diff<'a, S: StackState<'a> + WrittenState>(before: &S, after: &S) -> StateDiff {
+    let mut diff = StateDiff::new();
+    let mut written_before = None;
+    for (address, mut slots) in after.written() {
+        let mut account = AccountDiff {
+            destroyed: after.destructions(address) > before.destructions(address),
+            ..Default::default()
+        };
+        if account.destroyed {
+            // the storage of the destroyed accounts is cleared, so the slots written before
+            // the call may have changed as well
+            let written_before = written_before.get_or_insert_with(|| before.written());
+            slots.extend(written_before.get(&address).into_iter().flatten().copied());
+        }
+
+        let (old, new) = (before.basic(address), after.basic(address));
+        if old.balance != new.balance {
+            account.balance = Some(Change { from: old.balance, to: new.balance });
+        }
+        if old.nonce != new.nonce {
+            account.nonce = Some(Change { from: old.nonce, to: new.nonce });
+        }
+        let (old, new) = (before.code(address), after.code(address));
+        if old != new {
+            account.code = Some(Change { from: old.into(), to: new.into() });
+        }
+        for slot in slots {
+            let (old, new) = (before.storage(address, slot), after.storage(address, slot));
+            if old != new {
+                account.storage.insert(slot, Change { from: old, to: new });
+            }
+        }
+
+        if !account.is_empty() {
+            diff.insert(address, account);
+        }
+    }
+    diff
+}
+
+/// The callee and depth of the deepest frame of a call tree which used all of its gas
+fn exhausted_frame(nodes: &[CallTraceNode]) -> Option<(Address, usize)> {
+    nodes
+        .iter()
+        .map(|node| &node.trace)
+        .filter(|trace| !trace.success && trace.gas_used >= trace.gas_limit)
+        .max_by_key(|trace| trace.depth)
+        .map(|trace| (trace.address, trace.depth))
+}
+
+/// The cost of warming the accounts and storage slots of an access list (EIP-2930), which is
+/// part of the intrinsic cost of a transaction
+fn access_list_cost(config: &Config, access_list: &[(Address, Vec<H256>)]) -> u64 {
+    let slots = access_list.iter().map(|(_, slots)| slots.len() as u64).sum::<u64>();
+    access_list.len() as u64 * config.gas_access_list_address +
+        slots * config.gas_access_list_storage_key
+}
+
+/// Removes the intrinsic cost of a transaction (the base cost of a call or deployment, including
+/// its access list, and the cost of its data, which depend on the fork) from the gas it used,
+/// and applies its refunds, which are capped to half of the gas used (a fifth from London on)
+fn adjust_gas(config: &Config, base: u64, raw: u64, refunded: u64, data: &[u8]) -> GasUsed {
+    let intrinsic = base + calldata_cost(data, config.gas_transaction_non_zero_data);
+    let refunded = min(refunded, raw / config.max_refund_quotient);
+    GasUsed { raw, adjusted: raw.saturating_sub(intrinsic).saturating_sub(refunded) }
+}
+
 #[cfg(any(test, feature = "sputnik-helpers"))]
 pub mod helpers {
     use super::*;
@@ -131,6 +521,7 @@ pub mod helpers {
             block_timestamp: Default::default(),
             block_difficulty: Default::default(),
             block_gas_limit: Default::default(),
+            block_base_fee_per_gas: Default::default(),
             chain_id: U256::one(),
         }
     }
@@ -142,10 +533,16 @@ mod tests {
         helpers::{new_backend, new_vicinity},
         *,
     };
-    use crate::test_helpers::{can_call_vm_directly, solidity_unit_test, COMPILED};
+    use crate::{
+        sputnik::SpecId,
+        test_helpers::{
+            can_call_vm_directly, evaluated_outcomes, failing_assertion_logs, solidity_unit_test,
+            unreadable_failure_state, COMPILED,
+        },
+    };
     use dapp_utils::{decode_revert, get_func};
 
-    use ethers::utils::id;
+    use ethers::{abi::Token, utils::id};
     use sputnik::{ExitReason, ExitRevert, ExitSucceed};
 
     #[test]
@@ -225,6 +622,7 @@ mod tests {
                 &get_func("function setUp() external").unwrap(),
                 (),
                 0.into(),
+                None,
             )
             .unwrap();
         assert_eq!(status, ExitReason::Succeed(ExitSucceed::Stopped));
@@ -238,7 +636,408 @@ mod tests {
             vec![],
         );
         assert_eq!(status, ExitReason::Revert(ExitRevert::Reverted));
-        let reason = decode_revert(&res).unwrap();
+        let reason = decode_revert(&res);
         assert_eq!(reason, "not equal to `hi`");
     }
+
+    #[test]
+    fn gas_used() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let calldata = id("noop()").to_vec();
+        let (_, status, gas) = evm
+            .call_raw(Address::zero(), addr, calldata.clone().into(), 0.into(), false, None)
+            .unwrap();
+        assert_eq!(status, ExitReason::Succeed(ExitSucceed::Stopped));
+
+        // only the function dispatch is left after removing the intrinsic cost
+        let gas_used = evm.gas_used().unwrap();
+        assert_eq!(gas, gas_used.adjusted);
+        assert!(gas_used.adjusted < 200, "adjusted gas {} is not near zero", gas_used.adjusted);
+        let zero_bytes = calldata.iter().filter(|byte| **byte == 0).count() as u64;
+        let intrinsic = 21000 + zero_bytes * 4 + (4 - zero_bytes) * 16;
+        assert_eq!(gas_used.raw, gas_used.adjusted + intrinsic);
+    }
+
+    #[test]
+    fn out_of_gas() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let greet = get_func("function greet(string)").unwrap();
+
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        // storing the greeting costs more than the gas provided, which is all used
+        let (_, reason, gas) = evm
+            .call::<(), _>(Address::zero(), addr, &greet, "hi".to_owned(), 0.into(), Some(5000))
+            .unwrap();
+        assert_eq!(reason, ExitReason::Error(ExitError::OutOfGas));
+        assert_eq!(gas, 5000);
+        assert_eq!(evm.gas_used().unwrap().adjusted, 5000);
+        let out_of_gas = evm.out_of_gas().unwrap();
+        assert_eq!(out_of_gas, OutOfGas { gas_limit: 5000, gas_used: 5000, frame: None });
+        assert_eq!(out_of_gas.to_string(), "out of gas: used 5000 of the 5000 gas provided");
+
+        // the override only applies to a single call
+        let (_, reason, _) = evm
+            .call::<(), _>(Address::zero(), addr, &greet, "hi".to_owned(), 0.into(), None)
+            .unwrap();
+        assert_eq!(reason, ExitReason::Succeed(ExitSucceed::Stopped));
+        assert!(evm.out_of_gas().is_none());
+
+        // the frame which ran out of gas is found in the traces
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &cfg, false);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+        evm.set_tracing_enabled(true);
+        evm.call::<(), _>(Address::zero(), addr, &greet, "hi".to_owned(), 0.into(), Some(5000))
+            .unwrap();
+        assert_eq!(evm.out_of_gas().unwrap().frame, Some((addr, 0)));
+    }
+
+    #[test]
+    fn spec_changes_gas_costs() {
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let func = get_func("function greeting() returns (string)").unwrap();
+
+        let gas_used = |spec: SpecId| {
+            let vicinity = new_vicinity();
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = Executor::new(12_000_000, spec.config(), &backend);
+            evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+            let (_, status, gas) =
+                evm.call::<String, _>(Address::zero(), addr, &func, (), 0.into(), None).unwrap();
+            assert_eq!(status, ExitReason::Succeed(ExitSucceed::Returned));
+            gas
+        };
+
+        // reading the greeting is a cold `SLOAD` from Berlin on
+        assert_eq!(gas_used(SpecId::Berlin) - gas_used(SpecId::Istanbul), 2100 - 800);
+    }
+
+    #[test]
+    fn london_has_basefee() {
+        // BASEFEE PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let code = Bytes::from(hex::decode("4860005260206000f3").unwrap());
+        let addr = Address::repeat_byte(1);
+        let mut vicinity = new_vicinity();
+        vicinity.block_base_fee_per_gas = 7.into();
+
+        let call = |spec: SpecId| {
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = Executor::new(12_000_000, spec.config(), &backend);
+            evm.initialize_contracts(vec![(addr, code.clone())]);
+            evm.call_raw(Address::zero(), addr, Bytes::new(), 0.into(), false, None).unwrap()
+        };
+
+        let (retdata, status, _) = call(SpecId::London);
+        assert_eq!(status, ExitReason::Succeed(ExitSucceed::Returned));
+        assert_eq!(U256::from_big_endian(&retdata), U256::from(7));
+        // the opcode does not exist before London
+        let (_, status, _) = call(SpecId::Berlin);
+        assert!(matches!(status, ExitReason::Error(_)));
+    }
+
+    #[test]
+    fn check_success() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("FailureTest").expect("could not find contract");
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let vicinity = new_vicinity();
+        let check = |func: &str, should_fail: bool| {
+            // the cheatcodes are needed to write to the storage of the cheatcode address
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = Executor::new_with_cheatcodes(backend, 12_000_000, &cfg, false);
+            evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+            evm.set_dstest(true);
+            let (_, reason, _) = evm
+                .call_raw(Address::zero(), addr, id(func).to_vec().into(), 0.into(), false, None)
+                .unwrap();
+            evm.check_success(addr, &reason, should_fail)
+        };
+
+        // should_fail × reverted
+        assert!(check("succeed()", false));
+        assert!(!check("succeed()", true));
+        assert!(!check("revertNow()", false));
+        assert!(check("revertNow()", true));
+
+        // DSTest assertions fail without reverting
+        for func in ["fail()", "failGlobally()"] {
+            assert!(!check(func, false), "{} passed", func);
+            assert!(check(func, true), "{} did not fail", func);
+        }
+
+        // errors are never expected
+        assert!(!check("runOutOfGas()", false));
+        assert!(!check("runOutOfGas()", true));
+
+        let backend = new_backend(&vicinity, Default::default());
+        let evm = Executor::new(12_000_000, &cfg, &backend);
+        unreadable_failure_state(evm, addr, compiled);
+    }
+
+    #[test]
+    fn logs_failing_assertions() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("FailureTest").expect("could not find contract");
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        // only the executor with cheatcodes records logs
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let evm = Executor::new_with_cheatcodes(backend, 12_000_000, &cfg, false);
+        failing_assertion_logs(evm, addr, compiled);
+    }
+
+    #[test]
+    fn evaluates_test_outcomes() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("FailureTest").expect("could not find contract");
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let evm = Executor::new_with_cheatcodes(backend, 12_000_000, &cfg, false);
+        evaluated_outcomes(evm, addr, compiled);
+    }
+
+    #[test]
+    fn call_raw_passes_revert_data_through() {
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        // data which is not an `Error(string)`, e.g. a custom error
+        let data = vec![0xde, 0xad, 0xbe, 0xef, 0x01];
+        let func = get_func("function revertWith(bytes data)").unwrap();
+        let calldata = func.encode_input(&[Token::Bytes(data.clone())]).unwrap();
+        let (retdata, status, _) =
+            evm.call_raw(Address::zero(), addr, calldata.into(), 0.into(), false, None).unwrap();
+        assert_eq!(status, ExitReason::Revert(ExitRevert::Reverted));
+        assert_eq!(retdata.to_vec(), data);
+
+        // functions which are not in the ABI can be called as well
+        let (retdata, status, _) = evm
+            .call_raw(Address::zero(), addr, id("missing()").to_vec().into(), 0.into(), false, None)
+            .unwrap();
+        assert_eq!(status, ExitReason::Revert(ExitRevert::Reverted));
+        assert!(retdata.is_empty());
+    }
+
+    #[test]
+    fn state_accessors() {
+        let cfg = Config::istanbul();
+        let vicinity = new_vicinity();
+        // the backend nonces are not bounded by the `u64` nonces set via the `Evm`
+        let big = Address::repeat_byte(2);
+        let account = MemoryAccount {
+            nonce: U256::MAX,
+            balance: U256::zero(),
+            storage: Default::default(),
+            code: Vec::new(),
+        };
+        let backend = new_backend(&vicinity, vec![(big, account)].into_iter().collect());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        let addr = Address::repeat_byte(1);
+
+        assert_eq!(evm.get_nonce(big), U256::MAX);
+        assert!(evm.set_nonce(big, 5).is_err());
+
+        evm.set_balance(addr, 100.into());
+        assert_eq!(evm.get_balance(addr), 100.into());
+        // balances can be lowered as well
+        evm.set_balance(addr, 1.into());
+        assert_eq!(evm.get_balance(addr), 1.into());
+
+        evm.set_nonce(addr, 3).unwrap();
+        assert_eq!(evm.get_nonce(addr), 3.into());
+        assert!(evm.set_nonce(addr, 2).is_err());
+        // the nonce is increased one by one, so the increase is bounded
+        assert!(evm.set_nonce(addr, u64::MAX).is_err());
+        assert_eq!(evm.get_nonce(addr), 3.into());
+
+        // the changes made by transactions are observed before being committed
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let (deployed, _, _) = evm.deploy(addr, compiled.bytecode.clone(), 0.into()).unwrap();
+        assert_eq!(evm.get_nonce(addr), 4.into());
+        assert_eq!(evm.get_code(deployed), compiled.runtime_bytecode);
+        assert!(evm.get_code(addr).as_ref().is_empty());
+    }
+
+    #[test]
+    fn state_changes() {
+        let cfg = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+
+        // nothing is recorded when disabled
+        let (addr, _, _) = evm.deploy(from, compiled.bytecode.clone(), 0.into()).unwrap();
+        assert!(evm.state_changes().is_empty());
+
+        evm.set_state_diff_enabled(true);
+        let (addr2, _, _) = evm.deploy(from, compiled.bytecode.clone(), 0.into()).unwrap();
+        let changes = evm.state_changes();
+        assert_eq!(changes[&from].nonce, Some(Change { from: 1.into(), to: 2.into() }));
+        let code = changes[&addr2].code.clone().unwrap();
+        assert!(code.from.as_ref().is_empty());
+        assert_eq!(code.to, compiled.runtime_bytecode);
+        assert!(!changes.contains_key(&addr));
+
+        let greet = get_func("function greet(string)").unwrap();
+        evm.call::<(), _>(from, addr, &greet, "hi".to_owned(), 0.into(), None).unwrap();
+        let changes = evm.state_changes();
+        // short strings are stored along with their doubled length
+        let mut greeting = [0u8; 32];
+        greeting[..2].copy_from_slice(b"hi");
+        greeting[31] = 4;
+        let storage = vec![(H256::zero(), Change { from: H256::zero(), to: greeting.into() })];
+        assert_eq!(changes[&addr].storage, storage.into_iter().collect());
+        assert!(changes[&addr].balance.is_none() && changes[&addr].code.is_none());
+
+        // reads are left out
+        let func = get_func("function greeting() returns (string)").unwrap();
+        evm.call::<String, _>(from, addr, &func, (), 0.into(), None).unwrap();
+        assert!(!evm.state_changes().contains_key(&addr));
+
+        // as are the writes of reverted frames, including the ones within successful calls
+        let func = get_func("function tryGreet(string)").unwrap();
+        let (_, status, _) =
+            evm.call::<(), _>(from, addr, &func, "yo".to_owned(), 0.into(), None).unwrap();
+        assert_eq!(status, ExitReason::Succeed(ExitSucceed::Stopped));
+        assert!(!evm.state_changes().contains_key(&addr));
+        let (_, status, _) = evm
+            .call::<(), _>(
+                from,
+                addr,
+                &get_func("function greetAndRevert(string)").unwrap(),
+                "yo".to_owned(),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(status, ExitReason::Revert(ExitRevert::Reverted));
+        assert!(!evm.state_changes().contains_key(&addr));
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let cfg = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        let addr = Address::repeat_byte(1);
+
+        evm.set_balance(addr, 100.into());
+        let first = evm.snapshot();
+        evm.set_nonce(addr, 1).unwrap();
+        let second = evm.snapshot();
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let (deployed, _, _) = evm.deploy(addr, compiled.bytecode.clone(), 0.into()).unwrap();
+
+        evm.restore(second).unwrap();
+        assert_eq!(evm.get_nonce(addr), 1.into());
+        assert!(evm.get_code(deployed).as_ref().is_empty());
+
+        // restoring an older snapshot discards the newer ones, but can be repeated
+        evm.set_balance(addr, 5.into());
+        evm.restore(first).unwrap();
+        assert_eq!(evm.get_nonce(addr), 0.into());
+        assert_eq!(evm.get_balance(addr), 100.into());
+        assert!(evm.restore(second).is_err());
+        evm.restore(first).unwrap();
+
+        evm.clear_snapshots();
+        assert!(evm.restore(first).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fuzz")]
+    fn fuzz_shrinks_arrays_of_structs() {
+        use crate::fuzz::{fuzz, FuzzConfig};
+
+        let cfg = Config::istanbul();
+        let compiled = COMPILED.get("FuzzTest").expect("could not find contract");
+
+        let addr = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+        evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
+
+        let func = compiled.abi.function("testStructs").unwrap();
+        let config = FuzzConfig::deterministic();
+        let res = fuzz(&mut evm, &config, func, addr, false, &Default::default()).unwrap();
+        assert!(!res.success);
+        assert_eq!(res.reason.as_deref(), Some("amount too large"));
+
+        // only the failing entry is left
+        let counterexample = res.counterexample.unwrap();
+        let entries = counterexample.args[0].clone().into_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = entries[0].clone().into_tuple().unwrap();
+        assert!(entry[1].clone().into_uint().unwrap() >= 1000.into());
+    }
+
+    #[test]
+    fn deploy() {
+        let cfg = Config::istanbul();
+        let vicinity = new_vicinity();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = Executor::new(12_000_000, &cfg, &backend);
+
+        // the address is derived from the nonce of the deployer
+        let compiled = COMPILED.get("Greeter").expect("could not find contract");
+        let (addr, status, _) =
+            evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap();
+        assert_eq!(status, ExitReason::Succeed(ExitSucceed::Returned));
+        assert_eq!(addr, ethers::utils::get_contract_address(Address::zero(), 0u64));
+        assert_eq!(evm.state().code(addr), compiled.runtime_bytecode.to_vec());
+
+        // the constructor arguments are appended to the init code
+        let compiled = COMPILED.get("ConstructedGreeter").expect("could not find contract");
+        let mut init_code = compiled.bytecode.to_vec();
+        init_code.extend(ethers::abi::encode(&[Token::String("hi".to_owned())]));
+        let (addr, _, _) = evm.deploy(Address::zero(), init_code.into(), 0.into()).unwrap();
+        assert_eq!(addr, ethers::utils::get_contract_address(Address::zero(), 1u64));
+        let (greeting, _, _) = evm
+            .call::<String, _>(
+                Address::zero(),
+                addr,
+                &get_func("function greeting() returns (string)").unwrap(),
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(greeting, "hi");
+
+        let compiled = COMPILED.get("RevertingConstructor").expect("could not find contract");
+        let err = evm.deploy(Address::zero(), compiled.bytecode.clone(), 0.into()).unwrap_err();
+        assert_eq!(err.to_string(), "constructor reverted: constructor reverted");
+    }
 }
@@ -4,38 +4,370 @@ use sputnik::backend::{Backend, Basic, MemoryAccount, MemoryVicinity};
 
 use ethers::{
     providers::Middleware,
-    types::{H160, H256, U256},
+    types::{Address, BlockId, Bytes, H160, H256, U256},
 };
-use std::collections::BTreeMap;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+};
+
+/// Fetches the global context of the chain at the pinned block, or at the latest one if no
+/// block is pinned, so that the block values read by the tests match the forked chain.
+///
+/// The hashes of the blocks preceding it are not fetched: the [`ForkedBackend`] fetches the
+/// ones read via `BLOCKHASH` lazily. The blocks before London have no base fee, which reads as
+/// zero.
+pub async fn vicinity_from_provider<M: Middleware>(
+    provider: &M,
+    pin_block: Option<u64>,
+) -> Result<MemoryVicinity>
+where
+    M::Error: 'static,
+{
+    let block_number = if let Some(pin_block) = pin_block {
+        pin_block
+    } else {
+        provider.get_block_number().await?.as_u64()
+    };
+
+    let gas_price = provider.get_gas_price().await?;
+    let chain_id = provider.get_chainid().await?;
+    let block = provider
+        .get_block(block_number)
+        .await?
+        .ok_or_else(|| eyre::eyre!("block {} not found", block_number))?;
+    let block_number = block
+        .number
+        .ok_or_else(|| eyre::eyre!("block {} is still pending", block_number))?
+        .as_u64();
+
+    Ok(MemoryVicinity {
+        origin: Address::default(),
+        chain_id,
+        block_hashes: Vec::new(),
+        block_number: block_number.into(),
+        block_coinbase: block.author,
+        block_difficulty: block.difficulty,
+        block_gas_limit: block.gas_limit,
+        block_timestamp: block.timestamp,
+        block_base_fee_per_gas: block.base_fee_per_gas.unwrap_or_default(),
+        gas_price,
+    })
+}
+
+/// The balance and nonce of a forked account
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedAccount {
+    balance: U256,
+    nonce: U256,
+}
+
+/// The values fetched from the forked chain. They never change for a pinned block, so they
+/// only need to be fetched once.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct ForkCache {
+    accounts: BTreeMap<H160, CachedAccount>,
+    code: BTreeMap<H160, Bytes>,
+    storage: BTreeMap<H160, BTreeMap<H256, H256>>,
+    /// the caches written before the hashes were cached lack them
+    #[serde(default)]
+    block_hashes: BTreeMap<u64, H256>,
+}
+
+impl ForkCache {
+    /// Reads a cache written by [`ForkedBackend::flush_cache`]
+    fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Adds the values of another cache, keeping the current ones
+    fn merge(&mut self, other: ForkCache) {
+        for (address, account) in other.accounts {
+            self.accounts.entry(address).or_insert(account);
+        }
+        for (address, code) in other.code {
+            self.code.entry(address).or_insert(code);
+        }
+        for (address, storage) in other.storage {
+            let slots = self.storage.entry(address).or_default();
+            for (index, value) in storage {
+                slots.entry(index).or_insert(value);
+            }
+        }
+        for (number, hash) in other.block_hashes {
+            self.block_hashes.entry(number).or_insert(hash);
+        }
+    }
+}
 
 /// Memory backend with ability to fork another chain from an HTTP provider, storing all state
 /// values in a `BTreeMap` in memory.
+///
+/// The remote state is read lazily at the pinned block and cached, so that each account, code
+/// and storage slot is only requested once. The [`Backend`] methods are synchronous, so the
+/// requests are driven to completion by the tokio runtime owned by the [`BlockingProvider`].
+/// Wrap it in a [`CheatcodeBackend`](super::cheatcodes::CheatcodeBackend) so that cheatcodes
+/// like `store` or `deal` override the forked state locally.
+///
+/// The cache can also be persisted to disk via [`ForkedBackend::with_cache_dir`], so that
+/// later runs forking the same block do not have to fetch the state again. The clones of a
+/// backend share its cache, which is written once the last of them is dropped.
 #[derive(Clone, Debug)]
 // TODO: Add option to easily 1. impersonate accounts, 2. roll back to pinned block
-pub struct ForkMemoryBackend<M> {
+pub struct ForkedBackend<M> {
     /// ethers middleware for querying on-chain data
     pub provider: BlockingProvider<M>,
     /// the global context of the chain
     pub vicinity: MemoryVicinity,
-    /// state cache
-    // TODO: This should probably be abstracted away into something that efficiently
-    // also caches at disk etc.
+    /// local state, which takes precedence over the forked one
     pub state: BTreeMap<H160, MemoryAccount>,
+    /// the values fetched from the forked chain, shared with the clones of the backend
+    cache: Arc<SharedCache>,
+}
+
+/// The values fetched from the forked chain along with the requests being made, and the file
+/// they are persisted to
+#[derive(Debug, Default)]
+struct SharedCache {
+    /// the values fetched from the forked chain
+    values: RwLock<ForkCache>,
+    /// the values which are being fetched, so that concurrent reads of the same value wait
+    /// for the pending request instead of issuing another one
+    pending: Mutex<BTreeSet<FetchKey>>,
+    /// notified whenever a pending request completes
+    fetched: Condvar,
+    /// the file the cache is persisted to, if any
+    path: Option<PathBuf>,
+    /// whether values were fetched since the cache was last flushed
+    dirty: AtomicBool,
+    /// the number of reads served by the cache instead of the provider
+    hits: AtomicUsize,
 }
 
-impl<M: Middleware> ForkMemoryBackend<M> {
+/// Identifies a value fetched from the forked chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum FetchKey {
+    Account(H160),
+    Code(H160),
+    Storage(H160, H256),
+    BlockHash(u64),
+}
+
+impl<M: Middleware> ForkedBackend<M> {
     /// Create a new memory backend given a provider, an optional block to pin state
     /// against and a state tree
-    pub fn new(provider: M, pin_block: Option<u64>, state: BTreeMap<H160, MemoryAccount>) -> Self {
+    pub fn new(provider: M, pin_block: Option<u64>, state: BTreeMap<H160, MemoryAccount>) -> Self
+    where
+        M::Error: 'static,
+    {
         let provider = BlockingProvider::new(provider);
         let vicinity = provider
             .vicinity(pin_block)
             .expect("could not instantiate vicinity corresponding to upstream");
-        Self { provider, vicinity, state }
+        Self { provider, vicinity, state, cache: Default::default() }
+    }
+
+    /// Persists the fetched state under the provided directory, at
+    /// `<dir>/<chain id>/<block number>.json`. The values cached there by earlier runs are
+    /// loaded, so that they are not fetched again. The backend stops sharing its cache with
+    /// the clones made before.
+    pub fn with_cache_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        let path = dir
+            .as_ref()
+            .join(self.vicinity.chain_id.to_string())
+            .join(format!("{}.json", self.vicinity.block_number));
+        let mut values = self.cache.values.read().unwrap().clone();
+        if path.exists() {
+            match ForkCache::read(&path) {
+                Ok(cached) => values.merge(cached),
+                Err(err) => tracing::warn!("ignoring unreadable fork cache {:?}: {}", path, err),
+            }
+        }
+        self.cache = Arc::new(SharedCache {
+            values: RwLock::new(values),
+            path: Some(path),
+            dirty: AtomicBool::new(self.cache.dirty.load(Ordering::SeqCst)),
+            hits: AtomicUsize::new(self.cache_hits()),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// The block which the remote state is read at
+    fn pinned_block(&self) -> Option<BlockId> {
+        Some(self.vicinity.block_number.as_u64().into())
+    }
+
+    /// Reads a value from the cache, or fetches and caches it. If another thread is fetching
+    /// the same value already, this waits for it instead of issuing another request.
+    ///
+    /// Failed requests return `None` and are not cached, so that they get retried on the next
+    /// read.
+    fn fetch_cached<T: Clone>(
+        &self,
+        key: FetchKey,
+        cached: impl Fn(&ForkCache) -> Option<T>,
+        fetch: impl FnOnce() -> Option<T>,
+        insert: impl FnOnce(&mut ForkCache, T),
+    ) -> Option<T> {
+        let cache = &self.cache;
+        if let Some(value) = cached(&cache.values.read().unwrap()) {
+            cache.hits.fetch_add(1, Ordering::SeqCst);
+            return Some(value)
+        }
+
+        let mut pending = cache.pending.lock().unwrap();
+        loop {
+            // the value may have been fetched by the thread we waited for
+            if let Some(value) = cached(&cache.values.read().unwrap()) {
+                cache.hits.fetch_add(1, Ordering::SeqCst);
+                return Some(value)
+            }
+            if pending.insert(key) {
+                break
+            }
+            pending = cache.fetched.wait(pending).unwrap();
+        }
+        drop(pending);
+
+        let value = fetch();
+        if let Some(value) = &value {
+            insert(&mut cache.values.write().unwrap(), value.clone());
+            cache.dirty.store(true, Ordering::SeqCst);
+        }
+
+        cache.pending.lock().unwrap().remove(&key);
+        cache.fetched.notify_all();
+        value
+    }
+
+    /// Fetches the balance and nonce of an account, unless they are cached already
+    fn fetch_basic(&self, address: H160) -> Basic {
+        let account = self.fetch_cached(
+            FetchKey::Account(address),
+            |cache| cache.accounts.get(&address).copied(),
+            || {
+                let block = self.pinned_block();
+                let balance = self.provider.get_balance(address, block).ok()?;
+                let nonce = self.provider.get_transaction_count(address, block).ok()?;
+                Some(CachedAccount { balance, nonce })
+            },
+            |cache, account| {
+                cache.accounts.insert(address, account);
+            },
+        );
+        let account = account.unwrap_or_default();
+        Basic { balance: account.balance, nonce: account.nonce }
+    }
+
+    /// Fetches the code of an account, unless it is cached already
+    fn fetch_code(&self, address: H160) -> Vec<u8> {
+        self.fetch_cached(
+            FetchKey::Code(address),
+            |cache| cache.code.get(&address).cloned(),
+            || self.provider.get_code(address, self.pinned_block()).ok(),
+            |cache, code| {
+                cache.code.insert(address, code);
+            },
+        )
+        .map(|code| code.to_vec())
+        .unwrap_or_default()
+    }
+
+    /// Fetches a storage slot of an account, unless it is cached already
+    fn fetch_storage(&self, address: H160, index: H256) -> H256 {
+        self.fetch_cached(
+            FetchKey::Storage(address, index),
+            |cache| cache.storage.get(&address).and_then(|slots| slots.get(&index).copied()),
+            || self.provider.get_storage_at(address, index, self.pinned_block()).ok(),
+            |cache, value| {
+                cache.storage.entry(address).or_default().insert(index, value);
+            },
+        )
+        .unwrap_or_default()
+    }
+
+    /// Fetches the hash of a block, unless it is cached already
+    fn fetch_block_hash(&self, number: u64) -> H256 {
+        self.fetch_cached(
+            FetchKey::BlockHash(number),
+            |cache| cache.block_hashes.get(&number).copied(),
+            || self.provider.get_block_hash(number).ok().flatten(),
+            |cache, hash| {
+                cache.block_hashes.insert(number, hash);
+            },
+        )
+        .unwrap_or_default()
     }
 }
 
-impl<M: Middleware> Backend for ForkMemoryBackend<M> {
+impl<M> ForkedBackend<M> {
+    /// The number of reads which were served by the cache (including the values loaded from
+    /// disk) instead of the provider
+    pub fn cache_hits(&self) -> usize {
+        self.cache.hits.load(Ordering::SeqCst)
+    }
+
+    /// Writes the fetched values to the cache directory, if one is set. This also happens
+    /// when the last clone of the backend is dropped.
+    ///
+    /// The values written by other processes in the meantime are kept. The file is written
+    /// to a temporary file first and then renamed over the old one, so that processes
+    /// flushing concurrently never leave a partially written cache behind.
+    pub fn flush_cache(&self) -> Result<()> {
+        self.cache.flush()
+    }
+}
+
+impl SharedCache {
+    /// See [`ForkedBackend::flush_cache`]
+    fn flush(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) if self.dirty.load(Ordering::SeqCst) => path,
+            _ => return Ok(()),
+        };
+
+        let mut cache = self.values.read().unwrap().clone();
+        if path.exists() {
+            // a corrupted cache gets overwritten
+            if let Ok(cached) = ForkCache::read(path) {
+                cache.merge(cached);
+            }
+        } else if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // the pid and the address of the cache make the temporary file unique across the
+        // backends of all processes
+        let tmp_path = path.with_extension(format!("json.{}.{:p}.tmp", std::process::id(), self));
+        fs::write(&tmp_path, serde_json::to_vec(&cache)?)?;
+        if let Err(err) = fs::rename(&tmp_path, path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err.into())
+        }
+
+        self.dirty.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Drop for SharedCache {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            tracing::warn!("could not flush the fork cache: {}", err);
+        }
+    }
+}
+
+impl<M: Middleware> Backend for ForkedBackend<M> {
     fn gas_price(&self) -> U256 {
         self.vicinity.gas_price
     }
@@ -45,15 +377,12 @@ impl<M: Middleware> Backend for ForkMemoryBackend<M> {
     }
 
     fn block_hash(&self, number: U256) -> H256 {
-        if number >= self.vicinity.block_number ||
-            self.vicinity.block_number - number - U256::one() >=
-                U256::from(self.vicinity.block_hashes.len())
-        {
-            H256::default()
-        } else {
-            let index = (self.vicinity.block_number - number - U256::one()).as_usize();
-            self.vicinity.block_hashes[index]
+        // only the hashes of the 256 blocks preceding the current one are accessible
+        let current = self.vicinity.block_number;
+        if number >= current || current - number > U256::from(256) {
+            return H256::default()
         }
+        self.fetch_block_hash(number.as_u64())
     }
 
     fn block_number(&self) -> U256 {
@@ -76,89 +405,40 @@ impl<M: Middleware> Backend for ForkMemoryBackend<M> {
         self.vicinity.block_gas_limit
     }
 
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.vicinity.block_base_fee_per_gas
+    }
+
     fn chain_id(&self) -> U256 {
         self.vicinity.chain_id
     }
 
     fn exists(&self, address: H160) -> bool {
-        let mut exists = self.state.contains_key(&address);
-
-        // check non-zero balance
-        if !exists {
-            let balance = self
-                .provider
-                .get_balance(address, Some(self.vicinity.block_number.as_u64().into()))
-                .unwrap_or_default();
-            exists = balance != U256::zero();
-        }
-
-        // check non-zero nonce
-        if !exists {
-            let nonce = self
-                .provider
-                .get_transaction_count(address, Some(self.vicinity.block_number.as_u64().into()))
-                .unwrap_or_default();
-            exists = nonce != U256::zero();
+        if self.state.contains_key(&address) {
+            return true
         }
 
-        // check non-empty code
-        if !exists {
-            let code = self
-                .provider
-                .get_code(address, Some(self.vicinity.block_number.as_u64().into()))
-                .unwrap_or_default();
-            exists = !code.0.is_empty();
-        }
-
-        exists
+        // accounts with a non-zero balance or nonce, or with code, exist
+        let basic = self.fetch_basic(address);
+        !basic.balance.is_zero() || !basic.nonce.is_zero() || !self.fetch_code(address).is_empty()
     }
 
     fn basic(&self, address: H160) -> Basic {
         self.state
             .get(&address)
             .map(|a| Basic { balance: a.balance, nonce: a.nonce })
-            .unwrap_or_else(|| Basic {
-                balance: self
-                    .provider
-                    .get_balance(address, Some(self.vicinity.block_number.as_u64().into()))
-                    .unwrap_or_default(),
-                nonce: self
-                    .provider
-                    .get_transaction_count(
-                        address,
-                        Some(self.vicinity.block_number.as_u64().into()),
-                    )
-                    .unwrap_or_default(),
-            })
+            .unwrap_or_else(|| self.fetch_basic(address))
     }
 
     fn code(&self, address: H160) -> Vec<u8> {
-        self.state.get(&address).map(|v| v.code.clone()).unwrap_or_else(|| {
-            self.provider
-                .get_code(address, Some(self.vicinity.block_number.as_u64().into()))
-                .unwrap_or_default()
-                .to_vec()
-        })
+        self.state.get(&address).map(|v| v.code.clone()).unwrap_or_else(|| self.fetch_code(address))
     }
 
     fn storage(&self, address: H160, index: H256) -> H256 {
-        if let Some(acct) = self.state.get(&address) {
-            if let Some(store_data) = acct.storage.get(&index) {
-                *store_data
-            } else {
-                self.provider
-                    .get_storage_at(
-                        address,
-                        index,
-                        Some(self.vicinity.block_number.as_u64().into()),
-                    )
-                    .unwrap_or_default()
-            }
-        } else {
-            self.provider
-                .get_storage_at(address, index, Some(self.vicinity.block_number.as_u64().into()))
-                .unwrap_or_default()
-        }
+        self.state
+            .get(&address)
+            .and_then(|acct| acct.storage.get(&index).copied())
+            .unwrap_or_else(|| self.fetch_storage(address, index))
     }
 
     fn original_storage(&self, address: H160, index: H256) -> Option<H256> {
@@ -166,18 +446,449 @@ impl<M: Middleware> Backend for ForkMemoryBackend<M> {
     }
 }
 
+/// A cheaply cloneable handle to a [`ForkedBackend`], so that the tests executed in parallel
+/// share its cache and pending requests instead of each fetching the forked state again.
+#[derive(Debug)]
+pub struct SharedBackend<M> {
+    backend: Arc<ForkedBackend<M>>,
+}
+
+impl<M> Clone for SharedBackend<M> {
+    fn clone(&self) -> Self {
+        Self { backend: self.backend.clone() }
+    }
+}
+
+impl<M> SharedBackend<M> {
+    /// Shares the provided backend. Its cache is flushed once the last handle is dropped.
+    pub fn new(backend: ForkedBackend<M>) -> Self {
+        Self { backend: Arc::new(backend) }
+    }
+}
+
+impl<M> Deref for SharedBackend<M> {
+    type Target = ForkedBackend<M>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.backend
+    }
+}
+
+impl<M: Middleware> Backend for SharedBackend<M> {
+    fn gas_price(&self) -> U256 {
+        self.backend.gas_price()
+    }
+
+    fn origin(&self) -> H160 {
+        self.backend.origin()
+    }
+
+    fn block_hash(&self, number: U256) -> H256 {
+        self.backend.block_hash(number)
+    }
+
+    fn block_number(&self) -> U256 {
+        self.backend.block_number()
+    }
+
+    fn block_coinbase(&self) -> H160 {
+        self.backend.block_coinbase()
+    }
+
+    fn block_timestamp(&self) -> U256 {
+        self.backend.block_timestamp()
+    }
+
+    fn block_difficulty(&self) -> U256 {
+        self.backend.block_difficulty()
+    }
+
+    fn block_gas_limit(&self) -> U256 {
+        self.backend.block_gas_limit()
+    }
+
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.backend.block_base_fee_per_gas()
+    }
+
+    fn chain_id(&self) -> U256 {
+        self.backend.chain_id()
+    }
+
+    fn exists(&self, address: H160) -> bool {
+        self.backend.exists(address)
+    }
+
+    fn basic(&self, address: H160) -> Basic {
+        self.backend.basic(address)
+    }
+
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.backend.code(address)
+    }
+
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        self.backend.storage(address, index)
+    }
+
+    fn original_storage(&self, address: H160, index: H256) -> Option<H256> {
+        self.backend.original_storage(address, index)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{sputnik::Executor, test_helpers::COMPILED, Evm};
+    use crate::{
+        sputnik::{
+            cheatcodes::{CheatcodeBackend, MemoryStackStateOwned},
+            Executor,
+        },
+        test_helpers::COMPILED,
+        Evm,
+    };
+    use async_trait::async_trait;
     use ethers::{
-        providers::{Http, Provider},
-        types::Address,
+        providers::{Http, JsonRpcClient, MockError, MockProvider, Provider},
+        types::{Address, Block, TxHash, U64},
+    };
+    use serde::de::DeserializeOwned;
+    use sputnik::{
+        executor::{StackState, StackSubstateMetadata},
+        Config,
     };
-    use sputnik::Config;
-    use std::convert::TryFrom;
+    use std::{convert::TryFrom, thread, time::Duration};
 
     use super::*;
 
+    /// The header of a mocked block, whose fields are derived from its number
+    fn mocked_block(number: u64) -> Block<TxHash> {
+        Block {
+            number: Some(number.into()),
+            hash: Some(H256::from_low_u64_be(number + 1000)),
+            author: H160::from_low_u64_be(number + 2000),
+            timestamp: (number + 3000).into(),
+            difficulty: (number + 4000).into(),
+            gas_limit: (number + 5000).into(),
+            ..Default::default()
+        }
+    }
+
+    /// Instantiates a backend forking a mocked node at the provided block. The mock returns
+    /// the pushed responses in reverse order.
+    fn mocked_backend(block_number: u64) -> (ForkedBackend<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Block<TxHash>, _>(mocked_block(block_number)).unwrap();
+        // the chain id and the gas price
+        mock.push::<U256, _>(U256::one()).unwrap();
+        mock.push::<U256, _>(U256::from(10)).unwrap();
+
+        let backend = ForkedBackend::new(provider, Some(block_number), Default::default());
+        (backend, mock)
+    }
+
+    #[test]
+    fn fetches_vicinity() {
+        let (backend, mock) = mocked_backend(3);
+        let vicinity = &backend.vicinity;
+
+        assert_eq!(vicinity.gas_price, U256::from(10));
+        assert_eq!(vicinity.origin, Address::zero());
+        assert_eq!(vicinity.chain_id, U256::one());
+        assert_eq!(vicinity.block_number, U256::from(3));
+        assert_eq!(vicinity.block_coinbase, H160::from_low_u64_be(2003));
+        assert_eq!(vicinity.block_timestamp, U256::from(3003));
+        assert_eq!(vicinity.block_difficulty, U256::from(4003));
+        assert_eq!(vicinity.block_gas_limit, U256::from(5003));
+
+        // the hashes of the preceding blocks are fetched once they are read
+        assert!(vicinity.block_hashes.is_empty());
+        mock.push::<Block<TxHash>, _>(mocked_block(1)).unwrap();
+        assert_eq!(backend.block_hash(1.into()), H256::from_low_u64_be(1001));
+        assert_eq!(backend.block_hash(1.into()), H256::from_low_u64_be(1001));
+        assert_eq!(backend.cache_hits(), 1);
+        assert_eq!(backend.block_hash(3.into()), H256::zero());
+    }
+
+    #[test]
+    fn fetches_hashes_of_recent_blocks_only() {
+        let (backend, mock) = mocked_backend(300);
+        mock.push::<Block<TxHash>, _>(mocked_block(44)).unwrap();
+        assert_eq!(backend.block_hash(44.into()), H256::from_low_u64_be(1044));
+        // no request is made for the blocks which are too old
+        assert_eq!(backend.block_hash(43.into()), H256::zero());
+        assert_eq!(backend.cache_hits(), 0);
+    }
+
+    #[test]
+    fn missing_block_is_an_error() {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Option<Block<TxHash>>, _>(None).unwrap();
+        mock.push::<U256, _>(U256::one()).unwrap();
+        mock.push::<U256, _>(U256::from(10)).unwrap();
+        let provider = BlockingProvider::new(provider);
+
+        let err = provider.vicinity(Some(3)).unwrap_err();
+        assert_eq!(err.to_string(), "block 3 not found");
+    }
+
+    #[test]
+    fn cheatcodes_override_fetched_vicinity() {
+        let (backend, _mock) = mocked_backend(3);
+        let mut backend = CheatcodeBackend::new(backend);
+        backend.cheats.block_timestamp = Some(100.into());
+        backend.cheats.block_number = Some(200.into());
+        backend.cheats.chain_id = Some(99.into());
+
+        assert_eq!(backend.block_timestamp(), U256::from(100));
+        assert_eq!(backend.block_number(), U256::from(200));
+        assert_eq!(backend.chain_id(), U256::from(99));
+        // the values which were not overriden are still the fetched ones
+        assert_eq!(backend.block_coinbase(), H160::from_low_u64_be(2003));
+        assert_eq!(backend.block_difficulty(), U256::from(4003));
+    }
+
+    #[test]
+    fn caches_remote_reads() {
+        let (backend, mock) = mocked_backend(13292465);
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let slot = H256::from_low_u64_be(1);
+        let value = H256::from_low_u64_be(1337);
+
+        // the nonce is requested after the balance
+        mock.push::<U256, _>(U256::from(5)).unwrap();
+        mock.push::<U256, _>(U256::from(100)).unwrap();
+        mock.push::<Bytes, _>(Bytes::from(vec![0x60, 0x00])).unwrap();
+        mock.push::<H256, _>(value).unwrap();
+
+        // the mock has no responses left after the first reads, so any repeated request would
+        // fail and read as zero
+        for _ in 0..2 {
+            assert_eq!(backend.storage(addr, slot), value);
+            assert_eq!(backend.code(addr), vec![0x60, 0x00]);
+            let basic = backend.basic(addr);
+            assert_eq!(basic.balance, U256::from(100));
+            assert_eq!(basic.nonce, U256::from(5));
+            assert!(backend.exists(addr));
+        }
+    }
+
+    #[test]
+    fn failed_remote_reads_are_retried() {
+        let (backend, mock) = mocked_backend(13292465);
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let slot = H256::from_low_u64_be(1);
+
+        // no response was pushed, so the request fails
+        assert_eq!(backend.storage(addr, slot), H256::zero());
+
+        mock.push::<H256, _>(H256::from_low_u64_be(42)).unwrap();
+        assert_eq!(backend.storage(addr, slot), H256::from_low_u64_be(42));
+    }
+
+    #[test]
+    fn cheatcodes_override_forked_state() {
+        let cfg = Config::istanbul();
+        let (backend, mock) = mocked_backend(13292465);
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let slot = H256::from_low_u64_be(1);
+        let other_slot = H256::from_low_u64_be(2);
+
+        let metadata = StackSubstateMetadata::new(12_000_000, &cfg);
+        let mut state = MemoryStackStateOwned::new(metadata, CheatcodeBackend::new(backend));
+
+        // this is how `store` and `deal` write to the state
+        state.set_storage(addr, slot, H256::from_low_u64_be(7));
+        state.reset_balance(addr);
+        state.deposit(addr, U256::from(1000));
+
+        assert_eq!(state.storage(addr, slot), H256::from_low_u64_be(7));
+        assert_eq!(state.basic(addr).balance, U256::from(1000));
+
+        // slots which were not overriden are still read from the fork
+        mock.push::<H256, _>(H256::from_low_u64_be(3)).unwrap();
+        assert_eq!(state.storage(addr, other_slot), H256::from_low_u64_be(3));
+    }
+
+    /// A cache directory which is unique to the test
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fork-cache-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn persists_cache_to_disk() {
+        let dir = cache_dir("persist");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let slot = H256::from_low_u64_be(1);
+
+        let (backend, mock) = mocked_backend(13292465);
+        let backend = backend.with_cache_dir(&dir);
+        mock.push::<H256, _>(H256::from_low_u64_be(42)).unwrap();
+        assert_eq!(backend.storage(addr, slot), H256::from_low_u64_be(42));
+        assert_eq!(backend.cache_hits(), 0);
+        backend.flush_cache().unwrap();
+
+        // the cache is keyed by the chain id and the block number
+        let path = dir.join("1").join("13292465.json");
+        assert!(path.exists());
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        // no response is pushed, so the value has to come from the disk
+        let (backend, _mock) = mocked_backend(13292465);
+        let backend = backend.with_cache_dir(&dir);
+        assert_eq!(backend.storage(addr, slot), H256::from_low_u64_be(42));
+        assert_eq!(backend.cache_hits(), 1);
+
+        // other blocks have their own cache
+        let (backend, _mock) = mocked_backend(13292466);
+        let backend = backend.with_cache_dir(&dir);
+        assert_eq!(backend.storage(addr, slot), H256::zero());
+        assert_eq!(backend.cache_hits(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flushes_cache_on_drop() {
+        let dir = cache_dir("drop");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        let (backend, mock) = mocked_backend(13292465);
+        let backend = backend.with_cache_dir(&dir);
+        mock.push::<Bytes, _>(Bytes::from(vec![0x60, 0x00])).unwrap();
+        assert_eq!(backend.code(addr), vec![0x60, 0x00]);
+        drop(backend);
+
+        let (backend, _mock) = mocked_backend(13292465);
+        let backend = backend.with_cache_dir(&dir);
+        assert_eq!(backend.code(addr), vec![0x60, 0x00]);
+        assert_eq!(backend.cache_hits(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clones_share_cache() {
+        let dir = cache_dir("clone");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+        let path = dir.join("1").join("13292465.json");
+
+        let (backend, mock) = mocked_backend(13292465);
+        let backend = backend.with_cache_dir(&dir);
+        let clone = backend.clone();
+        mock.push::<Bytes, _>(Bytes::from(vec![0x60, 0x00])).unwrap();
+        assert_eq!(clone.code(addr), vec![0x60, 0x00]);
+        assert_eq!(backend.code(addr), vec![0x60, 0x00]);
+        assert_eq!(backend.cache_hits(), 1);
+
+        // the cache is written once, by the last clone
+        drop(clone);
+        assert!(!path.exists());
+        drop(backend);
+        assert!(path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flushing_keeps_values_of_other_backends() {
+        let dir = cache_dir("merge");
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        // both backends start from an empty cache, as if they ran in parallel
+        let (first, first_mock) = mocked_backend(13292465);
+        let first = first.with_cache_dir(&dir);
+        let (second, second_mock) = mocked_backend(13292465);
+        let second = second.with_cache_dir(&dir);
+
+        first_mock.push::<H256, _>(H256::from_low_u64_be(1)).unwrap();
+        first.storage(addr, H256::from_low_u64_be(1));
+        second_mock.push::<H256, _>(H256::from_low_u64_be(2)).unwrap();
+        second.storage(addr, H256::from_low_u64_be(2));
+        first.flush_cache().unwrap();
+        second.flush_cache().unwrap();
+
+        let (backend, _mock) = mocked_backend(13292465);
+        let backend = backend.with_cache_dir(&dir);
+        assert_eq!(backend.storage(addr, H256::from_low_u64_be(1)), H256::from_low_u64_be(1));
+        assert_eq!(backend.storage(addr, H256::from_low_u64_be(2)), H256::from_low_u64_be(2));
+        assert_eq!(backend.cache_hits(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A transport which answers like a node and counts the requests of each storage slot.
+    /// Unlike the [`MockProvider`], its responses depend on the request, so that it can be
+    /// used from many threads at once.
+    #[derive(Clone, Debug, Default)]
+    struct CountingTransport {
+        storage_requests: Arc<Mutex<BTreeMap<(String, String), usize>>>,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for CountingTransport {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Serialize + Send + Sync,
+            R: DeserializeOwned,
+        {
+            let params = serde_json::to_value(params)?;
+            let response = match method {
+                "eth_gasPrice" | "eth_chainId" => serde_json::to_value(U256::one())?,
+                "eth_getBlockByNumber" => {
+                    let number: U64 = serde_json::from_value(params[0].clone())?;
+                    serde_json::to_value(Block::<TxHash> {
+                        number: Some(number),
+                        ..Default::default()
+                    })?
+                }
+                "eth_getStorageAt" => {
+                    let key = (params[0].to_string(), params[1].to_string());
+                    *self.storage_requests.lock().unwrap().entry(key).or_default() += 1;
+                    // give the other threads time to read the same slot in the meantime
+                    thread::sleep(Duration::from_millis(10));
+                    // the value of each slot is its index
+                    params[1].clone()
+                }
+                _ => return Err(MockError::EmptyResponses),
+            };
+            Ok(serde_json::from_value(response)?)
+        }
+    }
+
+    #[test]
+    fn shared_backend_fetches_each_value_once() {
+        let transport = CountingTransport::default();
+        let backend =
+            ForkedBackend::new(Provider::new(transport.clone()), Some(1), BTreeMap::new());
+        let backend = SharedBackend::new(backend);
+        let addr: Address = "0x1000000000000000000000000000000000000000".parse().unwrap();
+
+        // every thread reads all the slots, starting at a different one
+        let threads: Vec<_> = (0..16u64)
+            .map(|i| {
+                let backend = backend.clone();
+                thread::spawn(move || {
+                    for slot in 0..8u64 {
+                        let slot = H256::from_low_u64_be((slot + i) % 8);
+                        assert_eq!(backend.storage(addr, slot), slot);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let requests = transport.storage_requests.lock().unwrap();
+        assert_eq!(requests.len(), 8);
+        assert!(requests.values().all(|count| *count == 1));
+        assert_eq!(backend.cache_hits(), 16 * 8 - 8);
+    }
+
     #[test]
     fn forked_backend() {
         let cfg = Config::istanbul();
@@ -188,7 +899,7 @@ mod tests {
             "https://mainnet.infura.io/v3/c60b0bb42f8a4c6481ecd229eddaca27",
         )
         .unwrap();
-        let backend = ForkMemoryBackend::new(provider, Some(13292465), Default::default());
+        let backend = ForkedBackend::new(provider, Some(13292465), Default::default());
         let mut evm = Executor::new(12_000_000, &cfg, &backend);
         evm.initialize_contracts(vec![(addr, compiled.runtime_bytecode.clone())]);
 
@@ -201,6 +912,7 @@ mod tests {
                 &dapp_utils::get_func("function time() public view returns (uint256)").unwrap(),
                 (),
                 0.into(),
+                None,
             )
             .unwrap();
 
@@ -2,4 +2,247 @@ mod evm;
 pub use evm::*;
 
 mod forked_backend;
-pub use forked_backend::ForkMemoryBackend;
+pub use forked_backend::{vicinity_from_provider, ForkedBackend, SharedBackend};
+
+mod spec;
+pub use spec::SpecId;
+
+pub mod precompiles;
+
+pub mod state_loader;
+
+pub mod cheatcodes;
+use cheatcodes::MemoryStackStateOwned;
+
+#[cfg(feature = "coverage")]
+use crate::coverage::BitSet;
+#[cfg(feature = "debug-steps")]
+use crate::debug_steps::{DebugConfig, DebugStep};
+use crate::{call_tracing::CallTraceNode, fuzz::FuzzDictionary, gas_report::GasReport, Interrupt};
+use ethers::{
+    abi::RawLog,
+    types::{Address, H160, H256, U256},
+};
+#[cfg(feature = "coverage")]
+use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+use sputnik::{
+    backend::{Apply, Backend},
+    executor::{MemoryStackState, StackExecutor, StackState},
+    Config, ExitReason, Handler,
+};
+
+/// Abstraction over the StackExecutor used inside of Sputnik, so that we can replace
+/// it with one that implements HEVM-style cheatcodes (or other features).
+pub trait SputnikExecutor<S> {
+    fn config(&self) -> &Config;
+    fn state(&self) -> &S;
+    fn state_mut(&mut self) -> &mut S;
+    fn gas_left(&self) -> U256;
+    fn transact_call(
+        &mut self,
+        caller: H160,
+        address: H160,
+        value: U256,
+        data: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>);
+
+    /// Deploys a contract, returning the revert data of its constructor if it reverted
+    fn transact_create(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>);
+
+    /// The human-readable names of addresses known to the executor
+    fn labels(&self) -> BTreeMap<Address, String> {
+        BTreeMap::new()
+    }
+
+    /// The number of expectations (e.g. set via the `expectRevert` cheatcode) which the last
+    /// transaction left unsatisfied
+    fn pending_expectations(&self) -> usize {
+        0
+    }
+
+    /// The logs emitted during the last call, excluding the ones of reverted calls
+    fn logs(&self) -> Vec<RawLog> {
+        Vec::new()
+    }
+
+    /// Clears any state set via cheatcodes, leaving the rest of the state untouched
+    fn reset_cheatcodes(&mut self) {}
+
+    /// Sets the flag which aborts the call being executed at its next instruction once raised
+    fn set_interrupt(&mut self, _interrupt: Option<Interrupt>) {}
+
+    /// Enables or disables the recording of call traces
+    fn set_tracing_enabled(&mut self, _enabled: bool) {}
+
+    /// The call tree of the last call, if tracing is enabled
+    fn traces(&self) -> Vec<CallTraceNode> {
+        Vec::new()
+    }
+
+    /// Enables or disables the collection of the gas report
+    fn set_gas_reporting_enabled(&mut self, _enabled: bool) {}
+
+    /// The gas used by the calls made so far, if gas reporting is enabled
+    fn gas_report(&self) -> GasReport {
+        GasReport::default()
+    }
+
+    /// Enables or disables the harvesting of the values for fuzzing
+    fn set_fuzz_dictionary_enabled(&mut self, _enabled: bool) {}
+
+    /// The values harvested for fuzzing, if harvesting is enabled
+    fn fuzz_dictionary(&self) -> FuzzDictionary {
+        FuzzDictionary::default()
+    }
+
+    /// Sets what to record of the executed steps, or disables their recording with `None`
+    #[cfg(feature = "debug-steps")]
+    fn set_debug_config(&mut self, _config: Option<DebugConfig>) {}
+
+    /// The steps executed by the last call, if their recording is enabled
+    #[cfg(feature = "debug-steps")]
+    fn debug_steps(&self) -> Vec<DebugStep> {
+        Vec::new()
+    }
+
+    /// Enables or disables the recording of the executed program counters, clearing the ones
+    /// recorded so far when enabling it
+    #[cfg(feature = "coverage")]
+    fn set_coverage_enabled(&mut self, _enabled: bool) {}
+
+    /// The program counters executed since the recording was enabled, by code hash
+    #[cfg(feature = "coverage")]
+    fn coverage(&self) -> HashMap<H256, BitSet> {
+        HashMap::new()
+    }
+}
+
+/// Stack states which can credit an account without a transfer, so that balances can be set
+/// directly, e.g. to fund the sender of the tests
+pub trait DepositState {
+    fn deposit(&mut self, address: H160, value: U256);
+}
+
+impl<'backend, 'config, B: Backend> DepositState for MemoryStackState<'backend, 'config, B> {
+    fn deposit(&mut self, address: H160, value: U256) {
+        MemoryStackState::deposit(self, address, value)
+    }
+}
+
+impl<'config, B: Backend> DepositState for MemoryStackStateOwned<'config, B> {
+    fn deposit(&mut self, address: H160, value: U256) {
+        MemoryStackStateOwned::deposit(self, address, value)
+    }
+}
+
+/// Stack states which can list the accounts and storage slots written to since they were
+/// created, so that the changes made by a call can be found
+pub trait WrittenState {
+    /// The written storage slots, by address of every written account (including the deleted
+    /// ones). The changes of the frames which reverted are discarded, so they are not listed.
+    fn written(&self) -> BTreeMap<H160, BTreeSet<H256>>;
+
+    /// The number of times the account self-destructed since the state was created
+    fn destructions(&self, address: H160) -> usize;
+}
+
+impl<'backend, 'config, B: Backend> WrittenState for MemoryStackState<'backend, 'config, B> {
+    fn written(&self) -> BTreeMap<H160, BTreeSet<H256>> {
+        let (applies, _) = self.clone().deconstruct();
+        written_slots(applies)
+    }
+
+    fn destructions(&self, address: H160) -> usize {
+        StackState::deleted(self, address) as usize
+    }
+}
+
+impl<'config, B: Backend> WrittenState for MemoryStackStateOwned<'config, B> {
+    fn written(&self) -> BTreeMap<H160, BTreeSet<H256>> {
+        // only the substate is cloned, the backend may be expensive to clone
+        // NB: the accounts destroyed once are always deleted by the substate, so the slots
+        // written after they were re-deployed are not listed
+        let (applies, _) = self.substate.clone().deconstruct(&self.backend);
+        written_slots(applies)
+    }
+
+    fn destructions(&self, address: H160) -> usize {
+        let finalized = self.destroyed.get(&address).map_or(0, |destroyed| destroyed.times);
+        finalized + StackState::deleted(self, address) as usize
+    }
+}
+
+fn written_slots<I, T>(applies: I) -> BTreeMap<H160, BTreeSet<H256>>
+where
+    I: IntoIterator<Item = Apply<T>>,
+    T: IntoIterator<Item = (H256, H256)>,
+{
+    let mut written = BTreeMap::<H160, BTreeSet<H256>>::new();
+    for apply in applies {
+        match apply {
+            Apply::Modify { address, storage, .. } => {
+                written.entry(address).or_default().extend(storage.into_iter().map(|(key, _)| key))
+            }
+            Apply::Delete { address } => {
+                written.entry(address).or_default();
+            }
+        }
+    }
+    written
+}
+
+// The implementation for the base Stack Executor just forwards to the internal methods.
+impl<'a, S: StackState<'a>> SputnikExecutor<S> for StackExecutor<'a, S> {
+    fn config(&self) -> &Config {
+        StackExecutor::config(self)
+    }
+
+    fn state(&self) -> &S {
+        StackExecutor::state(self)
+    }
+
+    fn state_mut(&mut self) -> &mut S {
+        StackExecutor::state_mut(self)
+    }
+
+    fn gas_left(&self) -> U256 {
+        Handler::gas_left(self)
+    }
+
+    fn transact_call(
+        &mut self,
+        caller: H160,
+        address: H160,
+        value: U256,
+        data: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        StackExecutor::transact_call(self, caller, address, value, data, gas_limit, access_list)
+    }
+
+    fn transact_create(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        // upstream does not return the revert data of the constructor
+        let reason =
+            StackExecutor::transact_create(self, caller, value, init_code, gas_limit, access_list);
+        (reason, Vec::new())
+    }
+}
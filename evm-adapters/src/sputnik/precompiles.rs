@@ -0,0 +1,528 @@
+//! The precompiled contracts of mainnet, charging the same gas as on mainnet so that gas
+//! reports stay meaningful. See appendix E of the yellow paper and the EIPs linked below.
+use bn::{pairing_batch, AffineG1, AffineG2, Fq, Fq2, Fr, Group, Gt, G1, G2};
+use ethers::types::{Signature, H160, H256, U256};
+use num_bigint::BigUint;
+use ripemd160::Ripemd160;
+use sha2::{Digest, Sha256};
+use sputnik::{
+    executor::{PrecompileFn, PrecompileOutput},
+    Config, Context, ExitError, ExitSucceed,
+};
+use std::{
+    cmp::{max, min},
+    collections::BTreeMap,
+    convert::TryInto,
+};
+
+/// The precompiles by their address, in the format expected by the Sputnik executor
+pub type Precompiles = BTreeMap<H160, PrecompileFn>;
+
+type PrecompileResult = Result<PrecompileOutput, ExitError>;
+
+/// The precompiles available in the hard fork of the provided config. Their gas costs
+/// depend on the hard fork as well.
+pub fn precompiles(config: &Config) -> Precompiles {
+    let address = H160::from_low_u64_be;
+    let mut precompiles = Precompiles::new();
+    precompiles.insert(address(1), ecrecover);
+    precompiles.insert(address(2), sha256);
+    precompiles.insert(address(3), ripemd160);
+    precompiles.insert(address(4), identity);
+
+    // Byzantium, which also introduced `REVERT`
+    if config.has_revert {
+        if config.increase_state_access_gas {
+            precompiles.insert(address(5), modexp_berlin);
+        } else {
+            precompiles.insert(address(5), modexp_byzantium);
+        }
+        if config.has_chain_id {
+            precompiles.insert(address(6), bn_add_istanbul);
+            precompiles.insert(address(7), bn_mul_istanbul);
+            precompiles.insert(address(8), bn_pairing_istanbul);
+        } else {
+            precompiles.insert(address(6), bn_add_byzantium);
+            precompiles.insert(address(7), bn_mul_byzantium);
+            precompiles.insert(address(8), bn_pairing_byzantium);
+        }
+    }
+
+    // Istanbul, which also introduced `CHAINID`
+    if config.has_chain_id {
+        precompiles.insert(address(9), blake2f);
+    }
+
+    precompiles
+}
+
+/// Fails if the cost exceeds the gas limit of the call
+fn charge(cost: u64, gas_limit: Option<u64>) -> Result<(), ExitError> {
+    match gas_limit {
+        Some(gas_limit) if cost > gas_limit => Err(ExitError::OutOfGas),
+        _ => Ok(()),
+    }
+}
+
+fn returned(cost: u64, output: Vec<u8>) -> PrecompileResult {
+    Ok(PrecompileOutput { exit_status: ExitSucceed::Returned, cost, output, logs: Vec::new() })
+}
+
+/// The cost of precompiles charging per 32 byte word of their input
+fn linear_cost(len: usize, base: u64, word: u64) -> u64 {
+    base + word * ((len as u64 + 31) / 32)
+}
+
+/// Reads `len` bytes at `offset`, right-padding the input with zeros
+fn read_padded(input: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    if offset < input.len() {
+        let available = min(len, input.len() - offset);
+        bytes[..available].copy_from_slice(&input[offset..offset + available]);
+    }
+    bytes
+}
+
+fn ecrecover(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    let cost = 3000;
+    charge(cost, gas_limit)?;
+
+    let input = read_padded(input, 0, 128);
+    let hash = H256::from_slice(&input[..32]);
+    let v = U256::from_big_endian(&input[32..64]);
+    let r = U256::from_big_endian(&input[64..96]);
+    let s = U256::from_big_endian(&input[96..]);
+
+    // invalid signatures return no data instead of failing
+    if v != 27.into() && v != 28.into() {
+        return returned(cost, Vec::new())
+    }
+    let signature = Signature { r, s, v: v.as_u64() };
+    let output = match signature.recover(hash) {
+        Ok(signer) => H256::from(signer).as_bytes().to_vec(),
+        Err(_) => Vec::new(),
+    };
+    returned(cost, output)
+}
+
+fn sha256(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    let cost = linear_cost(input.len(), 60, 12);
+    charge(cost, gas_limit)?;
+    returned(cost, Sha256::digest(input).to_vec())
+}
+
+fn ripemd160(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    let cost = linear_cost(input.len(), 600, 120);
+    charge(cost, gas_limit)?;
+    // the 20 byte digest is left-padded to a word
+    let mut output = vec![0u8; 12];
+    output.extend_from_slice(&Ripemd160::digest(input));
+    returned(cost, output)
+}
+
+fn identity(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    let cost = linear_cost(input.len(), 15, 3);
+    charge(cost, gas_limit)?;
+    returned(cost, input.to_vec())
+}
+
+fn modexp_byzantium(
+    input: &[u8],
+    gas_limit: Option<u64>,
+    _: &Context,
+    _: bool,
+) -> PrecompileResult {
+    modexp(input, gas_limit, false)
+}
+
+fn modexp_berlin(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    modexp(input, gas_limit, true)
+}
+
+/// Modular exponentiation, priced as in [EIP-198](https://eips.ethereum.org/EIPS/eip-198)
+/// before Berlin and as in [EIP-2565](https://eips.ethereum.org/EIPS/eip-2565) from then on
+fn modexp(input: &[u8], gas_limit: Option<u64>, berlin: bool) -> PrecompileResult {
+    let min_cost = if berlin { 200 } else { 0 };
+    let base_len = U256::from_big_endian(&read_padded(input, 0, 32));
+    let exp_len = U256::from_big_endian(&read_padded(input, 32, 32));
+    let mod_len = U256::from_big_endian(&read_padded(input, 64, 32));
+
+    if base_len.is_zero() && mod_len.is_zero() {
+        charge(min_cost, gas_limit)?;
+        return returned(min_cost, Vec::new())
+    }
+    // the cost of longer inputs exceeds any gas limit
+    let max_len = U256::from(u32::MAX);
+    if base_len > max_len || exp_len > max_len || mod_len > max_len {
+        return Err(ExitError::OutOfGas)
+    }
+    let (base_len, exp_len, mod_len) =
+        (base_len.as_usize(), exp_len.as_usize(), mod_len.as_usize());
+    let data = input.get(96..).unwrap_or_default();
+
+    // only the highest bit of the first word of the exponent is priced
+    let exp_head = U256::from_big_endian(&read_padded(data, base_len, min(exp_len, 32)));
+    let head_bits = (exp_head.bits() as u128).saturating_sub(1);
+    let adjusted_exp_len =
+        if exp_len <= 32 { head_bits } else { 8 * (exp_len as u128 - 32) + head_bits };
+
+    let len = max(base_len, mod_len) as u128;
+    let cost = if berlin {
+        let words = (len + 7) / 8;
+        max(200, words * words * max(adjusted_exp_len, 1) / 3)
+    } else {
+        let complexity = if len <= 64 {
+            len * len
+        } else if len <= 1024 {
+            len * len / 4 + 96 * len - 3072
+        } else {
+            len * len / 16 + 480 * len - 199680
+        };
+        complexity * max(adjusted_exp_len, 1) / 20
+    };
+    let cost: u64 = cost.try_into().map_err(|_| ExitError::OutOfGas)?;
+    charge(cost, gas_limit)?;
+
+    let base = BigUint::from_bytes_be(&read_padded(data, 0, base_len));
+    let exp = BigUint::from_bytes_be(&read_padded(data, base_len, exp_len));
+    let modulus = BigUint::from_bytes_be(&read_padded(data, base_len + exp_len, mod_len));
+
+    // the result is left-padded to the length of the modulus, and zero for a zero modulus
+    let mut output = vec![0u8; mod_len];
+    if modulus != BigUint::from(0u8) {
+        let result = base.modpow(&exp, &modulus).to_bytes_be();
+        output[mod_len - result.len()..].copy_from_slice(&result);
+    }
+    returned(cost, output)
+}
+
+fn invalid(reason: &'static str) -> ExitError {
+    ExitError::Other(reason.into())
+}
+
+/// Reads a point of the alt_bn128 curve, where `(0, 0)` is the point at infinity
+fn read_g1(input: &[u8], offset: usize) -> Result<G1, ExitError> {
+    let x = Fq::from_slice(&input[offset..offset + 32]).map_err(|_| invalid("invalid point"))?;
+    let y =
+        Fq::from_slice(&input[offset + 32..offset + 64]).map_err(|_| invalid("invalid point"))?;
+    if x == Fq::zero() && y == Fq::zero() {
+        Ok(G1::zero())
+    } else {
+        Ok(AffineG1::new(x, y).map_err(|_| invalid("point not on curve"))?.into())
+    }
+}
+
+fn encode_g1(point: G1) -> Vec<u8> {
+    let mut output = vec![0u8; 64];
+    if let Some(point) = AffineG1::from_jacobian(point) {
+        point.x().to_big_endian(&mut output[..32]).expect("output fits a field element");
+        point.y().to_big_endian(&mut output[32..]).expect("output fits a field element");
+    }
+    output
+}
+
+fn bn_add_byzantium(
+    input: &[u8],
+    gas_limit: Option<u64>,
+    _: &Context,
+    _: bool,
+) -> PrecompileResult {
+    bn_add(input, gas_limit, 500)
+}
+
+fn bn_add_istanbul(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    bn_add(input, gas_limit, 150)
+}
+
+/// Point addition on the alt_bn128 curve, see [EIP-196](https://eips.ethereum.org/EIPS/eip-196)
+fn bn_add(input: &[u8], gas_limit: Option<u64>, cost: u64) -> PrecompileResult {
+    charge(cost, gas_limit)?;
+    let input = read_padded(input, 0, 128);
+    let sum = read_g1(&input, 0)? + read_g1(&input, 64)?;
+    returned(cost, encode_g1(sum))
+}
+
+fn bn_mul_byzantium(
+    input: &[u8],
+    gas_limit: Option<u64>,
+    _: &Context,
+    _: bool,
+) -> PrecompileResult {
+    bn_mul(input, gas_limit, 40000)
+}
+
+fn bn_mul_istanbul(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    bn_mul(input, gas_limit, 6000)
+}
+
+/// Scalar multiplication on the alt_bn128 curve, see
+/// [EIP-196](https://eips.ethereum.org/EIPS/eip-196)
+fn bn_mul(input: &[u8], gas_limit: Option<u64>, cost: u64) -> PrecompileResult {
+    charge(cost, gas_limit)?;
+    let input = read_padded(input, 0, 96);
+    let point = read_g1(&input, 0)?;
+    let scalar = Fr::from_slice(&input[64..]).map_err(|_| invalid("invalid scalar"))?;
+    returned(cost, encode_g1(point * scalar))
+}
+
+fn bn_pairing_byzantium(
+    input: &[u8],
+    gas_limit: Option<u64>,
+    _: &Context,
+    _: bool,
+) -> PrecompileResult {
+    bn_pairing(input, gas_limit, 100000, 80000)
+}
+
+fn bn_pairing_istanbul(
+    input: &[u8],
+    gas_limit: Option<u64>,
+    _: &Context,
+    _: bool,
+) -> PrecompileResult {
+    bn_pairing(input, gas_limit, 45000, 34000)
+}
+
+/// Pairing check on the alt_bn128 curve, see [EIP-197](https://eips.ethereum.org/EIPS/eip-197)
+fn bn_pairing(input: &[u8], gas_limit: Option<u64>, base: u64, per_pair: u64) -> PrecompileResult {
+    if input.len() % 192 != 0 {
+        return Err(invalid("invalid input length"))
+    }
+    let cost = base + per_pair * (input.len() / 192) as u64;
+    charge(cost, gas_limit)?;
+
+    let mut pairs = Vec::new();
+    for pair in input.chunks(192) {
+        let a = read_g1(pair, 0)?;
+        // the coordinates of the twist are encoded with their imaginary part first
+        let mut coordinates = Vec::new();
+        for offset in (64..192).step_by(32) {
+            coordinates.push(
+                Fq::from_slice(&pair[offset..offset + 32]).map_err(|_| invalid("invalid point"))?,
+            );
+        }
+        let x = Fq2::new(coordinates[1], coordinates[0]);
+        let y = Fq2::new(coordinates[3], coordinates[2]);
+        let b = if x.is_zero() && y.is_zero() {
+            G2::zero()
+        } else {
+            AffineG2::new(x, y).map_err(|_| invalid("point not on curve"))?.into()
+        };
+        pairs.push((a, b));
+    }
+
+    let mut output = vec![0u8; 32];
+    if pairing_batch(&pairs) == Gt::one() {
+        output[31] = 1;
+    }
+    returned(cost, output)
+}
+
+/// The initialization vector of BLAKE2b
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// The message word permutations of the rounds of BLAKE2b
+const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// The mixing function of BLAKE2b
+fn blake2b_g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// The compression function of BLAKE2b, with a configurable number of rounds
+fn blake2b_f(h: &mut [u64; 8], m: &[u64; 16], t: [u64; 2], last: bool, rounds: u32) {
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= t[0];
+    v[13] ^= t[1];
+    if last {
+        v[14] = !v[14];
+    }
+
+    for round in 0..rounds as usize {
+        let s = &BLAKE2B_SIGMA[round % 10];
+        blake2b_g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+        blake2b_g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+        blake2b_g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+        blake2b_g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+        blake2b_g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+        blake2b_g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+        blake2b_g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+        blake2b_g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+
+    for (i, word) in h.iter_mut().enumerate() {
+        *word ^= v[i] ^ v[i + 8];
+    }
+}
+
+/// The BLAKE2b compression function, see [EIP-152](https://eips.ethereum.org/EIPS/eip-152)
+fn blake2f(input: &[u8], gas_limit: Option<u64>, _: &Context, _: bool) -> PrecompileResult {
+    if input.len() != 213 {
+        return Err(invalid("invalid input length"))
+    }
+    let rounds = u32::from_be_bytes(input[..4].try_into().unwrap());
+    let cost = rounds as u64;
+    charge(cost, gas_limit)?;
+
+    let last = match input[212] {
+        0 => false,
+        1 => true,
+        _ => return Err(invalid("invalid final block indicator")),
+    };
+    let word = |offset: usize| u64::from_le_bytes(input[offset..offset + 8].try_into().unwrap());
+    let mut h = [0u64; 8];
+    for (i, h) in h.iter_mut().enumerate() {
+        *h = word(4 + i * 8);
+    }
+    let mut m = [0u64; 16];
+    for (i, m) in m.iter_mut().enumerate() {
+        *m = word(68 + i * 8);
+    }
+    let t = [word(196), word(204)];
+
+    blake2b_f(&mut h, &m, t, last, rounds);
+    returned(cost, h.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Address;
+
+    fn context() -> Context {
+        Context { address: Address::zero(), caller: Address::zero(), apparent_value: U256::zero() }
+    }
+
+    fn call(config: &Config, address: u64, input: &[u8]) -> PrecompileOutput {
+        let precompiles = precompiles(config);
+        let precompile = precompiles.get(&H160::from_low_u64_be(address)).unwrap();
+        precompile(input, None, &context(), false).unwrap()
+    }
+
+    #[test]
+    fn available_precompiles_depend_on_hard_fork() {
+        assert_eq!(precompiles(&Config::frontier()).len(), 4);
+        assert_eq!(precompiles(&Config::istanbul()).len(), 9);
+        assert_eq!(precompiles(&Config::berlin()).len(), 9);
+    }
+
+    #[test]
+    fn hashes() {
+        let config = Config::istanbul();
+        let out = call(&config, 2, b"");
+        assert_eq!(
+            hex::encode(out.output),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(out.cost, 60);
+
+        let out = call(&config, 3, b"");
+        assert_eq!(
+            hex::encode(out.output),
+            "0000000000000000000000009c1185a5c5e9fc54612808977ee8f548b2258d31"
+        );
+        assert_eq!(out.cost, 600);
+
+        let out = call(&config, 4, &[1; 33]);
+        assert_eq!(out.output, vec![1; 33]);
+        assert_eq!(out.cost, 15 + 3 * 2);
+    }
+
+    #[test]
+    fn ecrecover_rejects_invalid_v() {
+        let out = call(&Config::istanbul(), 1, &[0; 128]);
+        assert!(out.output.is_empty());
+        assert_eq!(out.cost, 3000);
+    }
+
+    #[test]
+    fn modexp_gas_depends_on_hard_fork() {
+        // 3 ** 5 % 7
+        let mut input = Vec::new();
+        for len in [1u64, 1, 1] {
+            input.extend_from_slice(H256::from_low_u64_be(len).as_bytes());
+        }
+        input.extend_from_slice(&[3, 5, 7]);
+
+        let out = call(&Config::istanbul(), 5, &input);
+        assert_eq!(out.output, vec![5]);
+        assert_eq!(out.cost, 0);
+        let out = call(&Config::berlin(), 5, &input);
+        assert_eq!(out.output, vec![5]);
+        assert_eq!(out.cost, 200);
+    }
+
+    #[test]
+    fn bn_arithmetic() {
+        let generator = [H256::from_low_u64_be(1), H256::from_low_u64_be(2)];
+        let doubled = "030644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd3\
+                       15ed738c0e0a7c92e7845f96b2ae9c0a68a6a449e3538fc7ff3ebf7a5a18a2c4";
+
+        let input: Vec<u8> =
+            generator.iter().chain(generator.iter()).flat_map(|w| w.as_bytes().to_vec()).collect();
+        let out = call(&Config::istanbul(), 6, &input);
+        assert_eq!(hex::encode(out.output), doubled);
+        assert_eq!(out.cost, 150);
+
+        let mut input: Vec<u8> = generator.iter().flat_map(|w| w.as_bytes().to_vec()).collect();
+        input.extend_from_slice(H256::from_low_u64_be(2).as_bytes());
+        let out = call(&Config::istanbul(), 7, &input);
+        assert_eq!(hex::encode(out.output), doubled);
+        assert_eq!(out.cost, 6000);
+
+        // the empty product of pairings is one
+        let out = call(&Config::istanbul(), 8, &[]);
+        assert_eq!(out.output, H256::from_low_u64_be(1).as_bytes().to_vec());
+        assert_eq!(out.cost, 45000);
+    }
+
+    #[test]
+    fn blake2f_compresses() {
+        // the 5th test vector of EIP-152, the BLAKE2b hash of "abc"
+        let input = hex::decode(
+            "0000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f52\
+             0e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000\
+             000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
+             000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
+             000000000000000000000000000000000000000000000000000000000300000000000000000000000000\
+             000001",
+        )
+        .unwrap();
+        let out = call(&Config::istanbul(), 9, &input);
+        assert_eq!(
+            hex::encode(out.output),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d\
+             5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        assert_eq!(out.cost, 12);
+    }
+}
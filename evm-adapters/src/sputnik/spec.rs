@@ -0,0 +1,33 @@
+use once_cell::sync::Lazy;
+use sputnik::Config;
+
+static FRONTIER: Lazy<Config> = Lazy::new(Config::frontier);
+static ISTANBUL: Lazy<Config> = Lazy::new(Config::istanbul);
+static BERLIN: Lazy<Config> = Lazy::new(Config::berlin);
+static LONDON: Lazy<Config> = Lazy::new(Config::london);
+
+/// The hard forks whose rules the executor can follow
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecId {
+    Frontier,
+    Istanbul,
+    Berlin,
+    London,
+}
+
+impl SpecId {
+    /// The Sputnik configuration of the hard fork, which can be passed to the executor
+    /// constructors. It lives for the whole program, so the executor can borrow it.
+    ///
+    /// From Berlin on, accessing state costs more and the access lists of transactions are
+    /// applied (EIP-2929 and EIP-2930). London adds the `BASEFEE` opcode (EIP-3198) and lowers
+    /// the refunds (EIP-3529).
+    pub fn config(self) -> &'static Config {
+        match self {
+            SpecId::Frontier => &FRONTIER,
+            SpecId::Istanbul => &ISTANBUL,
+            SpecId::Berlin => &BERLIN,
+            SpecId::London => &LONDON,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Loading of the initial state of the EVM from a JSON file mapping addresses to their
+//! `balance`, `nonce`, `code` and `storage`, as supported by dapptools, and dumping of the
+//! state to the same format
+use super::MemoryState;
+
+use ethers::types::{Address, H256, U256};
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use sputnik::backend::MemoryAccount;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// An account as found in a state file. All values are hex-encoded, with or without a `0x`
+/// prefix, and the missing ones are zero (or empty).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateAccount {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    balance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    storage: BTreeMap<String, String>,
+}
+
+/// Parses a state file, to be passed to the `MemoryBackend` (which can in turn be wrapped in a
+/// `CheatcodeBackend`) the executor is constructed with. Storage keys and values are padded to
+/// 32 bytes. The errors name the account they were found in.
+pub fn load_state(json: &str) -> Result<MemoryState> {
+    let accounts: BTreeMap<String, serde_json::Value> =
+        serde_json::from_str(json).wrap_err("state must be an object of accounts by address")?;
+    accounts
+        .into_iter()
+        .map(|(address, account)| {
+            let parse = || -> Result<_> { Ok((parse_address(&address)?, parse_account(account)?)) };
+            parse().wrap_err_with(|| format!("invalid account {}", address))
+        })
+        .collect()
+}
+
+/// Reads and parses a state file, see [`load_state`]
+pub fn load_state_file(path: impl AsRef<Path>) -> Result<MemoryState> {
+    let path = path.as_ref();
+    let json = fs::read_to_string(path)
+        .wrap_err_with(|| format!("could not read state file {}", path.display()))?;
+    load_state(&json).wrap_err_with(|| format!("invalid state file {}", path.display()))
+}
+
+/// Formats the state in the format read by [`load_state`], e.g. to dump the state committed to
+/// a `MemoryBackend`. Loading the dump gives back the same state.
+pub fn dump_state(state: &MemoryState) -> Result<String> {
+    let accounts = state
+        .iter()
+        .map(|(address, account)| {
+            let storage = account
+                .storage
+                .iter()
+                .map(|(key, value)| (format!("{:?}", key), format!("{:?}", value)))
+                .collect();
+            let account = StateAccount {
+                balance: Some(format!("{:#x}", account.balance)),
+                nonce: Some(format!("{:#x}", account.nonce)),
+                code: Some(format!("0x{}", hex::encode(&account.code))),
+                storage,
+            };
+            (format!("{:?}", address), account)
+        })
+        .collect::<BTreeMap<_, _>>();
+    Ok(serde_json::to_string_pretty(&accounts)?)
+}
+
+fn parse_account(account: serde_json::Value) -> Result<MemoryAccount> {
+    let account: StateAccount = serde_json::from_value(account)?;
+    let storage = account
+        .storage
+        .iter()
+        .map(|(key, value)| {
+            let parsed_key =
+                parse_word(key).wrap_err_with(|| format!("invalid storage key {}", key))?;
+            let parsed_value = parse_word(value)
+                .wrap_err_with(|| format!("invalid value of storage key {}", key))?;
+            Ok((parsed_key, parsed_value))
+        })
+        .collect::<Result<_>>()?;
+    Ok(MemoryAccount {
+        balance: account.balance.as_deref().map(parse_uint).transpose()?.unwrap_or_default(),
+        nonce: account.nonce.as_deref().map(parse_uint).transpose()?.unwrap_or_default(),
+        code: account.code.as_deref().map(parse_bytes).transpose()?.unwrap_or_default(),
+        storage,
+    })
+}
+
+fn strip_hex_prefix(value: &str) -> &str {
+    value.strip_prefix("0x").unwrap_or(value)
+}
+
+fn parse_address(value: &str) -> Result<Address> {
+    let bytes = parse_bytes(value)?;
+    if bytes.len() != 20 {
+        eyre::bail!("addresses must be 20 bytes long")
+    }
+    Ok(Address::from_slice(&bytes))
+}
+
+fn parse_uint(value: &str) -> Result<U256> {
+    let digits = strip_hex_prefix(value);
+    if digits.len() > 64 {
+        eyre::bail!("{} does not fit in 32 bytes", value)
+    }
+    U256::from_str_radix(digits, 16).map_err(|_| eyre::eyre!("{} is not a hex number", value))
+}
+
+/// Parses a hex number as a 32 bytes word, padding it on the left
+fn parse_word(value: &str) -> Result<H256> {
+    let mut word = H256::zero();
+    parse_uint(value)?.to_big_endian(word.as_bytes_mut());
+    Ok(word)
+}
+
+fn parse_bytes(value: &str) -> Result<Vec<u8>> {
+    hex::decode(strip_hex_prefix(value)).wrap_err_with(|| format!("{} is not hex", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_and_dumps_state() {
+        let json = r#"{
+            "0x0000000000000000000000000000000000000001": {
+                "balance": "0x64",
+                "nonce": "2",
+                "code": "6000",
+                "storage": { "0x1": "ff", "2": "0x0000000000000000000000000000000000000000000000000000000000000003" }
+            },
+            "0000000000000000000000000000000000000002": {}
+        }"#;
+        let state = load_state(json).unwrap();
+
+        let account = &state[&Address::from_low_u64_be(1)];
+        assert_eq!(account.balance, 100.into());
+        assert_eq!(account.nonce, 2.into());
+        assert_eq!(account.code, vec![0x60, 0x00]);
+        assert_eq!(account.storage[&H256::from_low_u64_be(1)], H256::from_low_u64_be(0xff));
+        assert_eq!(account.storage[&H256::from_low_u64_be(2)], H256::from_low_u64_be(3));
+        assert_eq!(state[&Address::from_low_u64_be(2)], MemoryAccount::default());
+
+        // the dump is lossless
+        let dump = dump_state(&state).unwrap();
+        assert_eq!(load_state(&dump).unwrap(), state);
+        assert_eq!(dump_state(&load_state(&dump).unwrap()).unwrap(), dump);
+    }
+
+    #[test]
+    fn errors_name_the_account() {
+        let err = |json: &str| format!("{:#}", load_state(json).unwrap_err());
+
+        let address = "0x0000000000000000000000000000000000000001";
+        let invalid = err(&format!(r#"{{ "{}": {{ "balance": "0xzz" }} }}"#, address));
+        assert!(invalid.contains(address), "{}", invalid);
+        assert!(invalid.contains("0xzz is not a hex number"), "{}", invalid);
+
+        let invalid = err(&format!(r#"{{ "{}": {{ "storage": {{ "0x1": 1 }} }} }}"#, address));
+        assert!(invalid.contains(address), "{}", invalid);
+
+        let invalid = err(&format!(r#"{{ "{}": {{ "balanse": "0x1" }} }}"#, address));
+        assert!(invalid.contains(address) && invalid.contains("balanse"), "{}", invalid);
+
+        let invalid = err(r#"{ "0x01": {} }"#);
+        assert!(invalid.contains("invalid account 0x01"), "{}", invalid);
+    }
+}
@@ -0,0 +1,126 @@
+//! The changes made to the state by a call, so that they can be asserted on or printed for
+//! failing tests
+use ethers::types::{Address, Bytes, H256, U256};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// A value before and after a call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// The changes made to an account
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub balance: Option<Change<U256>>,
+    pub nonce: Option<Change<U256>>,
+    /// The code, which changes when a contract is deployed at the address
+    pub code: Option<Change<Bytes>>,
+    /// Whether the account self-destructed
+    pub destroyed: bool,
+    /// The storage slots whose value changed
+    pub storage: BTreeMap<H256, Change<H256>>,
+}
+
+impl AccountDiff {
+    pub fn is_empty(&self) -> bool {
+        self.balance.is_none() &&
+            self.nonce.is_none() &&
+            self.code.is_none() &&
+            !self.destroyed &&
+            self.storage.is_empty()
+    }
+}
+
+/// The accounts changed by a call, by address. Accounts which were only read are left out, as
+/// are the changes made by the frames which reverted.
+pub type StateDiff = BTreeMap<Address, AccountDiff>;
+
+/// Formats the changes as lines, one per changed value, naming the accounts by their label if
+/// they have one
+pub fn format_state_diff(diff: &StateDiff, labels: &BTreeMap<Address, String>) -> String {
+    let mut out = String::new();
+    for (address, account) in diff {
+        let name = labels.get(address).cloned().unwrap_or_else(|| format!("{:?}", address));
+        if account.destroyed {
+            let _ = writeln!(out, "{} was destroyed", name);
+        } else if let Some(code) = &account.code {
+            if code.from.as_ref().is_empty() {
+                let _ = writeln!(out, "{} was deployed ({} bytes)", name, code.to.as_ref().len());
+            } else {
+                let _ = writeln!(
+                    out,
+                    "code of {} changed from {} to {} bytes",
+                    name,
+                    code.from.as_ref().len(),
+                    code.to.as_ref().len()
+                );
+            }
+        }
+        if let Some(balance) = &account.balance {
+            let _ = writeln!(
+                out,
+                "balance of {} changed from {} to {}",
+                name, balance.from, balance.to
+            );
+        }
+        if let Some(nonce) = &account.nonce {
+            let _ = writeln!(out, "nonce of {} changed from {} to {}", name, nonce.from, nonce.to);
+        }
+        for (slot, value) in &account.storage {
+            let _ = writeln!(
+                out,
+                "storage of {} slot {:#x} changed from {:#x} to {:#x}",
+                name,
+                U256::from_big_endian(slot.as_bytes()),
+                U256::from_big_endian(value.from.as_bytes()),
+                U256::from_big_endian(value.to.as_bytes())
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_changes() {
+        let token = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+        let mut diff = StateDiff::new();
+        diff.insert(
+            token,
+            AccountDiff {
+                code: Some(Change { from: Bytes::default(), to: vec![0x60, 0x00].into() }),
+                storage: vec![(
+                    H256::from_low_u64_be(2),
+                    Change { from: H256::zero(), to: H256::from_low_u64_be(100) },
+                )]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            },
+        );
+        diff.insert(
+            other,
+            AccountDiff {
+                nonce: Some(Change { from: 1.into(), to: 2.into() }),
+                ..Default::default()
+            },
+        );
+        let labels = vec![(token, "Token".to_owned())].into_iter().collect();
+
+        assert_eq!(
+            format_state_diff(&diff, &labels),
+            format!(
+                "Token was deployed (2 bytes)\n\
+                 storage of Token slot 0x2 changed from 0x0 to 0x64\n\
+                 nonce of {:?} changed from 1 to 2\n",
+                other
+            )
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract Store {
+    uint256 public value;
+
+    function set(uint256 _value) public {
+        value = _value;
+    }
+
+    function setAndRevert(uint256 _value) public {
+        value = _value;
+        revert("reverted");
+    }
+}
+
+// Makes every kind of call to a `Store`. It has the same storage layout, so that the calls
+// executed in its context write to its own `value`.
+contract CallKinds {
+    uint256 public value;
+    Store public store;
+
+    function setUp() public {
+        store = new Store();
+    }
+
+    function callSet(uint256 _value) public {
+        store.set(_value);
+    }
+
+    function staticGet() public view returns (uint256) {
+        return store.value();
+    }
+
+    function staticSet() public view returns (bool success) {
+        (success, ) = address(store).staticcall(abi.encodeWithSelector(Store.set.selector, 1));
+    }
+
+    function delegateSet(uint256 _value) public {
+        (bool success, ) =
+            address(store).delegatecall(abi.encodeWithSelector(Store.set.selector, _value));
+        require(success, "delegatecall failed");
+    }
+
+    function callcodeSet(uint256 _value) public {
+        address target = address(store);
+        bytes memory data = abi.encodeWithSelector(Store.set.selector, _value);
+        bool success;
+        assembly {
+            success := callcode(gas(), target, 0, add(data, 32), mload(data), 0, 0)
+        }
+        require(success, "callcode failed");
+    }
+
+    function revertingCall(uint256 _value) public returns (bool success) {
+        (success, ) =
+            address(store).call(abi.encodeWithSelector(Store.setAndRevert.selector, _value));
+    }
+}
@@ -0,0 +1,870 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+interface Hevm {
+    function warp(uint256) external;
+    function roll(uint256) external;
+    function store(address,bytes32,bytes32) external;
+    function load(address,bytes32) external returns (bytes32);
+    function prank(address) external;
+    function startPrank(address) external;
+    function stopPrank() external;
+    function deal(address,uint256) external;
+    function etch(address,bytes calldata) external;
+    function sign(uint256,bytes32) external returns (uint8,bytes32,bytes32);
+    function addr(uint256) external returns (address);
+    function ffi(string[] calldata) external returns (bytes memory);
+    function expectRevert() external;
+    function expectRevert(bytes calldata) external;
+    function expectRevert(bytes4) external;
+    function expectEmit(bool,bool,bool,bool) external;
+    function expectCall(address,bytes calldata) external;
+    function expectCall(address,uint256,bytes calldata) external;
+    function mockCall(address,bytes calldata,bytes calldata) external;
+    function clearMockedCalls() external;
+    function record() external;
+    function accesses(address) external returns (bytes32[] memory reads, bytes32[] memory writes);
+    function snapshot() external returns (uint256);
+    function revertTo(uint256) external returns (bool);
+    function fee(uint256) external;
+    function difficulty(uint256) external;
+    function coinbase(address) external;
+    function chainId(uint256) external;
+    function getNonce(address) external returns (uint64);
+    function setNonce(address,uint64) external;
+    function label(address,string calldata) external;
+    function assume(bool) external;
+    function readFile(string calldata) external returns (string memory);
+    function writeFile(string calldata, string calldata) external;
+    function envString(string calldata) external returns (string memory);
+    function envUint(string calldata) external returns (uint256);
+    function envAddress(string calldata) external returns (address);
+    function envBool(string calldata) external returns (bool);
+    function setBlockhash(uint256,bytes32) external;
+    function txGasPrice(uint256) external;
+    function transact(address,address,uint256,bytes calldata) external returns (bool,bytes memory);
+    function clearPendingExpectations() external;
+}
+
+// an excerpt of hardhat's console.sol
+library console {
+    address constant CONSOLE_ADDRESS = address(0x000000000000000000636F6e736F6c652e6c6f67);
+
+    function _sendLogPayload(bytes memory payload) private view {
+        address consoleAddress = CONSOLE_ADDRESS;
+        assembly {
+            let r := staticcall(gas(), consoleAddress, add(payload, 32), mload(payload), 0, 0)
+        }
+    }
+
+    function log(string memory p0) internal view {
+        _sendLogPayload(abi.encodeWithSignature("log(string)", p0));
+    }
+
+    function log(string memory p0, uint p1) internal view {
+        _sendLogPayload(abi.encodeWithSignature("log(string,uint)", p0, p1));
+    }
+
+    function log(address p0, bool p1) internal view {
+        _sendLogPayload(abi.encodeWithSignature("log(address,bool)", p0, p1));
+    }
+}
+
+// declares cheatcodes as `view`, so that they are called via `STATICCALL`
+interface HevmView {
+    function load(address,bytes32) external view returns (bytes32);
+    function warp(uint256) external view;
+}
+
+interface HasGreeting {
+    function greeting() external view returns (string memory);
+}
+
+contract SenderRecorder {
+    function sender() public view returns (address) {
+        return msg.sender;
+    }
+
+    function senderAndNested(SenderRecorder other) public view returns (address, address) {
+        return (msg.sender, other.sender());
+    }
+}
+
+contract ReturnsConstant {
+    function value() public pure returns (uint256) {
+        return 42;
+    }
+}
+
+contract Reverter {
+    function revertWithReason(string memory reason) public pure {
+        revert(reason);
+    }
+
+    function revertWithData(bytes memory data) public pure {
+        assembly { revert(add(data, 32), mload(data)) }
+    }
+
+    function succeed() public pure returns (uint256) {
+        return 42;
+    }
+
+    function catchRevert(Reverter other) public pure returns (bool) {
+        try other.revertWithReason("nested") {
+            return false;
+        } catch {
+            return true;
+        }
+    }
+}
+
+contract Emitter {
+    event Transfer(address indexed from, address indexed to, uint256 amount);
+    event Other(uint256 indexed id);
+
+    function emitTransfer(address from, address to, uint256 amount) public {
+        emit Transfer(from, to, amount);
+    }
+
+    function emitOtherAndTransfer(address from, address to, uint256 amount) public {
+        emit Other(1);
+        emit Transfer(from, to, amount);
+    }
+
+    function emitAndRevert() public {
+        emit Other(2);
+        revert("reverted");
+    }
+
+    function emitNested(Emitter other, address from, address to, uint256 amount) public {
+        other.emitTransfer(from, to, amount);
+    }
+}
+
+contract Receiver {
+    function pay() public payable {}
+}
+
+contract ValueRecorder {
+    address public sender;
+    uint256 public value;
+    uint256 public balance;
+
+    function record() public payable returns (uint256) {
+        sender = msg.sender;
+        value = msg.value;
+        balance = address(this).balance;
+        return 42;
+    }
+
+    function fail() public payable {
+        revert("failed");
+    }
+}
+
+contract Mockable {
+    function value(uint256 x) public pure returns (uint256) {
+        return x;
+    }
+
+    function nestedValue(Mockable other, uint256 x) public pure returns (uint256) {
+        return other.value(x);
+    }
+}
+
+contract StorageAccessor {
+    uint256 a;
+    uint256 b;
+
+    function read() public view returns (uint256) {
+        return a;
+    }
+
+    function write(uint256 x) public {
+        b = x;
+    }
+}
+
+contract StorageSummer {
+    uint256[16] values;
+
+    function sum() public view returns (uint256 total) {
+        for (uint256 i = 0; i < values.length; i++) {
+            total += values[i];
+        }
+    }
+}
+
+address constant HEVM_ADDRESS = address(bytes20(uint160(uint256(keccak256('hevm cheat code')))));
+
+contract Deployed {
+    address public deployer;
+
+    constructor() payable {
+        deployer = msg.sender;
+    }
+}
+
+contract WarpingConstructor {
+    uint256 public timestamp;
+
+    constructor(uint256 time) {
+        Hevm(HEVM_ADDRESS).warp(time);
+        timestamp = block.timestamp;
+    }
+}
+
+contract RevertingConstructor {
+    constructor() {
+        revert("constructor reverted");
+    }
+}
+
+contract CheatCodes {
+    Hevm constant hevm = Hevm(HEVM_ADDRESS);
+
+    event Transfer(address indexed from, address indexed to, uint256 amount);
+    event Other(uint256 indexed id);
+
+    function warp(uint256 time) public {
+        hevm.warp(time);
+        require(block.timestamp == time, "timestamp was not warped");
+    }
+
+    // the override must be visible to subsequent calls as well
+    function timestamp() public view returns (uint256) {
+        return block.timestamp;
+    }
+
+    function testWarp() public {
+        hevm.warp(1641070800);
+        require(block.timestamp == 1641070800, "timestamp was not warped");
+    }
+
+    function testRoll() public {
+        hevm.roll(10);
+        require(block.number == 10, "block number was not rolled");
+    }
+
+    function testRollAndWarp() public {
+        hevm.roll(10);
+        hevm.warp(1641070800);
+        require(block.number == 10, "block number was not rolled");
+        require(block.timestamp == 1641070800, "timestamp was not warped");
+    }
+
+    function store(address who, bytes32 slot, bytes32 value) public {
+        hevm.store(who, slot, value);
+    }
+
+    function storeGreeting(address greeter) public {
+        // "gm", as a short string stored in slot 0 (data left-aligned, length * 2 last)
+        hevm.store(greeter, bytes32(0), 0x676d000000000000000000000000000000000000000000000000000000000004);
+        require(keccak256(bytes(HasGreeting(greeter).greeting())) == keccak256("gm"), "slot was not overwritten");
+    }
+
+    function load(address who, bytes32 slot) public returns (bytes32) {
+        return hevm.load(who, slot);
+    }
+
+    function storeAndLoad(address who, bytes32 slot, bytes32 value) public returns (bytes32) {
+        hevm.store(who, slot, value);
+        return hevm.load(who, slot);
+    }
+
+    function testPrank() public {
+        SenderRecorder recorder = new SenderRecorder();
+        hevm.prank(address(1337));
+        require(recorder.sender() == address(1337), "sender was not pranked");
+        // only the next call gets pranked
+        require(recorder.sender() == address(this), "prank was not cleared");
+    }
+
+    function testPrankNested() public {
+        SenderRecorder recorder = new SenderRecorder();
+        SenderRecorder other = new SenderRecorder();
+        hevm.prank(address(1337));
+        (address sender, address nestedSender) = recorder.senderAndNested(other);
+        require(sender == address(1337), "sender was not pranked");
+        require(nestedSender == address(recorder), "nested call was pranked");
+    }
+
+    function testFailPrankTwice() public {
+        hevm.prank(address(1337));
+        hevm.prank(address(1338));
+    }
+
+    function testStartPrank() public {
+        SenderRecorder recorder = new SenderRecorder();
+        SenderRecorder other = new SenderRecorder();
+        hevm.startPrank(address(1337));
+        require(recorder.sender() == address(1337), "sender was not pranked");
+        require(recorder.sender() == address(1337), "prank did not persist");
+        (address sender, address nestedSender) = recorder.senderAndNested(other);
+        require(sender == address(1337), "sender was not pranked");
+        require(nestedSender == address(recorder), "nested call was pranked");
+        hevm.stopPrank();
+        require(recorder.sender() == address(this), "prank was not stopped");
+    }
+
+    function startPrank(address who) public {
+        hevm.startPrank(who);
+    }
+
+    function testFailStopPrank() public {
+        hevm.stopPrank();
+    }
+
+    function testDeal() public {
+        address who = address(1337);
+        hevm.deal(who, 1 ether);
+        require(who.balance == 1 ether, "balance was not raised");
+        hevm.deal(who, 0.5 ether);
+        require(who.balance == 0.5 ether, "balance was not lowered");
+        hevm.deal(who, 0);
+        require(who.balance == 0, "balance was not zeroed");
+    }
+
+    function testDealSelfAndSend() public {
+        hevm.deal(address(this), 1 ether);
+        require(address(this).balance == 1 ether, "balance was not set");
+        payable(address(1338)).transfer(0.25 ether);
+        require(address(1338).balance == 0.25 ether, "value was not received");
+        require(address(this).balance == 0.75 ether, "value was not sent");
+    }
+
+    function codeSize(address who) internal view returns (uint256 size) {
+        assembly { size := extcodesize(who) }
+    }
+
+    function codeHash(address who) internal view returns (bytes32 hash) {
+        assembly { hash := extcodehash(who) }
+    }
+
+    function cheatcodeAddressCode() public returns (uint256, bytes32) {
+        // interface-style calls check that the target has code
+        hevm.warp(1);
+        return (codeSize(HEVM_ADDRESS), codeHash(HEVM_ADDRESS));
+    }
+
+    function testEtch() public {
+        bytes memory code = type(ReturnsConstant).runtimeCode;
+
+        // a fresh address
+        address fresh = address(0x4000);
+        hevm.etch(fresh, code);
+        require(codeSize(fresh) == code.length, "code size was not updated");
+        require(codeHash(fresh) == keccak256(code), "code hash was not updated");
+        require(ReturnsConstant(fresh).value() == 42, "etched code was not called");
+
+        // overwriting existing code
+        address existing = address(new SenderRecorder());
+        hevm.etch(existing, code);
+        require(ReturnsConstant(existing).value() == 42, "code was not overwritten");
+
+        // empty code turns the account into an EOA
+        hevm.etch(existing, "");
+        require(codeSize(existing) == 0, "code was not removed");
+        (bool success, bytes memory retdata) = existing.call(abi.encodeWithSignature("value()"));
+        require(success && retdata.length == 0, "account is not an EOA");
+    }
+
+    function sign(uint256 pk, bytes32 digest) public returns (uint8, bytes32, bytes32) {
+        return hevm.sign(pk, digest);
+    }
+
+    function addr(uint256 pk) public returns (address) {
+        return hevm.addr(pk);
+    }
+
+    function testEcrecover() public {
+        uint256 pk = 1337;
+        bytes32 digest = keccak256("gm");
+        (uint8 v, bytes32 r, bytes32 s) = hevm.sign(pk, digest);
+        require(ecrecover(digest, v, r, s) == hevm.addr(pk), "wrong signer recovered");
+    }
+
+    function ffi(string[] memory args) public returns (bytes memory) {
+        return hevm.ffi(args);
+    }
+
+    function readFile(string memory path) public returns (string memory) {
+        return hevm.readFile(path);
+    }
+
+    function writeFile(string memory path, string memory data) public {
+        hevm.writeFile(path, data);
+    }
+
+    function testEnv() public {
+        require(
+            keccak256(bytes(hevm.envString("DAPPTOOLS_TEST_ENV_STRING"))) == keccak256("gm"),
+            "envString failed"
+        );
+        require(hevm.envUint("DAPPTOOLS_TEST_ENV_UINT") == 1337, "envUint failed");
+        require(hevm.envUint("DAPPTOOLS_TEST_ENV_HEX_UINT") == 1337, "hex envUint failed");
+        require(
+            hevm.envAddress("DAPPTOOLS_TEST_ENV_ADDRESS") == address(0x1337),
+            "envAddress failed"
+        );
+        require(hevm.envBool("DAPPTOOLS_TEST_ENV_BOOL"), "envBool failed");
+    }
+
+    function envUint(string memory name) public returns (uint256) {
+        return hevm.envUint(name);
+    }
+
+    function testExpectRevertString() public {
+        Reverter reverter = new Reverter();
+        hevm.expectRevert(bytes("nope"));
+        reverter.revertWithReason("nope");
+        // only the next call is expected to revert
+        require(reverter.succeed() == 42, "call after the expected revert failed");
+    }
+
+    function testExpectRevertData() public {
+        Reverter reverter = new Reverter();
+        hevm.expectRevert(bytes(hex"deadbeef"));
+        reverter.revertWithData(hex"deadbeef");
+    }
+
+    function testExpectRevertSelector() public {
+        Reverter reverter = new Reverter();
+        bytes4 selector = bytes4(keccak256("CustomError(uint256)"));
+        hevm.expectRevert(selector);
+        reverter.revertWithData(abi.encodeWithSelector(selector, uint256(1)));
+    }
+
+    function testExpectRevertAny() public {
+        Reverter reverter = new Reverter();
+        hevm.expectRevert();
+        reverter.revertWithReason("anything");
+        hevm.expectRevert();
+        reverter.revertWithData(hex"");
+    }
+
+    function testFailExpectRevertAnySucceeds() public {
+        Reverter reverter = new Reverter();
+        hevm.expectRevert();
+        reverter.succeed();
+    }
+
+    function testFailExpectRevertWrongReason() public {
+        Reverter reverter = new Reverter();
+        hevm.expectRevert(bytes("nope"));
+        reverter.revertWithReason("other");
+    }
+
+    function testFailExpectRevertSucceeds() public {
+        Reverter reverter = new Reverter();
+        hevm.expectRevert(bytes("nope"));
+        reverter.succeed();
+    }
+
+    // the revert of a nested call which gets caught does not satisfy the expectation
+    function testFailExpectRevertNested() public {
+        Reverter reverter = new Reverter();
+        Reverter other = new Reverter();
+        hevm.expectRevert(bytes("nested"));
+        reverter.catchRevert(other);
+    }
+
+    function testFailExpectRevertNotCalled() public {
+        hevm.expectRevert(bytes("nope"));
+    }
+
+    function testExpectEmit() public {
+        Emitter emitter = new Emitter();
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+        emitter.emitTransfer(address(1), address(2), 100);
+    }
+
+    function testExpectEmitIgnoresData() public {
+        Emitter emitter = new Emitter();
+        hevm.expectEmit(true, true, true, false);
+        emit Transfer(address(1), address(2), 100);
+        emitter.emitTransfer(address(1), address(2), 200);
+    }
+
+    function testExpectEmitMultiple() public {
+        Emitter emitter = new Emitter();
+        hevm.expectEmit(true, false, false, false);
+        emit Other(1);
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+        emitter.emitOtherAndTransfer(address(1), address(2), 100);
+    }
+
+    function testFailExpectEmitWrongData() public {
+        Emitter emitter = new Emitter();
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+        emitter.emitTransfer(address(1), address(2), 200);
+    }
+
+    function testFailExpectEmitOutOfOrder() public {
+        Emitter emitter = new Emitter();
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+        hevm.expectEmit(true, false, false, false);
+        emit Other(1);
+        emitter.emitOtherAndTransfer(address(1), address(2), 100);
+    }
+
+    // the log has to be emitted by the callee itself
+    function testFailExpectEmitNested() public {
+        Emitter emitter = new Emitter();
+        Emitter other = new Emitter();
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+        emitter.emitNested(other, address(1), address(2), 100);
+    }
+
+    function testFailExpectEmitNotCalled() public {
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+    }
+
+    function testExpectCall() public {
+        Emitter emitter = new Emitter();
+        hevm.expectCall(address(emitter), abi.encodeWithSelector(emitter.emitTransfer.selector));
+        emitter.emitTransfer(address(1), address(2), 100);
+    }
+
+    function testExpectCallNested() public {
+        Emitter emitter = new Emitter();
+        Emitter other = new Emitter();
+        hevm.expectCall(
+            address(other),
+            abi.encodeWithSelector(other.emitTransfer.selector, address(1), address(2), 100)
+        );
+        emitter.emitNested(other, address(1), address(2), 100);
+    }
+
+    function testExpectCallWithValue() public {
+        Receiver receiver = new Receiver();
+        hevm.deal(address(this), 1 ether);
+        hevm.expectCall(address(receiver), 100, abi.encodeWithSelector(receiver.pay.selector));
+        receiver.pay{value: 100}();
+    }
+
+    function testFailExpectCallNotMade() public {
+        Emitter emitter = new Emitter();
+        hevm.expectCall(address(emitter), abi.encodeWithSelector(emitter.emitTransfer.selector));
+        emitter.emitOtherAndTransfer(address(1), address(2), 100);
+    }
+
+    function testFailExpectCallWrongValue() public {
+        Receiver receiver = new Receiver();
+        hevm.deal(address(this), 1 ether);
+        hevm.expectCall(address(receiver), 100, abi.encodeWithSelector(receiver.pay.selector));
+        receiver.pay{value: 50}();
+    }
+
+    function testMockCall() public {
+        Mockable mockable = new Mockable();
+        hevm.mockCall(
+            address(mockable),
+            abi.encodeWithSelector(mockable.value.selector),
+            abi.encode(uint256(7))
+        );
+        require(mockable.value(1) == 7, "call was not mocked");
+        // mocks persist until they are cleared
+        require(mockable.value(2) == 7, "mock did not persist");
+    }
+
+    function testMockCallLongestPrefix() public {
+        Mockable mockable = new Mockable();
+        hevm.mockCall(
+            address(mockable),
+            abi.encodeWithSelector(mockable.value.selector),
+            abi.encode(uint256(1))
+        );
+        hevm.mockCall(
+            address(mockable),
+            abi.encodeWithSelector(mockable.value.selector, uint256(2)),
+            abi.encode(uint256(2))
+        );
+        require(mockable.value(5) == 1, "selector mock was not used");
+        require(mockable.value(2) == 2, "longest prefix mock was not used");
+    }
+
+    function testMockCallNested() public {
+        Mockable mockable = new Mockable();
+        Mockable other = new Mockable();
+        hevm.mockCall(
+            address(other),
+            abi.encodeWithSelector(other.value.selector),
+            abi.encode(uint256(7))
+        );
+        require(mockable.nestedValue(other, 1) == 7, "nested call was not mocked");
+    }
+
+    function testClearMockedCalls() public {
+        Mockable mockable = new Mockable();
+        hevm.mockCall(
+            address(mockable),
+            abi.encodeWithSelector(mockable.value.selector),
+            abi.encode(uint256(7))
+        );
+        hevm.clearMockedCalls();
+        require(mockable.value(3) == 3, "mock was not cleared");
+    }
+
+    function testFailMockCheatcode() public {
+        hevm.mockCall(HEVM_ADDRESS, "", "");
+    }
+
+    function testRecordAccesses() public {
+        StorageAccessor accessor = new StorageAccessor();
+        // accesses before `record` are not tracked
+        accessor.write(2);
+
+        hevm.record();
+        accessor.read();
+        accessor.read();
+        accessor.write(1);
+
+        (bytes32[] memory reads, bytes32[] memory writes) = hevm.accesses(address(accessor));
+        // duplicates are preserved
+        require(reads.length == 2, "wrong number of reads");
+        require(reads[0] == bytes32(0) && reads[1] == bytes32(0), "wrong slots read");
+        require(writes.length == 1, "wrong number of writes");
+        require(writes[0] == bytes32(uint256(1)), "wrong slot written");
+
+        (reads, writes) = hevm.accesses(address(0x4000));
+        require(reads.length == 0 && writes.length == 0, "untouched address has accesses");
+    }
+
+    function testSnapshot() public {
+        address who = address(0x5000);
+        uint256 id = hevm.snapshot();
+
+        hevm.store(who, bytes32(0), bytes32(uint256(1)));
+        hevm.deal(who, 1 ether);
+        hevm.etch(who, type(ReturnsConstant).runtimeCode);
+        new SenderRecorder();
+        emit Other(1);
+
+        require(hevm.revertTo(id), "snapshot was not found");
+        require(hevm.load(who, bytes32(0)) == bytes32(0), "storage was not reverted");
+        require(who.balance == 0, "balance was not reverted");
+        require(codeSize(who) == 0, "code was not reverted");
+
+        // snapshots can be reverted to more than once
+        hevm.store(who, bytes32(0), bytes32(uint256(2)));
+        require(hevm.revertTo(id), "snapshot was not found");
+        require(hevm.load(who, bytes32(0)) == bytes32(0), "storage was not reverted");
+
+        require(!hevm.revertTo(1337), "reverted to a non-existent snapshot");
+    }
+
+    // `block.basefee` is not available with this compiler version and hard fork, so the
+    // override is inspected from the Rust side
+    function fee(uint256 baseFee) public {
+        hevm.fee(baseFee);
+    }
+
+    function testDifficulty() public {
+        hevm.difficulty(1337);
+        require(block.difficulty == 1337, "difficulty was not set");
+    }
+
+    function testCoinbase() public {
+        hevm.coinbase(address(0x6000));
+        require(block.coinbase == address(0x6000), "coinbase was not set");
+    }
+
+    function domainSeparator() internal view returns (bytes32) {
+        uint256 chainId;
+        assembly { chainId := chainid() }
+        return keccak256(abi.encode(
+            keccak256("EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"),
+            keccak256("CheatCodes"),
+            keccak256("1"),
+            chainId,
+            address(this)
+        ));
+    }
+
+    function testChainId() public returns (bytes32) {
+        bytes32 before = domainSeparator();
+        hevm.chainId(10);
+        uint256 chainId;
+        assembly { chainId := chainid() }
+        require(chainId == 10, "chain id was not set");
+        require(domainSeparator() != before, "domain separator did not change");
+        return domainSeparator();
+    }
+
+    // the address of a contract deployed via `CREATE`, for nonces up to 0xffff
+    function computeCreateAddress(address deployer, uint256 nonce) internal pure returns (address) {
+        bytes memory data;
+        if (nonce == 0) {
+            data = abi.encodePacked(bytes1(0xd6), bytes1(0x94), deployer, bytes1(0x80));
+        } else if (nonce <= 0x7f) {
+            data = abi.encodePacked(bytes1(0xd6), bytes1(0x94), deployer, uint8(nonce));
+        } else if (nonce <= 0xff) {
+            data = abi.encodePacked(bytes1(0xd7), bytes1(0x94), deployer, bytes1(0x81), uint8(nonce));
+        } else {
+            require(nonce <= 0xffff, "nonce too large");
+            data = abi.encodePacked(bytes1(0xd8), bytes1(0x94), deployer, bytes1(0x82), uint16(nonce));
+        }
+        return address(uint160(uint256(keccak256(data))));
+    }
+
+    function testSetNonce() public {
+        hevm.setNonce(address(this), 300);
+        require(hevm.getNonce(address(this)) == 300, "nonce was not set");
+        address deployed = address(new SenderRecorder());
+        require(deployed == computeCreateAddress(address(this), 300), "wrong deployment address");
+        require(hevm.getNonce(address(this)) == 301, "nonce was not increased by the deployment");
+    }
+
+    function testFailSetNonceLower() public {
+        hevm.setNonce(address(0x7000), 10);
+        hevm.setNonce(address(0x7000), 5);
+    }
+
+    function testFailExpectCallLabeled() public {
+        Emitter emitter = new Emitter();
+        hevm.label(address(emitter), "alice");
+        hevm.label(address(emitter), "emitter");
+        hevm.expectCall(address(emitter), abi.encodeWithSelector(emitter.emitTransfer.selector));
+    }
+
+    function testAssume(uint256 x) public {
+        hevm.assume(x > 10);
+        require(x > 10, "assumption was not enforced");
+    }
+
+    // not a test, since every input is rejected
+    function assumeNever(uint256 x) public {
+        hevm.assume(x != x);
+    }
+
+    function testSetBlockhash() public {
+        hevm.setBlockhash(5, bytes32(uint256(1337)));
+        require(blockhash(5) == bytes32(uint256(1337)), "blockhash was not set");
+
+        // blocks outside of the accessible window can be overriden too
+        hevm.setBlockhash(1000, bytes32(uint256(1)));
+        require(blockhash(1000) == bytes32(uint256(1)), "future blockhash was not set");
+        hevm.setBlockhash(1000, bytes32(0));
+        require(blockhash(1000) == bytes32(0), "blockhash was not cleared");
+    }
+
+    function testTxGasPrice() public {
+        require(tx.gasprice == 0, "unexpected initial gas price");
+        hevm.fee(7);
+        hevm.txGasPrice(42);
+        require(tx.gasprice == 42, "gas price was not set");
+    }
+
+    function testTransact() public {
+        ValueRecorder recorder = new ValueRecorder();
+        uint256 balance = address(this).balance;
+        (bool success, bytes memory retdata) = hevm.transact(
+            address(0x5000),
+            address(recorder),
+            1 ether,
+            abi.encodeWithSelector(ValueRecorder.record.selector)
+        );
+        require(success, "transact failed");
+        require(abi.decode(retdata, (uint256)) == 42, "wrong return data");
+        require(recorder.sender() == address(0x5000), "wrong sender");
+        require(recorder.value() == 1 ether, "wrong value");
+        require(recorder.balance() == 1 ether, "wrong balance");
+        require(address(this).balance == balance, "the test contract paid");
+
+        (success, retdata) = hevm.transact(
+            address(0x5000),
+            address(recorder),
+            0,
+            abi.encodeWithSelector(ValueRecorder.fail.selector)
+        );
+        require(!success, "failing transact succeeded");
+    }
+
+    function testClearPendingExpectations() public {
+        hevm.expectRevert(bytes("nope"));
+        hevm.expectCall(address(0x1234), hex"");
+        hevm.expectEmit(true, true, true, true);
+        emit Transfer(address(1), address(2), 100);
+        hevm.clearPendingExpectations();
+    }
+
+    function testFailPendingExpectations() public {
+        hevm.expectRevert(bytes("nope"));
+        hevm.expectCall(address(0x1234), hex"");
+    }
+
+    function loadView(address who, bytes32 slot) public view returns (bytes32) {
+        return HevmView(HEVM_ADDRESS).load(who, slot);
+    }
+
+    function warpView() public view {
+        HevmView(HEVM_ADDRESS).warp(1);
+    }
+
+    function testConsoleLog() public view {
+        console.log("gm");
+        console.log("balance", 1337);
+        console.log(address(0x1337), true);
+        // unknown overloads are ignored
+        (bool success, ) = address(0x000000000000000000636F6e736F6c652e6c6f67).staticcall(
+            abi.encodeWithSignature("log(bytes32,bytes32)", bytes32(0), bytes32(0))
+        );
+        require(success, "unknown console.log overload failed");
+    }
+
+    function testEmitLogs() public {
+        Emitter emitter = new Emitter();
+        emit Other(1);
+        emitter.emitTransfer(address(1), address(2), 100);
+        try emitter.emitAndRevert() {
+            revert("emitAndRevert did not revert");
+        } catch {}
+    }
+
+    function testPrankCreate() public {
+        uint256 nonce = hevm.getNonce(address(1337));
+        uint256 ownNonce = hevm.getNonce(address(this));
+        hevm.prank(address(1337));
+        Deployed deployed = new Deployed();
+        require(deployed.deployer() == address(1337), "deployer was not pranked");
+        require(
+            address(deployed) == computeCreateAddress(address(1337), nonce),
+            "address was not derived from the pranked deployer's nonce"
+        );
+        require(hevm.getNonce(address(1337)) == nonce + 1, "pranked nonce was not increased");
+        require(hevm.getNonce(address(this)) == ownNonce, "own nonce was increased");
+        // only the next deployment gets pranked
+        require(new Deployed().deployer() == address(this), "prank was not cleared");
+    }
+
+    function testPrankCreate2() public {
+        bytes32 salt = bytes32(uint256(1));
+        hevm.prank(address(1337));
+        Deployed deployed = new Deployed{salt: salt}();
+        require(deployed.deployer() == address(1337), "deployer was not pranked");
+        bytes32 hash = keccak256(
+            abi.encodePacked(bytes1(0xff), address(1337), salt, keccak256(type(Deployed).creationCode))
+        );
+        require(
+            address(deployed) == address(uint160(uint256(hash))),
+            "address was not derived from the pranked deployer"
+        );
+    }
+
+    function testExpectRevertCreate() public {
+        hevm.expectRevert(bytes("constructor reverted"));
+        new RevertingConstructor();
+    }
+
+    function testFailExpectRevertCreateSucceeds() public {
+        hevm.expectRevert(bytes("constructor reverted"));
+        new Deployed();
+    }
+}
@@ -0,0 +1,14 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract Branches {
+    uint256 public taken;
+
+    function branch(bool condition) public {
+        if (condition) {
+            taken = 1;
+        } else {
+            taken = 2;
+        }
+    }
+}
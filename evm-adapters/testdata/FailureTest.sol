@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+address constant HEVM = address(bytes20(uint160(uint256(keccak256('hevm cheat code')))));
+
+// Records failures like DSTest does: in its `failed` variable, or in the storage of the
+// cheatcode address like newer versions do
+contract FailureTest {
+    event log(string);
+    event log_named_uint(string key, uint256 val);
+
+    bool public failed;
+
+    function succeed() public {}
+
+    function fail() public {
+        failed = true;
+    }
+
+    function failGlobally() public {
+        (bool success, ) = HEVM.call(
+            abi.encodeWithSignature(
+                "store(address,bytes32,bytes32)",
+                HEVM,
+                bytes32("failed"),
+                bytes32(uint256(1))
+            )
+        );
+        require(success, "store failed");
+    }
+
+    // logs a failed assertion like DSTest's `assertEq(uint,uint)`, along with the logs of a
+    // call which reverts
+    function failWithLogs() public {
+        emit log("Error: a == b not satisfied [uint]");
+        emit log_named_uint("  Expected", 1);
+        emit log_named_uint("    Actual", 2);
+        try this.revertWithLogs() {} catch {}
+        failed = true;
+    }
+
+    function revertWithLogs() public {
+        emit log("reverted");
+        revert("reverted");
+    }
+
+    function revertNow() public pure {
+        revert("reverted");
+    }
+
+    function runOutOfGas() public pure {
+        while (true) {}
+    }
+}
@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+pragma abicoder v2;
+
+contract FuzzTest {
+    struct Entry {
+        address who;
+        uint256 amount;
+    }
+
+    function testStructs(Entry[] memory entries) public pure {
+        for (uint256 i = 0; i < entries.length; i++) {
+            require(entries[i].amount < 1000, "amount too large");
+        }
+    }
+}
+
+contract DictionaryTest {
+    uint256 threshold;
+
+    function setUp() public {
+        // computed at runtime so that it is not one of the constants of the code
+        threshold = uint256(keccak256(abi.encodePacked(address(this))));
+    }
+
+    function testThreshold(uint256 x) public view {
+        require(x != threshold, "found the threshold");
+    }
+}
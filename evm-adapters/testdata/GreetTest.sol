@@ -15,6 +15,31 @@ contract Greeter {
     function gm() public {
         greeting = "gm";
     }
+
+    function noop() public {}
+
+    function greetAndRevert(string memory _greeting) public {
+        greeting = _greeting;
+        revert("greeted");
+    }
+
+    function tryGreet(string memory _greeting) public {
+        try this.greetAndRevert(_greeting) {} catch {}
+    }
+
+    function revertWith(bytes memory data) public pure {
+        assembly {
+            revert(add(data, 32), mload(data))
+        }
+    }
+}
+
+contract ConstructedGreeter {
+    string public greeting;
+
+    constructor(string memory _greeting) {
+        greeting = _greeting;
+    }
 }
 
 contract GreeterTestSetup {
@@ -0,0 +1,20 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+// Its deployed code differs from its runtime code by the values of its immutables
+contract Immutables {
+    uint256 public immutable value;
+    address public immutable owner;
+
+    constructor(uint256 _value) {
+        value = _value;
+        owner = msg.sender;
+    }
+}
+
+// Its deployed code differs from its runtime code by its own address
+library DoubleLib {
+    function double(uint256 x) public pure returns (uint256) {
+        return 2 * x;
+    }
+}
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract Loop {
+    uint256 public calls;
+
+    // never returns, short of running out of gas
+    function spin() public pure {
+        uint256 i;
+        while (true) {
+            i++;
+        }
+    }
+
+    function count(uint256) public {
+        calls += 1;
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+contract Implementation {
+    uint256 public value;
+    address public sender;
+    uint256 public received;
+
+    function set(uint256 _value) public payable {
+        value = _value;
+        sender = msg.sender;
+        received = msg.value;
+    }
+}
+
+// A minimal EIP-1967 proxy, which delegates every call to its implementation. The address of
+// the implementation is kept in a pseudo-random slot, so that it does not clash with the
+// storage of the implementation.
+contract Proxy {
+    bytes32 constant IMPLEMENTATION_SLOT =
+        bytes32(uint256(keccak256("eip1967.proxy.implementation")) - 1);
+
+    constructor(address implementation) {
+        bytes32 slot = IMPLEMENTATION_SLOT;
+        assembly {
+            sstore(slot, implementation)
+        }
+    }
+
+    fallback() external payable {
+        bytes32 slot = IMPLEMENTATION_SLOT;
+        assembly {
+            let implementation := sload(slot)
+            calldatacopy(0, 0, calldatasize())
+            let success := delegatecall(gas(), implementation, 0, calldatasize(), 0, 0)
+            returndatacopy(0, 0, returndatasize())
+            switch success
+            case 0 {
+                revert(0, returndatasize())
+            }
+            default {
+                return(0, returndatasize())
+            }
+        }
+    }
+}
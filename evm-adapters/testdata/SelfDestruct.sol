@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity =0.7.6;
+
+interface SelfDestructHevm {
+    function deal(address,uint256) external;
+    function load(address,bytes32) external returns (bytes32);
+    function snapshot() external returns (uint256);
+    function revertTo(uint256) external returns (bool);
+}
+
+contract Destructible {
+    uint256 public value = 1;
+    uint256 public other;
+
+    constructor() payable {}
+
+    function setOther(uint256 _other) public {
+        other = _other;
+    }
+
+    function destroy(address payable beneficiary) public {
+        selfdestruct(beneficiary);
+    }
+}
+
+// Each function is meant to be called in a transaction of its own, as the accounts which
+// self-destructed are only removed once the transaction is over
+contract SelfDestructTest {
+    SelfDestructHevm constant hevm =
+        SelfDestructHevm(address(bytes20(uint160(uint256(keccak256('hevm cheat code'))))));
+    bytes32 constant SALT = bytes32("salt");
+    address payable constant BENEFICIARY = address(0xbeef);
+
+    Destructible public child;
+
+    function codeSize(address who) internal view returns (uint256 size) {
+        assembly {
+            size := extcodesize(who)
+        }
+    }
+
+    function setUp() public {
+        hevm.deal(address(this), 1 ether);
+        child = new Destructible{salt: SALT, value: 1 ether}();
+        child.setOther(7);
+    }
+
+    function destroy() public {
+        child.destroy(BENEFICIARY);
+        require(codeSize(address(child)) > 0, "code removed before the end of the transaction");
+        require(address(child).balance == 0, "balance left");
+        require(BENEFICIARY.balance == 1 ether, "balance not moved");
+    }
+
+    function destroyAndRevert() public {
+        uint256 id = hevm.snapshot();
+        child.destroy(BENEFICIARY);
+        require(hevm.revertTo(id), "could not revert");
+    }
+
+    function checkDestroyed() public {
+        require(codeSize(address(child)) == 0, "code left");
+        require(hevm.load(address(child), bytes32(uint256(1))) == 0, "storage left");
+        require(BENEFICIARY.balance == 1 ether, "balance not moved");
+    }
+
+    function checkAlive() public view {
+        require(codeSize(address(child)) > 0, "code missing");
+        require(child.value() == 1 && child.other() == 7, "storage missing");
+        require(address(child).balance == 1 ether, "balance missing");
+    }
+
+    function resurrect() public {
+        Destructible again = new Destructible{salt: SALT}();
+        require(address(again) == address(child), "deployed at another address");
+        require(again.value() == 1 && again.other() == 0, "storage not cleared");
+    }
+}
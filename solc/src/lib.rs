@@ -1,8 +1,12 @@
-use ethers::core::utils::{CompiledContract, Solc};
+use ethers::core::{
+    abi::{Abi, Function},
+    types::Bytes,
+    utils::{keccak256, CompiledContract, Solc},
+};
 use eyre::Result;
 use semver::{Version, VersionReq};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     fs::File,
     io::{BufRead, BufReader},
     path::{Path, PathBuf},
@@ -16,6 +20,25 @@ static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 #[cfg(test)]
 use ethers::prelude::Lazy;
 
+/// The offsets of the addresses of the libraries a contract calls, by library name, in its
+/// creation and runtime code. The compiled code has zeros in their place until it is linked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkReferences {
+    pub bytecode: BTreeMap<String, Vec<usize>>,
+    pub runtime_bytecode: BTreeMap<String, Vec<usize>>,
+}
+
+impl LinkReferences {
+    /// The libraries the contract depends on
+    pub fn libraries(&self) -> BTreeSet<&String> {
+        self.bytecode.keys().chain(self.runtime_bytecode.keys()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytecode.is_empty() && self.runtime_bytecode.is_empty()
+    }
+}
+
 /// Supports building contracts
 #[derive(Debug)]
 pub struct SolcBuilder<'a> {
@@ -24,6 +47,12 @@ pub struct SolcBuilder<'a> {
     lib_paths: &'a [String],
     versions: Vec<Version>,
     releases: Vec<Version>,
+    /// The link references of the contracts built so far which call libraries
+    link_references: HashMap<String, LinkReferences>,
+    /// The contracts declared in each of the files built so far
+    declared_contracts: HashMap<String, Vec<String>>,
+    /// The custom errors of the contracts built so far which declare or use any
+    custom_errors: HashMap<String, Vec<Function>>,
 }
 
 impl<'a> SolcBuilder<'a> {
@@ -41,7 +70,53 @@ impl<'a> SolcBuilder<'a> {
                 Vec::new()
             }
         };
-        Ok(Self { contracts, remappings, lib_paths, versions, releases })
+        Ok(Self {
+            contracts,
+            remappings,
+            lib_paths,
+            versions,
+            releases,
+            link_references: HashMap::new(),
+            declared_contracts: HashMap::new(),
+            custom_errors: HashMap::new(),
+        })
+    }
+
+    /// The link references of the contracts built so far which call libraries, by contract
+    /// name, see [`LinkReferences`]
+    pub fn link_references(&self) -> &HashMap<String, LinkReferences> {
+        &self.link_references
+    }
+
+    /// The names of the contracts, libraries and interfaces declared in each of the files built
+    /// so far, by canonicalized path
+    pub fn declared_contracts(&self) -> &HashMap<String, Vec<String>> {
+        &self.declared_contracts
+    }
+
+    /// The canonicalized paths of the files the contracts built so far are declared in, by
+    /// contract name. The contracts declared in several files are left out, since it is unknown
+    /// which of them was built.
+    pub fn source_paths(&self) -> HashMap<String, String> {
+        let mut paths = HashMap::<&String, Vec<&String>>::new();
+        for (file, names) in &self.declared_contracts {
+            for name in names {
+                paths.entry(name).or_default().push(file);
+            }
+        }
+        paths
+            .into_iter()
+            .filter_map(|(name, files)| match files.as_slice() {
+                [file] => Some((name.clone(), (*file).clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The custom errors in the ABIs of the contracts built so far, by contract name, see
+    /// [`parse_abi`]
+    pub fn custom_errors(&self) -> &HashMap<String, Vec<Function>> {
+        &self.custom_errors
     }
 
     /// Builds all provided contract files with the specified compiler version.
@@ -53,12 +128,12 @@ impl<'a> SolcBuilder<'a> {
         &self,
         version: &str,
         files: Vec<String>,
-    ) -> Result<HashMap<String, CompiledContract>> {
+    ) -> Result<HashMap<String, (CompiledContract, LinkReferences, Vec<Function>)>> {
         let compiler_path = find_installed_version_path(version)?
             .ok_or_else(|| eyre::eyre!("version {} not installed", version))?;
 
         // tracing::trace!(?files);
-        let mut solc = Solc::new_with_paths(files).solc_path(compiler_path);
+        let mut solc = Solc::new_with_paths(files.clone()).solc_path(compiler_path);
         let lib_paths = self
             .lib_paths
             .iter()
@@ -77,13 +152,38 @@ impl<'a> SolcBuilder<'a> {
             solc = solc.args(self.remappings)
         }
 
-        Ok(solc.build()?)
+        // the code of contracts which call libraries is not valid hex until it is linked
+        let contracts = solc.build_raw()?;
+        // the placeholders of libraries are derived from their fully qualified name, i.e.
+        // `/path/to/File.sol:Library`
+        let libraries = files
+            .iter()
+            .flat_map(|file| {
+                contracts.keys().map(move |name| {
+                    let hash = hex::encode(keccak256(format!("{}:{}", file, name)));
+                    (hash[..34].to_owned(), name.clone())
+                })
+            })
+            .collect::<HashMap<_, _>>();
+        contracts
+            .into_iter()
+            .map(|(name, contract)| {
+                let (bytecode, bytecode_refs) = decode_unlinked(&contract.bin, &libraries)?;
+                let (runtime_bytecode, runtime_refs) =
+                    decode_unlinked(&contract.runtime_bin, &libraries)?;
+                let (abi, errors) = parse_abi(serde_json::from_str(&contract.abi)?)?;
+                let links =
+                    LinkReferences { bytecode: bytecode_refs, runtime_bytecode: runtime_refs };
+                Ok((name, (CompiledContract { abi, bytecode, runtime_bytecode }, links, errors)))
+            })
+            .collect()
     }
 
     /// Builds all contracts with their corresponding compiler versions
     #[tracing::instrument(skip(self))]
     pub fn build_all(&mut self) -> Result<HashMap<String, CompiledContract>> {
         let contracts_by_version = self.contract_versions()?;
+        let files = contracts_by_version.values().flatten().cloned().collect::<Vec<_>>();
         let start = Instant::now();
         let res = contracts_by_version.into_iter().try_fold(
             HashMap::new(),
@@ -96,7 +196,25 @@ impl<'a> SolcBuilder<'a> {
         let duration = Instant::now().duration_since(start);
         tracing::info!(compilation_time = ?duration);
 
-        res
+        let mut contracts = HashMap::new();
+        for (name, (contract, links, errors)) in res? {
+            if !links.is_empty() {
+                self.link_references.insert(name.clone(), links);
+            }
+            if !errors.is_empty() {
+                self.custom_errors.insert(name.clone(), errors);
+            }
+            contracts.insert(name, contract);
+        }
+        for file in files {
+            let source = std::fs::read_to_string(&file)?;
+            let names = declared_contracts(&source)
+                .into_iter()
+                .filter(|name| contracts.contains_key(name))
+                .collect();
+            self.declared_contracts.insert(file, names);
+        }
+        Ok(contracts)
     }
 
     /// Given a Solidity file, it detects the latest compiler version which can be used
@@ -145,11 +263,11 @@ impl<'a> SolcBuilder<'a> {
     }
 
     fn install_version(&mut self, version: &Version) {
-        println!("Installing {}", version);
+        eprintln!("Installing {}", version);
         // Blocking call to install it over RPC.
         install_blocking(version).expect("could not install solc remotely");
         self.versions.push(version.clone());
-        println!("Done!");
+        eprintln!("Done!");
     }
 
     /// Gets a map of compiler version -> vec[contract paths]
@@ -157,7 +275,7 @@ impl<'a> SolcBuilder<'a> {
         // Group contracts in the nones with the same version pragma
         let files = glob::glob(self.contracts)?;
         // tracing::trace!("Compiling files under {}", self.contracts);
-        println!("Compiling files under {}", self.contracts);
+        eprintln!("Compiling files under {}", self.contracts);
 
         // get all the corresponding contract versions
         let contracts = files
@@ -218,6 +336,115 @@ impl<'a> SolcBuilder<'a> {
     }
 }
 
+/// The names of the contracts, libraries and interfaces declared in the Solidity source
+fn declared_contracts(source: &str) -> Vec<String> {
+    let source = strip_comments_and_strings(source);
+    let mut tokens = source
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .filter(|token| !token.is_empty());
+    let mut names = Vec::new();
+    while let Some(token) = tokens.next() {
+        if matches!(token, "contract" | "library" | "interface") {
+            names.extend(tokens.next().map(ToOwned::to_owned));
+        }
+    }
+    names
+}
+
+/// Replaces the comments and string literals of the Solidity source by spaces, so that the
+/// words in them are not mistaken for declarations
+fn strip_comments_and_strings(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                // up to the end of the line, which is kept
+                while chars.peek().map_or(false, |c| *c != '\n') {
+                    chars.next();
+                }
+                stripped.push(' ');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break
+                    }
+                    previous = c;
+                }
+                stripped.push(' ');
+            }
+            ('"', _) | ('\'', _) => {
+                while let Some(next) = chars.next() {
+                    match next {
+                        '\\' => {
+                            chars.next();
+                        }
+                        quote if quote == c => break,
+                        _ => {}
+                    }
+                }
+                stripped.push(' ');
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Parses the JSON ABI of a contract, and returns its custom errors apart as functions of the same
+/// name and inputs, since [`Abi`] does not keep them. The revert data of an error is encoded like
+/// the calldata of such a function.
+pub fn parse_abi(abi: serde_json::Value) -> Result<(Abi, Vec<Function>)> {
+    let items = match abi {
+        serde_json::Value::Array(items) => items,
+        other => eyre::bail!("expected the ABI to be an array, got {}", other),
+    };
+    let (errors, items): (Vec<_>, Vec<_>) =
+        items.into_iter().partition(|item| item["type"] == "error");
+    let errors = errors
+        .into_iter()
+        .map(|error| {
+            serde_json::json!({
+                "type": "function",
+                "name": error["name"],
+                "inputs": error["inputs"],
+                "outputs": [],
+                "stateMutability": "nonpayable",
+            })
+        })
+        .collect::<Vec<_>>();
+    let errors = serde_json::from_value::<Abi>(errors.into())?;
+    let abi = serde_json::from_value(items.into())?;
+    Ok((abi, errors.functions().cloned().collect()))
+}
+
+/// Decodes hex code which may contain the `__$<hash>$__` placeholders of the addresses of
+/// libraries, given the libraries' names by placeholder hash. The placeholders are zeroed and
+/// their offsets returned by library name.
+pub fn decode_unlinked(
+    code: &str,
+    libraries: &HashMap<String, String>,
+) -> Result<(Bytes, BTreeMap<String, Vec<usize>>)> {
+    let mut code = code.to_owned();
+    let mut references = BTreeMap::<String, Vec<usize>>::new();
+    while let Some(start) = code.find("__$") {
+        // each placeholder takes the 40 hex characters of the address
+        let placeholder = code
+            .get(start..start + 40)
+            .filter(|placeholder| placeholder.ends_with("$__"))
+            .ok_or_else(|| eyre::eyre!("malformed library placeholder at byte {}", start / 2))?;
+        let library = libraries
+            .get(&placeholder[3..37])
+            .ok_or_else(|| eyre::eyre!("unknown library placeholder `{}`", placeholder))?;
+        references.entry(library.clone()).or_default().push(start / 2);
+        code.replace_range(start..start + 40, &"0".repeat(40));
+    }
+    Ok((hex::decode(&code)?.into(), references))
+}
+
 /// Returns the path for an installed version
 fn find_installed_version_path(version: &str) -> Result<Option<PathBuf>> {
     let home_dir = svm::SVM_HOME.clone();
@@ -259,6 +486,74 @@ mod tests {
         assert_eq!(res.unwrap(), expected);
     }
 
+    #[test]
+    fn finds_declared_contracts() {
+        let source = "pragma solidity ^0.8.0;\nimport \"./Lib.sol\";\n\
+            abstract contract Base {}\ninterface IFoo{}\nlibrary $Math {}\n\
+            contract Foo is Base, IFoo { function f() external {} }";
+        assert_eq!(declared_contracts(source), ["Base", "IFoo", "$Math", "Foo"]);
+
+        // the words of comments and strings are not declarations
+        let source = "// this contract Foo is not a library Bar\n\
+            /* contract Baz\n * interface Qux */\n\
+            contract Greeter { string s = \"contract \\\"Quoted\\\" {}\";\n\
+            bytes b = 'library L'; }\n\
+            contract/**/Gm {}";
+        assert_eq!(declared_contracts(source), ["Greeter", "Gm"]);
+    }
+
+    #[test]
+    fn parses_custom_errors_apart() {
+        let abi = serde_json::json!([
+            {
+                "type": "error",
+                "name": "Unauthorized",
+                "inputs": [{ "name": "caller", "type": "address", "internalType": "address" }]
+            },
+            {
+                "type": "function",
+                "name": "greet",
+                "inputs": [{ "name": "greeting", "type": "string", "internalType": "string" }],
+                "outputs": [],
+                "stateMutability": "nonpayable"
+            }
+        ]);
+        let (abi, errors) = parse_abi(abi).unwrap();
+        assert_eq!(
+            abi.functions().map(|func| func.signature()).collect::<Vec<_>>(),
+            ["greet(string)"]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].signature(), "Unauthorized(address)");
+        assert_eq!(errors[0].short_signature(), ethers::core::utils::id("Unauthorized(address)"));
+
+        assert!(parse_abi(serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn decodes_unlinked_code() {
+        let libraries = std::iter::once(("ab".repeat(17), "Math".to_owned())).collect();
+        let placeholder = format!("__${}$__", "ab".repeat(17));
+        let code = format!("73{}3014{}00", placeholder, placeholder);
+        let (code, references) = decode_unlinked(&code, &libraries).unwrap();
+        let mut expected = vec![0x73];
+        expected.extend([0; 20]);
+        expected.extend([0x30, 0x14]);
+        expected.extend([0; 20]);
+        expected.push(0);
+        assert_eq!(code.as_ref(), &expected[..]);
+        assert_eq!(references["Math"], [1, 23]);
+
+        let (code, references) = decode_unlinked("6001", &libraries).unwrap();
+        assert_eq!(code.as_ref(), &[0x60, 0x01]);
+        assert!(references.is_empty());
+
+        let unknown = format!("73__${}$__", "cd".repeat(17));
+        let err = decode_unlinked(&unknown, &libraries).unwrap_err();
+        assert!(err.to_string().starts_with("unknown library placeholder"));
+        assert!(decode_unlinked("73__$abc", &libraries).is_err());
+    }
+
     #[test]
     fn does_not_find_not_installed_version() {
         let ver = "1.1.1";
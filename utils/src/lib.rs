@@ -1,28 +1,86 @@
 use ethers_core::{
-    abi::{self, parse_abi, Function, ParamType, Token, Tokenizable},
+    abi::{self, parse_abi, Abi, Event, Function, ParamType, RawLog, Token},
     types::*,
+    utils,
 };
 use eyre::Result;
-use rustc_hex::FromHex;
-use std::str::FromStr;
+use rustc_hex::{FromHex, ToHex};
+use std::{collections::BTreeMap, fmt, str::FromStr};
 
 const BASE_TX_COST: u64 = 21000;
 
+/// The cost of a transaction's zero bytes of data, in every fork
+const ZERO_BYTE_COST: u64 = 4;
+
+/// The cost of a transaction's non-zero bytes of data since EIP-2028 (Istanbul)
+pub const NON_ZERO_BYTE_COST: u64 = 16;
+
+/// The cost of a transaction's non-zero bytes of data before EIP-2028 (Istanbul)
+pub const NON_ZERO_BYTE_COST_FRONTIER: u64 = 68;
+
 pub fn remove_extra_costs(gas: U256, calldata: &[u8]) -> U256 {
-    let mut calldata_cost = 0;
-    for i in calldata {
-        if *i != 0 {
-            // TODO: Check if EVM pre-eip2028 and charge 64
-            calldata_cost += 16
-        } else {
-            calldata_cost += 8;
+    gas - calldata_cost(calldata, NON_ZERO_BYTE_COST) - BASE_TX_COST
+}
+
+/// The part of the intrinsic cost of a transaction which depends on its data, given the cost
+/// of its non-zero bytes in the fork
+pub fn calldata_cost(calldata: &[u8], non_zero_byte_cost: u64) -> u64 {
+    let zero_bytes = calldata.iter().filter(|byte| **byte == 0).count() as u64;
+    let non_zero_bytes = calldata.len() as u64 - zero_bytes;
+    zero_bytes * ZERO_BYTE_COST + non_zero_bytes * non_zero_byte_cost
+}
+
+/// Selector of `Error(string)`, which `revert("reason")` and `require(cond, "reason")` use
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`, which failing `assert`s and checked arithmetic use
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Decodes revert data into a human-readable reason: the message of `Error(string)` reverts,
+/// the cause of `Panic(uint256)` ones, and the hex-encoded data for anything else (e.g. custom
+/// errors)
+pub fn decode_revert(error: &[u8]) -> String {
+    if error.is_empty() {
+        return "<empty revert data>".to_owned()
+    }
+    if error.len() >= 4 && error[..4] == ERROR_SELECTOR {
+        if let Ok(Some(Token::String(reason))) =
+            abi::decode(&[ParamType::String], &error[4..]).map(|mut tokens| tokens.pop())
+        {
+            return reason
         }
     }
-    gas - calldata_cost - BASE_TX_COST
+    decode_panic(error).unwrap_or_else(|| format!("0x{}", error.to_hex::<String>()))
 }
 
-pub fn decode_revert(error: &[u8]) -> Result<String> {
-    Ok(abi::decode(&[abi::ParamType::String], &error[4..])?[0].to_string())
+/// Decodes `Panic(uint256)`-encoded revert data, e.g. `panic: division or modulo by zero
+/// (0x12)`. Returns `None` for any other revert data.
+pub fn decode_panic(error: &[u8]) -> Option<String> {
+    if error.len() < 4 || error[..4] != PANIC_SELECTOR {
+        return None
+    }
+    let code = match abi::decode(&[ParamType::Uint(256)], &error[4..]).ok()?.pop()? {
+        Token::Uint(code) => code,
+        _ => return None,
+    };
+    if code > U256::from(u8::MAX) {
+        return Some(format!("panic: unknown panic code ({:#x})", code))
+    }
+    let code = code.low_u64();
+    // https://docs.soliditylang.org/en/v0.8.10/control-structures.html#panic-via-assert-and-error-via-require
+    let cause = match code {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic underflow or overflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "conversion into non-existent enum type",
+        0x22 => "access to incorrectly encoded storage byte array",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to zero-initialized variable of internal function type",
+        _ => "unknown panic code",
+    };
+    Some(format!("panic: {} ({:#04x})", cause, code))
 }
 
 pub fn to_table(value: serde_json::Value) -> String {
@@ -39,72 +97,1024 @@ pub fn to_table(value: serde_json::Value) -> String {
     }
 }
 
+/// Whether `sig` is a JSON ABI fragment, i.e. an ABI item or an array of them
+fn is_json_fragment(sig: &str) -> bool {
+    sig.starts_with('{') || sig.starts_with('[')
+}
+
+/// Parses a 4-byte selector hex string, e.g. `0x0a9254e4`
+fn parse_selector(sig: &str) -> Option<[u8; 4]> {
+    let hex = sig.strip_prefix("0x")?;
+    if hex.len() != 8 {
+        return None
+    }
+    let bytes = hex.from_hex::<Vec<u8>>().ok()?;
+    let mut selector = [0; 4];
+    selector.copy_from_slice(&bytes);
+    Some(selector)
+}
+
+/// Parses a JSON ABI fragment, which may be a single item or an array of them
+fn parse_json_fragment(json: &str) -> Result<Abi> {
+    let parsed = if json.starts_with('{') {
+        serde_json::from_str(&format!("[{}]", json))
+    } else {
+        serde_json::from_str(json)
+    };
+    parsed.map_err(|err| eyre::eyre!("could not parse `{}` as a JSON ABI fragment: {}", json, err))
+}
+
+/// Parses a human-readable declaration of the given `kind` (`function` or `event`), which may
+/// omit the keyword, e.g. `transfer(address to, uint256 amount) returns (bool)`
+fn parse_declaration(sig: &str, kind: &str) -> Result<Abi> {
+    let declaration = if sig.starts_with(&format!("{} ", kind)) {
+        sig.to_owned()
+    } else {
+        format!("{} {}", kind, sig)
+    };
+    parse_abi(&[&declaration]).map_err(|err| {
+        eyre::eyre!("could not parse `{}` as a human-readable {} signature: {}", sig, kind, err)
+    })
+}
+
+/// Parses a function given as a bare signature (`setUp()`), a human-readable declaration with
+/// or without the `function` keyword and its return types (`function greeting() returns
+/// (string)`), or a JSON ABI fragment. Selectors can only be resolved via
+/// [`get_func_from_abi`].
 pub fn get_func(sig: &str) -> Result<Function> {
-    // TODO: Make human readable ABI better / more minimal
-    let abi = parse_abi(&[sig])?;
-    // get the function
-    let (_, func) =
-        abi.functions.iter().next().ok_or_else(|| eyre::eyre!("function name not found"))?;
-    let func = func.get(0).ok_or_else(|| eyre::eyre!("functions array empty"))?;
+    let sig = sig.trim();
+    if parse_selector(sig).is_some() {
+        eyre::bail!("cannot resolve the selector `{}` without an ABI, see `get_func_from_abi`", sig)
+    }
+    let abi = if is_json_fragment(sig) {
+        parse_json_fragment(sig)?
+    } else {
+        parse_declaration(sig, "function")?
+    };
+    let func =
+        abi.functions().next().ok_or_else(|| eyre::eyre!("`{}` declares no function", sig))?;
     Ok(func.clone())
 }
 
-pub fn encode_input(param: &ParamType, value: &str) -> Result<Token> {
+/// Like [`get_func`], but also resolves 4-byte selectors (e.g. `0x0a9254e4`) to the function
+/// of `abi` they belong to
+pub fn get_func_from_abi(sig: &str, abi: &Abi) -> Result<Function> {
+    let sig = sig.trim();
+    match parse_selector(sig) {
+        Some(selector) => abi
+            .functions()
+            .find(|func| func.short_signature() == selector)
+            .cloned()
+            .ok_or_else(|| eyre::eyre!("no function of the ABI has the selector `{}`", sig)),
+        None => get_func(sig),
+    }
+}
+
+/// Parses an event given as a bare signature (`Transfer(address,address,uint256)`), a
+/// human-readable declaration with or without the `event` keyword, or a JSON ABI fragment
+pub fn get_event(sig: &str) -> Result<Event> {
+    let sig = sig.trim();
+    let abi = if is_json_fragment(sig) {
+        parse_json_fragment(sig)?
+    } else {
+        parse_declaration(sig, "event")?
+    };
+    let event = abi.events().next().ok_or_else(|| eyre::eyre!("`{}` declares no event", sig))?;
+    Ok(event.clone())
+}
+
+/// The signature of a function, e.g. `transfer(address,uint256)`, from which its selector is
+/// derived
+pub fn func_signature(func: &Function) -> String {
+    let inputs = func.inputs.iter().map(|param| param.kind.to_string()).collect::<Vec<_>>();
+    format!("{}({})", func.name, inputs.join(","))
+}
+
+/// ABI-encodes a call of `func` with human-typed arguments, see [`parse_tokens`]
+pub fn encode_args(func: &Function, args: &[String]) -> Result<Vec<u8>> {
+    let params = func.inputs.iter().map(|param| param.kind.clone()).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    Ok(func.encode_input(&parse_tokens(&params, &args)?)?)
+}
+
+/// Appends the ABI-encoded `args` of the constructor of `abi` to the creation `bytecode`,
+/// after checking that they match its parameters. Contracts without a constructor take no
+/// arguments.
+pub fn encode_constructor_args(abi: &Abi, args: &[Token], bytecode: Bytes) -> Result<Bytes> {
+    let constructor = match &abi.constructor {
+        Some(constructor) => constructor,
+        None if args.is_empty() => return Ok(bytecode),
+        None => {
+            eyre::bail!("the ABI has no constructor, but {} argument(s) were provided", args.len())
+        }
+    };
+    if constructor.inputs.len() != args.len() {
+        eyre::bail!(
+            "the constructor takes {} argument(s), but {} were provided",
+            constructor.inputs.len(),
+            args.len()
+        )
+    }
+    for (i, (param, arg)) in constructor.inputs.iter().zip(args).enumerate() {
+        if !arg.type_check(&param.kind) {
+            eyre::bail!("constructor argument {} must be of type {}, got {:?}", i, param.kind, arg)
+        }
+    }
+    Ok(constructor.encode_input(bytecode.to_vec(), args)?.into())
+}
+
+/// Like [`encode_constructor_args`], but parses the arguments from human-typed values, see
+/// [`parse_token`]
+pub fn encode_constructor_args_from_str(
+    abi: &Abi,
+    args: &[String],
+    bytecode: Bytes,
+) -> Result<Bytes> {
+    let params = abi.constructor.as_ref().map(|constructor| &constructor.inputs[..]).unwrap_or(&[]);
+    if params.len() != args.len() {
+        eyre::bail!(
+            "the constructor takes {} argument(s), but {} were provided",
+            params.len(),
+            args.len()
+        )
+    }
+    let tokens = params
+        .iter()
+        .zip(args)
+        .enumerate()
+        .map(|(i, (param, arg))| {
+            parse_token(&param.kind, arg).map_err(|err| {
+                eyre::eyre!(
+                    "invalid constructor argument {} (`{}`) of type {}: {}",
+                    i,
+                    arg,
+                    param.kind,
+                    err
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    encode_constructor_args(abi, &tokens, bytecode)
+}
+
+/// Parses human-typed arguments, e.g. from the command line, as tokens of the given types, see
+/// [`parse_token`]. Errors name the argument which could not be parsed.
+pub fn parse_tokens(params: &[ParamType], values: &[&str]) -> Result<Vec<Token>> {
+    if params.len() != values.len() {
+        eyre::bail!("expected {} argument(s), got {}", params.len(), values.len())
+    }
+    params
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(i, (param, value))| {
+            parse_token(param, value).map_err(|err| {
+                eyre::eyre!("argument {} (`{}`) must be of type {}: {}", i, value, param, err)
+            })
+        })
+        .collect()
+}
+
+/// Parses a human-typed value as a token of the given type:
+/// - integers are decimal or `0x`-hex, and may carry a unit (`1.5 ether`, `10gwei`, `-1 wei`)
+/// - addresses are `0x`-hex, whose checksum is verified if they are mixed-case
+/// - booleans are `true` or `false`
+/// - strings may be double-quoted, with `\"` and `\\` escapes
+/// - bytes are hex, optionally `0x`-prefixed
+/// - arrays are written as `[1,2,3]` and tuples as `(1,"a")` or `[1,"a"]`, and may be nested
+///
+/// [`format_token`] formats tokens in a way this parses back.
+pub fn parse_token(param: &ParamType, value: &str) -> Result<Token> {
+    let value = value.trim();
     Ok(match param {
-        // TODO: Do the rest of the types
-        ParamType::Address => Address::from_str(value)?.into_token(),
-        ParamType::Bytes => Bytes::from(value.from_hex::<Vec<u8>>()?).into_token(),
-        ParamType::FixedBytes(_) => value.from_hex::<Vec<u8>>()?.into_token(),
-        ParamType::Uint(n) => {
-            let radix = if value.starts_with("0x") { 16 } else { 10 };
-            match n / 8 {
-                1 => u8::from_str_radix(value, radix)?.into_token(),
-                2 => u16::from_str_radix(value, radix)?.into_token(),
-                3..=4 => u32::from_str_radix(value, radix)?.into_token(),
-                5..=8 => u64::from_str_radix(value, radix)?.into_token(),
-                9..=16 => u128::from_str_radix(value, radix)?.into_token(),
-                17..=32 => {
-                    if radix == 16 { U256::from_str(value)? } else { U256::from_dec_str(value)? }
-                        .into_token()
-                }
-                _ => eyre::bail!("unsupoprted solidity type uint{}", n),
+        ParamType::Address => Token::Address(parse_address(value)?),
+        ParamType::Bool => Token::Bool(bool::from_str(value)?),
+        ParamType::String => Token::String(parse_string(value)?),
+        ParamType::Bytes => Token::Bytes(value.trim_start_matches("0x").from_hex()?),
+        ParamType::FixedBytes(size) => {
+            let bytes = value.trim_start_matches("0x").from_hex::<Vec<u8>>()?;
+            if bytes.len() > *size {
+                eyre::bail!("{} bytes do not fit in bytes{}", bytes.len(), size)
             }
+            Token::FixedBytes(bytes)
         }
-        ParamType::Int(n) => {
-            let radix = if value.starts_with("0x") { 16 } else { 10 };
-            match n / 8 {
-                1 => i8::from_str_radix(value, radix)?.into_token(),
-                2 => i16::from_str_radix(value, radix)?.into_token(),
-                3..=4 => i32::from_str_radix(value, radix)?.into_token(),
-                5..=8 => i64::from_str_radix(value, radix)?.into_token(),
-                9..=16 => i128::from_str_radix(value, radix)?.into_token(),
-                17..=32 => {
-                    if radix == 16 { I256::from_str(value)? } else { I256::from_dec_str(value)? }
-                        .into_token()
-                }
-                _ => eyre::bail!("unsupoprted solidity type uint{}", n),
+        ParamType::Uint(bits) => {
+            let amount = parse_amount(value)?;
+            if *bits < 256 && amount.bits() > *bits {
+                eyre::bail!("{} does not fit in uint{}", amount, bits)
             }
+            Token::Uint(amount)
         }
-        ParamType::Bool => bool::from_str(value)?.into_token(),
-        ParamType::String => value.to_string().into_token(),
-        ParamType::Array(_) => {
-            unimplemented!()
+        ParamType::Int(bits) => {
+            let (negative, magnitude) = match value.strip_prefix('-') {
+                Some(magnitude) => (true, parse_amount(magnitude)?),
+                None => (false, parse_amount(value)?),
+            };
+            // the range of int<bits> is [-2^(bits-1), 2^(bits-1) - 1]
+            let limit = U256::one() << (bits - 1);
+            if (negative && magnitude > limit) || (!negative && magnitude >= limit) {
+                eyre::bail!("{} does not fit in int{}", value, bits)
+            }
+            // tokens hold the two's complement of negative integers
+            Token::Int(if negative {
+                (!magnitude).overflowing_add(U256::one()).0
+            } else {
+                magnitude
+            })
         }
-        ParamType::FixedArray(_, _) => {
-            unimplemented!()
+        ParamType::Array(kind) => Token::Array(
+            split_list(value, '[', ']')?
+                .into_iter()
+                .map(|element| parse_token(kind, element))
+                .collect::<Result<_>>()?,
+        ),
+        ParamType::FixedArray(kind, len) => {
+            let elements = split_list(value, '[', ']')?;
+            if elements.len() != *len {
+                eyre::bail!("expected {} elements, got {}", len, elements.len())
+            }
+            Token::FixedArray(
+                elements
+                    .into_iter()
+                    .map(|element| parse_token(kind, element))
+                    .collect::<Result<_>>()?,
+            )
         }
-        ParamType::Tuple(_) => {
-            unimplemented!()
+        ParamType::Tuple(kinds) => {
+            let elements = split_list(value, '(', ')').or_else(|_| split_list(value, '[', ']'))?;
+            if elements.len() != kinds.len() {
+                eyre::bail!("expected {} tuple elements, got {}", kinds.len(), elements.len())
+            }
+            Token::Tuple(
+                kinds
+                    .iter()
+                    .zip(elements)
+                    .map(|(kind, element)| parse_token(kind, element))
+                    .collect::<Result<_>>()?,
+            )
         }
     })
 }
 
-pub fn encode_args(func: &Function, args: &[String]) -> Result<Vec<u8>> {
-    // Dynamically build up the calldata via the function sig
-    let mut inputs = Vec::new();
-    for (i, input) in func.inputs.iter().enumerate() {
-        let input = encode_input(&input.kind, &args[i])?;
-        inputs.push(input);
+/// Formats a value logged by a contract, e.g. via DS-Test's `log_named_*` events or
+/// `console.log`: strings as they are, and the other values like [`format_token`]
+pub fn format_log_token(token: &Token) -> String {
+    match token {
+        Token::String(value) => value.clone(),
+        token => format_token(token),
+    }
+}
+
+/// Formats a token so that [`parse_token`] parses it back, e.g. `[(0x00…01,"a b"),(…)]`
+pub fn format_token(token: &Token) -> String {
+    let list = |tokens: &[Token]| tokens.iter().map(format_token).collect::<Vec<_>>().join(",");
+    match token {
+        Token::Address(address) => format!("{:?}", address),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", bytes.to_hex::<String>()),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => format_int(*value),
+        Token::Array(tokens) | Token::FixedArray(tokens) => format!("[{}]", list(tokens)),
+        Token::Tuple(tokens) => format!("({})", list(tokens)),
+    }
+}
+
+/// Formats the two's complement `value` of an `int` as a signed decimal
+fn format_int(value: U256) -> String {
+    if value.bit(255) {
+        format!("-{}", (!value).overflowing_add(U256::one()).0)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders the values returned by `func` for users, see [`format_retdata`]
+pub fn format_output(func: &Function, retdata: &[u8], hex_uints: bool) -> String {
+    let params = func.outputs.iter().map(|param| param.kind.clone()).collect::<Vec<_>>();
+    format_retdata(&params, retdata, hex_uints)
+}
+
+/// Renders the return data of a call which returns values of the given types for users, one
+/// value per line, prefixed with its index if there are several. Return data which cannot be
+/// decoded is rendered as hex, with the reason.
+pub fn format_retdata(params: &[ParamType], retdata: &[u8], hex_uints: bool) -> String {
+    let tokens = match abi::decode(params, retdata) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            let params = params.iter().map(ToString::to_string).collect::<Vec<_>>();
+            return format!(
+                "0x{} (could not decode the return data as ({}): {})",
+                retdata.to_hex::<String>(),
+                params.join(","),
+                err
+            )
+        }
+    };
+    match &tokens[..] {
+        [token] => pretty_token(token, hex_uints),
+        tokens => tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| format!("[{}]: {}", i, pretty_token(token, hex_uints)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Renders a token for users: uints in decimal (with their hex alongside if `hex_uints`),
+/// checksummed addresses, `0x`-hex bytes, quoted and escaped strings, and arrays and tuples
+/// with one indexed element per line
+pub fn pretty_token(token: &Token, hex_uints: bool) -> String {
+    pretty_token_at(token, hex_uints, 0)
+}
+
+fn pretty_token_at(token: &Token, hex_uints: bool, indent: usize) -> String {
+    let list = |open: char, close: char, tokens: &[Token]| {
+        if tokens.is_empty() {
+            return format!("{}{}", open, close)
+        }
+        let mut rendered = format!("{}\n", open);
+        for (i, token) in tokens.iter().enumerate() {
+            let element = pretty_token_at(token, hex_uints, indent + 2);
+            rendered.push_str(&format!(
+                "{:indent$}[{}]: {}\n",
+                "",
+                i,
+                element,
+                indent = indent + 2
+            ));
+        }
+        format!("{}{:indent$}{}", rendered, "", close, indent = indent)
+    };
+    match token {
+        Token::Address(address) => utils::to_checksum(address, None),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", bytes.to_hex::<String>()),
+        Token::Uint(value) if hex_uints => format!("{} ({:#x})", value, value),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => format_int(*value),
+        Token::Array(tokens) | Token::FixedArray(tokens) => list('[', ']', tokens),
+        Token::Tuple(tokens) => list('(', ')', tokens),
+    }
+}
+
+/// Parses a `0x`-hex address, verifying its checksum if it is mixed-case
+fn parse_address(value: &str) -> Result<Address> {
+    let hex = value.strip_prefix("0x").unwrap_or(value);
+    if hex.len() != 40 {
+        eyre::bail!("addresses are 40 hex characters long, got {}", hex.len())
+    }
+    let address = Address::from_str(hex)?;
+    let mixed_case =
+        hex.chars().any(|c| c.is_ascii_uppercase()) && hex.chars().any(|c| c.is_ascii_lowercase());
+    if mixed_case && utils::to_checksum(&address, None)[2..] != *hex {
+        eyre::bail!("invalid checksum, expected {}", utils::to_checksum(&address, None))
+    }
+    Ok(address)
+}
+
+/// Unquotes and unescapes a double-quoted string. Unquoted strings are taken as they are.
+fn parse_string(value: &str) -> Result<String> {
+    let quoted = match value.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => return Ok(value.to_owned()),
+    };
+    let mut string = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('\\' | '"')) => string.push(escaped),
+                _ => eyre::bail!("only `\\\\` and `\\\"` can be escaped"),
+            },
+            '"' if chars.as_str().is_empty() => return Ok(string),
+            '"' => eyre::bail!("unescaped quote before the end of the string"),
+            c => string.push(c),
+        }
+    }
+    eyre::bail!("missing the closing quote")
+}
+
+/// Parses a decimal or `0x`-hex integer, which may carry a unit, e.g. `1.5 ether`, into wei
+fn parse_amount(value: &str) -> Result<U256> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        let hex = if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_owned() };
+        let bytes = hex.from_hex::<Vec<u8>>()?;
+        if bytes.len() > 32 {
+            eyre::bail!("{} is larger than 256 bits", value)
+        }
+        return Ok(U256::from_big_endian(&bytes))
+    }
+    // `gwei` has to be matched before `wei`, which it ends with
+    let (amount, decimals) = [("gwei", 9), ("ether", 18), ("wei", 0)]
+        .iter()
+        .find_map(|(unit, decimals)| Some((value.strip_suffix(unit)?.trim_end(), *decimals)))
+        .unwrap_or((value, 0));
+    if amount.chars().any(|c| c.is_ascii_alphabetic()) {
+        eyre::bail!("invalid amount `{}`, the units are `wei`, `gwei` and `ether`", value)
+    }
+    let (integer, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if fraction.len() > decimals {
+        eyre::bail!("`{}` has more than {} decimals", amount, decimals)
+    }
+    U256::from_dec_str(&format!("{}{:0<width$}", integer, fraction, width = decimals))
+        .map_err(|err| eyre::eyre!("invalid amount `{}`: {:?}", amount, err))
+}
+
+/// Splits a list written as `[a,b,c]` (or with other delimiters) into its elements, which may
+/// be nested lists or quoted strings containing commas
+fn split_list(value: &str, open: char, close: char) -> Result<Vec<&str>> {
+    let elements =
+        value.strip_prefix(open).and_then(|value| value.strip_suffix(close)).ok_or_else(|| {
+            eyre::eyre!(
+                "expected a list enclosed in `{}{}`, e.g. `{}1,2,3{}`",
+                open,
+                close,
+                open,
+                close
+            )
+        })?;
+    if elements.trim().is_empty() {
+        return Ok(Vec::new())
+    }
+    // only the commas outside of nested lists and strings separate the elements
+    let (mut depth, mut in_string, mut escaped) = (0, false, false);
+    let (mut start, mut split) = (0, Vec::new());
+    for (i, c) in elements.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(&elements[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&elements[start..]);
+    Ok(split)
+}
+
+/// A log decoded by an [`EventDecoder`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedLog {
+    /// The name of the event
+    pub name: String,
+    /// The names of the parameters of the event along with their rendered values, including
+    /// the indexed ones
+    pub params: Vec<(String, String)>,
+}
+
+impl fmt::Display for DecodedLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self
+            .params
+            .iter()
+            .map(
+                |(name, value)| {
+                    if name.is_empty() {
+                        value.clone()
+                    } else {
+                        format!("{}: {}", name, value)
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        write!(f, "{}({})", self.name, params.join(", "))
+    }
+}
+
+/// Decodes raw logs against the events of several ABIs, e.g. of all the compiled contracts
+#[derive(Clone, Debug, Default)]
+pub struct EventDecoder {
+    /// The events keyed by their topic. Events of the same signature may index different
+    /// parameters, e.g. ERC-20's and ERC-721's `Transfer`, so each of them is tried in turn.
+    events: BTreeMap<H256, Vec<Event>>,
+}
+
+impl EventDecoder {
+    /// Indexes the non-anonymous events of the ABIs by their topic, keeping one copy of the
+    /// events declared by several of them
+    pub fn new(abis: &[Abi]) -> Self {
+        let mut events = BTreeMap::<H256, Vec<Event>>::new();
+        // anonymous events have no topic to be identified by
+        for event in abis.iter().flat_map(|abi| abi.events()).filter(|event| !event.anonymous) {
+            let candidates = events.entry(event.signature()).or_default();
+            if !candidates.contains(event) {
+                candidates.push(event.clone());
+            }
+        }
+        Self { events }
+    }
+
+    /// Decodes a log emitted by any of the events, or returns `None` if none of them matches
+    pub fn decode_log(&self, log: &RawLog) -> Option<DecodedLog> {
+        let topic = log.topics.first()?;
+        self.events.get(topic)?.iter().find_map(|event| {
+            let params = decode_event_params(event, log)?;
+            Some(DecodedLog { name: event.name.clone(), params })
+        })
+    }
+}
+
+/// Decodes and renders the parameters of `event` from the topics and data of `log`, in the
+/// order they are declared in
+fn decode_event_params(event: &Event, log: &RawLog) -> Option<Vec<(String, String)>> {
+    let indexed = event.inputs.iter().filter(|input| input.indexed).count();
+    if log.topics.len() != indexed + 1 {
+        return None
+    }
+    let kinds = event.inputs.iter().filter(|input| !input.indexed).map(|input| input.kind.clone());
+    let mut data = abi::decode(&kinds.collect::<Vec<_>>(), &log.data).ok()?.into_iter();
+    let mut topics = log.topics[1..].iter();
+    event
+        .inputs
+        .iter()
+        .map(|input| {
+            let value = match &input.kind {
+                _ if !input.indexed => pretty_token(&data.next()?, false),
+                // the indexed values of dynamic types are hashed, their preimage is lost
+                ParamType::String |
+                ParamType::Bytes |
+                ParamType::Array(_) |
+                ParamType::FixedArray(..) |
+                ParamType::Tuple(_) => format!("{:?} (hashed)", topics.next()?),
+                kind => {
+                    let topic = topics.next()?;
+                    pretty_token(
+                        &abi::decode(&[kind.clone()], topic.as_bytes()).ok()?.pop()?,
+                        false,
+                    )
+                }
+            };
+            Some((input.name.clone(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::Tokenizable;
+    use proptest::{collection::vec, prelude::*};
+
+    fn revert_data(selector: [u8; 4], token: Token) -> Vec<u8> {
+        let mut data = selector.to_vec();
+        data.extend(abi::encode(&[token]));
+        data
+    }
+
+    #[test]
+    fn decodes_reverts() {
+        let error =
+            revert_data(ERROR_SELECTOR, Token::String("Ownable: caller is not the owner".into()));
+        assert_eq!(decode_revert(&error), "Ownable: caller is not the owner");
+
+        let panic = revert_data(PANIC_SELECTOR, Token::Uint(0x11.into()));
+        assert_eq!(decode_revert(&panic), "panic: arithmetic underflow or overflow (0x11)");
+        let panic = revert_data(PANIC_SELECTOR, Token::Uint(0x99.into()));
+        assert_eq!(decode_revert(&panic), "panic: unknown panic code (0x99)");
+
+        assert_eq!(decode_revert(&[]), "<empty revert data>");
+        // custom errors are not decoded
+        assert_eq!(decode_revert(&[0xde, 0xad, 0xbe, 0xef]), "0xdeadbeef");
+        assert_eq!(decode_panic(&error), None);
+    }
+
+    #[test]
+    fn parses_functions() {
+        let greeting = get_func("function greeting() returns (string)").unwrap();
+        assert_eq!(func_signature(&greeting), "greeting()");
+        assert_eq!(greeting.outputs[0].kind, ParamType::String);
+        for sig in [
+            "setUp()",
+            "setUp() external",
+            "function setUp()",
+            "  function setUp() public  ",
+            r#"{"type":"function","name":"setUp","inputs":[],"outputs":[],"stateMutability":"nonpayable"}"#,
+            r#"[{"name":"setUp","inputs":[],"outputs":[]}]"#,
+        ] {
+            assert_eq!(func_signature(&get_func(sig).unwrap()), "setUp()", "{}", sig);
+        }
+
+        let abi = parse_abi(&["function setUp()", "function greet(string)"]).unwrap();
+        let set_up = get_func_from_abi("0x0a9254e4", &abi).unwrap();
+        assert_eq!(func_signature(&set_up), "setUp()");
+        let greet = get_func_from_abi("greet(string)", &abi).unwrap();
+        assert_eq!(func_signature(&greet), "greet(string)");
+    }
+
+    #[test]
+    fn function_parsing_errors_name_the_form() {
+        let err = get_func("0x0a9254e4").unwrap_err().to_string();
+        assert!(err.contains("without an ABI"), "{}", err);
+        let abi = parse_abi(&["function greet(string)"]).unwrap();
+        let err = get_func_from_abi("0x0a9254e4", &abi).unwrap_err().to_string();
+        assert!(err.contains("no function of the ABI has the selector"), "{}", err);
+        let err = get_func("setUp(").unwrap_err().to_string();
+        assert!(err.contains("as a human-readable function signature"), "{}", err);
+        let err = get_func(r#"{"type":"function""#).unwrap_err().to_string();
+        assert!(err.contains("as a JSON ABI fragment"), "{}", err);
+        let err = get_func(r#"{"type":"event","name":"E","inputs":[],"anonymous":false}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("declares no function"), "{}", err);
+    }
+
+    #[test]
+    fn parses_events() {
+        for sig in [
+            "Transfer(address indexed from, address indexed to, uint256 amount)",
+            "event Transfer(address indexed from, address indexed to, uint256 amount)",
+            r#"{"type":"event","name":"Transfer","anonymous":false,"inputs":[
+                {"name":"from","type":"address","indexed":true},
+                {"name":"to","type":"address","indexed":true},
+                {"name":"amount","type":"uint256","indexed":false}]}"#,
+        ] {
+            let event = get_event(sig).unwrap();
+            assert_eq!(event.name, "Transfer");
+            assert_eq!(
+                event.inputs.iter().map(|param| param.indexed).collect::<Vec<_>>(),
+                [true, true, false]
+            );
+        }
+        let err = get_event("Transfer(address").unwrap_err().to_string();
+        assert!(err.contains("as a human-readable event signature"), "{}", err);
+    }
+
+    proptest! {
+        #[test]
+        fn function_signatures_round_trip(
+            name in "fn[a-zA-Z0-9_]{0,10}",
+            kinds in vec(
+                prop::sample::select(vec![
+                    "address", "bool", "bytes", "bytes32", "int8", "string", "uint256",
+                    "uint8[]", "address[2]",
+                ]),
+                0..5,
+            ),
+            keyword in any::<bool>(),
+        ) {
+            let sig = format!("{}({})", name, kinds.join(","));
+            let declaration = if keyword { format!("function {}", sig) } else { sig.clone() };
+            let func = get_func(&declaration).unwrap();
+            prop_assert_eq!(func_signature(&func), sig);
+        }
+    }
+
+    #[test]
+    fn encodes_constructor_args() {
+        let code = Bytes::from(vec![0x60, 0x80]);
+        let abi = parse_abi(&["constructor(address owner, uint256 supply, bool paused)"]).unwrap();
+        let owner = Address::repeat_byte(0xab);
+        let args = [owner.into_token(), Token::Uint(1.into()), Token::Bool(true)];
+        let encoded = encode_constructor_args(&abi, &args, code.clone()).unwrap();
+        assert_eq!(encoded[..2], code[..]);
+        assert_eq!(encoded[2..], abi::encode(&args)[..]);
+
+        let err = encode_constructor_args(&abi, &args[..2], code.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "the constructor takes 3 argument(s), but 2 were provided");
+        let wrong = [Token::Bool(true), Token::Uint(1.into()), Token::Bool(true)];
+        let err = encode_constructor_args(&abi, &wrong, code.clone()).unwrap_err();
+        assert!(err.to_string().starts_with("constructor argument 0 must be of type address"));
+
+        // contracts without a constructor take no arguments
+        let abi = parse_abi(&["function setUp()"]).unwrap();
+        assert_eq!(encode_constructor_args(&abi, &[], code.clone()).unwrap(), code);
+        assert!(encode_constructor_args(&abi, &args, code).is_err());
+    }
+
+    #[test]
+    fn encodes_constructor_args_from_str() {
+        let abi =
+            parse_abi(&["constructor(address owner, uint256 supply, bool paused, uint8[] ids)"])
+                .unwrap();
+        let args = ["0xabababababababababababababababababababab", "1.5 ether", "true", "[1,2,3]"]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let encoded = encode_constructor_args_from_str(&abi, &args, Bytes::default()).unwrap();
+        let expected = [
+            Address::repeat_byte(0xab).into_token(),
+            Token::Uint(U256::exp10(17) * 15),
+            Token::Bool(true),
+            Token::Array(vec![Token::Uint(1.into()), Token::Uint(2.into()), Token::Uint(3.into())]),
+        ];
+        assert_eq!(encoded.to_vec(), abi::encode(&expected));
+
+        let mut invalid = args.clone();
+        invalid[3] = "[1,256]".to_owned();
+        let err = encode_constructor_args_from_str(&abi, &invalid, Bytes::default()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("invalid constructor argument 3 (`[1,256]`) of type uint8[]"));
+        invalid[1] = "1 eth".to_owned();
+        let err = encode_constructor_args_from_str(&abi, &invalid, Bytes::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid constructor argument 1 (`1 eth`) of type uint256: invalid amount `1 eth`, the units are `wei`, `gwei` and `ether`"
+        );
+    }
+
+    #[test]
+    fn parses_tokens() {
+        assert_eq!(
+            parse_token(&ParamType::Uint(256), "10 gwei").unwrap(),
+            Token::Uint(U256::exp10(10))
+        );
+        assert!(parse_token(&ParamType::Uint(256), "0.1 wei").is_err());
+        assert!(parse_token(&ParamType::Uint(8), "1 gwei").is_err());
+        assert_eq!(
+            parse_token(&ParamType::Bytes, "0xdead").unwrap(),
+            Token::Bytes(vec![0xde, 0xad])
+        );
+        assert!(parse_token(&ParamType::FixedBytes(1), "0xdead").is_err());
+        let nested =
+            ParamType::FixedArray(Box::new(ParamType::Array(Box::new(ParamType::Bool))), 2);
+        assert_eq!(
+            parse_token(&nested, "[[true, false], []]").unwrap(),
+            Token::FixedArray(vec![
+                Token::Array(vec![Token::Bool(true), Token::Bool(false)]),
+                Token::Array(vec![])
+            ])
+        );
+        assert!(parse_token(&nested, "[[true]]").is_err());
+        assert!(parse_token(&nested, "true").is_err());
+    }
+
+    #[test]
+    fn encodes_human_typed_call_arguments() {
+        let func = get_func("function greetAll(uint256[] ids, string greeting)").unwrap();
+        let calldata = encode_args(&func, &["[1, 2]".to_owned(), "gm".to_owned()]).unwrap();
+        let ids = Token::Array(vec![Token::Uint(1.into()), Token::Uint(2.into())]);
+        assert_eq!(calldata, func.encode_input(&[ids, Token::String("gm".to_owned())]).unwrap());
+        // the logged values are rendered as they are
+        assert_eq!(format_log_token(&Token::String("gm".to_owned())), "gm");
+        assert!(encode_args(&func, &["[1, 2]".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn parses_human_typed_arguments() {
+        let params = [
+            ParamType::Uint(256),
+            ParamType::Int(8),
+            ParamType::Address,
+            ParamType::String,
+            ParamType::Tuple(vec![ParamType::Bool, ParamType::Array(Box::new(ParamType::String))]),
+        ];
+        let values = [
+            "0x10",
+            "-128",
+            "0xB7e390864a90b7b923C9f9310C6F98aafE43F707",
+            "\"say \\\"hi\\\"\"",
+            r#"(true, ["a,b", "[c]"])"#,
+        ];
+        let tokens = parse_tokens(&params, &values).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::Uint(16.into()),
+                Token::Int(U256::MAX - 127),
+                Token::Address("0xb7e390864a90b7b923c9f9310c6f98aafe43f707".parse().unwrap()),
+                Token::String("say \"hi\"".to_owned()),
+                Token::Tuple(vec![
+                    Token::Bool(true),
+                    Token::Array(vec![Token::String("a,b".into()), Token::String("[c]".into())])
+                ]),
+            ]
+        );
+        assert_eq!(
+            parse_token(&ParamType::Uint(256), "2gwei").unwrap(),
+            Token::Uint(2_000_000_000u64.into())
+        );
+        assert_eq!(
+            parse_token(&ParamType::String, "unquoted").unwrap(),
+            Token::String("unquoted".into())
+        );
+
+        let err = parse_tokens(&params[..1], &values).unwrap_err();
+        assert_eq!(err.to_string(), "expected 1 argument(s), got 5");
+        let err = parse_tokens(&[ParamType::Int(8)], &["128"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "argument 0 (`128`) must be of type int8: 128 does not fit in int8"
+        );
+        let err =
+            parse_tokens(&[ParamType::Address], &["0xb7E390864a90b7b923C9f9310C6F98aafE43F707"])
+                .unwrap_err();
+        assert!(err.to_string().contains("invalid checksum"), "{}", err);
+        let err = parse_tokens(&[ParamType::String], &["\"unterminated"]).unwrap_err();
+        assert!(err.to_string().ends_with("missing the closing quote"), "{}", err);
+    }
+
+    /// Any ABI type, with nested arrays and tuples
+    fn arb_param() -> impl Strategy<Value = ParamType> {
+        let leaf = prop_oneof![
+            Just(ParamType::Address),
+            Just(ParamType::Bool),
+            Just(ParamType::String),
+            Just(ParamType::Bytes),
+            (1..=32usize).prop_map(ParamType::FixedBytes),
+            (1..=32usize).prop_map(|bytes| ParamType::Uint(bytes * 8)),
+            (1..=32usize).prop_map(|bytes| ParamType::Int(bytes * 8)),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(|kind| ParamType::Array(Box::new(kind))),
+                (inner.clone(), 1..4usize)
+                    .prop_map(|(kind, len)| ParamType::FixedArray(Box::new(kind), len)),
+                vec(inner, 1..4).prop_map(ParamType::Tuple),
+            ]
+        })
+    }
+
+    /// Any token of the given type
+    fn arb_token(param: &ParamType) -> BoxedStrategy<Token> {
+        // the mask of the bits of an integer of the given width
+        let mask = |bits: usize| if bits == 256 { U256::MAX } else { (U256::one() << bits) - 1 };
+        match param.clone() {
+            ParamType::Address => {
+                any::<[u8; 20]>().prop_map(|bytes| Token::Address(bytes.into())).boxed()
+            }
+            ParamType::Bool => any::<bool>().prop_map(Token::Bool).boxed(),
+            ParamType::String => any::<String>().prop_map(Token::String).boxed(),
+            ParamType::Bytes => vec(any::<u8>(), 0..40).prop_map(Token::Bytes).boxed(),
+            ParamType::FixedBytes(size) => {
+                vec(any::<u8>(), size).prop_map(Token::FixedBytes).boxed()
+            }
+            ParamType::Uint(bits) => any::<[u8; 32]>()
+                .prop_map(move |bytes| Token::Uint(U256::from_big_endian(&bytes) & mask(bits)))
+                .boxed(),
+            ParamType::Int(bits) => any::<[u8; 32]>()
+                .prop_map(move |bytes| {
+                    // sign-extends the random bits-wide integer
+                    let value = U256::from_big_endian(&bytes) & mask(bits);
+                    Token::Int(if value.bit(bits - 1) { value | !mask(bits) } else { value })
+                })
+                .boxed(),
+            ParamType::Array(kind) => vec(arb_token(&kind), 0..4).prop_map(Token::Array).boxed(),
+            ParamType::FixedArray(kind, len) => {
+                vec(arb_token(&kind), len).prop_map(Token::FixedArray).boxed()
+            }
+            ParamType::Tuple(kinds) => {
+                kinds.iter().map(arb_token).collect::<Vec<_>>().prop_map(Token::Tuple).boxed()
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn formatted_tokens_round_trip(
+            (param, token) in arb_param().prop_flat_map(|param| (Just(param.clone()), arb_token(&param)))
+        ) {
+            let formatted = format_token(&token);
+            prop_assert_eq!(parse_token(&param, &formatted).unwrap(), token, "{}", formatted);
+        }
+    }
+
+    #[test]
+    fn formats_retdata() {
+        let uint = [ParamType::Uint(256)];
+        let max = abi::encode(&[Token::Uint(U256::MAX)]);
+        assert_eq!(
+            format_retdata(&uint, &max, false),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+        assert_eq!(
+            format_retdata(&uint, &abi::encode(&[Token::Uint(255.into())]), true),
+            "255 (0xff)"
+        );
+
+        let params = [
+            ParamType::Int(256),
+            ParamType::Address,
+            ParamType::Array(Box::new(ParamType::Uint(8))),
+            ParamType::Tuple(vec![
+                ParamType::String,
+                ParamType::Array(Box::new(ParamType::Bytes)),
+                ParamType::FixedBytes(2),
+            ]),
+        ];
+        let tokens = [
+            Token::Int(U256::MAX),
+            Token::Address("0xb7e390864a90b7b923c9f9310c6f98aafe43f707".parse().unwrap()),
+            Token::Array(vec![]),
+            Token::Tuple(vec![
+                Token::String("say \"hi\"\n".to_owned()),
+                Token::Array(vec![Token::Bytes(vec![]), Token::Bytes(vec![0xde, 0xad])]),
+                Token::FixedBytes(vec![0xbe, 0xef]),
+            ]),
+        ];
+        assert_eq!(
+            format_retdata(&params, &abi::encode(&tokens), true),
+            r#"[0]: -1
+[1]: 0xB7e390864a90b7b923C9f9310C6F98aafE43F707
+[2]: []
+[3]: (
+  [0]: "say \"hi\"\n"
+  [1]: [
+    [0]: 0x
+    [1]: 0xdead
+  ]
+  [2]: 0xbeef
+)"#
+        );
+
+        let func = get_func("function pair() returns (int8, bool)").unwrap();
+        let retdata = abi::encode(&[Token::Int(U256::MAX - 127), Token::Bool(false)]);
+        assert_eq!(format_output(&func, &retdata, false), "[0]: -128\n[1]: false");
+    }
+
+    #[test]
+    fn formats_undecodable_retdata() {
+        let rendered =
+            format_retdata(&[ParamType::Uint(256), ParamType::Bool], &[0xde, 0xad], true);
+        assert!(
+            rendered.starts_with("0xdead (could not decode the return data as (uint256,bool): "),
+            "{}",
+            rendered
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn formatting_garbage_retdata_does_not_panic(
+            param in arb_param(),
+            retdata in vec(any::<u8>(), 0..200),
+        ) {
+            format_retdata(&[param], &retdata, true);
+        }
+    }
+
+    #[test]
+    fn decodes_events_of_all_abis() {
+        let erc20 = parse_abi(&[
+            "event Transfer(address indexed from, address indexed to, uint256 amount)",
+            "event Named(string indexed name, string note)",
+        ])
+        .unwrap();
+        let erc721 = parse_abi(&[
+            "event Transfer(address indexed from, address indexed to, uint256 indexed id)",
+        ])
+        .unwrap();
+        let anonymous =
+            parse_json_fragment(r#"{"type":"event","name":"Hidden","anonymous":true,"inputs":[]}"#)
+                .unwrap();
+        let decoder = EventDecoder::new(&[erc20.clone(), erc721.clone(), erc20.clone(), anonymous]);
+
+        let transfer = erc20.event("Transfer").unwrap().signature();
+        let (from, to) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let amount = abi::encode(&[Token::Uint(7.into())]);
+        let log = RawLog { topics: vec![transfer, from.into(), to.into()], data: amount.clone() };
+        let decoded = decoder.decode_log(&log).unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            format!(
+                "Transfer(from: {}, to: {}, amount: 7)",
+                utils::to_checksum(&from, None),
+                utils::to_checksum(&to, None)
+            )
+        );
+
+        // the ERC-721 event of the same signature indexes the id
+        let log = RawLog {
+            topics: vec![transfer, from.into(), to.into(), H256::from_slice(&amount)],
+            data: vec![],
+        };
+        let decoded = decoder.decode_log(&log).unwrap();
+        assert_eq!(decoded.params[2], ("id".to_owned(), "7".to_owned()));
+
+        let name = H256::from(utils::keccak256("alice"));
+        let log = RawLog {
+            topics: vec![erc20.event("Named").unwrap().signature(), name],
+            data: abi::encode(&[Token::String("hi".into())]),
+        };
+        assert_eq!(
+            decoder.decode_log(&log).unwrap().params,
+            [
+                ("name".to_owned(), format!("{:?} (hashed)", name)),
+                ("note".to_owned(), "\"hi\"".to_owned())
+            ]
+        );
+
+        // unknown events and logs which do not match their event are not decoded
+        assert_eq!(decoder.decode_log(&RawLog { topics: vec![H256::zero()], data: vec![] }), None);
+        assert_eq!(decoder.decode_log(&RawLog { topics: vec![transfer], data: vec![] }), None);
+        assert_eq!(decoder.decode_log(&RawLog { topics: vec![], data: vec![] }), None);
+    }
+
+    #[test]
+    fn calldata_costs() {
+        assert_eq!(calldata_cost(&[0, 1, 0, 2], NON_ZERO_BYTE_COST), 2 * 4 + 2 * 16);
+        assert_eq!(calldata_cost(&[1], NON_ZERO_BYTE_COST_FRONTIER), 68);
+        assert_eq!(remove_extra_costs(21040.into(), &[0, 1, 0, 2]), 0.into());
     }
-    Ok(func.encode_input(&inputs)?)
 }